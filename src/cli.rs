@@ -1,11 +1,19 @@
-mod parse;
-mod util;
-
 use clap::*;
-use log::{error, LevelFilter};
+use log::{debug, error, warn, LevelFilter};
+use paca::fmt::{format_source, FormatOptions};
+use paca::parse::{dump, parse_source, printer};
+use paca::pir;
+use paca::pir::lower::lower;
+use paca::pir::opt::{OptLevel, PassManager, PASSES};
+use paca::pir::verify::PirError;
+use paca::sema;
+use paca::sema::modules::{ModuleGraph, ModuleLoader};
+use paca::sema::Analysis;
+use paca::util::GenerateErrorMessage;
 use std::fmt;
 use std::fmt::Formatter;
-use std::fs::read_to_string;
+use std::fs::{read_to_string, write};
+use std::path::{Path, PathBuf};
 
 /// The log level options
 #[derive(Default, ValueEnum, Clone, Debug, PartialEq)]
@@ -49,18 +57,68 @@ enum SourceType {
     Paca,
 }
 
+/// The intermediate results that can be emitted instead of the compiled output.
+#[derive(ValueEnum, Clone, Debug, PartialEq)]
+enum EmitType {
+    /// The abstract syntax tree as an indented tree.
+    Ast,
+    /// The abstract syntax tree as JSON.
+    AstJson,
+    /// The abstract syntax tree printed back as Paca source code.
+    Source,
+    /// The control-flow graph of every function after optimizing, in the DOT language of Graphviz.
+    Cfg,
+    /// The memory layout of every struct and enum, e.g. which enums need no tag.
+    Layout,
+}
+
+/// The optimization levels.
+#[derive(Default, ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OptimizationLevel {
+    /// Don't optimize, except for eliminating tail calls.
+    #[default]
+    #[value(name = "0")]
+    O0,
+    /// Promote variables to values, fold constants and remove dead code.
+    #[value(name = "1")]
+    O1,
+    /// Also inline small functions, share common subexpressions, hoist loop invariants and lay out
+    /// blocks by the profile of `--profile-use`.
+    #[value(name = "2")]
+    O2,
+    /// Like 2, but inline more aggressively.
+    #[value(name = "3")]
+    O3,
+}
+
+impl From<OptimizationLevel> for OptLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::O0 => OptLevel::O0,
+            OptimizationLevel::O1 => OptLevel::O1,
+            OptimizationLevel::O2 => OptLevel::O2,
+            OptimizationLevel::O3 => OptLevel::O3,
+        }
+    }
+}
+
 /// The argument parser for the CLI.
 #[derive(Parser, Debug)]
 #[clap(
     author,
     version,
     max_term_width = 90,
-    about = "Paca - an experimental nibbler"
+    about = "Paca - an experimental nibbler",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct CliArgs {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// The input file to the compiler.
-    #[clap(value_parser)]
-    input_file: String,
+    #[clap(value_parser, required = true)]
+    input_file: Option<String>,
 
     /// The file to write the output of the compiler to.
     #[clap(short, long, value_parser, default_value = "paca-out")]
@@ -75,8 +133,96 @@ struct CliArgs {
     target_type: TargetType,
 
     /// The log level to use.
-    #[clap(short, long, value_parser, default_value = "info")]
+    #[clap(short, long, value_parser, default_value = "info", global = true)]
     log_level: LogLevel,
+
+    /// Additional directories to look for imported modules in, after the directory of the input file.
+    #[clap(short = 'L', long, value_parser)]
+    lib_path: Vec<PathBuf>,
+
+    /// Print an intermediate result to stdout instead of compiling.
+    #[clap(long, value_parser)]
+    emit: Option<EmitType>,
+
+    /// The optimization level.
+    #[clap(short = 'O', value_parser, default_value = "0")]
+    opt_level: OptimizationLevel,
+
+    /// Print the PIR to stderr after every run of the pass.
+    #[clap(long, value_parser = builder::PossibleValuesParser::new(PASSES))]
+    print_after: Vec<String>,
+
+    /// Print to stderr what the pass changed in the program, e.g. which allocations `stack-alloc`
+    /// took off the heap.
+    #[clap(long, value_parser = builder::PossibleValuesParser::new(PASSES))]
+    remarks: Vec<String>,
+
+    /// Count how many times every block and branch runs, for `paca run` to write the counts to a
+    /// profile.
+    #[clap(long)]
+    profile_generate: bool,
+
+    /// Optimize for the profile in the file, written by a run of the program built with
+    /// `--profile-generate`.
+    #[clap(long, value_parser)]
+    profile_use: Option<String>,
+}
+
+/// The subcommands of the CLI, used instead of compiling an input file.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Format Paca source files in place.
+    Fmt(FmtArgs),
+    /// Run a program by interpreting its PIR, without compiling it.
+    Run(RunArgs),
+}
+
+/// The arguments of `paca fmt`.
+#[derive(clap::Args, Debug)]
+struct FmtArgs {
+    /// The files to format.
+    #[clap(value_parser, required = true)]
+    files: Vec<String>,
+
+    /// Only check that the files are formatted, and fail if any of them is not.
+    #[clap(long)]
+    check: bool,
+
+    /// The maximum line width.
+    #[clap(long, value_parser, default_value_t = 100)]
+    width: usize,
+}
+
+/// The arguments of `paca run`.
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    /// The program to run.
+    #[clap(value_parser, required = true)]
+    input_file: String,
+
+    /// The type of the source code to run.
+    #[clap(short, value_parser, default_value = "paca")]
+    source_type: SourceType,
+
+    /// Additional directories to look for imported modules in, after the directory of the input file.
+    #[clap(short = 'L', long, value_parser)]
+    lib_path: Vec<PathBuf>,
+
+    /// The optimization level.
+    #[clap(short = 'O', value_parser, default_value = "0")]
+    opt_level: OptimizationLevel,
+
+    /// Count how many times every block and branch runs, and write the counts to the profile.
+    #[clap(long)]
+    profile_generate: bool,
+
+    /// Optimize for the profile in the file, written by a run with `--profile-generate`.
+    #[clap(long, value_parser)]
+    profile_use: Option<String>,
+
+    /// The file to write the profile to, if the program counts anything.
+    #[clap(long, value_parser, default_value = "paca.profile")]
+    profile_output: String,
 }
 
 /// The types of errors returned by the CLI.
@@ -85,8 +231,19 @@ enum Error {
     IO(std::io::Error),
     /// Error parsing the source code.
     Parse(String),
-    /// Error generating IR code.
-    Pir(String), // TODO: Change the type to appropriate IR Error type.
+    /// Errors found by the semantic checks.
+    Semantic(Vec<String>),
+    /// Violations of the rules of PIR, with the PIR text their locations point into.
+    Pir {
+        errors: Vec<PirError>,
+        source: String,
+    },
+    /// A panic of a program run by `paca run`, with its stack trace.
+    Panic(String),
+    /// A profile given with `--profile-use` that can't be read.
+    Profile(String),
+    /// Error generating code in the target language.
+    Codegen(String),
     /// Error assembling input code.
     Pasm(String), // TODO: Change the type to appropriate PASM Error type.
 }
@@ -95,12 +252,313 @@ impl fmt::Debug for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Error::IO(e) => write!(f, "IO error: {:?}", e),
-            Error::Parse(e) => write!(f, "Parse error: {:?}", e),
-            Error::Pir(e) => write!(f, "IR error: {:?}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Semantic(errors) => write!(f, "{}", errors.join("\n\n")),
+            Error::Pir { errors, source } => {
+                let messages: Vec<String> = errors
+                    .iter()
+                    .map(|e| e.clone().generate_error_message(source))
+                    .collect();
+                write!(f, "{}", messages.join("\n\n"))
+            }
+            Error::Panic(trace) => write!(f, "{}", trace),
+            Error::Profile(e) => write!(f, "Invalid profile: {}", e),
+            Error::Codegen(e) => write!(f, "{}", e),
             Error::Pasm(e) => write!(f, "Assembly error: {:?}", e),
         }
     }
 }
+
+/// Compile the given source code according to the CLI arguments.
+fn compile(args: &CliArgs, source: &str) -> Result<(), Error> {
+    match args.source_type {
+        SourceType::Paca => {
+            let module = parse_source(args.input_file.clone(), source)
+                .map_err(|e| Error::Parse(e.generate_error_message(source)))?;
+            debug!("Parsed {} top-level items.", module.items.len());
+            match args.emit {
+                Some(EmitType::Ast) => print!("{}", dump::to_tree(&module)),
+                Some(EmitType::AstJson) => println!("{}", dump::to_json(&module)),
+                Some(EmitType::Source) => print!("{}", printer::print_module(&module)),
+                Some(EmitType::Cfg | EmitType::Layout) | None => {
+                    let input_file = args.input_file.as_deref().unwrap_or_default();
+                    let graph = load(input_file, &args.lib_path)?;
+                    let analysis = check(&graph)?;
+                    let mut program = lower(&graph, &analysis);
+                    debug!("Lowered {} functions to PIR.", program.functions.len());
+                    check_tail_calls(&graph, &program)?;
+                    if cfg!(debug_assertions) {
+                        verify(&program, None)?;
+                    }
+                    profile(
+                        args.profile_generate,
+                        args.profile_use.as_deref(),
+                        &mut program,
+                    )?;
+                    optimize(
+                        args.opt_level,
+                        &args.print_after,
+                        &args.remarks,
+                        &mut program,
+                    )?;
+                    output(args, &program)?;
+                }
+            }
+            Ok(())
+        }
+        SourceType::Pir => {
+            let (mut program, spans) =
+                pir::parser::parse_module_with_spans(args.input_file.clone(), source)
+                    .map_err(|e| Error::Parse(e.generate_error_message(source)))?;
+            debug!("Parsed {} PIR functions.", program.functions.len());
+            verify(&program, Some((source, &spans)))?;
+            profile(
+                args.profile_generate,
+                args.profile_use.as_deref(),
+                &mut program,
+            )?;
+            optimize(
+                args.opt_level,
+                &args.print_after,
+                &args.remarks,
+                &mut program,
+            )?;
+            output(args, &program)
+        }
+        SourceType::Pasm => Err(Error::Pasm("PASM input is not supported yet.".to_string())),
+    }
+}
+
+/// Run the PIR verifier on the program, pointing the errors at its source if it was read from
+/// one, or at the program printed otherwise.
+fn verify(program: &pir::Module, source: Option<(&str, &pir::Spans)>) -> Result<(), Error> {
+    let Err(errors) = pir::verify::verify(program) else {
+        return Ok(());
+    };
+    let (source, spans) = match source {
+        Some((source, spans)) => (source.to_string(), spans.clone()),
+        None => pir::printer::print_module_with_spans(program),
+    };
+    Err(Error::Pir {
+        errors: errors.into_iter().map(|e| e.locate(&spans)).collect(),
+        source,
+    })
+}
+
+/// Give the program the counts of the profile in the `profile_use` file, and put counters in it
+/// if `generate` is set.
+fn profile(
+    generate: bool,
+    profile_use: Option<&str>,
+    program: &mut pir::Module,
+) -> Result<(), Error> {
+    if let Some(file) = profile_use {
+        let text = read_to_string(file).map_err(Error::IO)?;
+        let profile = pir::profile::Profile::parse(&text)
+            .map_err(|e| Error::Profile(format!("{file}, {e}")))?;
+        for warning in pir::profile::annotate(program, &profile) {
+            warn!("{file}: {warning}");
+        }
+    }
+    if generate {
+        pir::profile::instrument(program);
+    }
+    Ok(())
+}
+
+/// Run the pipeline of the optimization level on the program, verifying it after every pass in
+/// debug builds, and print the remarks of the passes in `remarks`.
+fn optimize(
+    level: OptimizationLevel,
+    print_after: &[String],
+    remarks: &[String],
+    program: &mut pir::Module,
+) -> Result<(), Error> {
+    let mut manager = PassManager::new(level.into())
+        .print_after(print_after.to_vec())
+        .verify_each(cfg!(debug_assertions));
+    debug!("Running the passes {:?}.", manager.pipeline());
+    let result = manager.run(program, |pass, program| {
+        eprintln!("// After {pass}:\n{}", pir::printer::print_module(program));
+    });
+    for remark in manager.remarks() {
+        if remarks.iter().any(|name| name == remark.pass) {
+            eprintln!("{remark}");
+        }
+    }
+    let Err(e) = result else {
+        return Ok(());
+    };
+    error!("The PIR is invalid after the `{}` pass.", e.pass);
+    let (source, spans) = pir::printer::print_module_with_spans(program);
+    Err(Error::Pir {
+        errors: e.errors.into_iter().map(|e| e.locate(&spans)).collect(),
+        source,
+    })
+}
+
+/// Print the control-flow graph or the layouts if they were asked for, or compile the program
+/// otherwise.
+fn output(args: &CliArgs, program: &pir::Module) -> Result<(), Error> {
+    match args.emit {
+        Some(EmitType::Cfg) => {
+            print!("{}", pir::dot::to_dot(program));
+            Ok(())
+        }
+        Some(EmitType::Layout) => {
+            print!("{}", pir::layout::describe(program));
+            Ok(())
+        }
+        _ => generate(args, program),
+    }
+}
+
+/// Write the program in the target language to the output file.
+fn generate(args: &CliArgs, program: &pir::Module) -> Result<(), Error> {
+    match args.target_type {
+        TargetType::Pir => {
+            write(&args.output_file, pir::printer::print_module(program)).map_err(Error::IO)
+        }
+        TargetType::Pasm => Err(Error::Pasm("PASM output is not supported yet.".to_string())),
+        // TODO: Once there is C output, hint the likely target of every branch of a program built
+        // with `--profile-use`, from `pir::profile::likely_target`.
+        TargetType::C => Err(Error::Codegen("C output is not supported yet.".to_string())),
+    }
+}
+
+/// Run the program in the file given to `paca run` with the PIR interpreter.
+fn run(args: &RunArgs) -> Result<(), Error> {
+    let source = read_to_string(&args.input_file).map_err(Error::IO)?;
+    let mut stdout = std::io::stdout().lock();
+    match args.source_type {
+        SourceType::Paca => {
+            let graph = load(&args.input_file, &args.lib_path)?;
+            let analysis = check(&graph)?;
+            let mut program = lower(&graph, &analysis);
+            check_tail_calls(&graph, &program)?;
+            if cfg!(debug_assertions) {
+                verify(&program, None)?;
+            }
+            profile(
+                args.profile_generate,
+                args.profile_use.as_deref(),
+                &mut program,
+            )?;
+            optimize(args.opt_level, &[], &[], &mut program)?;
+            interpret(args, &program, &mut stdout)
+                .map_err(|panic| Error::Panic(panic.render(|loc| graph.source_of(loc))))
+        }
+        SourceType::Pir => {
+            let (mut program, spans) =
+                pir::parser::parse_module_with_spans(Some(args.input_file.clone()), &source)
+                    .map_err(|e| Error::Parse(e.generate_error_message(&source)))?;
+            verify(&program, Some((&source, &spans)))?;
+            profile(
+                args.profile_generate,
+                args.profile_use.as_deref(),
+                &mut program,
+            )?;
+            optimize(args.opt_level, &[], &[], &mut program)?;
+            interpret(args, &program, &mut stdout)
+                .map_err(|panic| Error::Panic(panic.locate(&spans).render(|_| &source)))
+        }
+        SourceType::Pasm => Err(Error::Pasm("PASM input is not supported yet.".to_string())),
+    }
+}
+
+/// Run the program with the interpreter, writing what its counters counted to the profile file
+/// of `paca run`, even if it panics.
+fn interpret(
+    args: &RunArgs,
+    program: &pir::Module,
+    out: &mut dyn std::io::Write,
+) -> Result<(), pir::interp::Panic> {
+    let mut counts = pir::profile::Counts::new();
+    let result = pir::interp::run_counting(program, out, &mut counts);
+    if !counts.is_empty() {
+        let profile = pir::profile::Profile::new(program, &counts);
+        match write(&args.profile_output, profile.to_string()) {
+            Ok(()) => debug!("Wrote the profile to {}.", args.profile_output),
+            Err(e) => error!("Couldn't write the profile to {}: {e}", args.profile_output),
+        }
+    }
+    result
+}
+
+/// Load the input file and every module it imports.
+fn load(input_file: &str, lib_path: &[PathBuf]) -> Result<ModuleGraph, Error> {
+    let input_file = Path::new(input_file);
+    let directory = input_file
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut roots = vec![directory.to_path_buf()];
+    roots.extend(lib_path.iter().cloned());
+    let graph = ModuleLoader::new(roots)
+        .load(input_file)
+        .map_err(|e| Error::Parse(e.message()))?;
+    debug!("Loaded {} modules.", graph.modules.len());
+    Ok(graph)
+}
+
+/// Run the semantic checks on every module, logging warnings and failing on errors.
+fn check(graph: &ModuleGraph) -> Result<Analysis, Error> {
+    let (analysis, diagnostics) = sema::analyze(graph);
+    let mut errors = Vec::new();
+    for diagnostic in diagnostics {
+        let source = graph.source_of(&diagnostic.loc);
+        if diagnostic.is_error() {
+            errors.push(diagnostic.generate_error_message(source));
+        } else {
+            warn!("{}", diagnostic.generate_error_message(source));
+        }
+    }
+    match analysis {
+        Some(analysis) if errors.is_empty() => Ok(analysis),
+        _ => Err(Error::Semantic(errors)),
+    }
+}
+
+/// Report the recursive calls of `@tailcall` functions that can't be turned into jumps.
+fn check_tail_calls(graph: &ModuleGraph, program: &pir::Module) -> Result<(), Error> {
+    let errors: Vec<String> = pir::tail::check(program)
+        .into_iter()
+        .map(|diagnostic| {
+            let source = graph.source_of(&diagnostic.loc);
+            diagnostic.generate_error_message(source)
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Semantic(errors))
+    }
+}
+
+/// Format the files given to `paca fmt`. Returns whether every file was already formatted.
+fn format_files(args: &FmtArgs) -> Result<bool, Error> {
+    let options = FormatOptions {
+        line_width: args.width,
+    };
+    let mut formatted = true;
+    for file in &args.files {
+        let source = read_to_string(file).map_err(Error::IO)?;
+        let output = format_source(Some(file.clone()), &source, &options)
+            .map_err(|e| Error::Parse(e.generate_error_message(&source)))?;
+        if output == source {
+            continue;
+        }
+        formatted = false;
+        if args.check {
+            println!("{file} is not formatted.");
+        } else {
+            write(file, output).map_err(Error::IO)?;
+            debug!("Formatted {file}.");
+        }
+    }
+    Ok(formatted)
+}
+
 fn main() {
     // Parse the CLI arguments.
     let args = CliArgs::parse();
@@ -119,10 +577,44 @@ fn main() {
 
     log_builder.init();
 
-    match read_to_string(&args.input_file).map_err(Error::IO) {
+    if let Some(Command::Fmt(fmt_args)) = &args.command {
+        match format_files(fmt_args) {
+            Ok(true) => {}
+            Ok(false) if !fmt_args.check => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("{e:?}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(Command::Run(run_args)) = &args.command {
+        match run(run_args) {
+            Ok(()) => {}
+            Err(Error::Panic(trace)) => {
+                eprintln!("{trace}");
+                std::process::exit(101);
+            }
+            Err(e) => {
+                error!("{e:?}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let input_file = args.input_file.as_deref().unwrap_or_default();
+    match read_to_string(input_file).map_err(Error::IO) {
         Ok(file_content) => {
-            todo!()
+            if let Err(e) = compile(&args, &file_content) {
+                error!("{e:?}");
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            error!("Error reading file: {e:?}");
+            std::process::exit(1);
         }
-        Err(e) => error!("Error reading file: {e:?}"),
     }
 }
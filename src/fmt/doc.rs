@@ -0,0 +1,236 @@
+//! A Wadler-style pretty-printing engine.
+//!
+//! A `Doc` describes text together with the places where it may be broken into several lines. Every
+//! `Group` is printed on a single line if it fits into the remaining width, otherwise its `Line`s
+//! become newlines. `HardLine`s always break, and so does every group containing one.
+
+/// Number of spaces per indentation level.
+const INDENT: usize = 4;
+
+/// A document to pretty-print.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Doc {
+    Nil,
+    Text(String),
+    /// A space, or a newline if the enclosing group is broken.
+    Line,
+    /// Nothing, or a newline if the enclosing group is broken.
+    SoftLine,
+    /// Always a newline.
+    HardLine,
+    Concat(Vec<Doc>),
+    /// Indent the newlines inside the document by one level.
+    Nest(Box<Doc>),
+    /// Print the document on one line if it fits, otherwise break its lines.
+    Group(Box<Doc>),
+    /// Only printed if the enclosing group is broken, e.g. for trailing commas.
+    IfBreak(Box<Doc>),
+}
+
+pub fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+pub fn concat(docs: Vec<Doc>) -> Doc {
+    Doc::Concat(docs)
+}
+
+pub fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+}
+
+pub fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+pub fn if_break(doc: Doc) -> Doc {
+    Doc::IfBreak(Box::new(doc))
+}
+
+/// Join `docs` with `separator`.
+pub fn join(docs: Vec<Doc>, separator: Doc) -> Doc {
+    let mut out = Vec::with_capacity(docs.len() * 2);
+    for (i, doc) in docs.into_iter().enumerate() {
+        if i > 0 {
+            out.push(separator.clone());
+        }
+        out.push(doc);
+    }
+    Doc::Concat(out)
+}
+
+/// A comma-separated list between `open` and `close`, put one item per line with a trailing comma if it
+/// doesn't fit. `padding` adds spaces inside the delimiters when the list stays on one line.
+pub fn delimited(open: &str, items: Vec<Doc>, close: &str, padding: bool) -> Doc {
+    if items.is_empty() {
+        return text(format!("{open}{close}"));
+    }
+    let line = if padding { Doc::Line } else { Doc::SoftLine };
+    let trailing = if ends_with_rest(&items) {
+        Doc::Nil
+    } else {
+        if_break(text(","))
+    };
+    group(concat(vec![
+        text(open),
+        nest(concat(vec![
+            line.clone(),
+            join(items, concat(vec![text(","), Doc::Line])),
+            trailing,
+        ])),
+        line,
+        text(close),
+    ]))
+}
+
+/// Whether the items end with the `..` of a struct pattern, which can't be followed by a comma.
+pub fn ends_with_rest(items: &[Doc]) -> bool {
+    items.last() == Some(&text(".."))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Render the document, trying to keep lines within `width` columns.
+pub fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if *doc == Doc::Line {
+                    out.push(' ');
+                    column += 1;
+                }
+            }
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                // Never leave trailing whitespace behind, e.g. on empty lines.
+                let trimmed = out.trim_end_matches(' ').len();
+                out.truncate(trimmed);
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((indent, mode, doc));
+                }
+            }
+            Doc::Nest(doc) => stack.push((indent + INDENT, mode, doc)),
+            Doc::Group(inner) => {
+                let mode =
+                    if mode == Mode::Flat || fits(width.saturating_sub(column), inner, &stack) {
+                        Mode::Flat
+                    } else {
+                        Mode::Break
+                    };
+                stack.push((indent, mode, inner));
+            }
+            Doc::IfBreak(doc) => {
+                if mode == Mode::Break {
+                    stack.push((indent, mode, doc));
+                }
+            }
+        }
+    }
+
+    let trimmed = out.trim_end().len();
+    out.truncate(trimmed);
+    out.push('\n');
+    out
+}
+
+/// Check whether `doc` printed flat, followed by the rest of the line, takes at most `width` columns.
+fn fits(width: usize, doc: &Doc, rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut remaining = width as isize;
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+
+    loop {
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Nil => {}
+            Doc::Text(s) => {
+                remaining -= s.chars().count() as isize;
+                if remaining < 0 {
+                    return false;
+                }
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if *doc == Doc::Line {
+                    remaining -= 1;
+                    if remaining < 0 {
+                        return false;
+                    }
+                }
+            }
+            // A group containing a hard line can never be flat.
+            Doc::HardLine if mode == Mode::Flat => return false,
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((mode, doc));
+                }
+            }
+            Doc::Nest(doc) => stack.push((mode, doc)),
+            Doc::Group(doc) => stack.push((mode, doc)),
+            Doc::IfBreak(doc) => {
+                if mode == Mode::Break {
+                    stack.push((mode, doc));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(args: usize) -> Doc {
+        let items = (0..args).map(|i| text(format!("argument{i}"))).collect();
+        concat(vec![text("f"), delimited("(", items, ")", false)])
+    }
+
+    #[test]
+    fn groups_break_only_when_needed() {
+        assert_eq!(render(&call(2), 80), "f(argument0, argument1)\n");
+        assert_eq!(
+            render(&call(2), 20),
+            "f(\n    argument0,\n    argument1,\n)\n"
+        );
+    }
+
+    #[test]
+    fn hard_lines_break_enclosing_groups() {
+        let doc = group(concat(vec![
+            text("{"),
+            nest(concat(vec![
+                Doc::Line,
+                text("a"),
+                Doc::HardLine,
+                Doc::HardLine,
+                text("b"),
+            ])),
+            Doc::Line,
+            text("}"),
+        ]));
+        assert_eq!(render(&doc, 80), "{\n    a\n\n    b\n}\n");
+    }
+}
@@ -0,0 +1,1057 @@
+//! The opinionated source formatter behind `paca fmt`.
+//!
+//! The source is tokenized with comments kept, the remaining tokens are parsed, and the AST is turned
+//! into a `Doc` that is rendered within the configured line width. Comments are re-attached at the
+//! closest item, statement, field, variant or match arm boundary, next to the parameter they follow,
+//! or next to the argument, element, struct literal field or operator they follow inside an
+//! expression and the field or alternative they follow inside a pattern, and single blank lines
+//! between them are kept. Since the output only depends on the AST, the comments and the blank lines, formatting
+//! formatted code doesn't change it.
+
+pub mod doc;
+
+use crate::parse::ast::*;
+use crate::parse::printer::{literal_str, print_pattern, print_type};
+use crate::parse::{Lexer, Parser, SourceCodeLocation, SyntaxError, TokenKind, Tokenize};
+use doc::{concat, delimited, ends_with_rest, group, join, nest, render, text, Doc};
+
+/// Options for the formatter.
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    /// The maximum number of columns to fill before breaking lines.
+    pub line_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self { line_width: 100 }
+    }
+}
+
+/// Format the given source code.
+pub fn format_source(
+    filename: Option<String>,
+    source: &str,
+    options: &FormatOptions,
+) -> Result<String, SyntaxError> {
+    let tokens = Lexer::new(filename, source)
+        .keep_comments()
+        .tokenize()
+        .map_err(SyntaxError::Lex)?;
+
+    let (comments, tokens): (Vec<_>, Vec<_>) = tokens
+        .into_iter()
+        .partition(|t| matches!(t.kind, TokenKind::Comment(_)));
+    let comments = comments
+        .into_iter()
+        .filter_map(|t| match t.kind {
+            TokenKind::Comment(text) => Some(Comment {
+                text,
+                line: t.loc.line,
+                offset: t.loc.offset,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    let module = Parser::new(tokens).parse().map_err(SyntaxError::Parse)?;
+
+    let mut formatter = Formatter {
+        comments,
+        next_comment: 0,
+        line_starts: line_starts(source),
+        no_struct: false,
+    };
+    let doc = formatter.module(&module);
+    Ok(render(&doc, options.line_width))
+}
+
+/// The character offsets at which each line starts.
+fn line_starts(source: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    for (i, c) in source.chars().enumerate() {
+        if c == '\n' {
+            starts.push(i + 1);
+        }
+    }
+    starts
+}
+
+struct Comment {
+    /// The text after `//`.
+    text: String,
+    line: usize,
+    offset: usize,
+}
+
+/// Blank lines between consecutive nodes are kept where the source has one or more, and `Spacing`
+/// decides where one is always put in addition.
+type Spacing<T> = fn(&T, &T) -> bool;
+
+fn preserve<T>(_: &T, _: &T) -> bool {
+    false
+}
+
+/// A member of a trait or impl body.
+enum Member<'a> {
+    Type(&'a AssocType),
+    Method(&'a FnDecl),
+}
+
+impl Member<'_> {
+    fn loc(&self) -> &SourceCodeLocation {
+        match self {
+            Member::Type(assoc) => &assoc.loc,
+            Member::Method(method) => &method.loc,
+        }
+    }
+}
+
+/// The members of a trait or impl in source order.
+fn members<'a>(assoc_types: &'a [AssocType], methods: &'a [FnDecl]) -> Vec<Member<'a>> {
+    let mut members = assoc_types
+        .iter()
+        .map(Member::Type)
+        .chain(methods.iter().map(Member::Method))
+        .collect::<Vec<_>>();
+    members.sort_by_key(|m| m.loc().offset);
+    members
+}
+
+struct Formatter {
+    comments: Vec<Comment>,
+    next_comment: usize,
+    line_starts: Vec<usize>,
+    /// Whether a `{` would start a body, so struct literals and trailing closures need parentheses.
+    no_struct: bool,
+}
+
+impl Formatter {
+    /// The line on which a location ends.
+    fn end_line(&self, loc: &SourceCodeLocation) -> usize {
+        self.line_at(Self::end_offset(loc))
+    }
+
+    /// The line of the character at an offset.
+    fn line_at(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+
+    /// The offset of the last character of a location, e.g. the closing brace of a block.
+    fn end_offset(loc: &SourceCodeLocation) -> usize {
+        (loc.offset + loc.length).saturating_sub(1)
+    }
+
+    fn comment_before(&self, offset: usize) -> Option<&Comment> {
+        self.comments
+            .get(self.next_comment)
+            .filter(|c| c.offset < offset)
+    }
+
+    /// The text of every comment left before the offset.
+    fn take_comments(&mut self, offset: usize) -> Vec<String> {
+        let mut texts = Vec::new();
+        while let Some(comment) = self.comment_before(offset) {
+            texts.push(comment.text.clone());
+            self.next_comment += 1;
+        }
+        texts
+    }
+
+    /// Lay out nodes one per line, together with the comments between them and up to `end`.
+    fn lines<T>(
+        &mut self,
+        nodes: &[T],
+        end: usize,
+        spacing: Spacing<T>,
+        loc: impl Fn(&T) -> &SourceCodeLocation,
+        mut format: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> Doc {
+        let mut parts = Vec::new();
+        let mut previous_line: Option<usize> = None;
+
+        let separate =
+            |parts: &mut Vec<Doc>, previous_line: Option<usize>, line: usize, blank: bool| {
+                if let Some(previous_line) = previous_line {
+                    parts.push(Doc::HardLine);
+                    if blank || line > previous_line + 1 {
+                        parts.push(Doc::HardLine);
+                    }
+                }
+            };
+
+        for (i, node) in nodes.iter().enumerate() {
+            let start = loc(node).clone();
+            let mut blank = i > 0 && spacing(&nodes[i - 1], node);
+
+            while let Some(comment) = self.comment_before(start.offset) {
+                let line = comment.line;
+                let text = text(format!("//{}", comment.text));
+                separate(&mut parts, previous_line, line, blank);
+                blank = false;
+                parts.push(text);
+                previous_line = Some(line);
+                self.next_comment += 1;
+            }
+
+            separate(&mut parts, previous_line, start.line, blank);
+            parts.push(format(self, node));
+            let end_line = self.end_line(&start);
+
+            if let Some(comment) = self.comment_before(end) {
+                if comment.line == end_line {
+                    parts.push(text(format!(" //{}", comment.text)));
+                    self.next_comment += 1;
+                }
+            }
+            previous_line = Some(end_line);
+        }
+
+        while let Some(comment) = self.comment_before(end) {
+            let line = comment.line;
+            let text = text(format!("//{}", comment.text));
+            separate(&mut parts, previous_line, line, false);
+            parts.push(text);
+            previous_line = Some(line);
+            self.next_comment += 1;
+        }
+
+        concat(parts)
+    }
+
+    /// A `{ ... }` body holding nodes laid out with `lines`.
+    fn braced<T>(
+        &mut self,
+        nodes: &[T],
+        end: usize,
+        spacing: Spacing<T>,
+        loc: impl Fn(&T) -> &SourceCodeLocation,
+        format: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> Doc {
+        if nodes.is_empty() && self.comment_before(end).is_none() {
+            return text("{}");
+        }
+        let body = self.lines(nodes, end, spacing, loc, format);
+        concat(vec![
+            text("{"),
+            nest(concat(vec![Doc::HardLine, body])),
+            Doc::HardLine,
+            text("}"),
+        ])
+    }
+
+    /// A list like `delimited`, ending at `end`, that keeps the comments between its items: one on
+    /// the line an item ends stays after its comma, the others get lines of their own. Lists with
+    /// comments are always broken.
+    fn list<T>(
+        &mut self,
+        (open, close): (&str, &str),
+        items: &[T],
+        end: usize,
+        padding: bool,
+        span: impl Fn(&T) -> (usize, usize),
+        mut format: impl FnMut(&mut Self, &T) -> Doc,
+    ) -> Doc {
+        let mut commented = false;
+        let mut docs = Vec::new();
+        let mut lines = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let (start, last) = span(item);
+            for comment in self.take_comments(start) {
+                lines.push(text(format!("//{comment}")));
+                commented = true;
+            }
+            let doc = format(self, item);
+            docs.push(doc.clone());
+            let mut line = vec![doc];
+            if !ends_with_rest(&docs) {
+                line.push(text(","));
+            }
+            let next = items.get(i + 1).map_or(end, |next| span(next).0);
+            let last_line = self.line_at(last);
+            if let Some(comment) = self.comment_before(next).filter(|c| c.line == last_line) {
+                line.push(text(format!(" //{}", comment.text)));
+                self.next_comment += 1;
+                commented = true;
+            }
+            lines.push(concat(line));
+        }
+        for comment in self.take_comments(end) {
+            lines.push(text(format!("//{comment}")));
+            commented = true;
+        }
+        if !commented {
+            return delimited(open, docs, close, padding);
+        }
+        let lines = lines.into_iter().flat_map(|line| [Doc::HardLine, line]);
+        concat(vec![
+            text(open),
+            nest(concat(lines.collect())),
+            Doc::HardLine,
+            text(close),
+        ])
+    }
+
+    // ---- Items ----
+
+    fn module(&mut self, module: &Module) -> Doc {
+        // Imports stay together, every other item gets a blank line around it.
+        let spacing: Spacing<Item> = |a, b| {
+            !matches!(
+                (&a.kind, &b.kind),
+                (ItemKind::Import(_), ItemKind::Import(_))
+            )
+        };
+        self.lines(
+            &module.items,
+            usize::MAX,
+            spacing,
+            |i| &i.loc,
+            |f, i| f.item(i),
+        )
+    }
+
+    fn item(&mut self, item: &Item) -> Doc {
+        let end = Self::end_offset(&item.loc);
+        match &item.kind {
+            ItemKind::Import(import) => {
+                let mut path = import
+                    .path
+                    .iter()
+                    .map(|i| i.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join("::");
+                if let Some(group) = &import.group {
+                    let names = group
+                        .iter()
+                        .map(|name| match &name.alias {
+                            Some(alias) => format!("{} as {}", name.name.name, alias.name),
+                            None => name.name.name.clone(),
+                        })
+                        .collect::<Vec<_>>();
+                    path += &format!("::({})", names.join(", "));
+                }
+                if let Some(alias) = &import.alias {
+                    path += &format!(" as {}", alias.name);
+                }
+                text(format!("import {path};"))
+            }
+            ItemKind::Export(names) => {
+                let names = names.iter().map(|i| text(&i.name)).collect();
+                group(concat(vec![
+                    text("export"),
+                    nest(concat(vec![
+                        Doc::Line,
+                        join(names, concat(vec![text(","), Doc::Line])),
+                    ])),
+                    text(";"),
+                ]))
+            }
+            ItemKind::Struct(decl) => concat(vec![
+                text(format!("struct {}", decl.name.name)),
+                generic_params(&decl.generics),
+                text(" "),
+                self.braced(
+                    &decl.fields,
+                    end,
+                    preserve,
+                    |f| &f.loc,
+                    |_, field| {
+                        text(format!(
+                            "{}{}: {},",
+                            if field.mutable { "$" } else { "" },
+                            field.name.name,
+                            print_type(&field.ty)
+                        ))
+                    },
+                ),
+            ]),
+            ItemKind::Enum(decl) => concat(vec![
+                text(format!("enum {}", decl.name.name)),
+                generic_params(&decl.generics),
+                text(" "),
+                self.braced(
+                    &decl.variants,
+                    end,
+                    preserve,
+                    |v| &v.loc,
+                    |f, variant| {
+                        let fields = variant.fields.iter().map(|t| text(print_type(t))).collect();
+                        concat(vec![
+                            text(&variant.name.name),
+                            if variant.fields.is_empty() {
+                                Doc::Nil
+                            } else {
+                                delimited("(", fields, ")", false)
+                            },
+                            match &variant.discriminant {
+                                Some(discriminant) => {
+                                    concat(vec![text(" = "), f.expr(discriminant)])
+                                }
+                                None => Doc::Nil,
+                            },
+                            text(","),
+                        ])
+                    },
+                ),
+            ]),
+            ItemKind::Trait(decl) => concat(vec![
+                text(format!("trait {}", decl.name.name)),
+                generic_params(&decl.generics),
+                text(" "),
+                self.members(&decl.assoc_types, &decl.methods, end),
+            ]),
+            ItemKind::Impl(decl) => {
+                let trait_ref = match &decl.trait_ref {
+                    Some(path) => path_str(path),
+                    None => "methods".to_string(),
+                };
+                concat(vec![
+                    text(format!(
+                        "impl {trait_ref} for {}",
+                        decl.target.segments[0].ident.name
+                    )),
+                    generic_params(&decl.generics),
+                    text(" "),
+                    self.members(&decl.assoc_types, &decl.methods, end),
+                ])
+            }
+            ItemKind::Fn(decl) => self.fn_decl(decl),
+            ItemKind::Const(decl) => concat(vec![
+                text(format!(
+                    "const {}: {} = ",
+                    decl.name.name,
+                    print_type(&decl.ty)
+                )),
+                self.expr(&decl.value),
+                text(";"),
+            ]),
+        }
+    }
+
+    /// A trait or impl body, where associated types stay together and methods get blank lines around
+    /// them.
+    fn members(&mut self, assoc_types: &[AssocType], methods: &[FnDecl], end: usize) -> Doc {
+        let spacing: Spacing<Member> = |a, b| !matches!((a, b), (Member::Type(_), Member::Type(_)));
+        self.braced(
+            &members(assoc_types, methods),
+            end,
+            spacing,
+            Member::loc,
+            |f, member| match member {
+                Member::Type(assoc) => text(match &assoc.ty {
+                    Some(ty) => format!("type {} = {};", assoc.name.name, print_type(ty)),
+                    None => format!("type {};", assoc.name.name),
+                }),
+                Member::Method(method) => f.fn_decl(method),
+            },
+        )
+    }
+
+    fn fn_decl(&mut self, decl: &FnDecl) -> Doc {
+        let head = text(format!(
+            "{}def {}",
+            if decl.tailcall.is_some() {
+                "@tailcall "
+            } else if decl.native.is_some() {
+                "@native "
+            } else {
+                ""
+            },
+            decl.name.name
+        ));
+        // `None` stands for `self`.
+        let mut params = decl.self_param.iter().map(|_| None).collect::<Vec<_>>();
+        params.extend(decl.params.iter().map(Some));
+        let params = self.list(
+            ("(", ")"),
+            &params,
+            decl.ret.loc.offset,
+            false,
+            |param| match param {
+                Some(param) => (param.loc.offset, Self::end_offset(&param.loc)),
+                None => {
+                    let loc = decl.self_param.as_ref().expect("only methods take `self`");
+                    (loc.offset, Self::end_offset(loc))
+                }
+            },
+            |f, param| match param {
+                Some(param) => concat(vec![
+                    text(if param.variadic { "*" } else { "" }),
+                    f.pattern(&param.pattern),
+                    text(format!(": {}", print_type(&param.ty))),
+                ]),
+                None => text("self"),
+            },
+        );
+        concat(vec![
+            group(concat(vec![
+                head,
+                generic_params(&decl.generics),
+                params,
+                text(format!(" {}", print_type(&decl.ret))),
+            ])),
+            match &decl.body {
+                Some(body) => concat(vec![text(" "), self.block(body)]),
+                None => text(";"),
+            },
+        ])
+    }
+
+    // ---- Statements ----
+
+    fn block(&mut self, block: &Block) -> Doc {
+        let old = std::mem::replace(&mut self.no_struct, false);
+        let doc = self.braced(
+            &block.stmts,
+            Self::end_offset(&block.loc),
+            preserve,
+            |s| &s.loc,
+            |f, s| f.stmt(s),
+        );
+        self.no_struct = old;
+        doc
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Doc {
+        match &stmt.kind {
+            StmtKind::Let { pattern, ty, value } => {
+                let mut parts = vec![text("let "), self.pattern(pattern)];
+                if let Some(ty) = ty {
+                    parts.push(text(format!(": {}", print_type(ty))));
+                }
+                if let Some(value) = value {
+                    parts.push(text(" = "));
+                    parts.push(self.expr(value));
+                }
+                parts.push(text(";"));
+                concat(parts)
+            }
+            StmtKind::If { .. } => self.if_stmt(stmt),
+            StmtKind::While { cond, body } => concat(vec![
+                text("while "),
+                self.cond_expr(cond),
+                text(" "),
+                self.block(body),
+            ]),
+            StmtKind::Match { scrutinee, arms } => {
+                let scrutinee = self.cond_expr(scrutinee);
+                let end = Self::end_offset(&stmt.loc);
+                let arms = self.braced(
+                    arms,
+                    end,
+                    preserve,
+                    |a| &a.loc,
+                    |f, arm| {
+                        let mut parts = vec![f.pattern(&arm.pattern)];
+                        if let Some(guard) = &arm.guard {
+                            parts.push(text(" if "));
+                            parts.push(f.expr(guard));
+                        }
+                        parts.push(text(" => "));
+                        match &arm.body.kind {
+                            StmtKind::Block(block) => parts.push(f.block(block)),
+                            _ => {
+                                parts.push(f.simple_stmt(&arm.body));
+                                parts.push(text(","));
+                            }
+                        }
+                        concat(parts)
+                    },
+                );
+                concat(vec![text("match "), scrutinee, text(" "), arms])
+            }
+            StmtKind::Block(block) => self.block(block),
+            _ => concat(vec![self.simple_stmt(stmt), text(";")]),
+        }
+    }
+
+    /// Format a statement that needs a terminator, without the terminator.
+    fn simple_stmt(&mut self, stmt: &Stmt) -> Doc {
+        match &stmt.kind {
+            StmtKind::Assign { target, op, value } => concat(vec![
+                self.expr(target),
+                text(format!(" {}= ", op.map_or("", |op| op.symbol()))),
+                self.expr(value),
+            ]),
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Return(Some(value)) => concat(vec![text("return "), self.expr(value)]),
+            StmtKind::Return(None) => text("return"),
+            StmtKind::Break => text("break"),
+            StmtKind::Continue => text("continue"),
+            // Compound statements can't appear without a block around them.
+            _ => concat(vec![
+                text("{"),
+                nest(concat(vec![Doc::HardLine, self.stmt(stmt)])),
+                Doc::HardLine,
+                text("}"),
+            ]),
+        }
+    }
+
+    fn if_stmt(&mut self, stmt: &Stmt) -> Doc {
+        let StmtKind::If {
+            cond,
+            then_block,
+            else_block,
+        } = &stmt.kind
+        else {
+            return Doc::Nil;
+        };
+        let mut parts = vec![
+            text("if "),
+            self.cond_expr(cond),
+            text(" "),
+            self.block(then_block),
+        ];
+        if let Some(else_block) = else_block {
+            parts.push(text(" else "));
+            match else_block.stmts.as_slice() {
+                [nested @ Stmt {
+                    kind: StmtKind::If { .. },
+                    ..
+                }] => parts.push(self.if_stmt(nested)),
+                _ => parts.push(self.block(else_block)),
+            }
+        }
+        concat(parts)
+    }
+
+    // ---- Patterns ----
+
+    /// Format a pattern, keeping the comments between its fields and alternatives like those of
+    /// expressions.
+    fn pattern(&mut self, pattern: &Pattern) -> Doc {
+        let end = Self::end_offset(&pattern.loc);
+        if self.comment_before(end).is_none() {
+            return text(print_pattern(pattern));
+        }
+        match &pattern.kind {
+            PatternKind::Binding {
+                name,
+                sub: Some(sub),
+                mutable,
+            } => concat(vec![
+                text(format!(
+                    "{}{} @ ",
+                    if *mutable { "mut " } else { "" },
+                    name.name
+                )),
+                self.nested_pattern(sub),
+            ]),
+            // `(a,)` needs its comma even when it isn't broken.
+            PatternKind::Tuple(patterns) if patterns.len() != 1 => self.pattern_list(patterns, end),
+            PatternKind::Variant {
+                path,
+                fields: Some(fields),
+            } => concat(vec![text(path_str(path)), self.pattern_list(fields, end)]),
+            PatternKind::Struct { path, fields, rest } => {
+                // `None` stands for the `..` after the fields.
+                let mut items = fields.iter().map(Some).collect::<Vec<_>>();
+                if *rest {
+                    items.push(None);
+                }
+                let fields = self.list(
+                    ("{", "}"),
+                    &items,
+                    end,
+                    true,
+                    |field| match field {
+                        Some(field) => {
+                            (field.name.loc.offset, Self::end_offset(&field.pattern.loc))
+                        }
+                        None => (end, end),
+                    },
+                    |f, field| match field {
+                        Some(field) => match &field.pattern.kind {
+                            PatternKind::Binding {
+                                name, sub: None, ..
+                            } if name.name == field.name.name => f.pattern(&field.pattern),
+                            _ => concat(vec![
+                                text(format!("{} => ", field.name.name)),
+                                f.pattern(&field.pattern),
+                            ]),
+                        },
+                        None => text(".."),
+                    },
+                );
+                concat(vec![text(format!("{} ", path_str(path))), fields])
+            }
+            PatternKind::Or(alternatives) => {
+                let mut parts = Vec::new();
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    let comments = self.take_comments(alternative.loc.offset);
+                    let doc = self.nested_pattern(alternative);
+                    match comments.split_first() {
+                        // The first comment stays after the `|`, the others go before the
+                        // alternative.
+                        Some((first, rest)) if i > 0 => {
+                            let mut lines = Vec::new();
+                            for comment in rest {
+                                lines.push(Doc::HardLine);
+                                lines.push(text(format!("//{comment}")));
+                            }
+                            lines.push(Doc::HardLine);
+                            lines.push(doc);
+                            parts.push(text(format!(" | //{first}")));
+                            parts.push(nest(concat(lines)));
+                        }
+                        _ if i > 0 => {
+                            parts.push(text(" | "));
+                            parts.push(doc);
+                        }
+                        _ => parts.push(doc),
+                    }
+                }
+                concat(parts)
+            }
+            _ => text(print_pattern(pattern)),
+        }
+    }
+
+    /// A pattern nested in another one, parenthesized if it's an or-pattern.
+    fn nested_pattern(&mut self, pattern: &Pattern) -> Doc {
+        match pattern.kind {
+            PatternKind::Or(_) => concat(vec![text("("), self.pattern(pattern), text(")")]),
+            _ => self.pattern(pattern),
+        }
+    }
+
+    /// The parenthesized patterns of a tuple or variant pattern ending at `end`.
+    fn pattern_list(&mut self, patterns: &[Pattern], end: usize) -> Doc {
+        self.list(
+            ("(", ")"),
+            patterns,
+            end,
+            false,
+            |p| (p.loc.offset, Self::end_offset(&p.loc)),
+            |f, p| f.pattern(p),
+        )
+    }
+
+    // ---- Expressions ----
+
+    fn cond_expr(&mut self, expr: &Expr) -> Doc {
+        let old = std::mem::replace(&mut self.no_struct, true);
+        let doc = self.expr(expr);
+        self.no_struct = old;
+        doc
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Doc {
+        self.expr_prec(expr, 0)
+    }
+
+    /// Format an expression, parenthesizing it if it binds looser than `min_prec`, like the printer does.
+    fn expr_prec(&mut self, expr: &Expr, min_prec: u8) -> Doc {
+        let prec = match &expr.kind {
+            ExprKind::Binary { op, .. } => op.precedence(),
+            ExprKind::Unary { .. } => 8,
+            ExprKind::Literal(Literal::Int(n)) if *n < 0 => 8,
+            ExprKind::Literal(Literal::Float(n)) if n.is_sign_negative() => 8,
+            _ => 9,
+        };
+        let needs_struct_parens = self.no_struct
+            && match &expr.kind {
+                ExprKind::StructLit { .. } => true,
+                ExprKind::Call { args, .. } | ExprKind::MethodCall { args, .. } => {
+                    matches!(
+                        args.last(),
+                        Some(Expr {
+                            kind: ExprKind::Closure(_),
+                            ..
+                        })
+                    )
+                }
+                _ => false,
+            };
+        if prec < min_prec || needs_struct_parens {
+            let old = std::mem::replace(&mut self.no_struct, false);
+            let doc = self.expr_kind(expr);
+            self.no_struct = old;
+            concat(vec![text("("), doc, text(")")])
+        } else {
+            self.expr_kind(expr)
+        }
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) -> Doc {
+        match &expr.kind {
+            ExprKind::Literal(literal) => text(literal_str(literal)),
+            ExprKind::Path(path) => text(path_str(path)),
+            ExprKind::SelfValue => text("self"),
+            ExprKind::Tuple(exprs) if exprs.len() == 1 => {
+                let old = std::mem::replace(&mut self.no_struct, false);
+                let doc = concat(vec![text("("), self.expr(&exprs[0]), text(",)")]);
+                self.no_struct = old;
+                doc
+            }
+            ExprKind::Tuple(exprs) => {
+                self.expr_list(("(", ")"), exprs, Self::end_offset(&expr.loc))
+            }
+            ExprKind::Array(exprs) => {
+                self.expr_list(("[", "]"), exprs, Self::end_offset(&expr.loc))
+            }
+            ExprKind::Unary { op, expr } => concat(vec![
+                text(match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                }),
+                self.expr_prec(expr, 8),
+            ]),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let prec = op.precedence();
+                let lhs = self.expr_prec(lhs, prec);
+                let comments = self.take_comments(rhs.loc.offset);
+                let rhs = self.expr_prec(rhs, prec + 1);
+                if let Some((first, rest)) = comments.split_first() {
+                    // The first comment stays after the operator, the others go before `rhs`.
+                    let mut lines = Vec::new();
+                    for comment in rest {
+                        lines.push(Doc::HardLine);
+                        lines.push(text(format!("//{comment}")));
+                    }
+                    lines.push(Doc::HardLine);
+                    lines.push(rhs);
+                    return concat(vec![
+                        lhs,
+                        text(format!(" {} //{first}", op.symbol())),
+                        nest(concat(lines)),
+                    ]);
+                }
+                group(concat(vec![
+                    lhs,
+                    text(format!(" {}", op.symbol())),
+                    nest(concat(vec![Doc::Line, rhs])),
+                ]))
+            }
+            ExprKind::Call { callee, args } => {
+                let callee = self.expr_prec(callee, 9);
+                concat(vec![callee, self.args(args, expr)])
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                let receiver = self.expr_prec(receiver, 9);
+                concat(vec![
+                    receiver,
+                    text(format!("->{}", method.name)),
+                    self.args(args, expr),
+                ])
+            }
+            ExprKind::Field { base, field } => {
+                let base = self.expr_prec(base, 9);
+                concat(vec![base, text(format!("->{}", field.name))])
+            }
+            ExprKind::StructLit { path, fields } => {
+                let old = std::mem::replace(&mut self.no_struct, false);
+                let fields = self.list(
+                    ("{", "}"),
+                    fields,
+                    Self::end_offset(&expr.loc),
+                    true,
+                    |field| (field.name.loc.offset, Self::end_offset(&field.value.loc)),
+                    |f, field| {
+                        concat(vec![
+                            text(format!("{} => ", field.name.name)),
+                            f.expr(&field.value),
+                        ])
+                    },
+                );
+                self.no_struct = old;
+                concat(vec![text(format!("{} ", path_str(path))), fields])
+            }
+            ExprKind::Closure(closure) => self.closure(closure, expr),
+        }
+    }
+
+    /// Format the expressions of a list ending at `end`.
+    fn expr_list(&mut self, delimiters: (&str, &str), exprs: &[Expr], end: usize) -> Doc {
+        let old = std::mem::replace(&mut self.no_struct, false);
+        let doc = self.list(
+            delimiters,
+            exprs,
+            end,
+            false,
+            |e| (e.loc.offset, Self::end_offset(&e.loc)),
+            |f, e| f.expr(e),
+        );
+        self.no_struct = old;
+        doc
+    }
+
+    /// Format the arguments of a call, moving a closure in the last position after the parentheses.
+    fn args(&mut self, args: &[Expr], call: &Expr) -> Doc {
+        match args.split_last() {
+            Some((
+                closure @ Expr {
+                    kind: ExprKind::Closure(_),
+                    ..
+                },
+                rest,
+            )) => {
+                let args = self.expr_list(("(", ")"), rest, closure.loc.offset);
+                concat(vec![args, text(" "), self.expr(closure)])
+            }
+            _ => self.expr_list(("(", ")"), args, Self::end_offset(&call.loc)),
+        }
+    }
+
+    fn closure(&mut self, closure: &Closure, expr: &Expr) -> Doc {
+        let params = closure
+            .params
+            .iter()
+            .map(|param| match &param.ty {
+                Some(ty) => format!("{}: {}", print_pattern(&param.pattern), print_type(ty)),
+                None => print_pattern(&param.pattern),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let old = std::mem::replace(&mut self.no_struct, false);
+        let doc = match &closure.body {
+            ClosureBody::Expr(body) => group(concat(vec![
+                text(format!("{{ ({params}) =")),
+                nest(concat(vec![Doc::Line, self.expr(body)])),
+                Doc::Line,
+                text("}"),
+            ])),
+            ClosureBody::Block(block) => {
+                let end = Self::end_offset(&expr.loc);
+                let body = self.lines(&block.stmts, end, preserve, |s| &s.loc, |f, s| f.stmt(s));
+                concat(vec![
+                    text(format!("{{ ({params}) =>")),
+                    nest(concat(vec![Doc::HardLine, body])),
+                    Doc::HardLine,
+                    text("}"),
+                ])
+            }
+        };
+        self.no_struct = old;
+        doc
+    }
+}
+
+fn path_str(path: &Path) -> String {
+    path.segments
+        .iter()
+        .map(|segment| {
+            if segment.generics.is_empty() {
+                segment.ident.name.clone()
+            } else {
+                let generics = segment.generics.iter().map(print_type).collect::<Vec<_>>();
+                format!("{}<{}>", segment.ident.name, generics.join(", "))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+fn generic_params(generics: &[GenericParam]) -> Doc {
+    if generics.is_empty() {
+        return Doc::Nil;
+    }
+    let params = generics
+        .iter()
+        .map(|param| {
+            let bounds = param.bounds.iter().map(path_str).collect::<Vec<_>>();
+            if bounds.is_empty() {
+                text(&param.name.name)
+            } else {
+                text(format!("{}: {}", param.name.name, bounds.join(" + ")))
+            }
+        })
+        .collect();
+    delimited("<", params, ">", false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_source;
+    use crate::parse::printer::tests::without_locations;
+
+    fn format(source: &str, line_width: usize) -> String {
+        format_source(None, source, &FormatOptions { line_width }).unwrap()
+    }
+
+    #[test]
+    fn fixtures_are_stable() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let src = std::fs::read_to_string(&path).unwrap();
+            let module = parse_source(None, &src).unwrap();
+
+            for width in [20, 60, 100] {
+                let formatted = format(&src, width);
+                let reparsed = parse_source(None, &formatted)
+                    .unwrap_or_else(|e| panic!("{}: {:?}\n{formatted}", path.display(), e));
+                assert_eq!(
+                    without_locations(&module),
+                    without_locations(&reparsed),
+                    "{} changes meaning at width {width}:\n{formatted}",
+                    path.display()
+                );
+                assert_eq!(formatted, format(&formatted, width), "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/comments.paca");
+        let src = std::fs::read_to_string(path).unwrap();
+        assert_eq!(format(&src, 100), src);
+
+        let messy = "// Lead.\nimport a;\nimport b;\ndef f() void { let x = 1; // one\n\n\n\n    x = 2;\n// end\n}";
+        assert_eq!(
+            format(messy, 100),
+            "// Lead.\nimport a;\nimport b;\n\ndef f() void {\n    let x = 1; // one\n\n    x = 2;\n    // end\n}\n"
+        );
+
+        let inside = "def f() void {\n    call(a, // first\n        b);\n    let x = a + // why\n    b;\n    \
+                      let p = Point { a => 1, // field a\n        b => 2 };\n    let xs = [1, // one\n    ];\n}";
+        assert_eq!(
+            format(inside, 100),
+            "def f() void {\n    call(\n        a, // first\n        b,\n    );\n    \
+             let x = a + // why\n        b;\n    \
+             let p = Point {\n        a => 1, // field a\n        b => 2,\n    };\n    \
+             let xs = [\n        1, // one\n    ];\n}\n"
+        );
+        assert_eq!(format(&format(inside, 100), 100), format(inside, 100));
+
+        let params = "def add(a: int, // the first\n    b: int // the second\n) int {\n    \
+                      return a + b;\n}\n";
+        assert_eq!(
+            format(params, 100),
+            "def add(\n    a: int, // the first\n    b: int, // the second\n) int {\n    \
+             return a + b;\n}\n"
+        );
+
+        let patterns = "def f(a: int) void {\n    match a {\n        1 | // one\n        \
+                        2 => println(a),\n        _ => {}\n    }\n    \
+                        let (x, // ex\n        y) = (1, 2);\n}\n";
+        assert_eq!(
+            format(patterns, 100),
+            "def f(a: int) void {\n    match a {\n        1 | // one\n            \
+             2 => println(a),\n        _ => {}\n    }\n    \
+             let (\n        x, // ex\n        y,\n    ) = (1, 2);\n}\n"
+        );
+        assert_eq!(format(&format(patterns, 100), 100), format(patterns, 100));
+    }
+
+    #[test]
+    fn breaks_long_lines() {
+        let src = "def f() void { call(first_argument, second_argument, third_argument); }";
+        assert_eq!(
+            format(src, 40),
+            "def f() void {\n    call(\n        first_argument,\n        second_argument,\n        third_argument,\n    );\n}\n"
+        );
+        assert_eq!(
+            format(src, 100),
+            "def f() void {\n    call(first_argument, second_argument, third_argument);\n}\n"
+        );
+    }
+}
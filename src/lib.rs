@@ -0,0 +1,5 @@
+pub mod fmt;
+pub mod parse;
+pub mod pir;
+pub mod sema;
+pub mod util;
@@ -0,0 +1,490 @@
+use crate::parse::SourceCodeLocation;
+use serde::Serialize;
+
+/// A whole source file.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Module {
+    pub items: Vec<Item>,
+}
+
+/// An identifier together with its location.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Ident {
+    pub name: String,
+    pub loc: SourceCodeLocation,
+}
+
+/// A `::`-separated path, e.g. `Array::init<str>` or `Either::Left`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Path {
+    pub segments: Vec<PathSegment>,
+    pub loc: SourceCodeLocation,
+}
+
+/// A single segment of a `Path` with its optional generic arguments.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PathSegment {
+    pub ident: Ident,
+    pub generics: Vec<Type>,
+}
+
+/// A top-level declaration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Item {
+    pub kind: ItemKind,
+    pub loc: SourceCodeLocation,
+}
+
+/// Types of top-level declarations.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum ItemKind {
+    /// `import std::collections::Array;` or `import std::collections::tuple::(first, second as snd);`
+    Import(Import),
+    /// `export Either, Option;`
+    Export(Vec<Ident>),
+    /// `struct Entry<K, V> { ... }`
+    Struct(StructDecl),
+    /// `enum Option<T> { ... }`
+    Enum(EnumDecl),
+    /// `trait Hashable { ... }`
+    Trait(TraitDecl),
+    /// `impl methods for T { ... }` or `impl Trait for T { ... }`
+    Impl(ImplDecl),
+    /// `def main() void { ... }`
+    Fn(Box<FnDecl>),
+    /// `const MAX: int = 1 << 10;`
+    Const(ConstDecl),
+}
+
+/// An import declaration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Import {
+    /// The path leading up to the imported name(s).
+    pub path: Vec<Ident>,
+    /// The names in a grouped import, `None` if the last segment of `path` is the imported name.
+    pub group: Option<Vec<ImportName>>,
+    /// The name the last segment of `path` is imported as, e.g. `io` in `import std::io as io;`.
+    pub alias: Option<Ident>,
+}
+
+/// A name in a grouped import, optionally renamed with `as`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImportName {
+    pub name: Ident,
+    pub alias: Option<Ident>,
+}
+
+impl ImportName {
+    /// The name the import is known by in the importing module.
+    pub fn local(&self) -> &Ident {
+        self.alias.as_ref().unwrap_or(&self.name)
+    }
+}
+
+/// A generic parameter with its trait bounds, e.g. `K: Hashable`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GenericParam {
+    pub name: Ident,
+    pub bounds: Vec<Path>,
+}
+
+/// A struct declaration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StructDecl {
+    pub name: Ident,
+    pub generics: Vec<GenericParam>,
+    pub fields: Vec<FieldDecl>,
+}
+
+/// A field of a struct.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldDecl {
+    pub name: Ident,
+    pub ty: Type,
+    /// Fields marked with `$` may be reassigned through `->`.
+    pub mutable: bool,
+    pub loc: SourceCodeLocation,
+}
+
+/// An enum declaration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EnumDecl {
+    pub name: Ident,
+    pub generics: Vec<GenericParam>,
+    pub variants: Vec<VariantDecl>,
+}
+
+/// A variant of an enum, either `None` or `Some(T)`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VariantDecl {
+    pub name: Ident,
+    pub fields: Vec<Type>,
+    /// The value of the variant set with `Read = 1 << 2`, only for variants without fields.
+    pub discriminant: Option<Expr>,
+    pub loc: SourceCodeLocation,
+}
+
+/// A constant, whose value is computed at compile time.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConstDecl {
+    pub name: Ident,
+    pub ty: Type,
+    pub value: Expr,
+}
+
+/// A trait declaration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TraitDecl {
+    pub name: Ident,
+    pub generics: Vec<GenericParam>,
+    pub assoc_types: Vec<AssocType>,
+    /// The methods, with a body for those that have a default.
+    pub methods: Vec<FnDecl>,
+}
+
+/// An associated type, declared by a trait as `type Output;` and defined by an impl as
+/// `type Output = int;`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AssocType {
+    pub name: Ident,
+    /// The definition, `None` in a trait.
+    pub ty: Option<Type>,
+    pub loc: SourceCodeLocation,
+}
+
+/// An `impl` block.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ImplDecl {
+    pub generics: Vec<GenericParam>,
+    /// The implemented trait, `None` for `impl methods for`.
+    pub trait_ref: Option<Path>,
+    pub target: Path,
+    pub assoc_types: Vec<AssocType>,
+    pub methods: Vec<FnDecl>,
+}
+
+/// A function or method declaration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FnDecl {
+    /// The location of `@tailcall` if the function has it, which requires its recursive calls to
+    /// be tail calls.
+    pub tailcall: Option<SourceCodeLocation>,
+    /// The location of `@native` if the function has it, which makes it a declaration of a function
+    /// the runtime implements. Only the std modules declare them.
+    pub native: Option<SourceCodeLocation>,
+    pub name: Ident,
+    pub generics: Vec<GenericParam>,
+    /// The location of `self` if this is a method taking `self`.
+    pub self_param: Option<SourceCodeLocation>,
+    pub params: Vec<Param>,
+    pub ret: Type,
+    /// `None` for a trait method without a default, e.g. `def hash(self) int;`.
+    pub body: Option<Block>,
+    pub loc: SourceCodeLocation,
+}
+
+/// A function parameter, e.g. `(key, val): (K, V)` or `*entries: []Entry`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Param {
+    pub pattern: Pattern,
+    pub ty: Type,
+    /// Whether this parameter collects the rest of the arguments (`*name`).
+    pub variadic: bool,
+    pub loc: SourceCodeLocation,
+}
+
+/// A type annotation.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Type {
+    pub kind: TypeKind,
+    pub loc: SourceCodeLocation,
+}
+
+/// Types of type annotations.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum TypeKind {
+    /// A named type such as `int`, `Self` or `Option<T>`.
+    Path(Path),
+    /// `[]T`
+    Array(Box<Type>),
+    /// `(A, B)`
+    Tuple(Vec<Type>),
+    /// `def(A, B) R`, the type of functions and closures.
+    Fn { params: Vec<Type>, ret: Box<Type> },
+}
+
+/// A `{ ... }` block of statements.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub loc: SourceCodeLocation,
+}
+
+/// A statement.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub loc: SourceCodeLocation,
+}
+
+/// Types of statements.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum StmtKind {
+    /// `let pattern: T = value;`
+    Let {
+        pattern: Pattern,
+        ty: Option<Type>,
+        value: Option<Expr>,
+    },
+    /// `target = value;` or a compound assignment such as `target += value;`
+    Assign {
+        target: Expr,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
+    /// An expression evaluated for its side effects.
+    Expr(Expr),
+    /// `return value;`
+    Return(Option<Expr>),
+    /// `break;`
+    Break,
+    /// `continue;`
+    Continue,
+    /// `if cond { ... } else { ... }`, where `else if` is an `else` block holding a single `if`.
+    If {
+        cond: Expr,
+        then_block: Block,
+        else_block: Option<Block>,
+    },
+    /// `while cond { ... }`
+    While { cond: Expr, body: Block },
+    /// `match scrutinee { arms }`
+    Match {
+        scrutinee: Expr,
+        arms: Vec<MatchArm>,
+    },
+    /// A nested block.
+    Block(Block),
+}
+
+/// An arm of a `match` statement.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    /// The `if` guard, if any.
+    pub guard: Option<Expr>,
+    /// Either a block or a single statement without its trailing `;`.
+    pub body: Box<Stmt>,
+    pub loc: SourceCodeLocation,
+}
+
+/// An expression.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub loc: SourceCodeLocation,
+}
+
+/// Types of expressions.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum ExprKind {
+    Literal(Literal),
+    /// A variable, function or enum variant.
+    Path(Path),
+    /// `self`
+    SelfValue,
+    /// `(a, b)`
+    Tuple(Vec<Expr>),
+    /// `[a, b]`
+    Array(Vec<Expr>),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// `callee(args)`; a trailing closure is passed as the last argument.
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    /// `receiver->method(args)`
+    MethodCall {
+        receiver: Box<Expr>,
+        method: Ident,
+        args: Vec<Expr>,
+    },
+    /// `base->field`
+    Field {
+        base: Box<Expr>,
+        field: Ident,
+    },
+    /// `Entry { key => key, val => val }`
+    StructLit {
+        path: Path,
+        fields: Vec<FieldInit>,
+    },
+    /// A trailing closure such as `{ (entry) = entry->key }`.
+    Closure(Closure),
+}
+
+/// A field initializer in a struct literal, `name => value`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldInit {
+    pub name: Ident,
+    pub value: Expr,
+}
+
+/// A closure passed after a call.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Closure {
+    pub params: Vec<ClosureParam>,
+    pub body: ClosureBody,
+}
+
+/// A closure parameter with an optional type annotation.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ClosureParam {
+    pub pattern: Pattern,
+    pub ty: Option<Type>,
+}
+
+/// The body of a closure.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum ClosureBody {
+    /// `(params) = expr`
+    Expr(Box<Expr>),
+    /// `(params) => stmts`
+    Block(Block),
+}
+
+/// Literal values shared by expressions and patterns.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Char(char),
+    Bool(bool),
+}
+
+/// Unary operators.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum UnaryOp {
+    /// -
+    Neg,
+    /// !
+    Not,
+}
+
+/// Binary operators.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    Eq,
+    NotEq,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// How tightly the operator binds, higher values bind tighter.
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::NotEq => 3,
+            BinaryOp::Less | BinaryOp::LessEq | BinaryOp::Greater | BinaryOp::GreaterEq => 4,
+            BinaryOp::Shl | BinaryOp::Shr => 5,
+            BinaryOp::Add | BinaryOp::Sub => 6,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 7,
+        }
+    }
+
+    /// The operator as written in the source code.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Eq => "==",
+            BinaryOp::NotEq => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEq => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEq => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
+/// A pattern, used in `match` arms, `let` statements and parameters.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub loc: SourceCodeLocation,
+}
+
+/// Types of patterns.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum PatternKind {
+    /// `_`
+    Wildcard,
+    /// `..`, only allowed among the fields of tuple, variant and struct patterns.
+    Rest,
+    /// `name` or `name @ pattern`, optionally preceded by `mut`.
+    Binding {
+        name: Ident,
+        sub: Option<Box<Pattern>>,
+        /// Whether the binding is declared `mut`, so it may be assigned to.
+        mutable: bool,
+    },
+    /// `123`, `-1.5`, `"str"`, `'c'`, `true`
+    Literal(Literal),
+    /// `1..10` or `'a'..='z'`
+    Range {
+        start: Literal,
+        end: Literal,
+        inclusive: bool,
+    },
+    /// `(a, b)`
+    Tuple(Vec<Pattern>),
+    /// `Option::None` (no `fields`) or `Either::Left(content)`
+    Variant {
+        path: Path,
+        fields: Option<Vec<Pattern>>,
+    },
+    /// `Entry { key, val => v, .. }`
+    Struct {
+        path: Path,
+        fields: Vec<FieldPattern>,
+        rest: bool,
+    },
+    /// `A | B`
+    Or(Vec<Pattern>),
+}
+
+/// A field in a struct pattern; `key` is shorthand for `key => key`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldPattern {
+    pub name: Ident,
+    pub pattern: Pattern,
+}
@@ -0,0 +1,123 @@
+use crate::parse::ast::Module;
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// Serialize the AST into pretty-printed JSON, including every location.
+pub fn to_json(module: &Module) -> String {
+    serde_json::to_string_pretty(module).expect("the AST is always serializable")
+}
+
+/// Dump the AST as an indented tree, one node per line, with the location of each node.
+///
+/// The tree is rendered from the serialized form of the AST so that it never falls behind the node types.
+pub fn to_tree(module: &Module) -> String {
+    let value = serde_json::to_value(module).expect("the AST is always serializable");
+    let mut out = String::from("Module\n");
+    if let Value::Object(map) = &value {
+        for (key, child) in map {
+            write_node(&mut out, key, child, 1);
+        }
+    }
+    out
+}
+
+/// Write a node labeled by its field name, or `-` for elements of a list.
+fn write_node(out: &mut String, label: &str, value: &Value, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let label = if label == "-" {
+        label.to_string()
+    } else {
+        format!("{label}:")
+    };
+    match value {
+        Value::Null => {}
+        Value::Array(items) if items.is_empty() => {}
+        Value::Array(items) => {
+            let _ = writeln!(out, "{indent}{}", label.trim_end_matches(':'));
+            for item in items {
+                write_node(out, "-", item, depth + 1);
+            }
+        }
+        Value::Object(map) if is_loc(map) => {
+            let _ = writeln!(out, "{indent}{label} {}", loc_str(map));
+        }
+        Value::Object(map) => {
+            let (header, children) = describe(map);
+            let _ = writeln!(out, "{indent}{label}{header}");
+            for (key, child) in children {
+                write_node(out, key, child, depth + 1);
+            }
+        }
+        Value::Bool(false) => {}
+        scalar => {
+            let _ = writeln!(out, "{indent}{label} {scalar}");
+        }
+    }
+}
+
+/// Build the header line of a node and collect the children to print below it.
+fn describe(map: &Map<String, Value>) -> (String, Vec<(&str, &Value)>) {
+    let mut header = String::new();
+    let mut children = Vec::new();
+
+    // Identifiers are printed on a single line.
+    if let (Some(Value::String(name)), Some(Value::Object(loc)), 2) =
+        (map.get("name"), map.get("loc"), map.len())
+    {
+        return (format!(" {name:?} {}", loc_str(loc)), children);
+    }
+
+    // Externally tagged enums such as `{"Int": 1}` show their variant in the header.
+    if map.len() == 1 {
+        if let Some((key, inner)) = map.iter().next() {
+            if key.starts_with(char::is_uppercase) {
+                header.push_str(&format!(" {key}"));
+                push_variant_children(inner, &mut children);
+                return (header, children);
+            }
+        }
+    }
+
+    // So do the `kind` fields of nodes.
+    match map.get("kind") {
+        Some(Value::String(name)) => header.push_str(&format!(" {name}")),
+        Some(Value::Object(kind)) => {
+            if let Some((name, inner)) = kind.iter().next() {
+                header.push_str(&format!(" {name}"));
+                push_variant_children(inner, &mut children);
+            }
+        }
+        _ => {}
+    }
+    if let Some(Value::Object(loc)) = map.get("loc") {
+        header.push_str(&format!(" {}", loc_str(loc)));
+    }
+    for (key, child) in map {
+        if key != "kind" && key != "loc" {
+            children.push((key.as_str(), child));
+        }
+    }
+    (header, children)
+}
+
+/// Struct-like variants list their fields, other variants get a single `value` child.
+fn push_variant_children<'a>(inner: &'a Value, children: &mut Vec<(&'a str, &'a Value)>) {
+    match inner {
+        Value::Object(fields) if !is_loc(fields) && !fields.contains_key("loc") => {
+            children.extend(fields.iter().map(|(k, v)| (k.as_str(), v)))
+        }
+        other => children.push(("value", other)),
+    }
+}
+
+fn is_loc(map: &Map<String, Value>) -> bool {
+    map.contains_key("line") && map.contains_key("offset")
+}
+
+fn loc_str(map: &Map<String, Value>) -> String {
+    format!(
+        "@{}:{}",
+        map.get("line").unwrap_or(&Value::Null),
+        map.get("column").unwrap_or(&Value::Null)
+    )
+}
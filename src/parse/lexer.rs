@@ -1,6 +1,6 @@
 use crate::parse::{LexError, LexErrorType, SourceCodeLocation};
-use crate::util::{escape_char, weird_while};
-use log::{debug, info};
+use crate::util::escape_char;
+use log::debug;
 use std::iter::Peekable;
 use std::rc::Rc;
 use std::str::Chars;
@@ -62,6 +62,8 @@ pub enum TokenKind {
     RemEq,
     /// ,
     Comma,
+    /// |
+    Vertical,
     /// ||
     DoubleVertical,
     /// &&
@@ -84,6 +86,10 @@ pub enum TokenKind {
     DoubleEq,
     /// .
     Dot,
+    /// ..
+    DoubleDot,
+    /// ..=
+    DoubleDotEq,
     /// =>
     EqGreaterThan,
     /// ::
@@ -92,8 +98,14 @@ pub enum TokenKind {
     Colon,
     /// ;
     SemiColon,
+    /// @
+    At,
+    /// $
+    Dollar,
     /// Keywords are stored in this.
     Keyword(Keyword),
+    /// A `//` comment without the slashes, only produced when the lexer keeps comments.
+    Comment(String),
 }
 
 /// All keyword types
@@ -120,8 +132,13 @@ pub enum Keyword {
     Methods,
     Import,
     Export,
+    As,
     True,
     False,
+    Trait,
+    Type,
+    Mut,
+    Const,
 }
 
 impl TryInto<Keyword> for String {
@@ -150,8 +167,13 @@ impl TryInto<Keyword> for String {
             "methods" => Ok(Keyword::Methods),
             "import" => Ok(Keyword::Import),
             "export" => Ok(Keyword::Export),
+            "as" => Ok(Keyword::As),
             "true" => Ok(Keyword::True),
             "false" => Ok(Keyword::False),
+            "trait" => Ok(Keyword::Trait),
+            "type" => Ok(Keyword::Type),
+            "mut" => Ok(Keyword::Mut),
+            "const" => Ok(Keyword::Const),
             _ => Err(()),
         }
     }
@@ -166,14 +188,14 @@ pub struct Token {
 }
 
 impl Token {
-    fn new(kind: TokenKind, loc: SourceCodeLocation) -> Self {
+    pub fn new(kind: TokenKind, loc: SourceCodeLocation) -> Self {
         Self { kind, loc }
     }
 }
 
-impl Into<SourceCodeLocation> for Token {
-    fn into(self) -> SourceCodeLocation {
-        self.loc
+impl From<Token> for SourceCodeLocation {
+    fn from(token: Token) -> Self {
+        token.loc
     }
 }
 
@@ -187,12 +209,22 @@ pub struct Lexer<'src> {
     tokens: Vec<Token>,
     /// Current character
     c: char,
+    /// Whether to produce `Comment` tokens.
+    keep_comments: bool,
 
+    /// Line of the current character.
     line: usize,
-    column_c: usize,
+    /// Column of the current character.
     column: usize,
-    offset: usize,
-    length: usize,
+    /// Number of characters consumed so far.
+    consumed: usize,
+
+    /// Line at which the token being lexed starts.
+    start_line: usize,
+    /// Column at which the token being lexed starts.
+    start_column: usize,
+    /// Offset at which the token being lexed starts.
+    start_offset: usize,
 }
 
 impl<'src> Tokenize for Lexer<'src> {
@@ -203,26 +235,18 @@ impl<'src> Tokenize for Lexer<'src> {
 
         self.next();
         while !self.is_end() {
+            self.mark();
             match self.c {
                 // An identifier or keyword
                 n if n.is_alphabetic() || n == '_' => {
                     let mut ident = String::new();
                     ident.push(self.c);
-                    self.next();
 
-                    if self.is_end() || (!self.c.is_alphanumeric() && self.c != '_') {
-                        self.push(TokenKind::Ident(ident));
-                        continue;
-                    }
-
-                    weird_while! {
-                        ident.push(self.c),
-                        !self.is_end() && (self.c.is_alphanumeric() || self.c == '_'),
-                        self.next()
+                    while matches!(self.peek(), Some(&c) if c.is_alphanumeric() || c == '_') {
+                        self.next();
+                        ident.push(self.c);
                     }
 
-                    // let keyword: Result<Keyword, ()>;
-                    // if Ok(keyword) = ident.try_into() {}
                     if let Ok(keyword) = ident.clone().try_into() {
                         self.push(TokenKind::Keyword(keyword));
                     } else {
@@ -232,20 +256,21 @@ impl<'src> Tokenize for Lexer<'src> {
 
                 // A string
                 '"' => {
-                    self.next();
-
                     let mut str = String::new();
 
-                    weird_while! {
-                        {
-                            if self.c == '\\' {
-                                escape_char!(self, str);
-                            } else {
-                                str.push(self.c);
+                    loop {
+                        self.next();
+                        match self.c {
+                            '\0' => {
+                                return Err(LexError::new(
+                                    LexErrorType::InvalidString,
+                                    self.generate_loc(),
+                                ))
                             }
-                        },
-                        !self.is_end() && self.c != '"',
-                        self.next()
+                            '"' => break,
+                            '\\' => escape_char!(self, str),
+                            _ => str.push(self.c),
+                        }
                     }
 
                     self.push(TokenKind::Str(str));
@@ -253,29 +278,33 @@ impl<'src> Tokenize for Lexer<'src> {
 
                 // A character
                 '\'' => {
-                    self.next();
-
                     let mut char = String::new();
 
-                    weird_while! {
-                        {
-                            if self.c == '\\' {
-                                escape_char!(self, char);
-                            } else {
-                                char.push(self.c);
+                    loop {
+                        self.next();
+                        match self.c {
+                            '\0' | '\n' => {
+                                return Err(LexError::new(
+                                    LexErrorType::InvalidCharacterLiteral,
+                                    self.generate_loc(),
+                                ))
                             }
-                        },
-                        !self.is_end() && self.c != '\'',
-                        self.next()
+                            '\'' => break,
+                            '\\' => escape_char!(self, char),
+                            _ => char.push(self.c),
+                        }
                     }
 
-                    if char.len() != 1 {
-                        return Err(LexError::new(
-                            LexErrorType::InvalidCharacterLiteral,
-                            self.generate_loc(),
-                        ));
+                    let mut chars = char.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => self.push(TokenKind::Char(c)),
+                        _ => {
+                            return Err(LexError::new(
+                                LexErrorType::InvalidCharacterLiteral,
+                                self.generate_loc(),
+                            ))
+                        }
                     }
-                    self.push(TokenKind::Char(char.chars().nth(0).unwrap()));
                 }
 
                 // An integer, a float, or a hexadecimal number.
@@ -283,21 +312,11 @@ impl<'src> Tokenize for Lexer<'src> {
                     if self.c == '0' && self.peek() == Some(&'x') {
                         // A hexadecimal number!
                         self.next();
-                        self.next();
 
                         let mut hex = String::new();
-
-                        if self.is_end() || !self.peek().unwrap_or(&'\0').is_ascii_hexdigit() {
-                            return Err(LexError::new(
-                                LexErrorType::InvalidHexadecimalNumber,
-                                self.generate_loc(),
-                            ));
-                        }
-
-                        weird_while! {
-                            hex.push(self.c),
-                            !self.is_end() && self.c.is_ascii_hexdigit(),
-                            self.next()
+                        while matches!(self.peek(), Some(c) if c.is_ascii_hexdigit()) {
+                            self.next();
+                            hex.push(self.c);
                         }
 
                         if let Ok(hex) = i64::from_str_radix(&hex, 16) {
@@ -311,26 +330,27 @@ impl<'src> Tokenize for Lexer<'src> {
                     } else {
                         // An integer or a float.
                         let mut num = String::new();
+                        num.push(self.c);
                         let mut has_dot = false;
 
-                        weird_while! {
-                            {
-                                num.push(self.c);
-                                if self.peek() == Some(&'.') {
+                        loop {
+                            match self.peek().copied() {
+                                Some(c) if c.is_ascii_digit() => {}
+                                // A `.` followed by another `.` is a range, not a fraction.
+                                Some('.') if self.peek_second() != Some('.') => {
                                     if has_dot {
+                                        self.next();
                                         return Err(LexError::new(
                                             LexErrorType::InvalidFloatingPointNumber,
-                                            self.generate_loc())
-                                        );
-                                    } else {
-                                        has_dot = true;
-                                        num.push('.');
-                                        self.next();
+                                            self.generate_loc(),
+                                        ));
                                     }
+                                    has_dot = true;
                                 }
-                            },
-                            !self.is_end() && (self.c.is_ascii_digit() || self.c == '.'),
-                            self.next()
+                                _ => break,
+                            }
+                            self.next();
+                            num.push(self.c);
                         }
 
                         if has_dot {
@@ -342,24 +362,17 @@ impl<'src> Tokenize for Lexer<'src> {
                                     self.generate_loc(),
                                 ));
                             }
+                        } else if let Ok(int) = str::parse::<i64>(&num) {
+                            self.push(TokenKind::Int(int));
                         } else {
-                            if let Ok(int) = str::parse::<i64>(&num) {
-                                self.push(TokenKind::Int(int));
-                            } else {
-                                return Err(LexError::new(
-                                    LexErrorType::InvalidInteger,
-                                    self.generate_loc(),
-                                ));
-                            }
+                            return Err(LexError::new(
+                                LexErrorType::InvalidInteger,
+                                self.generate_loc(),
+                            ));
                         }
                     }
                 }
 
-                '\n' => {
-                    self.line += 1;
-                    self.column = 1;
-                    self.column_c = 1;
-                }
                 n if n.is_whitespace() => {}
                 '(' => self.push(TokenKind::LeftParen),
                 ')' => self.push(TokenKind::RightParen),
@@ -369,93 +382,84 @@ impl<'src> Tokenize for Lexer<'src> {
                 ']' => self.push(TokenKind::RightBracket),
                 '\\' => self.push(TokenKind::BackSlash),
                 '+' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::PlusEq),
-                    Some(_) => self.push(TokenKind::Plus),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::PlusEq),
+                    _ => self.push(TokenKind::Plus),
                 },
                 '-' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::MinusEq),
-                    Some(&'>') => self.push_and_consume(TokenKind::MinusGreaterThan),
-                    Some(_) => self.push(TokenKind::Minus),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::MinusEq),
+                    Some(&'>') => self.consume_and_push(TokenKind::MinusGreaterThan),
+                    _ => self.push(TokenKind::Minus),
                 },
                 '*' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::MulEq),
-                    Some(_) => self.push(TokenKind::Mul),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::MulEq),
+                    _ => self.push(TokenKind::Mul),
                 },
                 '/' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::DivEq),
+                    Some(&'=') => self.consume_and_push(TokenKind::DivEq),
                     Some(&'/') => {
-                        // Comment!
-                        while self.c != '\n' && !self.is_end() {
+                        // Comment! Stop right before the newline so that it still gets counted.
+                        self.next();
+                        let mut comment = String::new();
+                        while !matches!(self.peek(), Some(&'\n') | None) {
                             self.next();
+                            comment.push(self.c);
+                        }
+                        if self.keep_comments {
+                            self.push(TokenKind::Comment(comment));
                         }
-                        self.next();
                     }
-                    Some(_) => self.push(TokenKind::Div),
-                    _ => {}
+                    _ => self.push(TokenKind::Div),
                 },
                 '%' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::RemEq),
-                    Some(_) => self.push(TokenKind::Rem),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::RemEq),
+                    _ => self.push(TokenKind::Rem),
                 },
                 ',' => self.push(TokenKind::Comma),
-                '.' => self.push(TokenKind::Dot),
-                '|' => {
-                    if {
+                '.' => match (self.peek().copied(), self.peek_second()) {
+                    (Some('.'), Some('=')) => {
                         self.next();
-                        self.c == '|'
-                    } {
-                        self.push(TokenKind::DoubleAmp);
-                    } else {
-                        return Err(LexError::new(
-                            LexErrorType::InvalidToken(vec!["||"]),
-                            self.generate_loc(),
-                        ));
+                        self.consume_and_push(TokenKind::DoubleDotEq)
                     }
-                }
-                '&' => {
-                    if {
-                        self.next();
-                        self.c == '&'
-                    } {
-                        self.push(TokenKind::DoubleVertical);
-                    } else {
+                    (Some('.'), _) => self.consume_and_push(TokenKind::DoubleDot),
+                    _ => self.push(TokenKind::Dot),
+                },
+                '|' => match self.peek() {
+                    Some(&'|') => self.consume_and_push(TokenKind::DoubleVertical),
+                    _ => self.push(TokenKind::Vertical),
+                },
+                '&' => match self.peek() {
+                    Some(&'&') => self.consume_and_push(TokenKind::DoubleAmp),
+                    _ => {
                         return Err(LexError::new(
                             LexErrorType::InvalidToken(vec!["&&"]),
                             self.generate_loc(),
                         ));
                     }
-                }
+                },
                 '>' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::GreaterThanOrEq),
-                    Some(_) => self.push(TokenKind::GreaterThan),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::GreaterThanOrEq),
+                    _ => self.push(TokenKind::GreaterThan),
                 },
                 '<' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::LessThanOrEq),
-                    Some(_) => self.push(TokenKind::LessThan),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::LessThanOrEq),
+                    _ => self.push(TokenKind::LessThan),
                 },
                 '!' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::BangEq),
-                    Some(_) => self.push(TokenKind::Bang),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::BangEq),
+                    _ => self.push(TokenKind::Bang),
                 },
                 '=' => match self.peek() {
-                    Some(&'=') => self.push_and_consume(TokenKind::DoubleEq),
-                    Some(&'>') => self.push_and_consume(TokenKind::EqGreaterThan),
-                    Some(_) => self.push(TokenKind::Eq),
-                    None => {}
+                    Some(&'=') => self.consume_and_push(TokenKind::DoubleEq),
+                    Some(&'>') => self.consume_and_push(TokenKind::EqGreaterThan),
+                    _ => self.push(TokenKind::Eq),
                 },
                 ':' => match self.peek() {
-                    Some(&':') => self.push_and_consume(TokenKind::DoubleColon),
-                    Some(_) => self.push(TokenKind::Colon),
-                    None => {}
+                    Some(&':') => self.consume_and_push(TokenKind::DoubleColon),
+                    _ => self.push(TokenKind::Colon),
                 },
                 ';' => self.push(TokenKind::SemiColon),
+                '@' => self.push(TokenKind::At),
+                '$' => self.push(TokenKind::Dollar),
                 _ => {
                     return Err(LexError::new(
                         LexErrorType::InvalidCharacter,
@@ -473,41 +477,48 @@ impl<'src> Tokenize for Lexer<'src> {
 
 impl<'src> Lexer<'src> {
     /// Create a new `Lexer` object.
-    pub fn new(filename: Option<String>, source: &'src String) -> Self {
-        let filename = if let Some(name) = filename {
-            Some(Rc::from(name))
-        } else {
-            None
-        };
-
+    pub fn new(filename: Option<String>, source: &'src str) -> Self {
         Self {
-            filename,
+            filename: filename.map(Rc::from),
             source: source.chars().peekable(),
             tokens: Vec::new(),
             c: '\0',
+            keep_comments: false,
             line: 1,
-            column: 1,
-            column_c: 1,
-            offset: 0,
-            length: 0,
+            column: 0,
+            consumed: 0,
+            start_line: 1,
+            start_column: 1,
+            start_offset: 0,
         }
     }
 
+    /// Produce `Comment` tokens instead of skipping comments, e.g. for the formatter.
+    pub fn keep_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
+
     /// Check whether it reached the end of the source code or not.
     #[inline]
     fn is_end(&self) -> bool {
         self.c == '\0'
     }
 
-    /// Advances the `source` iterator.
-    /// May panic.
+    /// Advances the `source` iterator, keeping track of the line and column of the new character.
     fn next(&mut self) -> Option<char> {
-        self.offset += 1;
+        if self.c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         let n = self.source.next();
+        if n.is_some() {
+            self.consumed += 1;
+        }
         self.c = n.unwrap_or('\0');
-        self.length += 1;
-        self.column_c += 1;
-        return n;
+        n
     }
 
     /// Return a reference to the next character without consuming it.
@@ -515,28 +526,38 @@ impl<'src> Lexer<'src> {
         self.source.peek()
     }
 
-    /// Create a new `Token` and then append it to `tokens` vector.
+    /// Return the character after the next one without consuming anything.
+    fn peek_second(&self) -> Option<char> {
+        self.source.clone().nth(1)
+    }
+
+    /// Remember the current position as the start of the next token.
+    fn mark(&mut self) {
+        self.start_line = self.line;
+        self.start_column = self.column;
+        self.start_offset = self.consumed - 1;
+    }
+
+    /// Create a new `Token` ending at the current character and then append it to `tokens` vector.
     fn push(&mut self, kind: TokenKind) {
         let loc = self.generate_loc();
         let token = Token::new(kind, loc);
-        self.length = 0;
-        self.column = self.column_c;
         self.tokens.push(token);
     }
 
-    /// Do whatever `push` method does and then `next`.
-    fn push_and_consume(&mut self, kind: TokenKind) {
-        self.push(kind);
+    /// Consume the next character and then do whatever `push` method does.
+    fn consume_and_push(&mut self, kind: TokenKind) {
         self.next();
+        self.push(kind);
     }
 
-    /// Generate a `SourceCodeLocation` with the current location information stored in the lexer struct.
+    /// Generate a `SourceCodeLocation` spanning from the start of the current token to the current character.
     fn generate_loc(&self) -> SourceCodeLocation {
         SourceCodeLocation {
-            line: self.line,
-            column: self.column,
-            offset: self.offset,
-            length: self.length - 1,
+            line: self.start_line,
+            column: self.start_column,
+            offset: self.start_offset,
+            length: self.consumed.max(self.start_offset + 1) - self.start_offset,
             filename: self.filename.clone(),
         }
     }
@@ -548,14 +569,14 @@ mod tests {
 
     #[test]
     fn keywords_and_types() {
-        let src = "if else match def let str int float struct enum impl for while self Self break return continue methods import export true false".to_string();
+        let src = "if else match def let str int float struct enum impl for while self Self break return continue methods import export true false as trait type mut const".to_string();
         let lexer = Lexer::new(None, &src);
         let tokens = lexer.tokenize();
 
-        assert!(matches!(tokens, Ok(_)));
+        assert!(tokens.is_ok());
 
         let tokens = tokens.unwrap();
-        assert_eq!(tokens.len(), 23);
+        assert_eq!(tokens.len(), 28);
 
         assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::If));
         assert_eq!(tokens[1].kind, TokenKind::Keyword(Keyword::Else));
@@ -580,6 +601,11 @@ mod tests {
         assert_eq!(tokens[20].kind, TokenKind::Keyword(Keyword::Export));
         assert_eq!(tokens[21].kind, TokenKind::Keyword(Keyword::True));
         assert_eq!(tokens[22].kind, TokenKind::Keyword(Keyword::False));
+        assert_eq!(tokens[23].kind, TokenKind::Keyword(Keyword::As));
+        assert_eq!(tokens[24].kind, TokenKind::Keyword(Keyword::Trait));
+        assert_eq!(tokens[25].kind, TokenKind::Keyword(Keyword::Type));
+        assert_eq!(tokens[26].kind, TokenKind::Keyword(Keyword::Mut));
+        assert_eq!(tokens[27].kind, TokenKind::Keyword(Keyword::Const));
     }
 
     #[test]
@@ -588,7 +614,7 @@ mod tests {
         let lexer = Lexer::new(None, &src);
         let tokens = lexer.tokenize();
 
-        assert!(matches!(tokens, Ok(_)));
+        assert!(tokens.is_ok());
 
         let tokens = tokens.unwrap();
         assert_eq!(tokens.len(), 6);
@@ -600,4 +626,24 @@ mod tests {
         assert_eq!(tokens[4].kind, TokenKind::Char('\n'));
         assert_eq!(tokens[5].kind, TokenKind::Ident("identifier".to_string()));
     }
+
+    #[test]
+    fn symbols_and_locations() {
+        let src = "a || b && c | d @ e $f ..\n// comment\n  x->y 1..=2".to_string();
+        let tokens = Lexer::new(None, &src).tokenize().unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+
+        assert_eq!(kinds[1], TokenKind::DoubleVertical);
+        assert_eq!(kinds[3], TokenKind::DoubleAmp);
+        assert_eq!(kinds[5], TokenKind::Vertical);
+        assert_eq!(kinds[7], TokenKind::At);
+        assert_eq!(kinds[9], TokenKind::Dollar);
+        assert_eq!(kinds[11], TokenKind::DoubleDot);
+
+        let arrow = &tokens[13];
+        assert_eq!(arrow.kind, TokenKind::MinusGreaterThan);
+        assert_eq!((arrow.loc.line, arrow.loc.column), (3, 4));
+        assert_eq!((arrow.loc.offset, arrow.loc.length), (40, 2));
+        assert_eq!(kinds[16], TokenKind::DoubleDotEq);
+    }
 }
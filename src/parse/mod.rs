@@ -1,7 +1,16 @@
 use crate::util::GenerateErrorMessage;
+use serde::Serialize;
 use std::rc::Rc;
 
+pub mod ast;
+pub mod dump;
 mod lexer;
+mod parser;
+pub mod printer;
+pub mod visit;
+
+pub use lexer::{Keyword, Lexer, Token, TokenKind, Tokenize};
+pub use parser::Parser;
 
 /// Struct for lexer errors.
 #[derive(Clone, Debug)]
@@ -17,6 +26,7 @@ impl LexError {
 }
 
 /// Types of errors tokenizing the source code.
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug)]
 pub enum LexErrorType {
     /// Encountered an invalid character.
@@ -37,7 +47,7 @@ pub enum LexErrorType {
 
 impl GenerateErrorMessage for LexError {
     /// Generate a properly formatted error message
-    fn generate_error_message(self, source_code: &String) -> String {
+    fn generate_error_message(self, source_code: &str) -> String {
         let parse_err = "\nParse Error: ";
         let loc = self.loc;
         match self.r#type {
@@ -71,9 +81,127 @@ impl GenerateErrorMessage for LexError {
     }
 }
 
+/// Struct for parser errors.
+#[derive(Clone, Debug)]
+pub struct ParseError {
+    r#type: ParseErrorType,
+    loc: SourceCodeLocation,
+    /// A hint at what the code may have meant, e.g. the keyword a name is a typo of.
+    help: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(t: ParseErrorType, loc: SourceCodeLocation) -> Self {
+        Self {
+            r#type: t,
+            loc,
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+/// Types of errors parsing the tokens.
+#[derive(Clone, Debug)]
+pub enum ParseErrorType {
+    /// Encountered a token other than the expected ones.
+    UnexpectedToken(Vec<&'static str>),
+    /// Reached the end of the source code while expecting one of the given tokens.
+    UnexpectedEnd(Vec<&'static str>),
+    /// Encountered `..` outside of a tuple, variant or struct pattern.
+    MisplacedRest,
+    /// Encountered an `@` binding whose left-hand side isn't a plain name.
+    InvalidBinding,
+    /// Encountered an assignment to something that isn't a variable or a field.
+    InvalidAssignmentTarget,
+    /// Encountered a reference to something of the kind that isn't defined, e.g. a PIR value.
+    UnknownName(&'static str, String),
+    /// Encountered a second definition of something of the kind with the same name.
+    DuplicateName(&'static str, String),
+}
+
+impl GenerateErrorMessage for ParseError {
+    /// Generate a properly formatted error message
+    fn generate_error_message(self, source_code: &str) -> String {
+        let parse_err = "\nParse Error: ";
+        let loc = self.loc;
+        let message = match self.r#type {
+            ParseErrorType::UnexpectedToken(expected) => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + "Expected "
+                    + &expected.join(", ")
+            }
+            ParseErrorType::UnexpectedEnd(expected) => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + "Unexpected end of file, expected "
+                    + &expected.join(", ")
+            }
+            ParseErrorType::MisplacedRest => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + "`..` is only allowed inside tuple, variant and struct patterns."
+            }
+            ParseErrorType::InvalidBinding => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + "Only a plain name can be bound with `@`."
+            }
+            ParseErrorType::InvalidAssignmentTarget => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + "Only variables and fields can be assigned to."
+            }
+            ParseErrorType::UnknownName(kind, name) => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + &format!("Unknown {kind} `{name}`.")
+            }
+            ParseErrorType::DuplicateName(kind, name) => {
+                loc.line_in_source_code(source_code)
+                    + parse_err
+                    + &format!("The {kind} `{name}` is defined more than once.")
+            }
+        };
+        match self.help {
+            Some(help) => message + "\nHelp: " + &help,
+            None => message,
+        }
+    }
+}
+
+/// Errors produced while turning source code into an AST.
+#[derive(Clone, Debug)]
+pub enum SyntaxError {
+    Lex(LexError),
+    Parse(ParseError),
+}
+
+impl GenerateErrorMessage for SyntaxError {
+    fn generate_error_message(self, source_code: &str) -> String {
+        match self {
+            SyntaxError::Lex(e) => e.generate_error_message(source_code),
+            SyntaxError::Parse(e) => e.generate_error_message(source_code),
+        }
+    }
+}
+
+/// Tokenize and parse the given source code.
+pub fn parse_source(filename: Option<String>, source: &str) -> Result<ast::Module, SyntaxError> {
+    let tokens = Lexer::new(filename, source)
+        .tokenize()
+        .map_err(SyntaxError::Lex)?;
+    Parser::new(tokens).parse().map_err(SyntaxError::Parse)
+}
+
 /// A struct that represents a location in the input source code.
 /// Used for properly format errors.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct SourceCodeLocation {
     pub line: usize,
     pub column: usize,
@@ -101,7 +229,7 @@ impl SourceCodeLocation {
 
     /// Generate a string with two lines: the line at which the error occurred and a line
     /// with ^'s, pointing at precise location of the error.
-    pub fn line_in_source_code(&self, source_code: &String) -> String {
+    pub fn line_in_source_code(&self, source_code: &str) -> String {
         let SourceCodeLocation {
             line,
             column,
@@ -112,19 +240,21 @@ impl SourceCodeLocation {
 
         let loc = format!(
             "{}:{}:{}:{}",
-            filename.unwrap_or(Rc::from("unknown")).to_string(),
+            filename.unwrap_or(Rc::from("unknown")),
             line,
             column,
             offset
         );
         let mut lines = source_code.lines();
-        let mut line = lines.nth(line - 1).unwrap().to_string();
+        let mut line = lines.nth(line - 1).unwrap_or("").to_string();
 
+        // Spans covering several lines are only underlined up to the end of the first one.
+        let width = line.chars().count().saturating_sub(column - 1).max(1);
         let mut hats = String::new();
         for _ in 1..column {
             hats.push(' ');
         }
-        for _ in 0..length {
+        for _ in 0..length.min(width) {
             hats.push('^');
         }
 
@@ -133,4 +263,13 @@ impl SourceCodeLocation {
 
         line
     }
+
+    /// Create a location spanning from the start of `self` to the end of `end`.
+    pub fn to(&self, end: &SourceCodeLocation) -> SourceCodeLocation {
+        let length = (end.offset + end.length).saturating_sub(self.offset);
+        SourceCodeLocation {
+            length: length.max(self.length),
+            ..self.clone()
+        }
+    }
 }
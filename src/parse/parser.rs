@@ -0,0 +1,1476 @@
+use crate::parse::ast::*;
+use crate::parse::{Keyword, ParseError, ParseErrorType, SourceCodeLocation, Token, TokenKind};
+use crate::util::suggest;
+use log::debug;
+
+type ParseResult<T> = Result<T, ParseError>;
+
+/// The keywords that start statements, and so may be what a misspelled name at the start of one
+/// meant.
+const STMT_KEYWORDS: [&str; 7] = ["let", "if", "while", "match", "return", "break", "continue"];
+
+/// A recursive descent parser turning tokens into a `Module`.
+pub struct Parser {
+    /// All the tokens of the source code.
+    tokens: Vec<Token>,
+    /// Index of the current token.
+    current: usize,
+    /// Whether struct literals and trailing closures are disallowed, as in
+    /// `if`, `while` and `match` heads where a `{` starts the body.
+    no_struct: bool,
+}
+
+impl Parser {
+    /// Create a new `Parser` object.
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            current: 0,
+            no_struct: false,
+        }
+    }
+
+    /// Parse all the tokens into a `Module`.
+    pub fn parse(mut self) -> ParseResult<Module> {
+        debug!("Starting parsing the tokens...");
+
+        let mut items = Vec::new();
+        while !self.is_end() {
+            items.push(self.item()?);
+        }
+
+        debug!("Finished parsing the tokens.");
+        Ok(Module { items })
+    }
+
+    // ---- Items ----
+
+    fn item(&mut self) -> ParseResult<Item> {
+        let start = self.loc();
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Import)) => ItemKind::Import(self.import()?),
+            Some(TokenKind::Keyword(Keyword::Export)) => {
+                self.advance();
+                let mut names = vec![self.ident()?];
+                while self.eat(&TokenKind::Comma) {
+                    names.push(self.ident()?);
+                }
+                self.expect(&TokenKind::SemiColon, "`;`")?;
+                ItemKind::Export(names)
+            }
+            Some(TokenKind::Keyword(Keyword::Struct)) => ItemKind::Struct(self.struct_decl()?),
+            Some(TokenKind::Keyword(Keyword::Enum)) => ItemKind::Enum(self.enum_decl()?),
+            Some(TokenKind::Keyword(Keyword::Trait)) => ItemKind::Trait(self.trait_decl()?),
+            Some(TokenKind::Keyword(Keyword::Impl)) => ItemKind::Impl(self.impl_decl()?),
+            Some(TokenKind::Keyword(Keyword::Def) | TokenKind::At) => {
+                ItemKind::Fn(Box::new(self.fn_decl()?))
+            }
+            Some(TokenKind::Keyword(Keyword::Const)) => ItemKind::Const(self.const_decl()?),
+            _ => {
+                return Err(self.error(vec![
+                    "`import`",
+                    "`export`",
+                    "`struct`",
+                    "`enum`",
+                    "`trait`",
+                    "`impl`",
+                    "`def`",
+                    "`const`",
+                    "`@tailcall`",
+                ]))
+            }
+        };
+        Ok(Item {
+            kind,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn import(&mut self) -> ParseResult<Import> {
+        self.expect(&TokenKind::Keyword(Keyword::Import), "`import`")?;
+        let mut path = vec![self.ident()?];
+        let mut group = None;
+        while self.eat(&TokenKind::DoubleColon) {
+            if self.eat(&TokenKind::LeftParen) {
+                group = Some(self.comma_separated(&TokenKind::RightParen, |p| {
+                    Ok(ImportName {
+                        name: p.ident()?,
+                        alias: p.alias()?,
+                    })
+                })?);
+                break;
+            }
+            path.push(self.ident()?);
+        }
+        let alias = if group.is_none() { self.alias()? } else { None };
+        self.expect(&TokenKind::SemiColon, "`;`")?;
+        Ok(Import { path, group, alias })
+    }
+
+    /// Parse an optional `as name`.
+    fn alias(&mut self) -> ParseResult<Option<Ident>> {
+        if self.eat(&TokenKind::Keyword(Keyword::As)) {
+            Ok(Some(self.ident()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn struct_decl(&mut self) -> ParseResult<StructDecl> {
+        self.expect(&TokenKind::Keyword(Keyword::Struct), "`struct`")?;
+        let name = self.ident()?;
+        let generics = self.generic_params()?;
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        let fields = self.comma_separated(&TokenKind::RightBrace, |p| p.field_decl())?;
+        Ok(StructDecl {
+            name,
+            generics,
+            fields,
+        })
+    }
+
+    fn field_decl(&mut self) -> ParseResult<FieldDecl> {
+        let start = self.loc();
+        let mutable = self.eat(&TokenKind::Dollar);
+        let name = self.ident()?;
+        self.expect(&TokenKind::Colon, "`:`")?;
+        let ty = self.ty()?;
+        Ok(FieldDecl {
+            name,
+            ty,
+            mutable,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn enum_decl(&mut self) -> ParseResult<EnumDecl> {
+        self.expect(&TokenKind::Keyword(Keyword::Enum), "`enum`")?;
+        let name = self.ident()?;
+        let generics = self.generic_params()?;
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        let variants = self.comma_separated(&TokenKind::RightBrace, |p| {
+            let start = p.loc();
+            let name = p.ident()?;
+            let fields = if p.eat(&TokenKind::LeftParen) {
+                p.comma_separated(&TokenKind::RightParen, |p| p.ty())?
+            } else {
+                Vec::new()
+            };
+            let discriminant = if fields.is_empty() && p.eat(&TokenKind::Eq) {
+                Some(p.expr()?)
+            } else {
+                None
+            };
+            Ok(VariantDecl {
+                name,
+                fields,
+                discriminant,
+                loc: p.loc_from(&start),
+            })
+        })?;
+        Ok(EnumDecl {
+            name,
+            generics,
+            variants,
+        })
+    }
+
+    fn const_decl(&mut self) -> ParseResult<ConstDecl> {
+        self.expect(&TokenKind::Keyword(Keyword::Const), "`const`")?;
+        let name = self.ident()?;
+        self.expect(&TokenKind::Colon, "`:`")?;
+        let ty = self.ty()?;
+        self.expect(&TokenKind::Eq, "`=`")?;
+        let value = self.expr()?;
+        self.expect(&TokenKind::SemiColon, "`;`")?;
+        Ok(ConstDecl { name, ty, value })
+    }
+
+    fn trait_decl(&mut self) -> ParseResult<TraitDecl> {
+        self.expect(&TokenKind::Keyword(Keyword::Trait), "`trait`")?;
+        let name = self.ident()?;
+        let generics = self.generic_params()?;
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        let mut assoc_types = Vec::new();
+        let mut methods = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            if self.check(&TokenKind::Keyword(Keyword::Type)) {
+                assoc_types.push(self.assoc_type(false)?);
+            } else {
+                methods.push(self.fn_decl_with(true)?);
+            }
+        }
+        Ok(TraitDecl {
+            name,
+            generics,
+            assoc_types,
+            methods,
+        })
+    }
+
+    /// Parse `type Output;` in a trait, or `type Output = int;` in an impl.
+    fn assoc_type(&mut self, defined: bool) -> ParseResult<AssocType> {
+        let start = self.loc();
+        self.expect(&TokenKind::Keyword(Keyword::Type), "`type`")?;
+        let name = self.ident()?;
+        let ty = if defined {
+            self.expect(&TokenKind::Eq, "`=`")?;
+            Some(self.ty()?)
+        } else {
+            None
+        };
+        self.expect(&TokenKind::SemiColon, "`;`")?;
+        Ok(AssocType {
+            name,
+            ty,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn impl_decl(&mut self) -> ParseResult<ImplDecl> {
+        self.expect(&TokenKind::Keyword(Keyword::Impl), "`impl`")?;
+        let trait_ref = if self.eat(&TokenKind::Keyword(Keyword::Methods)) {
+            None
+        } else {
+            Some(self.type_path()?)
+        };
+        self.expect(&TokenKind::Keyword(Keyword::For), "`for`")?;
+
+        // `impl methods for HashMap<K: Hashable, V>` declares the generic parameters on the target.
+        let start = self.loc();
+        let name = self.ident()?;
+        let generics = self.generic_params()?;
+        let target = Path {
+            segments: vec![PathSegment {
+                ident: name,
+                generics: generics.iter().map(generic_param_as_type).collect(),
+            }],
+            loc: self.loc_from(&start),
+        };
+
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        let mut assoc_types = Vec::new();
+        let mut methods = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            if self.check(&TokenKind::Keyword(Keyword::Type)) {
+                assoc_types.push(self.assoc_type(true)?);
+            } else {
+                methods.push(self.fn_decl()?);
+            }
+        }
+        Ok(ImplDecl {
+            generics,
+            trait_ref,
+            target,
+            assoc_types,
+            methods,
+        })
+    }
+
+    fn fn_decl(&mut self) -> ParseResult<FnDecl> {
+        self.fn_decl_with(false)
+    }
+
+    /// Parse a function, whose body is replaced by `;` in a trait and for `@native` functions.
+    fn fn_decl_with(&mut self, in_trait: bool) -> ParseResult<FnDecl> {
+        let start = self.loc();
+        let (mut tailcall, mut native) = (None, None);
+        if self.eat(&TokenKind::At) {
+            let loc = self.loc();
+            let name = self.ident()?;
+            match name.name.as_str() {
+                "tailcall" => tailcall = Some(self.loc_from(&start)),
+                "native" => native = Some(self.loc_from(&start)),
+                _ => {
+                    return Err(ParseError::new(
+                        ParseErrorType::UnknownName("annotation", name.name),
+                        loc,
+                    ))
+                }
+            }
+        }
+        self.expect(&TokenKind::Keyword(Keyword::Def), "`def`")?;
+        let name = self.ident()?;
+        let generics = self.generic_params()?;
+        self.expect(&TokenKind::LeftParen, "`(`")?;
+
+        let mut self_param = None;
+        if self.check(&TokenKind::Keyword(Keyword::LilSelf)) {
+            self_param = Some(self.advance().loc);
+            if !self.check(&TokenKind::RightParen) {
+                self.expect(&TokenKind::Comma, "`,`")?;
+            }
+        }
+        let params = self.comma_separated(&TokenKind::RightParen, |p| p.param())?;
+        let ret = self.ty()?;
+        let body = if native.is_some() {
+            self.expect(&TokenKind::SemiColon, "`;`")?;
+            None
+        } else if in_trait && self.eat(&TokenKind::SemiColon) {
+            None
+        } else {
+            Some(self.block()?)
+        };
+
+        Ok(FnDecl {
+            tailcall,
+            native,
+            name,
+            generics,
+            self_param,
+            params,
+            ret,
+            body,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn param(&mut self) -> ParseResult<Param> {
+        let start = self.loc();
+        let variadic = self.eat(&TokenKind::Mul);
+        let pattern = self.pattern()?;
+        self.expect(&TokenKind::Colon, "`:`")?;
+        let ty = self.ty()?;
+        Ok(Param {
+            pattern,
+            ty,
+            variadic,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Parse `<K: Hashable + Equal, V>` if present.
+    fn generic_params(&mut self) -> ParseResult<Vec<GenericParam>> {
+        if !self.eat(&TokenKind::LessThan) {
+            return Ok(Vec::new());
+        }
+        self.comma_separated(&TokenKind::GreaterThan, |p| {
+            let name = p.ident()?;
+            let mut bounds = Vec::new();
+            if p.eat(&TokenKind::Colon) {
+                bounds.push(p.type_path()?);
+                while p.eat(&TokenKind::Plus) {
+                    bounds.push(p.type_path()?);
+                }
+            }
+            Ok(GenericParam { name, bounds })
+        })
+    }
+
+    // ---- Types ----
+
+    fn ty(&mut self) -> ParseResult<Type> {
+        let start = self.loc();
+        let kind = match self.peek() {
+            Some(TokenKind::LeftBracket) => {
+                self.advance();
+                self.expect(&TokenKind::RightBracket, "`]`")?;
+                TypeKind::Array(Box::new(self.ty()?))
+            }
+            Some(TokenKind::LeftParen) => {
+                self.advance();
+                let (mut types, trailing_comma) =
+                    self.comma_separated_with_trailing(&TokenKind::RightParen, |p| p.ty())?;
+                if types.len() == 1 && !trailing_comma {
+                    return Ok(types.remove(0));
+                }
+                TypeKind::Tuple(types)
+            }
+            Some(TokenKind::Keyword(Keyword::Def)) => {
+                self.advance();
+                self.expect(&TokenKind::LeftParen, "`(`")?;
+                let params = self.comma_separated(&TokenKind::RightParen, |p| p.ty())?;
+                TypeKind::Fn {
+                    params,
+                    ret: Box::new(self.ty()?),
+                }
+            }
+            _ => TypeKind::Path(self.type_path()?),
+        };
+        Ok(Type {
+            kind,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Parse a path in a type position, where every segment may have generic arguments.
+    fn type_path(&mut self) -> ParseResult<Path> {
+        let start = self.loc();
+        let mut segments = Vec::new();
+        loop {
+            let ident = self.path_ident()?;
+            let generics = if self.eat(&TokenKind::LessThan) {
+                self.comma_separated(&TokenKind::GreaterThan, |p| p.ty())?
+            } else {
+                Vec::new()
+            };
+            segments.push(PathSegment { ident, generics });
+            if !self.eat(&TokenKind::DoubleColon) {
+                break;
+            }
+        }
+        Ok(Path {
+            segments,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Parse an identifier that may start or continue a path, including `Self` and primitive type names.
+    fn path_ident(&mut self) -> ParseResult<Ident> {
+        let name = match self.peek() {
+            Some(TokenKind::Ident(name)) => name.clone(),
+            Some(TokenKind::Keyword(Keyword::BigSelf)) => "Self".to_string(),
+            Some(TokenKind::Keyword(Keyword::Int)) => "int".to_string(),
+            Some(TokenKind::Keyword(Keyword::Float)) => "float".to_string(),
+            Some(TokenKind::Keyword(Keyword::Str)) => "str".to_string(),
+            _ => return Err(self.error(vec!["identifier"])),
+        };
+        let loc = self.advance().loc;
+        Ok(Ident { name, loc })
+    }
+
+    // ---- Statements ----
+
+    fn block(&mut self) -> ParseResult<Block> {
+        let start = self.loc();
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        let old = std::mem::replace(&mut self.no_struct, false);
+        let mut stmts = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            if self.is_end() {
+                return Err(self.error(vec!["`}`"]));
+            }
+            stmts.push(self.stmt()?);
+        }
+        self.no_struct = old;
+        Ok(Block {
+            stmts,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn stmt(&mut self) -> ParseResult<Stmt> {
+        let start = self.loc();
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Let)) => {
+                self.advance();
+                let pattern = self.pattern()?;
+                let ty = if self.eat(&TokenKind::Colon) {
+                    Some(self.ty()?)
+                } else {
+                    None
+                };
+                let value = if self.eat(&TokenKind::Eq) {
+                    Some(self.expr()?)
+                } else {
+                    None
+                };
+                self.expect(&TokenKind::SemiColon, "`;`")?;
+                StmtKind::Let { pattern, ty, value }
+            }
+            Some(TokenKind::Keyword(Keyword::If)) => self.if_stmt()?,
+            Some(TokenKind::Keyword(Keyword::While)) => {
+                self.advance();
+                let cond = self.cond_expr()?;
+                let body = self.block()?;
+                StmtKind::While { cond, body }
+            }
+            Some(TokenKind::Keyword(Keyword::Match)) => self.match_stmt()?,
+            Some(TokenKind::LeftBrace) => StmtKind::Block(self.block()?),
+            _ => {
+                let typo = match self.peek() {
+                    Some(TokenKind::Ident(name)) => suggest(name, STMT_KEYWORDS),
+                    _ => None,
+                };
+                let kind = self
+                    .simple_stmt()
+                    .and_then(|kind| {
+                        self.expect(&TokenKind::SemiColon, "`;`")?;
+                        Ok(kind)
+                    })
+                    .map_err(|e| match typo {
+                        Some(keyword) => e.with_help(format!("did you mean `{keyword}`?")),
+                        None => e,
+                    })?;
+                kind
+            }
+        };
+        Ok(Stmt {
+            kind,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Parse a statement that needs a terminator: `return`, `break`, `continue`, an assignment or an expression.
+    fn simple_stmt(&mut self) -> ParseResult<StmtKind> {
+        Ok(match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Return)) => {
+                self.advance();
+                if self.check(&TokenKind::SemiColon)
+                    || self.check(&TokenKind::Comma)
+                    || self.check(&TokenKind::RightBrace)
+                {
+                    StmtKind::Return(None)
+                } else {
+                    StmtKind::Return(Some(self.expr()?))
+                }
+            }
+            Some(TokenKind::Keyword(Keyword::Break)) => {
+                self.advance();
+                StmtKind::Break
+            }
+            Some(TokenKind::Keyword(Keyword::Continue)) => {
+                self.advance();
+                StmtKind::Continue
+            }
+            _ => {
+                let expr = self.expr()?;
+                let op = match self.peek() {
+                    Some(TokenKind::Eq) => None,
+                    Some(TokenKind::PlusEq) => Some(BinaryOp::Add),
+                    Some(TokenKind::MinusEq) => Some(BinaryOp::Sub),
+                    Some(TokenKind::MulEq) => Some(BinaryOp::Mul),
+                    Some(TokenKind::DivEq) => Some(BinaryOp::Div),
+                    Some(TokenKind::RemEq) => Some(BinaryOp::Rem),
+                    _ => return Ok(StmtKind::Expr(expr)),
+                };
+                self.advance();
+                if !matches!(
+                    expr.kind,
+                    ExprKind::Path(_) | ExprKind::Field { .. } | ExprKind::SelfValue
+                ) {
+                    return Err(ParseError::new(
+                        ParseErrorType::InvalidAssignmentTarget,
+                        expr.loc,
+                    ));
+                }
+                let value = self.expr()?;
+                StmtKind::Assign {
+                    target: expr,
+                    op,
+                    value,
+                }
+            }
+        })
+    }
+
+    fn if_stmt(&mut self) -> ParseResult<StmtKind> {
+        self.expect(&TokenKind::Keyword(Keyword::If), "`if`")?;
+        let cond = self.cond_expr()?;
+        let then_block = self.block()?;
+        let else_block = if self.eat(&TokenKind::Keyword(Keyword::Else)) {
+            if self.check(&TokenKind::Keyword(Keyword::If)) {
+                let start = self.loc();
+                let kind = self.if_stmt()?;
+                let loc = self.loc_from(&start);
+                Some(Block {
+                    stmts: vec![Stmt {
+                        kind,
+                        loc: loc.clone(),
+                    }],
+                    loc,
+                })
+            } else {
+                Some(self.block()?)
+            }
+        } else {
+            None
+        };
+        Ok(StmtKind::If {
+            cond,
+            then_block,
+            else_block,
+        })
+    }
+
+    fn match_stmt(&mut self) -> ParseResult<StmtKind> {
+        self.expect(&TokenKind::Keyword(Keyword::Match), "`match`")?;
+        let scrutinee = self.cond_expr()?;
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+
+        let mut arms = Vec::new();
+        while !self.eat(&TokenKind::RightBrace) {
+            let start = self.loc();
+            let pattern = self.pattern()?;
+            let guard = if self.eat(&TokenKind::Keyword(Keyword::If)) {
+                Some(self.expr()?)
+            } else {
+                None
+            };
+            self.expect(&TokenKind::EqGreaterThan, "`=>`")?;
+
+            let body_start = self.loc();
+            let (kind, needs_comma) = if self.check(&TokenKind::LeftBrace) {
+                (StmtKind::Block(self.block()?), false)
+            } else {
+                (self.simple_stmt()?, true)
+            };
+            let body = Stmt {
+                kind,
+                loc: self.loc_from(&body_start),
+            };
+
+            if !self.eat(&TokenKind::Comma) && needs_comma && !self.check(&TokenKind::RightBrace) {
+                return Err(self.error(vec!["`,`", "`}`"]));
+            }
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body: Box::new(body),
+                loc: self.loc_from(&start),
+            });
+        }
+        Ok(StmtKind::Match { scrutinee, arms })
+    }
+
+    // ---- Expressions ----
+
+    pub fn expr(&mut self) -> ParseResult<Expr> {
+        self.binary(0)
+    }
+
+    /// Parse an expression in front of a `{` body, where struct literals and trailing closures aren't allowed.
+    fn cond_expr(&mut self) -> ParseResult<Expr> {
+        let old = std::mem::replace(&mut self.no_struct, true);
+        let expr = self.expr();
+        self.no_struct = old;
+        expr
+    }
+
+    /// Precedence climbing over the binary operators.
+    fn binary(&mut self, min_prec: u8) -> ParseResult<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let (op, prec, tokens) = match self.shift_op() {
+                Some(op) => (op, op.precedence(), 2),
+                None => match self.peek().and_then(binary_op) {
+                    Some((op, prec)) => (op, prec, 1),
+                    None => break,
+                },
+            };
+            if prec < min_prec {
+                break;
+            }
+            for _ in 0..tokens {
+                self.advance();
+            }
+            let rhs = self.binary(prec + 1)?;
+            let loc = lhs.loc.to(&rhs.loc);
+            lhs = Expr {
+                kind: ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+                loc,
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// `<<` or `>>`, lexed as two adjacent `<` or `>` so that `Option<Option<int>>` still closes
+    /// two generic argument lists.
+    fn shift_op(&self) -> Option<BinaryOp> {
+        let [first, second] = self.tokens.get(self.current..self.current + 2)? else {
+            return None;
+        };
+        if second.loc.offset != first.loc.offset + 1 {
+            return None;
+        }
+        match (&first.kind, &second.kind) {
+            (TokenKind::LessThan, TokenKind::LessThan) => Some(BinaryOp::Shl),
+            (TokenKind::GreaterThan, TokenKind::GreaterThan) => Some(BinaryOp::Shr),
+            _ => None,
+        }
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        let start = self.loc();
+        let op = match self.peek() {
+            Some(TokenKind::Minus) => UnaryOp::Neg,
+            Some(TokenKind::Bang) => UnaryOp::Not,
+            _ => return self.postfix(),
+        };
+        self.advance();
+        let expr = self.unary()?;
+        Ok(Expr {
+            kind: ExprKind::Unary {
+                op,
+                expr: Box::new(expr),
+            },
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn postfix(&mut self) -> ParseResult<Expr> {
+        let start = self.loc();
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(&TokenKind::LeftParen) {
+                let args = self.call_args()?;
+                expr = Expr {
+                    kind: ExprKind::Call {
+                        callee: Box::new(expr),
+                        args,
+                    },
+                    loc: self.loc_from(&start),
+                };
+            } else if self.eat(&TokenKind::MinusGreaterThan) {
+                let name = self.ident()?;
+                if self.eat(&TokenKind::LeftParen) {
+                    let args = self.call_args()?;
+                    expr = Expr {
+                        kind: ExprKind::MethodCall {
+                            receiver: Box::new(expr),
+                            method: name,
+                            args,
+                        },
+                        loc: self.loc_from(&start),
+                    };
+                } else {
+                    expr = Expr {
+                        kind: ExprKind::Field {
+                            base: Box::new(expr),
+                            field: name,
+                        },
+                        loc: self.loc_from(&start),
+                    };
+                }
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parse the arguments after `(` up to `)`, followed by an optional trailing closure.
+    fn call_args(&mut self) -> ParseResult<Vec<Expr>> {
+        let old = std::mem::replace(&mut self.no_struct, false);
+        let mut args = self.comma_separated(&TokenKind::RightParen, |p| p.expr())?;
+        self.no_struct = old;
+        if !self.no_struct && self.is_closure_start() {
+            let start = self.loc();
+            let closure = self.closure()?;
+            args.push(Expr {
+                kind: ExprKind::Closure(closure),
+                loc: self.loc_from(&start),
+            });
+        }
+        Ok(args)
+    }
+
+    /// Check whether the tokens ahead look like `{ (params) =` or `{ (params) =>`.
+    fn is_closure_start(&self) -> bool {
+        if !self.check(&TokenKind::LeftBrace) || self.peek_nth(1) != Some(&TokenKind::LeftParen) {
+            return false;
+        }
+        let mut depth = 0;
+        let mut i = 1;
+        while let Some(kind) = self.peek_nth(i) {
+            match kind {
+                TokenKind::LeftParen => depth += 1,
+                TokenKind::RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return matches!(
+                            self.peek_nth(i + 1),
+                            Some(TokenKind::Eq) | Some(TokenKind::EqGreaterThan)
+                        );
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        false
+    }
+
+    fn closure(&mut self) -> ParseResult<Closure> {
+        let start = self.loc();
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        self.expect(&TokenKind::LeftParen, "`(`")?;
+        let params = self.comma_separated(&TokenKind::RightParen, |p| {
+            let pattern = p.pattern()?;
+            let ty = if p.eat(&TokenKind::Colon) {
+                Some(p.ty()?)
+            } else {
+                None
+            };
+            Ok(ClosureParam { pattern, ty })
+        })?;
+
+        let body = if self.eat(&TokenKind::Eq) {
+            let expr = self.expr()?;
+            self.expect(&TokenKind::RightBrace, "`}`")?;
+            ClosureBody::Expr(Box::new(expr))
+        } else {
+            self.expect(&TokenKind::EqGreaterThan, "`=`, `=>`")?;
+            let mut stmts = Vec::new();
+            while !self.eat(&TokenKind::RightBrace) {
+                if self.is_end() {
+                    return Err(self.error(vec!["`}`"]));
+                }
+                stmts.push(self.stmt()?);
+            }
+            ClosureBody::Block(Block {
+                stmts,
+                loc: self.loc_from(&start),
+            })
+        };
+        Ok(Closure { params, body })
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let start = self.loc();
+        if let Some(literal) = self.literal() {
+            return Ok(Expr {
+                kind: ExprKind::Literal(literal),
+                loc: start,
+            });
+        }
+
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::LilSelf)) => {
+                self.advance();
+                ExprKind::SelfValue
+            }
+            Some(TokenKind::LeftParen) => {
+                self.advance();
+                let old = std::mem::replace(&mut self.no_struct, false);
+                let (mut exprs, trailing_comma) =
+                    self.comma_separated_with_trailing(&TokenKind::RightParen, |p| p.expr())?;
+                self.no_struct = old;
+                if exprs.len() == 1 && !trailing_comma {
+                    let mut expr = exprs.remove(0);
+                    expr.loc = self.loc_from(&start);
+                    return Ok(expr);
+                }
+                ExprKind::Tuple(exprs)
+            }
+            Some(TokenKind::LeftBracket) => {
+                self.advance();
+                let old = std::mem::replace(&mut self.no_struct, false);
+                let exprs = self.comma_separated(&TokenKind::RightBracket, |p| p.expr())?;
+                self.no_struct = old;
+                ExprKind::Array(exprs)
+            }
+            Some(TokenKind::Ident(_))
+            | Some(TokenKind::Keyword(Keyword::BigSelf))
+            | Some(TokenKind::Keyword(Keyword::Int))
+            | Some(TokenKind::Keyword(Keyword::Float))
+            | Some(TokenKind::Keyword(Keyword::Str)) => {
+                let path = self.expr_path()?;
+                if !self.no_struct && self.is_struct_lit_start() {
+                    self.advance();
+                    let fields = self.comma_separated(&TokenKind::RightBrace, |p| {
+                        let name = p.ident()?;
+                        p.expect(&TokenKind::EqGreaterThan, "`=>`")?;
+                        let value = p.expr()?;
+                        Ok(FieldInit { name, value })
+                    })?;
+                    ExprKind::StructLit { path, fields }
+                } else {
+                    ExprKind::Path(path)
+                }
+            }
+            _ => return Err(self.error(vec!["expression"])),
+        };
+        Ok(Expr {
+            kind,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Check whether the tokens ahead look like `{ }` or `{ name =>`.
+    fn is_struct_lit_start(&self) -> bool {
+        self.check(&TokenKind::LeftBrace)
+            && match self.peek_nth(1) {
+                Some(TokenKind::RightBrace) => true,
+                Some(TokenKind::Ident(_)) => self.peek_nth(2) == Some(&TokenKind::EqGreaterThan),
+                _ => false,
+            }
+    }
+
+    /// Parse a path in an expression position. Generic arguments such as in `Array::init<str>(...)`
+    /// are only accepted if they are followed by `(` or `::`, otherwise `<` is a comparison.
+    fn expr_path(&mut self) -> ParseResult<Path> {
+        let start = self.loc();
+        let mut segments = Vec::new();
+        loop {
+            let ident = self.path_ident()?;
+            let generics = self.speculative_generic_args();
+            segments.push(PathSegment { ident, generics });
+            if !self.eat(&TokenKind::DoubleColon) {
+                break;
+            }
+        }
+        Ok(Path {
+            segments,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    fn speculative_generic_args(&mut self) -> Vec<Type> {
+        if !self.check(&TokenKind::LessThan) {
+            return Vec::new();
+        }
+        let saved = self.current;
+        self.advance();
+        match self.comma_separated(&TokenKind::GreaterThan, |p| p.ty()) {
+            Ok(types)
+                if self.check(&TokenKind::LeftParen) || self.check(&TokenKind::DoubleColon) =>
+            {
+                types
+            }
+            _ => {
+                self.current = saved;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Parse a literal token if there's one.
+    fn literal(&mut self) -> Option<Literal> {
+        let literal = match self.peek()? {
+            TokenKind::Int(n) => Literal::Int(*n),
+            TokenKind::Float(n) => Literal::Float(*n),
+            TokenKind::Str(s) => Literal::Str(s.clone()),
+            TokenKind::Char(c) => Literal::Char(*c),
+            TokenKind::Keyword(Keyword::True) => Literal::Bool(true),
+            TokenKind::Keyword(Keyword::False) => Literal::Bool(false),
+            _ => return None,
+        };
+        self.advance();
+        Some(literal)
+    }
+
+    // ---- Patterns ----
+
+    /// Parse a pattern, including or-patterns.
+    pub fn pattern(&mut self) -> ParseResult<Pattern> {
+        let start = self.loc();
+        let first = self.single_pattern()?;
+        if !self.check(&TokenKind::Vertical) {
+            return Ok(first);
+        }
+        let mut alternatives = vec![first];
+        while self.eat(&TokenKind::Vertical) {
+            alternatives.push(self.single_pattern()?);
+        }
+        Ok(Pattern {
+            kind: PatternKind::Or(alternatives),
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Parse a pattern that isn't an or-pattern.
+    fn single_pattern(&mut self) -> ParseResult<Pattern> {
+        let start = self.loc();
+
+        if self.check(&TokenKind::DoubleDot) {
+            return Err(ParseError::new(ParseErrorType::MisplacedRest, start));
+        }
+
+        if let Some(literal) = self.pattern_literal()? {
+            let kind = if self.check(&TokenKind::DoubleDot) || self.check(&TokenKind::DoubleDotEq) {
+                let inclusive = self.check(&TokenKind::DoubleDotEq);
+                self.advance();
+                let Some(end) = self.pattern_literal()? else {
+                    return Err(self.error(vec!["literal"]));
+                };
+                PatternKind::Range {
+                    start: literal,
+                    end,
+                    inclusive,
+                }
+            } else {
+                PatternKind::Literal(literal)
+            };
+            return Ok(Pattern {
+                kind,
+                loc: self.loc_from(&start),
+            });
+        }
+
+        let kind = match self.peek() {
+            Some(TokenKind::LeftParen) => {
+                self.advance();
+                let (mut patterns, trailing_comma) = self
+                    .comma_separated_with_trailing(&TokenKind::RightParen, |p| p.list_pattern())?;
+                if patterns.len() == 1 && !trailing_comma {
+                    let mut pattern = patterns.remove(0);
+                    if pattern.kind == PatternKind::Rest {
+                        return Err(ParseError::new(ParseErrorType::MisplacedRest, pattern.loc));
+                    }
+                    pattern.loc = self.loc_from(&start);
+                    return Ok(pattern);
+                }
+                PatternKind::Tuple(patterns)
+            }
+            Some(TokenKind::Ident(_)) | Some(TokenKind::Keyword(Keyword::BigSelf)) => {
+                let path = self.type_path()?;
+                let is_name = path.segments.len() == 1
+                    && path.segments[0].generics.is_empty()
+                    && path.segments[0].ident.name != "Self";
+
+                if self.eat(&TokenKind::LeftParen) {
+                    let fields =
+                        self.comma_separated(&TokenKind::RightParen, |p| p.list_pattern())?;
+                    PatternKind::Variant {
+                        path,
+                        fields: Some(fields),
+                    }
+                } else if self.eat(&TokenKind::LeftBrace) {
+                    self.struct_pattern(path)?
+                } else if is_name && path.segments[0].ident.name == "_" {
+                    PatternKind::Wildcard
+                } else if is_name && self.eat(&TokenKind::At) {
+                    let sub = self.single_pattern()?;
+                    PatternKind::Binding {
+                        name: path.segments.into_iter().next().unwrap().ident,
+                        sub: Some(Box::new(sub)),
+                        mutable: false,
+                    }
+                } else if is_name {
+                    PatternKind::Binding {
+                        name: path.segments.into_iter().next().unwrap().ident,
+                        sub: None,
+                        mutable: false,
+                    }
+                } else {
+                    PatternKind::Variant { path, fields: None }
+                }
+            }
+            Some(TokenKind::Keyword(Keyword::Mut)) => {
+                self.advance();
+                let name = self.ident()?;
+                let sub = if self.eat(&TokenKind::At) {
+                    Some(Box::new(self.single_pattern()?))
+                } else {
+                    None
+                };
+                PatternKind::Binding {
+                    name,
+                    sub,
+                    mutable: true,
+                }
+            }
+            _ => return Err(self.error(vec!["pattern"])),
+        };
+
+        if self.check(&TokenKind::At) {
+            return Err(ParseError::new(ParseErrorType::InvalidBinding, start));
+        }
+        Ok(Pattern {
+            kind,
+            loc: self.loc_from(&start),
+        })
+    }
+
+    /// Parse a pattern inside a tuple or variant pattern, where `..` is allowed.
+    fn list_pattern(&mut self) -> ParseResult<Pattern> {
+        let start = self.loc();
+        if self.eat(&TokenKind::DoubleDot) {
+            return Ok(Pattern {
+                kind: PatternKind::Rest,
+                loc: start,
+            });
+        }
+        self.pattern()
+    }
+
+    /// Parse a literal in a pattern, where numbers may be negative.
+    fn pattern_literal(&mut self) -> ParseResult<Option<Literal>> {
+        if !self.eat(&TokenKind::Minus) {
+            return Ok(self.literal());
+        }
+        let literal = match self.peek() {
+            Some(TokenKind::Int(n)) => Literal::Int(-*n),
+            Some(TokenKind::Float(n)) => Literal::Float(-*n),
+            _ => return Err(self.error(vec!["number"])),
+        };
+        self.advance();
+        Ok(Some(literal))
+    }
+
+    /// Parse the fields of a struct pattern after `{`.
+    fn struct_pattern(&mut self, path: Path) -> ParseResult<PatternKind> {
+        let mut fields = Vec::new();
+        let mut rest = false;
+        while !self.eat(&TokenKind::RightBrace) {
+            if self.eat(&TokenKind::DoubleDot) {
+                rest = true;
+                self.expect(&TokenKind::RightBrace, "`}`")?;
+                break;
+            }
+            let start = self.loc();
+            let mutable = self.eat(&TokenKind::Keyword(Keyword::Mut));
+            let name = self.ident()?;
+            let pattern = if !mutable && self.eat(&TokenKind::EqGreaterThan) {
+                self.pattern()?
+            } else {
+                Pattern {
+                    kind: PatternKind::Binding {
+                        name: name.clone(),
+                        sub: None,
+                        mutable,
+                    },
+                    loc: self.loc_from(&start),
+                }
+            };
+            fields.push(FieldPattern { name, pattern });
+            if !self.eat(&TokenKind::Comma) {
+                self.expect(&TokenKind::RightBrace, "`,`, `}`")?;
+                break;
+            }
+        }
+        Ok(PatternKind::Struct { path, fields, rest })
+    }
+
+    // ---- Helpers ----
+
+    /// Parse `item, item, ...` up to and including `end`, allowing a trailing comma.
+    fn comma_separated<T>(
+        &mut self,
+        end: &TokenKind,
+        item: impl FnMut(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        Ok(self.comma_separated_with_trailing(end, item)?.0)
+    }
+
+    /// Same as `comma_separated`, but also return whether there was a trailing comma.
+    fn comma_separated_with_trailing<T>(
+        &mut self,
+        end: &TokenKind,
+        mut item: impl FnMut(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<(Vec<T>, bool)> {
+        let mut items = Vec::new();
+        let mut trailing_comma = false;
+        while !self.eat(end) {
+            items.push(item(self)?);
+            trailing_comma = self.eat(&TokenKind::Comma);
+            if !trailing_comma {
+                self.expect(end, token_description(end))?;
+                break;
+            }
+        }
+        Ok((items, trailing_comma))
+    }
+
+    fn ident(&mut self) -> ParseResult<Ident> {
+        match self.peek() {
+            Some(TokenKind::Ident(name)) => {
+                let name = name.clone();
+                let loc = self.advance().loc;
+                Ok(Ident { name, loc })
+            }
+            _ => Err(self.error(vec!["identifier"])),
+        }
+    }
+
+    #[inline]
+    fn is_end(&self) -> bool {
+        self.current >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&TokenKind> {
+        self.tokens.get(self.current + n).map(|t| &t.kind)
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.peek() == Some(kind)
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.current].clone();
+        self.current += 1;
+        token
+    }
+
+    /// Consume the current token if it is `kind`.
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind, description: &'static str) -> ParseResult<Token> {
+        if self.check(kind) {
+            Ok(self.advance())
+        } else {
+            Err(self.error(vec![description]))
+        }
+    }
+
+    /// Create an error for the current token.
+    fn error(&self, expected: Vec<&'static str>) -> ParseError {
+        if self.is_end() {
+            ParseError::new(ParseErrorType::UnexpectedEnd(expected), self.loc())
+        } else {
+            ParseError::new(ParseErrorType::UnexpectedToken(expected), self.loc())
+        }
+    }
+
+    /// The location of the current token, or of the last one at the end.
+    fn loc(&self) -> SourceCodeLocation {
+        self.tokens
+            .get(self.current)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.loc.clone())
+            .unwrap_or_else(|| SourceCodeLocation::new(1, 1, 0, 0, None))
+    }
+
+    /// A location spanning from `start` to the end of the previous token.
+    fn loc_from(&self, start: &SourceCodeLocation) -> SourceCodeLocation {
+        match self.current.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(prev) => start.to(&prev.loc),
+            None => start.clone(),
+        }
+    }
+}
+
+/// Get the binary operator and its precedence for a token.
+fn binary_op(kind: &TokenKind) -> Option<(BinaryOp, u8)> {
+    let op = match kind {
+        TokenKind::DoubleVertical => BinaryOp::Or,
+        TokenKind::DoubleAmp => BinaryOp::And,
+        TokenKind::DoubleEq => BinaryOp::Eq,
+        TokenKind::BangEq => BinaryOp::NotEq,
+        TokenKind::LessThan => BinaryOp::Less,
+        TokenKind::LessThanOrEq => BinaryOp::LessEq,
+        TokenKind::GreaterThan => BinaryOp::Greater,
+        TokenKind::GreaterThanOrEq => BinaryOp::GreaterEq,
+        TokenKind::Plus => BinaryOp::Add,
+        TokenKind::Minus => BinaryOp::Sub,
+        TokenKind::Mul => BinaryOp::Mul,
+        TokenKind::Div => BinaryOp::Div,
+        TokenKind::Rem => BinaryOp::Rem,
+        _ => return None,
+    };
+    Some((op, op.precedence()))
+}
+
+/// Turn a generic parameter into the type that refers to it.
+fn generic_param_as_type(param: &GenericParam) -> Type {
+    Type {
+        kind: TypeKind::Path(Path {
+            segments: vec![PathSegment {
+                ident: param.name.clone(),
+                generics: Vec::new(),
+            }],
+            loc: param.name.loc.clone(),
+        }),
+        loc: param.name.loc.clone(),
+    }
+}
+
+/// A human-readable description of the closing delimiters.
+fn token_description(kind: &TokenKind) -> &'static str {
+    match kind {
+        TokenKind::RightParen => "`,`, `)`",
+        TokenKind::RightBrace => "`,`, `}`",
+        TokenKind::RightBracket => "`,`, `]`",
+        TokenKind::GreaterThan => "`,`, `>`",
+        _ => "`,`",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{Lexer, Tokenize};
+
+    fn parse(src: &str) -> ParseResult<Module> {
+        let tokens = Lexer::new(None, src).tokenize().unwrap();
+        Parser::new(tokens).parse()
+    }
+
+    fn first_fn(module: &Module) -> &FnDecl {
+        match &module.items[0].kind {
+            ItemKind::Fn(f) => f,
+            kind => panic!("expected a function, found {kind:?}"),
+        }
+    }
+
+    #[test]
+    fn fixtures() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let src = std::fs::read_to_string(&path).unwrap();
+            if let Err(e) = parse(&src) {
+                panic!("{}: {:?}", path.display(), e);
+            }
+        }
+    }
+
+    #[test]
+    fn match_patterns() {
+        let module = parse(
+            "def f() void { match x { A::B(0 | 1, ..) if y => return, n @ (_, 'c') => {} } }",
+        )
+        .unwrap();
+        let StmtKind::Match { arms, .. } = &first_fn(&module).body.as_ref().unwrap().stmts[0].kind
+        else {
+            panic!("expected a match");
+        };
+        assert_eq!(arms.len(), 2);
+
+        let PatternKind::Variant {
+            path,
+            fields: Some(fields),
+        } = &arms[0].pattern.kind
+        else {
+            panic!("expected a variant pattern");
+        };
+        assert_eq!(path.segments.len(), 2);
+        assert!(matches!(&fields[0].kind, PatternKind::Or(alts) if alts.len() == 2));
+        assert_eq!(fields[1].kind, PatternKind::Rest);
+        assert!(arms[0].guard.is_some());
+        assert_eq!(arms[0].body.kind, StmtKind::Return(None));
+
+        let PatternKind::Binding {
+            name,
+            sub: Some(sub),
+            ..
+        } = &arms[1].pattern.kind
+        else {
+            panic!("expected a binding pattern");
+        };
+        assert_eq!(name.name, "n");
+        assert!(matches!(&sub.kind, PatternKind::Tuple(p) if p[0].kind == PatternKind::Wildcard));
+    }
+
+    #[test]
+    fn consts_and_shifts() {
+        let module = parse(
+            "const MAX: int = 1 << 2 + 1 > 3 >> 1;\nenum E { A = MAX, B }\n\
+             def f(x: Option<Option<int>>) void { return; }",
+        )
+        .unwrap();
+        let ItemKind::Const(decl) = &module.items[0].kind else {
+            panic!("expected a constant");
+        };
+        assert_eq!(decl.name.name, "MAX");
+        let ExprKind::Binary { op, lhs, rhs } = &decl.value.kind else {
+            panic!("expected a comparison");
+        };
+        assert_eq!(*op, BinaryOp::Greater);
+        assert!(matches!(
+            lhs.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Shl,
+                ..
+            }
+        ));
+        assert!(matches!(
+            rhs.kind,
+            ExprKind::Binary {
+                op: BinaryOp::Shr,
+                ..
+            }
+        ));
+
+        let ItemKind::Enum(decl) = &module.items[1].kind else {
+            panic!("expected an enum");
+        };
+        assert!(decl.variants[0].discriminant.is_some());
+        assert!(decl.variants[1].discriminant.is_none());
+    }
+
+    #[test]
+    fn destructuring_let_and_params() {
+        let module =
+            parse("def f((a, b): (int, int), P { mut x, y => -1 }: P) void { let (c, ..) = a; }")
+                .unwrap();
+        let f = first_fn(&module);
+        assert!(matches!(&f.params[0].pattern.kind, PatternKind::Tuple(p) if p.len() == 2));
+
+        let PatternKind::Struct { fields, rest, .. } = &f.params[1].pattern.kind else {
+            panic!("expected a struct pattern");
+        };
+        assert!(!rest);
+        assert!(
+            matches!(&fields[0].pattern.kind, PatternKind::Binding { name, mutable: true, .. } if name.name == "x")
+        );
+        assert_eq!(
+            fields[1].pattern.kind,
+            PatternKind::Literal(Literal::Int(-1))
+        );
+
+        let StmtKind::Let { pattern, .. } = &f.body.as_ref().unwrap().stmts[0].kind else {
+            panic!("expected a let statement");
+        };
+        assert!(matches!(&pattern.kind, PatternKind::Tuple(p) if p[1].kind == PatternKind::Rest));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(matches!(
+            parse("def f() void { let .. = x; }").map_err(|e| e.r#type),
+            Err(ParseErrorType::MisplacedRest)
+        ));
+        assert!(matches!(
+            parse("def f() void { let A::B @ c = x; }").map_err(|e| e.r#type),
+            Err(ParseErrorType::InvalidBinding)
+        ));
+    }
+
+    #[test]
+    fn trailing_closures_and_conditions() {
+        let module = parse(
+            "def f() void { if g(x) { (a) = a; } Array::map(xs) { (a) = a }; while a < b { } }",
+        )
+        .unwrap();
+        let stmts = &first_fn(&module).body.as_ref().unwrap().stmts;
+        let StmtKind::If { cond, .. } = &stmts[0].kind else {
+            panic!("expected an if statement");
+        };
+        assert!(matches!(&cond.kind, ExprKind::Call { args, .. } if args.len() == 1));
+        let StmtKind::Expr(call) = &stmts[1].kind else {
+            panic!("expected an expression statement");
+        };
+        assert!(matches!(
+            &call.kind,
+            ExprKind::Call { args, .. } if matches!(args[1].kind, ExprKind::Closure(_))
+        ));
+    }
+
+    #[test]
+    fn fn_annotations() {
+        let module = parse("@tailcall def f(n: int) int { return f(n); }").unwrap();
+        let tailcall = first_fn(&module).tailcall.as_ref().unwrap();
+        assert_eq!((tailcall.column, tailcall.length), (1, 9));
+        assert!(matches!(
+            parse("@inline def f() void {}").map_err(|e| e.r#type),
+            Err(ParseErrorType::UnknownName("annotation", name)) if name == "inline"
+        ));
+
+        let module = parse("@native def length<T>(array: []T) int;").unwrap();
+        let native = first_fn(&module);
+        assert!(native.native.is_some() && native.body.is_none());
+        assert!(parse("@native def f() void {}").is_err());
+    }
+
+    #[test]
+    fn suggests_misspelled_statement_keywords() {
+        for (src, help) in [
+            ("def f() void {\n    retrun 1;\n}\n", Some("return")),
+            ("def f() void {\n    whiel x < 2 {}\n}\n", Some("while")),
+            ("def f() void {\n    value 1;\n}\n", None),
+        ] {
+            let error = parse(src).unwrap_err();
+            assert_eq!(
+                error.help,
+                help.map(|keyword| format!("did you mean `{keyword}`?"))
+            );
+        }
+        assert!(parse("def f() void {\n    retrun;\n}\n").is_ok());
+    }
+}
@@ -0,0 +1,813 @@
+use crate::parse::ast::*;
+
+/// Turn an AST back into paca source code.
+///
+/// The output is meant to be parsed again rather than to look pretty, so comments and the original
+/// layout are lost. Parentheses are only inserted where the structure of the tree requires them.
+pub fn print_module(module: &Module) -> String {
+    let mut printer = Printer::default();
+    printer.module(module);
+    printer.out
+}
+
+/// Turn a single expression into paca source code.
+pub fn print_expr(expr: &Expr) -> String {
+    let mut printer = Printer::default();
+    printer.expr(expr);
+    printer.out
+}
+
+/// Turn a single pattern into paca source code.
+pub fn print_pattern(pattern: &Pattern) -> String {
+    let mut printer = Printer::default();
+    printer.pattern(pattern);
+    printer.out
+}
+
+/// Turn a single type annotation into paca source code.
+pub fn print_type(ty: &Type) -> String {
+    let mut printer = Printer::default();
+    printer.ty(ty);
+    printer.out
+}
+
+/// The source representation of a literal.
+pub fn literal_str(literal: &Literal) -> String {
+    match literal {
+        Literal::Int(n) => n.to_string(),
+        Literal::Float(n) => {
+            let s = n.to_string();
+            if s.contains('.') {
+                s
+            } else {
+                s + ".0"
+            }
+        }
+        Literal::Str(s) => format!("\"{}\"", escape(s, '"')),
+        Literal::Char(c) => format!("'{}'", escape(&c.to_string(), '\'')),
+        Literal::Bool(b) => b.to_string(),
+    }
+}
+
+fn escape(s: &str, quote: char) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\0' => out.push_str("\\0"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    indent: usize,
+    /// Whether a `{` would start a body, so struct literals and trailing closures need parentheses.
+    no_struct: bool,
+}
+
+impl Printer {
+    fn write(&mut self, s: &str) {
+        self.out.push_str(s);
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    /// Write `items` separated by `, ` using `f`.
+    fn list<T>(&mut self, items: &[T], mut f: impl FnMut(&mut Self, &T)) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            f(self, item);
+        }
+    }
+
+    // ---- Items ----
+
+    fn module(&mut self, module: &Module) {
+        let mut previous: Option<&ItemKind> = None;
+        for item in &module.items {
+            if let Some(previous) = previous {
+                let both_imports = matches!(previous, ItemKind::Import(_))
+                    && matches!(item.kind, ItemKind::Import(_));
+                if !both_imports {
+                    self.write("\n");
+                }
+            }
+            self.item(item);
+            self.write("\n");
+            previous = Some(&item.kind);
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Import(import) => {
+                self.write("import ");
+                self.idents(&import.path, "::");
+                if let Some(group) = &import.group {
+                    self.write("::(");
+                    for (i, name) in group.iter().enumerate() {
+                        if i > 0 {
+                            self.write(", ");
+                        }
+                        self.write(&name.name.name);
+                        self.alias(&name.alias);
+                    }
+                    self.write(")");
+                }
+                self.alias(&import.alias);
+                self.write(";");
+            }
+            ItemKind::Export(names) => {
+                self.write("export ");
+                self.idents(names, ", ");
+                self.write(";");
+            }
+            ItemKind::Struct(decl) => {
+                self.write("struct ");
+                self.write(&decl.name.name);
+                self.generic_params(&decl.generics);
+                self.write(" {");
+                self.indent += 1;
+                for field in &decl.fields {
+                    self.newline();
+                    if field.mutable {
+                        self.write("$");
+                    }
+                    self.write(&field.name.name);
+                    self.write(": ");
+                    self.ty(&field.ty);
+                    self.write(",");
+                }
+                self.indent -= 1;
+                self.newline();
+                self.write("}");
+            }
+            ItemKind::Enum(decl) => {
+                self.write("enum ");
+                self.write(&decl.name.name);
+                self.generic_params(&decl.generics);
+                self.write(" {");
+                self.indent += 1;
+                for variant in &decl.variants {
+                    self.newline();
+                    self.write(&variant.name.name);
+                    if !variant.fields.is_empty() {
+                        self.write("(");
+                        self.list(&variant.fields, |p, t| p.ty(t));
+                        self.write(")");
+                    }
+                    if let Some(discriminant) = &variant.discriminant {
+                        self.write(" = ");
+                        self.expr(discriminant);
+                    }
+                    self.write(",");
+                }
+                self.indent -= 1;
+                self.newline();
+                self.write("}");
+            }
+            ItemKind::Trait(decl) => {
+                self.write("trait ");
+                self.write(&decl.name.name);
+                self.generic_params(&decl.generics);
+                self.write(" {");
+                self.members(&decl.assoc_types, &decl.methods);
+                self.write("}");
+            }
+            ItemKind::Impl(decl) => {
+                self.write("impl ");
+                match &decl.trait_ref {
+                    Some(path) => self.path(path),
+                    None => self.write("methods"),
+                }
+                self.write(" for ");
+                self.write(&decl.target.segments[0].ident.name);
+                self.generic_params(&decl.generics);
+                self.write(" {");
+                self.members(&decl.assoc_types, &decl.methods);
+                self.write("}");
+            }
+            ItemKind::Fn(decl) => self.fn_decl(decl),
+            ItemKind::Const(decl) => {
+                self.write("const ");
+                self.write(&decl.name.name);
+                self.write(": ");
+                self.ty(&decl.ty);
+                self.write(" = ");
+                self.expr(&decl.value);
+                self.write(";");
+            }
+        }
+    }
+
+    /// The body of a trait or impl: the associated types, then the methods separated by blank lines.
+    fn members(&mut self, assoc_types: &[AssocType], methods: &[FnDecl]) {
+        self.indent += 1;
+        for assoc in assoc_types {
+            self.newline();
+            self.write("type ");
+            self.write(&assoc.name.name);
+            if let Some(ty) = &assoc.ty {
+                self.write(" = ");
+                self.ty(ty);
+            }
+            self.write(";");
+        }
+        for (i, method) in methods.iter().enumerate() {
+            if i > 0 || !assoc_types.is_empty() {
+                self.write("\n");
+            }
+            self.newline();
+            self.fn_decl(method);
+        }
+        self.indent -= 1;
+        self.newline();
+    }
+
+    fn idents(&mut self, idents: &[Ident], separator: &str) {
+        for (i, ident) in idents.iter().enumerate() {
+            if i > 0 {
+                self.write(separator);
+            }
+            self.write(&ident.name);
+        }
+    }
+
+    fn alias(&mut self, alias: &Option<Ident>) {
+        if let Some(alias) = alias {
+            self.write(" as ");
+            self.write(&alias.name);
+        }
+    }
+
+    fn generic_params(&mut self, generics: &[GenericParam]) {
+        if generics.is_empty() {
+            return;
+        }
+        self.write("<");
+        self.list(generics, |p, param| {
+            p.write(&param.name.name);
+            for (i, bound) in param.bounds.iter().enumerate() {
+                p.write(if i == 0 { ": " } else { " + " });
+                p.path(bound);
+            }
+        });
+        self.write(">");
+    }
+
+    fn fn_decl(&mut self, decl: &FnDecl) {
+        if decl.tailcall.is_some() {
+            self.write("@tailcall ");
+        } else if decl.native.is_some() {
+            self.write("@native ");
+        }
+        self.write("def ");
+        self.write(&decl.name.name);
+        self.generic_params(&decl.generics);
+        self.write("(");
+        if decl.self_param.is_some() {
+            self.write("self");
+            if !decl.params.is_empty() {
+                self.write(", ");
+            }
+        }
+        self.list(&decl.params, |p, param| {
+            if param.variadic {
+                p.write("*");
+            }
+            p.pattern(&param.pattern);
+            p.write(": ");
+            p.ty(&param.ty);
+        });
+        self.write(") ");
+        self.ty(&decl.ret);
+        match &decl.body {
+            Some(body) => {
+                self.write(" ");
+                self.block(body);
+            }
+            None => self.write(";"),
+        }
+    }
+
+    // ---- Types and paths ----
+
+    fn ty(&mut self, ty: &Type) {
+        match &ty.kind {
+            TypeKind::Path(path) => self.path(path),
+            TypeKind::Array(inner) => {
+                self.write("[]");
+                self.ty(inner);
+            }
+            TypeKind::Tuple(types) => {
+                self.write("(");
+                self.list(types, |p, t| p.ty(t));
+                if types.len() == 1 {
+                    self.write(",");
+                }
+                self.write(")");
+            }
+            TypeKind::Fn { params, ret } => {
+                self.write("def(");
+                self.list(params, |p, t| p.ty(t));
+                self.write(") ");
+                self.ty(ret);
+            }
+        }
+    }
+
+    fn path(&mut self, path: &Path) {
+        for (i, segment) in path.segments.iter().enumerate() {
+            if i > 0 {
+                self.write("::");
+            }
+            self.write(&segment.ident.name);
+            if !segment.generics.is_empty() {
+                self.write("<");
+                self.list(&segment.generics, |p, t| p.ty(t));
+                self.write(">");
+            }
+        }
+    }
+
+    // ---- Statements ----
+
+    fn block(&mut self, block: &Block) {
+        self.write("{");
+        let old = std::mem::replace(&mut self.no_struct, false);
+        self.stmts(&block.stmts);
+        self.no_struct = old;
+        self.write("}");
+    }
+
+    /// Write indented statements followed by a newline at the outer indentation.
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        self.indent += 1;
+        for stmt in stmts {
+            self.newline();
+            self.stmt(stmt);
+        }
+        self.indent -= 1;
+        if !stmts.is_empty() {
+            self.newline();
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { pattern, ty, value } => {
+                self.write("let ");
+                self.pattern(pattern);
+                if let Some(ty) = ty {
+                    self.write(": ");
+                    self.ty(ty);
+                }
+                if let Some(value) = value {
+                    self.write(" = ");
+                    self.expr(value);
+                }
+                self.write(";");
+            }
+            StmtKind::If { .. } => self.if_stmt(stmt),
+            StmtKind::While { cond, body } => {
+                self.write("while ");
+                self.cond_expr(cond);
+                self.write(" ");
+                self.block(body);
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                self.write("match ");
+                self.cond_expr(scrutinee);
+                self.write(" {");
+                self.indent += 1;
+                for arm in arms {
+                    self.newline();
+                    self.pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        self.write(" if ");
+                        self.expr(guard);
+                    }
+                    self.write(" => ");
+                    match &arm.body.kind {
+                        StmtKind::Block(block) => self.block(block),
+                        _ => {
+                            self.simple_stmt(&arm.body);
+                            self.write(",");
+                        }
+                    }
+                }
+                self.indent -= 1;
+                self.newline();
+                self.write("}");
+            }
+            StmtKind::Block(block) => self.block(block),
+            _ => {
+                self.simple_stmt(stmt);
+                self.write(";");
+            }
+        }
+    }
+
+    /// Write a statement that needs a terminator, without the terminator.
+    fn simple_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Assign { target, op, value } => {
+                self.expr(target);
+                self.write(" ");
+                if let Some(op) = op {
+                    self.write(op.symbol());
+                }
+                self.write("= ");
+                self.expr(value);
+            }
+            StmtKind::Expr(expr) => self.expr(expr),
+            StmtKind::Return(value) => {
+                self.write("return");
+                if let Some(value) = value {
+                    self.write(" ");
+                    self.expr(value);
+                }
+            }
+            StmtKind::Break => self.write("break"),
+            StmtKind::Continue => self.write("continue"),
+            // Compound statements can't appear without a block around them.
+            _ => {
+                self.write("{");
+                self.stmts(std::slice::from_ref(stmt));
+                self.write("}");
+            }
+        }
+    }
+
+    fn if_stmt(&mut self, stmt: &Stmt) {
+        let StmtKind::If {
+            cond,
+            then_block,
+            else_block,
+        } = &stmt.kind
+        else {
+            return;
+        };
+        self.write("if ");
+        self.cond_expr(cond);
+        self.write(" ");
+        self.block(then_block);
+        if let Some(else_block) = else_block {
+            self.write(" else ");
+            match else_block.stmts.as_slice() {
+                [nested @ Stmt {
+                    kind: StmtKind::If { .. },
+                    ..
+                }] => self.if_stmt(nested),
+                _ => self.block(else_block),
+            }
+        }
+    }
+
+    // ---- Expressions ----
+
+    fn cond_expr(&mut self, expr: &Expr) {
+        let old = std::mem::replace(&mut self.no_struct, true);
+        self.expr(expr);
+        self.no_struct = old;
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        self.expr_prec(expr, 0);
+    }
+
+    /// Write an expression, parenthesizing it if it binds looser than `min_prec`.
+    /// Unary operators use precedence 8 and postfix expressions 9.
+    fn expr_prec(&mut self, expr: &Expr, min_prec: u8) {
+        let prec = match &expr.kind {
+            ExprKind::Binary { op, .. } => op.precedence(),
+            ExprKind::Unary { .. } => 8,
+            ExprKind::Literal(Literal::Int(n)) if *n < 0 => 8,
+            ExprKind::Literal(Literal::Float(n)) if n.is_sign_negative() => 8,
+            _ => 9,
+        };
+        let needs_struct_parens = self.no_struct
+            && match &expr.kind {
+                ExprKind::StructLit { .. } => true,
+                ExprKind::Call { args, .. } | ExprKind::MethodCall { args, .. } => {
+                    matches!(
+                        args.last(),
+                        Some(Expr {
+                            kind: ExprKind::Closure(_),
+                            ..
+                        })
+                    )
+                }
+                _ => false,
+            };
+        if prec < min_prec || needs_struct_parens {
+            self.write("(");
+            let old = std::mem::replace(&mut self.no_struct, false);
+            self.expr_kind(expr);
+            self.no_struct = old;
+            self.write(")");
+        } else {
+            self.expr_kind(expr);
+        }
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.write(&literal_str(literal)),
+            ExprKind::Path(path) => self.path(path),
+            ExprKind::SelfValue => self.write("self"),
+            ExprKind::Tuple(exprs) => {
+                self.write("(");
+                let old = std::mem::replace(&mut self.no_struct, false);
+                self.list(exprs, |p, e| p.expr(e));
+                self.no_struct = old;
+                if exprs.len() == 1 {
+                    self.write(",");
+                }
+                self.write(")");
+            }
+            ExprKind::Array(exprs) => {
+                self.write("[");
+                let old = std::mem::replace(&mut self.no_struct, false);
+                self.list(exprs, |p, e| p.expr(e));
+                self.no_struct = old;
+                self.write("]");
+            }
+            ExprKind::Unary { op, expr } => {
+                self.write(match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "!",
+                });
+                self.expr_prec(expr, 8);
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let prec = op.precedence();
+                self.expr_prec(lhs, prec);
+                self.write(" ");
+                self.write(op.symbol());
+                self.write(" ");
+                self.expr_prec(rhs, prec + 1);
+            }
+            ExprKind::Call { callee, args } => {
+                self.expr_prec(callee, 9);
+                self.args(args);
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => {
+                self.expr_prec(receiver, 9);
+                self.write("->");
+                self.write(&method.name);
+                self.args(args);
+            }
+            ExprKind::Field { base, field } => {
+                self.expr_prec(base, 9);
+                self.write("->");
+                self.write(&field.name);
+            }
+            ExprKind::StructLit { path, fields } => {
+                self.path(path);
+                if fields.is_empty() {
+                    self.write(" {}");
+                    return;
+                }
+                self.write(" {");
+                self.indent += 1;
+                for field in fields {
+                    self.newline();
+                    self.write(&field.name.name);
+                    self.write(" => ");
+                    self.expr(&field.value);
+                    self.write(",");
+                }
+                self.indent -= 1;
+                self.newline();
+                self.write("}");
+            }
+            ExprKind::Closure(closure) => self.closure(closure),
+        }
+    }
+
+    /// Write call arguments, moving a closure in the last position after the parentheses.
+    fn args(&mut self, args: &[Expr]) {
+        let (args, closure) = match args.split_last() {
+            Some((
+                Expr {
+                    kind: ExprKind::Closure(closure),
+                    ..
+                },
+                rest,
+            )) => (rest, Some(closure)),
+            _ => (args, None),
+        };
+        self.write("(");
+        let old = std::mem::replace(&mut self.no_struct, false);
+        self.list(args, |p, e| p.expr(e));
+        self.no_struct = old;
+        self.write(")");
+        if let Some(closure) = closure {
+            self.write(" ");
+            self.closure(closure);
+        }
+    }
+
+    fn closure(&mut self, closure: &Closure) {
+        self.write("{ (");
+        self.list(&closure.params, |p, param| {
+            p.pattern(&param.pattern);
+            if let Some(ty) = &param.ty {
+                p.write(": ");
+                p.ty(ty);
+            }
+        });
+        self.write(")");
+        let old = std::mem::replace(&mut self.no_struct, false);
+        match &closure.body {
+            ClosureBody::Expr(expr) => {
+                self.write(" = ");
+                self.expr(expr);
+                self.write(" }");
+            }
+            ClosureBody::Block(block) => {
+                self.write(" =>");
+                self.stmts(&block.stmts);
+                if block.stmts.is_empty() {
+                    self.write(" ");
+                }
+                self.write("}");
+            }
+        }
+        self.no_struct = old;
+    }
+
+    // ---- Patterns ----
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match &pattern.kind {
+            PatternKind::Wildcard => self.write("_"),
+            PatternKind::Rest => self.write(".."),
+            PatternKind::Binding { name, sub, mutable } => {
+                if *mutable {
+                    self.write("mut ");
+                }
+                self.write(&name.name);
+                if let Some(sub) = sub {
+                    self.write(" @ ");
+                    self.nested_pattern(sub);
+                }
+            }
+            PatternKind::Literal(literal) => self.write(&literal_str(literal)),
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.write(&literal_str(start));
+                self.write(if *inclusive { "..=" } else { ".." });
+                self.write(&literal_str(end));
+            }
+            PatternKind::Tuple(patterns) => {
+                self.write("(");
+                self.list(patterns, |p, pat| p.pattern(pat));
+                if patterns.len() == 1 {
+                    self.write(",");
+                }
+                self.write(")");
+            }
+            PatternKind::Variant { path, fields } => {
+                self.path(path);
+                if let Some(fields) = fields {
+                    self.write("(");
+                    self.list(fields, |p, pat| p.pattern(pat));
+                    self.write(")");
+                }
+            }
+            PatternKind::Struct { path, fields, rest } => {
+                self.path(path);
+                if fields.is_empty() && !rest {
+                    self.write(" {}");
+                    return;
+                }
+                self.write(" { ");
+                self.list(fields, |p, field| {
+                    let shorthand = matches!(
+                        &field.pattern.kind,
+                        PatternKind::Binding { name, sub: None, .. } if name.name == field.name.name
+                    );
+                    if shorthand {
+                        p.pattern(&field.pattern);
+                    } else {
+                        p.write(&field.name.name);
+                        p.write(" => ");
+                        p.pattern(&field.pattern);
+                    }
+                });
+                if *rest {
+                    self.write(if fields.is_empty() { ".." } else { ", .." });
+                }
+                self.write(" }");
+            }
+            PatternKind::Or(alternatives) => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        self.write(" | ");
+                    }
+                    self.nested_pattern(alternative);
+                }
+            }
+        }
+    }
+
+    /// Write a pattern that appears inside an or-pattern or after `@`, where or-patterns need parentheses.
+    fn nested_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Or(_) = pattern.kind {
+            self.write("(");
+            self.pattern(pattern);
+            self.write(")");
+        } else {
+            self.pattern(pattern);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::parse::parse_source;
+    use serde_json::Value;
+
+    /// Serialize the AST with every location replaced by `null`, so that two trees can be compared structurally.
+    pub(crate) fn without_locations(module: &Module) -> Value {
+        fn strip(value: &mut Value) {
+            match value {
+                Value::Object(map) if map.contains_key("line") && map.contains_key("offset") => {
+                    *value = Value::Null
+                }
+                Value::Object(map) => map.values_mut().for_each(strip),
+                Value::Array(items) => items.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let mut value = serde_json::to_value(module).unwrap();
+        strip(&mut value);
+        value
+    }
+
+    #[test]
+    fn fixtures_round_trip() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let src = std::fs::read_to_string(&path).unwrap();
+            let module = parse_source(None, &src).unwrap();
+
+            let printed = print_module(&module);
+            let reparsed = parse_source(None, &printed)
+                .unwrap_or_else(|e| panic!("{}: {:?}\n{printed}", path.display(), e));
+
+            assert_eq!(
+                without_locations(&module),
+                without_locations(&reparsed),
+                "{} doesn't round-trip:\n{printed}",
+                path.display()
+            );
+            assert_eq!(printed, print_module(&reparsed));
+        }
+    }
+
+    #[test]
+    fn parenthesizes_where_needed() {
+        let src = "def f() void { x = (a + b) * -(c - d); if (P { x => 1 })->x == (f(y) { (z) = z }) { } \
+                   match v { a @ (A | B) | (C,) => {} } }";
+        let module = parse_source(None, src).unwrap();
+        let printed = print_module(&module);
+        assert!(printed.contains("x = (a + b) * -(c - d);"), "{printed}");
+        assert!(printed.contains("if (P {"), "{printed}");
+        assert!(printed.contains("== (f(y) { (z) = z }) {}"), "{printed}");
+        assert!(printed.contains("a @ (A | B) | (C,) => {}"), "{printed}");
+        assert_eq!(
+            without_locations(&module),
+            without_locations(&parse_source(None, &printed).unwrap())
+        );
+    }
+}
@@ -0,0 +1,569 @@
+//! Traversal of the AST.
+//!
+//! `Visitor` walks a tree by shared reference and `VisitorMut` by mutable reference. Every `visit_*`
+//! method defaults to the matching `walk_*` function, which visits the children of the node, so an
+//! analysis only overrides the nodes it cares about and calls `walk_*` to keep recursing.
+//!
+//! The walkers destructure every node and match every variant without wildcards, so adding a node
+//! type, a variant or a field doesn't compile until the traversal knows about it.
+
+use crate::parse::ast::*;
+
+macro_rules! make_visitor {
+    ($(#[$attr:meta])* $visitor:ident, $walk:ident, $($mutability:ident)?) => {
+        $(#[$attr])*
+        pub trait $visitor: Sized {
+            fn visit_module(&mut self, module: &$($mutability)? Module) {
+                $walk::walk_module(self, module)
+            }
+            fn visit_item(&mut self, item: &$($mutability)? Item) {
+                $walk::walk_item(self, item)
+            }
+            fn visit_import(&mut self, import: &$($mutability)? Import) {
+                $walk::walk_import(self, import)
+            }
+            fn visit_struct_decl(&mut self, decl: &$($mutability)? StructDecl) {
+                $walk::walk_struct_decl(self, decl)
+            }
+            fn visit_field_decl(&mut self, field: &$($mutability)? FieldDecl) {
+                $walk::walk_field_decl(self, field)
+            }
+            fn visit_enum_decl(&mut self, decl: &$($mutability)? EnumDecl) {
+                $walk::walk_enum_decl(self, decl)
+            }
+            fn visit_variant_decl(&mut self, variant: &$($mutability)? VariantDecl) {
+                $walk::walk_variant_decl(self, variant)
+            }
+            fn visit_trait_decl(&mut self, decl: &$($mutability)? TraitDecl) {
+                $walk::walk_trait_decl(self, decl)
+            }
+            fn visit_assoc_type(&mut self, assoc: &$($mutability)? AssocType) {
+                $walk::walk_assoc_type(self, assoc)
+            }
+            fn visit_impl_decl(&mut self, decl: &$($mutability)? ImplDecl) {
+                $walk::walk_impl_decl(self, decl)
+            }
+            fn visit_fn_decl(&mut self, decl: &$($mutability)? FnDecl) {
+                $walk::walk_fn_decl(self, decl)
+            }
+            fn visit_const_decl(&mut self, decl: &$($mutability)? ConstDecl) {
+                $walk::walk_const_decl(self, decl)
+            }
+            fn visit_generic_param(&mut self, param: &$($mutability)? GenericParam) {
+                $walk::walk_generic_param(self, param)
+            }
+            fn visit_param(&mut self, param: &$($mutability)? Param) {
+                $walk::walk_param(self, param)
+            }
+            fn visit_type(&mut self, ty: &$($mutability)? Type) {
+                $walk::walk_type(self, ty)
+            }
+            fn visit_path(&mut self, path: &$($mutability)? Path) {
+                $walk::walk_path(self, path)
+            }
+            fn visit_ident(&mut self, _ident: &$($mutability)? Ident) {}
+            fn visit_block(&mut self, block: &$($mutability)? Block) {
+                $walk::walk_block(self, block)
+            }
+            fn visit_stmt(&mut self, stmt: &$($mutability)? Stmt) {
+                $walk::walk_stmt(self, stmt)
+            }
+            fn visit_match_arm(&mut self, arm: &$($mutability)? MatchArm) {
+                $walk::walk_match_arm(self, arm)
+            }
+            fn visit_expr(&mut self, expr: &$($mutability)? Expr) {
+                $walk::walk_expr(self, expr)
+            }
+            fn visit_closure(&mut self, closure: &$($mutability)? Closure) {
+                $walk::walk_closure(self, closure)
+            }
+            fn visit_literal(&mut self, _literal: &$($mutability)? Literal) {}
+            fn visit_pattern(&mut self, pattern: &$($mutability)? Pattern) {
+                $walk::walk_pattern(self, pattern)
+            }
+        }
+
+        /// The default traversal of every node.
+        pub mod $walk {
+            use super::*;
+
+            pub fn walk_module<V: $visitor>(v: &mut V, module: &$($mutability)? Module) {
+                let Module { items } = module;
+                for item in items {
+                    v.visit_item(item);
+                }
+            }
+
+            pub fn walk_item<V: $visitor>(v: &mut V, item: &$($mutability)? Item) {
+                let Item { kind, loc: _ } = item;
+                match kind {
+                    ItemKind::Import(import) => v.visit_import(import),
+                    ItemKind::Export(names) => {
+                        for name in names {
+                            v.visit_ident(name);
+                        }
+                    }
+                    ItemKind::Struct(decl) => v.visit_struct_decl(decl),
+                    ItemKind::Enum(decl) => v.visit_enum_decl(decl),
+                    ItemKind::Trait(decl) => v.visit_trait_decl(decl),
+                    ItemKind::Impl(decl) => v.visit_impl_decl(decl),
+                    ItemKind::Fn(decl) => v.visit_fn_decl(decl),
+                    ItemKind::Const(decl) => v.visit_const_decl(decl),
+                }
+            }
+
+            pub fn walk_import<V: $visitor>(v: &mut V, import: &$($mutability)? Import) {
+                let Import { path, group, alias } = import;
+                for ident in path {
+                    v.visit_ident(ident);
+                }
+                if let Some(group) = group {
+                    for ImportName { name, alias } in group {
+                        v.visit_ident(name);
+                        if let Some(alias) = alias {
+                            v.visit_ident(alias);
+                        }
+                    }
+                }
+                if let Some(alias) = alias {
+                    v.visit_ident(alias);
+                }
+            }
+
+            pub fn walk_struct_decl<V: $visitor>(v: &mut V, decl: &$($mutability)? StructDecl) {
+                let StructDecl {
+                    name,
+                    generics,
+                    fields,
+                } = decl;
+                v.visit_ident(name);
+                for param in generics {
+                    v.visit_generic_param(param);
+                }
+                for field in fields {
+                    v.visit_field_decl(field);
+                }
+            }
+
+            pub fn walk_field_decl<V: $visitor>(v: &mut V, field: &$($mutability)? FieldDecl) {
+                let FieldDecl {
+                    name,
+                    ty,
+                    mutable: _,
+                    loc: _,
+                } = field;
+                v.visit_ident(name);
+                v.visit_type(ty);
+            }
+
+            pub fn walk_enum_decl<V: $visitor>(v: &mut V, decl: &$($mutability)? EnumDecl) {
+                let EnumDecl {
+                    name,
+                    generics,
+                    variants,
+                } = decl;
+                v.visit_ident(name);
+                for param in generics {
+                    v.visit_generic_param(param);
+                }
+                for variant in variants {
+                    v.visit_variant_decl(variant);
+                }
+            }
+
+            pub fn walk_variant_decl<V: $visitor>(v: &mut V, variant: &$($mutability)? VariantDecl) {
+                let VariantDecl {
+                    name,
+                    fields,
+                    discriminant,
+                    loc: _,
+                } = variant;
+                v.visit_ident(name);
+                for ty in fields {
+                    v.visit_type(ty);
+                }
+                if let Some(discriminant) = discriminant {
+                    v.visit_expr(discriminant);
+                }
+            }
+
+            pub fn walk_const_decl<V: $visitor>(v: &mut V, decl: &$($mutability)? ConstDecl) {
+                let ConstDecl { name, ty, value } = decl;
+                v.visit_ident(name);
+                v.visit_type(ty);
+                v.visit_expr(value);
+            }
+
+            pub fn walk_trait_decl<V: $visitor>(v: &mut V, decl: &$($mutability)? TraitDecl) {
+                let TraitDecl {
+                    name,
+                    generics,
+                    assoc_types,
+                    methods,
+                } = decl;
+                v.visit_ident(name);
+                for param in generics {
+                    v.visit_generic_param(param);
+                }
+                for assoc in assoc_types {
+                    v.visit_assoc_type(assoc);
+                }
+                for method in methods {
+                    v.visit_fn_decl(method);
+                }
+            }
+
+            pub fn walk_assoc_type<V: $visitor>(v: &mut V, assoc: &$($mutability)? AssocType) {
+                let AssocType { name, ty, loc: _ } = assoc;
+                v.visit_ident(name);
+                if let Some(ty) = ty {
+                    v.visit_type(ty);
+                }
+            }
+
+            pub fn walk_impl_decl<V: $visitor>(v: &mut V, decl: &$($mutability)? ImplDecl) {
+                let ImplDecl {
+                    generics,
+                    trait_ref,
+                    target,
+                    assoc_types,
+                    methods,
+                } = decl;
+                for param in generics {
+                    v.visit_generic_param(param);
+                }
+                if let Some(trait_ref) = trait_ref {
+                    v.visit_path(trait_ref);
+                }
+                v.visit_path(target);
+                for assoc in assoc_types {
+                    v.visit_assoc_type(assoc);
+                }
+                for method in methods {
+                    v.visit_fn_decl(method);
+                }
+            }
+
+            pub fn walk_fn_decl<V: $visitor>(v: &mut V, decl: &$($mutability)? FnDecl) {
+                let FnDecl {
+                    tailcall: _,
+                    native: _,
+                    name,
+                    generics,
+                    self_param: _,
+                    params,
+                    ret,
+                    body,
+                    loc: _,
+                } = decl;
+                v.visit_ident(name);
+                for param in generics {
+                    v.visit_generic_param(param);
+                }
+                for param in params {
+                    v.visit_param(param);
+                }
+                v.visit_type(ret);
+                if let Some(body) = body {
+                    v.visit_block(body);
+                }
+            }
+
+            pub fn walk_generic_param<V: $visitor>(v: &mut V, param: &$($mutability)? GenericParam) {
+                let GenericParam { name, bounds } = param;
+                v.visit_ident(name);
+                for bound in bounds {
+                    v.visit_path(bound);
+                }
+            }
+
+            pub fn walk_param<V: $visitor>(v: &mut V, param: &$($mutability)? Param) {
+                let Param {
+                    pattern,
+                    ty,
+                    variadic: _,
+                    loc: _,
+                } = param;
+                v.visit_pattern(pattern);
+                v.visit_type(ty);
+            }
+
+            pub fn walk_type<V: $visitor>(v: &mut V, ty: &$($mutability)? Type) {
+                let Type { kind, loc: _ } = ty;
+                match kind {
+                    TypeKind::Path(path) => v.visit_path(path),
+                    TypeKind::Array(inner) => v.visit_type(inner),
+                    TypeKind::Tuple(types) => {
+                        for ty in types {
+                            v.visit_type(ty);
+                        }
+                    }
+                    TypeKind::Fn { params, ret } => {
+                        for ty in params {
+                            v.visit_type(ty);
+                        }
+                        v.visit_type(ret);
+                    }
+                }
+            }
+
+            pub fn walk_path<V: $visitor>(v: &mut V, path: &$($mutability)? Path) {
+                let Path { segments, loc: _ } = path;
+                for PathSegment { ident, generics } in segments {
+                    v.visit_ident(ident);
+                    for ty in generics {
+                        v.visit_type(ty);
+                    }
+                }
+            }
+
+            pub fn walk_block<V: $visitor>(v: &mut V, block: &$($mutability)? Block) {
+                let Block { stmts, loc: _ } = block;
+                for stmt in stmts {
+                    v.visit_stmt(stmt);
+                }
+            }
+
+            pub fn walk_stmt<V: $visitor>(v: &mut V, stmt: &$($mutability)? Stmt) {
+                let Stmt { kind, loc: _ } = stmt;
+                match kind {
+                    StmtKind::Let { pattern, ty, value } => {
+                        v.visit_pattern(pattern);
+                        if let Some(ty) = ty {
+                            v.visit_type(ty);
+                        }
+                        if let Some(value) = value {
+                            v.visit_expr(value);
+                        }
+                    }
+                    StmtKind::Assign {
+                        target,
+                        op: _,
+                        value,
+                    } => {
+                        v.visit_expr(target);
+                        v.visit_expr(value);
+                    }
+                    StmtKind::Expr(expr) => v.visit_expr(expr),
+                    StmtKind::Return(value) => {
+                        if let Some(value) = value {
+                            v.visit_expr(value);
+                        }
+                    }
+                    StmtKind::Break | StmtKind::Continue => {}
+                    StmtKind::If {
+                        cond,
+                        then_block,
+                        else_block,
+                    } => {
+                        v.visit_expr(cond);
+                        v.visit_block(then_block);
+                        if let Some(else_block) = else_block {
+                            v.visit_block(else_block);
+                        }
+                    }
+                    StmtKind::While { cond, body } => {
+                        v.visit_expr(cond);
+                        v.visit_block(body);
+                    }
+                    StmtKind::Match { scrutinee, arms } => {
+                        v.visit_expr(scrutinee);
+                        for arm in arms {
+                            v.visit_match_arm(arm);
+                        }
+                    }
+                    StmtKind::Block(block) => v.visit_block(block),
+                }
+            }
+
+            pub fn walk_match_arm<V: $visitor>(v: &mut V, arm: &$($mutability)? MatchArm) {
+                let MatchArm {
+                    pattern,
+                    guard,
+                    body,
+                    loc: _,
+                } = arm;
+                v.visit_pattern(pattern);
+                if let Some(guard) = guard {
+                    v.visit_expr(guard);
+                }
+                v.visit_stmt(body);
+            }
+
+            pub fn walk_expr<V: $visitor>(v: &mut V, expr: &$($mutability)? Expr) {
+                let Expr { kind, loc: _ } = expr;
+                match kind {
+                    ExprKind::Literal(literal) => v.visit_literal(literal),
+                    ExprKind::Path(path) => v.visit_path(path),
+                    ExprKind::SelfValue => {}
+                    ExprKind::Tuple(exprs) | ExprKind::Array(exprs) => {
+                        for expr in exprs {
+                            v.visit_expr(expr);
+                        }
+                    }
+                    ExprKind::Unary { op: _, expr } => v.visit_expr(expr),
+                    ExprKind::Binary { op: _, lhs, rhs } => {
+                        v.visit_expr(lhs);
+                        v.visit_expr(rhs);
+                    }
+                    ExprKind::Call { callee, args } => {
+                        v.visit_expr(callee);
+                        for arg in args {
+                            v.visit_expr(arg);
+                        }
+                    }
+                    ExprKind::MethodCall {
+                        receiver,
+                        method,
+                        args,
+                    } => {
+                        v.visit_expr(receiver);
+                        v.visit_ident(method);
+                        for arg in args {
+                            v.visit_expr(arg);
+                        }
+                    }
+                    ExprKind::Field { base, field } => {
+                        v.visit_expr(base);
+                        v.visit_ident(field);
+                    }
+                    ExprKind::StructLit { path, fields } => {
+                        v.visit_path(path);
+                        for FieldInit { name, value } in fields {
+                            v.visit_ident(name);
+                            v.visit_expr(value);
+                        }
+                    }
+                    ExprKind::Closure(closure) => v.visit_closure(closure),
+                }
+            }
+
+            pub fn walk_closure<V: $visitor>(v: &mut V, closure: &$($mutability)? Closure) {
+                let Closure { params, body } = closure;
+                for ClosureParam { pattern, ty } in params {
+                    v.visit_pattern(pattern);
+                    if let Some(ty) = ty {
+                        v.visit_type(ty);
+                    }
+                }
+                match body {
+                    ClosureBody::Expr(expr) => v.visit_expr(expr),
+                    ClosureBody::Block(block) => v.visit_block(block),
+                }
+            }
+
+            pub fn walk_pattern<V: $visitor>(v: &mut V, pattern: &$($mutability)? Pattern) {
+                let Pattern { kind, loc: _ } = pattern;
+                match kind {
+                    PatternKind::Wildcard | PatternKind::Rest => {}
+                    PatternKind::Binding { name, sub, .. } => {
+                        v.visit_ident(name);
+                        if let Some(sub) = sub {
+                            v.visit_pattern(sub);
+                        }
+                    }
+                    PatternKind::Literal(literal) => v.visit_literal(literal),
+                    PatternKind::Range { start, end, .. } => {
+                        v.visit_literal(start);
+                        v.visit_literal(end);
+                    }
+                    PatternKind::Tuple(patterns) | PatternKind::Or(patterns) => {
+                        for pattern in patterns {
+                            v.visit_pattern(pattern);
+                        }
+                    }
+                    PatternKind::Variant { path, fields } => {
+                        v.visit_path(path);
+                        if let Some(fields) = fields {
+                            for field in fields {
+                                v.visit_pattern(field);
+                            }
+                        }
+                    }
+                    PatternKind::Struct {
+                        path,
+                        fields,
+                        rest: _,
+                    } => {
+                        v.visit_path(path);
+                        for FieldPattern { name, pattern } in fields {
+                            v.visit_ident(name);
+                            v.visit_pattern(pattern);
+                        }
+                    }
+                }
+            }
+        }
+    };
+}
+
+make_visitor!(
+    /// Walks the AST by shared reference, e.g. for lints, name resolution and metrics.
+    Visitor,
+    walk,
+);
+make_visitor!(
+    /// Walks the AST by mutable reference, e.g. for desugaring and rewriting passes.
+    VisitorMut,
+    walk_mut,
+    mut
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_source;
+    use crate::parse::printer::print_module;
+
+    /// Counts calls, stopping at closures to check that overridden methods can cut the traversal short.
+    #[derive(Default)]
+    struct CallCounter {
+        calls: usize,
+    }
+
+    impl Visitor for CallCounter {
+        fn visit_expr(&mut self, expr: &Expr) {
+            if let ExprKind::Call { .. } | ExprKind::MethodCall { .. } = expr.kind {
+                self.calls += 1;
+            }
+            walk::walk_expr(self, expr);
+        }
+
+        fn visit_closure(&mut self, _closure: &Closure) {}
+    }
+
+    struct Renamer;
+
+    impl VisitorMut for Renamer {
+        fn visit_ident(&mut self, ident: &mut Ident) {
+            if ident.name == "x" {
+                ident.name = "renamed".to_string();
+            }
+        }
+    }
+
+    #[test]
+    fn visitor_overrides() {
+        let module = parse_source(
+            None,
+            "def f() void { g(h(1), a->b()); Array::map(xs) { (x) = x->y() }; }",
+        )
+        .unwrap();
+        let mut counter = CallCounter::default();
+        counter.visit_module(&module);
+        assert_eq!(counter.calls, 4);
+    }
+
+    #[test]
+    fn visitor_mut_rewrites() {
+        let mut module = parse_source(
+            None,
+            "def f(x: int) int { let (x, y) = (x, 2); match x { x @ 1 => return x->x, _ => {} } return x; }",
+        )
+        .unwrap();
+        Renamer.visit_module(&mut module);
+        let printed = print_module(&module);
+        assert!(!printed.contains(['x']), "{printed}");
+        assert_eq!(printed.matches("renamed").count(), 8);
+    }
+}
@@ -0,0 +1,110 @@
+//! A cache of the analyses of a module's functions, computed when first asked for and kept until
+//! a pass changes the function.
+//!
+//! The pass manager hands the cache to every pass and, after a pass changed the module, drops what
+//! the pass doesn't preserve. A pass that asks for an analysis of a function it has already changed
+//! in the same run must `invalidate` the function first.
+
+use super::dom::DomTree;
+use super::liveness::Liveness;
+use super::loops::LoopInfo;
+use super::{FuncId, Module};
+use std::collections::HashMap;
+
+/// What a pass keeps valid when it changes a module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preserved {
+    /// Nothing, e.g. because blocks or jumps changed.
+    Nothing,
+    /// The blocks and the jumps between them, so the dominators and loops, but not the values.
+    Cfg,
+}
+
+#[derive(Default)]
+struct FunctionAnalyses {
+    dom: Option<DomTree>,
+    post_dom: Option<DomTree>,
+    loops: Option<LoopInfo>,
+    liveness: Option<Liveness>,
+}
+
+#[derive(Default)]
+pub struct Analyses {
+    functions: HashMap<FuncId, FunctionAnalyses>,
+}
+
+impl Analyses {
+    pub fn dom(&mut self, module: &Module, id: FuncId) -> &DomTree {
+        let cached = self.functions.entry(id).or_default();
+        cached
+            .dom
+            .get_or_insert_with(|| DomTree::new(&module.functions[id]))
+    }
+
+    pub fn post_dom(&mut self, module: &Module, id: FuncId) -> &DomTree {
+        let cached = self.functions.entry(id).or_default();
+        cached
+            .post_dom
+            .get_or_insert_with(|| DomTree::post_dominators(&module.functions[id]))
+    }
+
+    pub fn loops(&mut self, module: &Module, id: FuncId) -> &LoopInfo {
+        let cached = self.functions.entry(id).or_default();
+        let function = &module.functions[id];
+        let dom = cached.dom.get_or_insert_with(|| DomTree::new(function));
+        cached
+            .loops
+            .get_or_insert_with(|| LoopInfo::new(function, dom))
+    }
+
+    pub fn liveness(&mut self, module: &Module, id: FuncId) -> &Liveness {
+        let cached = self.functions.entry(id).or_default();
+        cached
+            .liveness
+            .get_or_insert_with(|| Liveness::new(&module.functions[id]))
+    }
+
+    /// Forget the analyses of a function.
+    pub fn invalidate(&mut self, id: FuncId) {
+        self.functions.remove(&id);
+    }
+
+    /// Forget the analyses of every function that a change keeping `preserved` makes invalid.
+    pub fn invalidate_all(&mut self, preserved: Preserved) {
+        match preserved {
+            Preserved::Nothing => self.functions.clear(),
+            Preserved::Cfg => {
+                for cached in self.functions.values_mut() {
+                    cached.liveness = None;
+                }
+            }
+        }
+    }
+
+    /// Whether any analysis of the function is cached.
+    pub fn is_cached(&self, id: FuncId) -> bool {
+        self.functions.contains_key(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn keeps_analyses_until_invalidated() {
+        let module = parse_module(None, "def f() void {\nbb0:\n    return\n}\n").unwrap();
+        let mut analyses = Analyses::default();
+        let dom: *const DomTree = analyses.dom(&module, 0);
+        analyses.liveness(&module, 0);
+        analyses.invalidate_all(Preserved::Cfg);
+        assert!(std::ptr::eq(dom, analyses.dom(&module, 0)));
+        analyses.invalidate_all(Preserved::Nothing);
+        assert!(!analyses.is_cached(0));
+        analyses.loops(&module, 0);
+        assert!(analyses.is_cached(0));
+        analyses.invalidate(0);
+        assert!(!analyses.is_cached(0));
+    }
+}
@@ -0,0 +1,236 @@
+//! Dominators and post-dominators of the blocks of a function, computed with the algorithm of
+//! Cooper, Harvey and Kennedy: the immediate dominator of every block is refined over the blocks
+//! in reverse postorder until nothing changes, intersecting the dominators of its predecessors.
+//!
+//! Post-dominators are the dominators of the reversed CFG, entered from a virtual exit that every
+//! `return` and `unreachable` leads to.
+
+use super::{BlockId, Function};
+
+/// The predecessors of every block, in the order of the blocks jumping to them. A block that jumps
+/// to the same block twice, e.g. with a `branch`, is listed twice.
+pub fn predecessors(function: &Function) -> Vec<Vec<BlockId>> {
+    let mut preds = vec![Vec::new(); function.blocks.len()];
+    for (id, block) in function.blocks.iter().enumerate() {
+        for target in block.term.targets() {
+            if let Some(preds) = preds.get_mut(target.block) {
+                preds.push(id);
+            }
+        }
+    }
+    preds
+}
+
+/// The successors of every block, in the order of the targets of its terminator.
+pub fn successors(function: &Function) -> Vec<Vec<BlockId>> {
+    function
+        .blocks
+        .iter()
+        .map(|block| {
+            block
+                .term
+                .targets()
+                .into_iter()
+                .map(|target| target.block)
+                .filter(|&succ| succ < function.blocks.len())
+                .collect()
+        })
+        .collect()
+}
+
+/// The blocks reachable from the entry, each one before its successors except along back edges.
+pub fn reverse_postorder(function: &Function) -> Vec<BlockId> {
+    graph_reverse_postorder(&successors(function), 0)
+}
+
+/// The nodes of the graph reachable from the root, in reverse postorder.
+fn graph_reverse_postorder(succs: &[Vec<usize>], root: usize) -> Vec<usize> {
+    let mut visited = vec![false; succs.len()];
+    let mut order = Vec::new();
+    // The stack holds each node with the number of its successors visited so far.
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    while let Some((node, next)) = stack.pop() {
+        match succs[node].get(next) {
+            Some(&succ) => {
+                stack.push((node, next + 1));
+                if !visited[succ] {
+                    visited[succ] = true;
+                    stack.push((succ, 0));
+                }
+            }
+            None => order.push(node),
+        }
+    }
+    order.reverse();
+    order
+}
+
+/// The dominator tree of a function's blocks, or its post-dominator tree, in which a block
+/// dominates another if every path from that one to the exit goes through it.
+#[derive(Clone, Debug)]
+pub struct DomTree {
+    /// The immediate dominator of every reachable node, the root being its own. The nodes are the
+    /// blocks, followed by the virtual exit in a post-dominator tree.
+    idom: Vec<Option<usize>>,
+    /// The position of every reachable node in reverse postorder.
+    rpo_index: Vec<usize>,
+    /// The blocks every block immediately dominates.
+    children: Vec<Vec<BlockId>>,
+}
+
+impl DomTree {
+    pub fn new(function: &Function) -> Self {
+        Self::compute(&successors(function), &predecessors(function), 0)
+    }
+
+    /// The post-dominator tree. Blocks from which no path leads to a `return` or `unreachable`,
+    /// like those of an endless loop, are left out, as unreachable blocks are from dominator trees.
+    pub fn post_dominators(function: &Function) -> Self {
+        let exit = function.blocks.len();
+        let mut succs = predecessors(function);
+        let mut preds = successors(function);
+        let mut exits = Vec::new();
+        for (block, preds) in preds.iter_mut().enumerate() {
+            if preds.is_empty() {
+                preds.push(exit);
+                exits.push(block);
+            }
+        }
+        succs.push(exits);
+        preds.push(Vec::new());
+        let mut tree = Self::compute(&succs, &preds, exit);
+        tree.idom.truncate(exit);
+        tree.children.truncate(exit);
+        tree
+    }
+
+    fn compute(succs: &[Vec<usize>], preds: &[Vec<usize>], root: usize) -> Self {
+        let rpo = graph_reverse_postorder(succs, root);
+        let mut rpo_index = vec![usize::MAX; succs.len()];
+        for (i, &node) in rpo.iter().enumerate() {
+            rpo_index[node] = i;
+        }
+        let mut idom = vec![None; succs.len()];
+        idom[root] = Some(root);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &node in &rpo[1..] {
+                let mut new_idom = None;
+                for &pred in &preds[node] {
+                    if idom[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, &rpo_index, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idom[node] != new_idom {
+                    idom[node] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children = vec![Vec::new(); succs.len()];
+        for (node, &parent) in idom.iter().enumerate() {
+            if let Some(parent) = parent.filter(|&parent| parent != node) {
+                children[parent].push(node);
+            }
+        }
+        Self {
+            idom,
+            rpo_index,
+            children,
+        }
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.idom.get(block).is_some_and(Option::is_some)
+    }
+
+    /// The immediate dominator of the block, or `None` for the root and unreachable blocks. In a
+    /// post-dominator tree, the blocks leaving the function have none either.
+    pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+        self.idom[block].filter(|&idom| idom != block && idom < self.idom.len())
+    }
+
+    /// The blocks the block immediately dominates, in order.
+    pub fn children(&self, block: BlockId) -> &[BlockId] {
+        &self.children[block]
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`, or every path from `b` to the
+    /// exit in a post-dominator tree. Every block dominates itself, and unreachable blocks neither
+    /// dominate nor are dominated by any block.
+    pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        while self.rpo_index[b] > self.rpo_index[a] {
+            match self.idom(b) {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+        a == b
+    }
+}
+
+/// The closest common dominator of two nodes, walking up the tree from the one later in reverse
+/// postorder.
+fn intersect(idom: &[Option<usize>], rpo_index: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rpo_index[a] > rpo_index[b] {
+            a = idom[a].expect("processed nodes have a dominator");
+        }
+        while rpo_index[b] > rpo_index[a] {
+            b = idom[b].expect("processed nodes have a dominator");
+        }
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::{Target, Terminator, Type};
+
+    #[test]
+    fn finds_immediate_dominators_of_loops_and_joins() {
+        // bb0 -> bb1 -> {bb2, bb3}; bb2 -> bb1; bb3 -> bb5; bb4 is unreachable and jumps to bb5.
+        let mut function = Function::new("f", vec![Type::Bool], Type::Void);
+        let cond = function.params()[0];
+        for _ in 1..6 {
+            function.add_block();
+        }
+        let jump = |block| Terminator::Jump(Target::new(block, vec![]));
+        function.blocks[0].term = jump(1);
+        function.blocks[1].term = Terminator::Branch {
+            cond,
+            then_target: Target::new(2, vec![]),
+            else_target: Target::new(3, vec![]),
+        };
+        function.blocks[2].term = jump(1);
+        function.blocks[3].term = jump(5);
+        function.blocks[4].term = jump(5);
+        function.blocks[5].term = Terminator::Return(None);
+
+        assert_eq!(reverse_postorder(&function), [0, 1, 3, 5, 2]);
+        assert_eq!(predecessors(&function)[5], [3, 4]);
+        let dom = DomTree::new(&function);
+        let idoms: Vec<_> = (0..6).map(|b| dom.idom(b)).collect();
+        assert_eq!(idoms, [None, Some(0), Some(1), Some(1), None, Some(3)]);
+        assert!(dom.dominates(1, 5) && dom.dominates(5, 5));
+        assert!(!dom.dominates(2, 1) && !dom.dominates(4, 5) && !dom.is_reachable(4));
+        assert_eq!(dom.children(1), [2, 3]);
+
+        let post = DomTree::post_dominators(&function);
+        let ipdoms: Vec<_> = (0..6).map(|b| post.idom(b)).collect();
+        assert_eq!(ipdoms, [Some(1), Some(3), Some(1), Some(5), Some(5), None]);
+        assert!(post.dominates(5, 0) && post.dominates(3, 1) && !post.dominates(2, 1));
+    }
+}
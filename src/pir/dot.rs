@@ -0,0 +1,96 @@
+//! The control-flow graphs of a module in the DOT language of Graphviz, e.g. for
+//! `dot -Tsvg cfg.dot -o cfg.svg`.
+//!
+//! Every function is a cluster of blocks, each labeled with its PIR text. The edges of a `branch`
+//! are labeled with the value of the condition taking them, and back edges of loops are dashed.
+
+use super::dom::DomTree;
+use super::loops::LoopInfo;
+use super::printer::print_block;
+use super::{Module, Terminator};
+use std::fmt::Write;
+
+pub fn to_dot(module: &Module) -> String {
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+    for (id, function) in module.functions.iter().enumerate() {
+        let loops = LoopInfo::new(function, &DomTree::new(function));
+        let node = |block| quote(&format!("{}.bb{block}", function.name));
+        writeln!(out, "    subgraph cluster_{id} {{").unwrap();
+        writeln!(out, "        label = {};", quote(&function.name)).unwrap();
+        for block in 0..function.blocks.len() {
+            // `\l` ends a line aligned to the left.
+            let label = escape(&print_block(module, function, block)).replace('\n', "\\l");
+            writeln!(out, "        {} [label=\"{label}\"];", node(block)).unwrap();
+        }
+        for (block, data) in function.blocks.iter().enumerate() {
+            let labels: &[&str] = match data.term {
+                Terminator::Branch { .. } => &["true", "false"],
+                _ => &[""],
+            };
+            for (target, label) in data.term.targets().into_iter().zip(labels) {
+                let mut attrs = Vec::new();
+                if !label.is_empty() {
+                    attrs.push(format!("label={label}"));
+                }
+                if loops.is_back_edge(block, target.block) {
+                    attrs.push("style=dashed".to_string());
+                }
+                write!(out, "        {} -> {}", node(block), node(target.block)).unwrap();
+                if !attrs.is_empty() {
+                    write!(out, " [{}]", attrs.join(", ")).unwrap();
+                }
+                out.push_str(";\n");
+            }
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn writes_clusters_with_labeled_and_back_edges() {
+        let module = parse_module(
+            None,
+            "def f(%0: bool) void {
+bb0:
+    jump bb1
+bb1:
+    branch %0, bb1, bb2
+bb2:
+    return
+}
+",
+        )
+        .unwrap();
+        assert_eq!(
+            to_dot(&module),
+            r#"digraph cfg {
+    node [shape=box, fontname=monospace];
+    subgraph cluster_0 {
+        label = "f";
+        "f.bb0" [label="bb0:\l    jump bb1\l"];
+        "f.bb1" [label="bb1:\l    branch %0, bb1, bb2\l"];
+        "f.bb2" [label="bb2:\l    return\l"];
+        "f.bb0" -> "f.bb1";
+        "f.bb1" -> "f.bb1" [label=true, style=dashed];
+        "f.bb1" -> "f.bb2" [label=false];
+    }
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Escape analysis: which structs and arrays may outlive the call that allocates them.
+//!
+//! A reference escapes when it is returned, stored in memory, put in another aggregate or a
+//! closure, passed to a closure, passed to a parameter that escapes, or pushed onto an array with
+//! the `array_push` native. Reading or writing the fields of a struct, and passing it to the other
+//! natives, doesn't make it escape. Passing a reference from block to block counts as escaping
+//! too: the block may run again, and then the reference would be live while its allocation makes
+//! another struct in the same place.
+//!
+//! Which parameters escape is worked out for all functions at once, starting from none and
+//! adding those that do until nothing changes, so recursive functions get the least answer.
+
+use super::{FuncId, Function, InstKind, Module, ValueId};
+use std::collections::HashSet;
+
+/// How far an allocated struct or array gets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escape {
+    /// It may outlive the call, so it has to be on the heap.
+    Escapes,
+    /// It dies with the call, but is passed to other functions, so it can be on the stack.
+    Frame,
+    /// Only its fields are read and written, so it can be replaced by a variable per field.
+    Fields,
+}
+
+#[derive(Clone, Debug)]
+pub struct EscapeInfo {
+    /// Whether each parameter of each function escapes.
+    params: Vec<Vec<bool>>,
+    /// The values escaping in each function, whatever their type.
+    escaping: Vec<HashSet<ValueId>>,
+}
+
+impl EscapeInfo {
+    pub fn new(module: &Module) -> Self {
+        let mut params: Vec<Vec<bool>> = module
+            .functions
+            .iter()
+            .map(|function| vec![false; function.params().len()])
+            .collect();
+        loop {
+            let escaping: Vec<HashSet<ValueId>> = module
+                .functions
+                .iter()
+                .map(|function| escaping(function, &params))
+                .collect();
+            let mut changed = false;
+            for (id, function) in module.functions.iter().enumerate() {
+                for (index, param) in function.params().iter().enumerate() {
+                    if escaping[id].contains(param) && !params[id][index] {
+                        params[id][index] = true;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return Self { params, escaping };
+            }
+        }
+    }
+
+    pub fn param_escapes(&self, func: FuncId, index: usize) -> bool {
+        self.params[func].get(index).copied().unwrap_or(true)
+    }
+
+    pub fn escapes(&self, func: FuncId, value: ValueId) -> bool {
+        self.escaping[func].contains(&value)
+    }
+
+    /// How far the struct or array allocated as `value` in the function gets.
+    pub fn allocation(&self, module: &Module, func: FuncId, value: ValueId) -> Escape {
+        if self.escapes(func, value) {
+            return Escape::Escapes;
+        }
+        let function = &module.functions[func];
+        let is_struct = function.blocks.iter().any(|block| {
+            block
+                .insts
+                .iter()
+                .any(|inst| inst.result == value && matches!(inst.kind, InstKind::New(..)))
+        });
+        let only_fields = function.blocks.iter().all(|block| {
+            block.insts.iter().all(|inst| match &inst.kind {
+                InstKind::GetField(..) | InstKind::SetField(..) => true,
+                kind => !kind.operands().contains(&value),
+            })
+        });
+        if is_struct && only_fields {
+            Escape::Fields
+        } else {
+            Escape::Frame
+        }
+    }
+}
+
+/// The values escaping in the function, given which parameters of the functions it calls escape.
+fn escaping(function: &Function, params: &[Vec<bool>]) -> HashSet<ValueId> {
+    let mut escaping = HashSet::new();
+    for block in &function.blocks {
+        for inst in &block.insts {
+            match &inst.kind {
+                InstKind::CallNative(native, args) if native.name == "array_push" => {
+                    escaping.insert(args[1]);
+                }
+                InstKind::GetField(..) | InstKind::CallNative(..) => {}
+                InstKind::SetField(_, _, value) => {
+                    escaping.insert(*value);
+                }
+                InstKind::Call(func, args) => {
+                    for (index, &arg) in args.iter().enumerate() {
+                        if params[*func].get(index).copied().unwrap_or(true) {
+                            escaping.insert(arg);
+                        }
+                    }
+                }
+                kind => escaping.extend(kind.operands()),
+            }
+        }
+        escaping.extend(block.term.clone().operands_mut().into_iter().map(|v| *v));
+    }
+    escaping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn follows_references_through_calls() {
+        let source = "struct Point { x: int, y: int }
+
+def keep(%0: Point) Point {
+bb0:
+    return %0
+}
+
+def norm(%0: Point) int {
+bb0:
+    %1: int = get_field %0, x
+    %2: void = call_native println(%0)
+    return %1
+}
+
+def main() void {
+bb0:
+    %0: int = const 1
+    %1: Point = new Point(%0, %0)
+    %2: int = get_field %1, y
+    %3: Point = new Point(%0, %2)
+    %4: int = call @norm(%3)
+    %5: Point = new Point(%0, %4)
+    %6: Point = call @keep(%5)
+    return
+}
+";
+        let module = parse_module(None, source).unwrap();
+        let info = EscapeInfo::new(&module);
+        assert!(info.param_escapes(0, 0));
+        assert!(!info.param_escapes(1, 0));
+        assert_eq!(info.allocation(&module, 2, 1), Escape::Fields);
+        assert_eq!(info.allocation(&module, 2, 3), Escape::Frame);
+        assert_eq!(info.allocation(&module, 2, 5), Escape::Escapes);
+    }
+}
@@ -0,0 +1,808 @@
+//! An interpreter of PIR, which runs a program without compiling it and serves as the reference
+//! semantics of the backends.
+//!
+//! Calls don't recurse in the interpreter: every call pushes a frame on a stack of its own, which
+//! is also what the stack trace of a panic is made of. Integers wrap around on overflow, while
+//! dividing by zero and shifting by a negative amount or by 64 or more panic, as does calling
+//! `panic`.
+
+use super::profile::Counts;
+use super::{
+    AdtKind, BinaryOp, BlockId, CompareOp, Const, FuncId, Function, InstKind, Module, Position,
+    Spans, Terminator, Type, UnaryOp, ValueId,
+};
+use crate::parse::SourceCodeLocation;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::rc::Rc;
+
+/// How many calls may be in progress before the program panics with a stack overflow.
+const MAX_FRAMES: usize = 100_000;
+/// The longest cycle of frames a stack trace shows once instead of repeating it, e.g. for
+/// functions that are recursive with each other.
+const MAX_CYCLE: usize = 4;
+/// How many times a cycle of frames has to repeat to be collapsed.
+const MIN_REPEATS: usize = 3;
+
+/// The indices of the variants of the prelude's `Option` and `Either` that natives return.
+const SOME: usize = 0;
+const NONE: usize = 1;
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+
+/// A value at run time. Structs, arrays and the memory of `alloca` are shared by reference.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Void,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    Str(Rc<str>),
+    Ptr(Rc<RefCell<Value>>),
+    Struct(Rc<RefCell<Vec<Value>>>),
+    /// A variant, by index, with its fields.
+    Variant(usize, Rc<[Value]>),
+    Tuple(Rc<[Value]>),
+    /// The elements of an array, which the std natives change in place.
+    Array(Rc<RefCell<Vec<Value>>>),
+    Closure(FuncId, Rc<Value>),
+}
+
+/// A panic, with the calls in progress when it happened.
+#[derive(Clone, Debug)]
+pub struct Panic {
+    pub message: String,
+    /// The calls, innermost first.
+    pub frames: Vec<Frame>,
+}
+
+/// A call in progress and the instruction it was running.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub function: String,
+    pub position: Position,
+    /// Where the instruction comes from in the Paca source, if it's known.
+    pub loc: Option<SourceCodeLocation>,
+}
+
+impl Panic {
+    /// Point the frames the source doesn't locate at the PIR text the module was read from.
+    pub fn locate(mut self, spans: &Spans) -> Self {
+        for frame in &mut self.frames {
+            if frame.loc.is_none() {
+                frame.loc = spans.get(frame.position).cloned();
+            }
+        }
+        self
+    }
+
+    /// The message and stack trace, showing the line of the innermost located frame in the source
+    /// `source_of` gives for its file.
+    pub fn render<'s>(&self, source_of: impl Fn(&SourceCodeLocation) -> &'s str) -> String {
+        let mut out = String::new();
+        if let Some(loc) = self.frames.iter().find_map(|frame| frame.loc.as_ref()) {
+            out += &loc.line_in_source_code(source_of(loc));
+            out.push('\n');
+        }
+        match self.frames.first() {
+            Some(frame) => out += &format!("Panic in `{}`: {}", frame.function, self.message),
+            None => out += &format!("Panic: {}", self.message),
+        }
+        if !self.frames.is_empty() {
+            out += "\nStack trace:";
+        }
+        let lines: Vec<String> = self
+            .frames
+            .iter()
+            .map(|frame| match &frame.loc {
+                Some(loc) => {
+                    let file = loc.filename.as_deref().unwrap_or("unknown");
+                    format!("at {} ({file}:{}:{})", frame.function, loc.line, loc.column)
+                }
+                None => format!("at {}", frame.function),
+            })
+            .collect();
+        let mut i = 0;
+        while i < lines.len() {
+            let (cycle, repeats) = (1..=MAX_CYCLE)
+                .map(|cycle| (cycle, repeats(&lines[i..], cycle)))
+                .find(|&(_, repeats)| repeats >= MIN_REPEATS)
+                .unwrap_or((1, 1));
+            for line in &lines[i..i + cycle] {
+                out += &format!("\n    {line}");
+            }
+            let more = cycle * (repeats - 1);
+            if more > 0 {
+                let of = match cycle {
+                    1 => format!("of `{}`", self.frames[i].function),
+                    _ => format!("repeating the {cycle} above"),
+                };
+                out += &format!("\n    ... {} more frames {of}", thousands(more));
+            }
+            i += cycle * repeats;
+        }
+        out
+    }
+}
+
+/// How many times the first `cycle` lines repeat in a row at the start of the lines.
+fn repeats(lines: &[String], cycle: usize) -> usize {
+    if lines.len() < cycle {
+        return 0;
+    }
+    let mut repeats = 1;
+    while lines[cycle * repeats..]
+        .get(..cycle)
+        .is_some_and(|next| next == &lines[..cycle])
+    {
+        repeats += 1;
+    }
+    repeats
+}
+
+/// The number with commas between thousands, e.g. `99,997`.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// An `Option` holding the value if there is one.
+fn option(value: Option<Value>) -> Value {
+    match value {
+        Some(value) => Value::Variant(SOME, Rc::new([value])),
+        None => Value::Variant(NONE, Rc::new([])),
+    }
+}
+
+/// The hash of a primitive value: integers, characters and booleans are their own hash, and strings
+/// hash with the 64-bit FNV-1a hash of their bytes.
+fn hash(value: &Value) -> i64 {
+    match value {
+        Value::Int(n) => *n,
+        Value::Char(c) => *c as i64,
+        Value::Bool(b) => *b as i64,
+        Value::Str(s) => s.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        }) as i64,
+        _ => unreachable!("only primitive values are hashed built in"),
+    }
+}
+
+/// Run the `main` function of the module, writing what the program prints to `out`.
+pub fn run(module: &Module, out: &mut dyn Write) -> Result<(), Panic> {
+    run_counting(module, out, &mut Counts::new())
+}
+
+/// Run the `main` function of the module like `run`, adding what its `count` instructions count
+/// to `counts`, even when it panics.
+pub fn run_counting(
+    module: &Module,
+    out: &mut dyn Write,
+    counts: &mut Counts,
+) -> Result<(), Panic> {
+    let Some(main) = module
+        .functions
+        .iter()
+        .position(|f| f.name == "main" && f.params().is_empty())
+    else {
+        return Err(Panic {
+            message: "There is no `main` function without parameters to run.".to_string(),
+            frames: Vec::new(),
+        });
+    };
+    let mut interpreter = Interpreter {
+        module,
+        out,
+        counts,
+        frames: Vec::new(),
+    };
+    interpreter.call(main, Vec::new())?;
+    interpreter.run()
+}
+
+struct Interpreter<'a> {
+    module: &'a Module,
+    out: &'a mut dyn Write,
+    counts: &'a mut Counts,
+    frames: Vec<CallFrame>,
+}
+
+struct CallFrame {
+    func: FuncId,
+    block: BlockId,
+    /// The instruction running, or the terminator once it's past the instructions.
+    index: usize,
+    values: Vec<Option<Value>>,
+}
+
+/// What running an instruction does.
+enum Step {
+    Value(Value),
+    Call(FuncId, Vec<Value>),
+}
+
+impl Interpreter<'_> {
+    fn run(&mut self) -> Result<(), Panic> {
+        let module = self.module;
+        while let Some(frame) = self.frames.last() {
+            let function = &module.functions[frame.func];
+            let block = &function.blocks[frame.block];
+            if let Some(inst) = block.insts.get(frame.index) {
+                match self.inst(function, &inst.kind)? {
+                    Step::Value(value) => self.finish_inst(value),
+                    Step::Call(func, args) => self.call(func, args)?,
+                }
+                continue;
+            }
+            match &block.term {
+                Terminator::Jump(target) => self.jump(target.block, &target.args),
+                Terminator::Branch {
+                    cond,
+                    then_target,
+                    else_target,
+                } => {
+                    let target = match self.get(*cond) {
+                        Value::Bool(true) => then_target,
+                        _ => else_target,
+                    };
+                    self.jump(target.block, &target.args);
+                }
+                Terminator::Return(value) => {
+                    let value = value.map_or(Value::Void, |value| self.get(value));
+                    self.frames.pop();
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.finish_inst(value);
+                }
+                Terminator::Unreachable => return Err(self.panic("Reached unreachable code.")),
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("code runs in a call")
+    }
+
+    fn get(&self, value: ValueId) -> Value {
+        let frame = self.frames.last().expect("code runs in a call");
+        frame.values[value]
+            .clone()
+            .expect("verified PIR defines values before using them")
+    }
+
+    /// Give the value to the instruction running and move on to the next one.
+    fn finish_inst(&mut self, value: Value) {
+        let module = self.module;
+        let frame = self.frame();
+        let result = module.functions[frame.func].blocks[frame.block].insts[frame.index].result;
+        frame.values[result] = Some(value);
+        frame.index += 1;
+    }
+
+    fn call(&mut self, func: FuncId, args: Vec<Value>) -> Result<(), Panic> {
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(self.panic("Stack overflow."));
+        }
+        let function = &self.module.functions[func];
+        let mut values = vec![None; function.values.len()];
+        for (&param, arg) in function.params().iter().zip(args) {
+            values[param] = Some(arg);
+        }
+        self.frames.push(CallFrame {
+            func,
+            block: 0,
+            index: 0,
+            values,
+        });
+        Ok(())
+    }
+
+    fn jump(&mut self, block: BlockId, args: &[ValueId]) {
+        let args: Vec<Value> = args.iter().map(|&arg| self.get(arg)).collect();
+        let module = self.module;
+        let frame = self.frame();
+        let params = &module.functions[frame.func].blocks[block].params;
+        for (&param, arg) in params.iter().zip(args) {
+            frame.values[param] = Some(arg);
+        }
+        frame.block = block;
+        frame.index = 0;
+    }
+
+    fn inst(&mut self, function: &Function, kind: &InstKind) -> Result<Step, Panic> {
+        let values = |this: &Self, values: &[ValueId]| -> Vec<Value> {
+            values.iter().map(|&value| this.get(value)).collect()
+        };
+        let value = match kind {
+            InstKind::Const(value) => match value {
+                Const::Int(n) => Value::Int(*n),
+                Const::Float(n) => Value::Float(*n),
+                Const::Bool(b) => Value::Bool(*b),
+                Const::Char(c) => Value::Char(*c),
+                Const::Str(s) => Value::Str(Rc::from(s.as_str())),
+                Const::Void => Value::Void,
+            },
+            InstKind::Unary(op, value) => match (op, self.get(*value)) {
+                (UnaryOp::Neg, Value::Int(n)) => Value::Int(n.wrapping_neg()),
+                (UnaryOp::Neg, Value::Float(n)) => Value::Float(-n),
+                (UnaryOp::Not, Value::Bool(b)) => Value::Bool(!b),
+                (op, value) => return Err(self.mismatch(op, &[value])),
+            },
+            InstKind::Binary(op, a, b) => self.binary(*op, self.get(*a), self.get(*b))?,
+            InstKind::Compare(op, a, b) => {
+                let (a, b) = (self.get(*a), self.get(*b));
+                let ordering = match (&a, &b) {
+                    (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+                    (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+                    (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+                    (Value::Char(a), Value::Char(b)) => Some(a.cmp(b)),
+                    (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                    _ => return Err(self.mismatch(op, &[a, b])),
+                };
+                Value::Bool(match (op, ordering) {
+                    (CompareOp::Ne, None) => true,
+                    (_, None) => false,
+                    (CompareOp::Eq, Some(o)) => o == Ordering::Equal,
+                    (CompareOp::Ne, Some(o)) => o != Ordering::Equal,
+                    (CompareOp::Lt, Some(o)) => o == Ordering::Less,
+                    (CompareOp::Le, Some(o)) => o != Ordering::Greater,
+                    (CompareOp::Gt, Some(o)) => o == Ordering::Greater,
+                    (CompareOp::Ge, Some(o)) => o != Ordering::Less,
+                })
+            }
+            InstKind::Count(func, counter) => {
+                *self.counts.entry((*func, *counter)).or_default() += 1;
+                Value::Void
+            }
+            InstKind::Alloca(_) => Value::Ptr(Rc::new(RefCell::new(Value::Void))),
+            InstKind::Load(address) => match self.get(*address) {
+                Value::Ptr(memory) => memory.borrow().clone(),
+                value => return Err(self.mismatch("load", &[value])),
+            },
+            InstKind::Store(address, value) => match self.get(*address) {
+                Value::Ptr(memory) => {
+                    *memory.borrow_mut() = self.get(*value);
+                    Value::Void
+                }
+                value => return Err(self.mismatch("store", &[value])),
+            },
+            InstKind::Call(func, args) => return Ok(Step::Call(*func, values(self, args))),
+            InstKind::CallNative(native, args) => {
+                let args: Vec<(ValueId, Value)> =
+                    args.iter().map(|&arg| (arg, self.get(arg))).collect();
+                self.native(function, native.name, &args)?
+            }
+            InstKind::CallIndirect(closure, args) => match self.get(*closure) {
+                Value::Closure(func, env) => {
+                    let mut args = values(self, args);
+                    args.insert(0, (*env).clone());
+                    return Ok(Step::Call(func, args));
+                }
+                value => return Err(self.mismatch("call_indirect", &[value])),
+            },
+            InstKind::Closure(func, env) => Value::Closure(*func, Rc::new(self.get(*env))),
+            InstKind::New(_, fields, _) => {
+                Value::Struct(Rc::new(RefCell::new(values(self, fields))))
+            }
+            InstKind::GetField(value, index) => match self.get(*value) {
+                Value::Struct(fields) => fields.borrow()[*index].clone(),
+                value => return Err(self.mismatch("get_field", &[value])),
+            },
+            InstKind::SetField(target, index, value) => match self.get(*target) {
+                Value::Struct(fields) => {
+                    fields.borrow_mut()[*index] = self.get(*value);
+                    Value::Void
+                }
+                value => return Err(self.mismatch("set_field", &[value])),
+            },
+            InstKind::Variant(_, variant, fields) => {
+                Value::Variant(*variant, values(self, fields).into())
+            }
+            InstKind::Tag(value) => {
+                let variant = match self.get(*value) {
+                    Value::Variant(variant, _) => variant,
+                    value => return Err(self.mismatch("tag", &[value])),
+                };
+                let discriminant = match &function.values[*value] {
+                    Type::Adt(adt) => match &self.module.adts[*adt].kind {
+                        AdtKind::Enum(variants) => variants[variant].discriminant,
+                        AdtKind::Struct(_) => variant as i64,
+                    },
+                    _ => variant as i64,
+                };
+                Value::Int(discriminant)
+            }
+            InstKind::Payload(value, variant, field) => match self.get(*value) {
+                Value::Variant(actual, fields) if actual == *variant => fields[*field].clone(),
+                _ => return Err(self.panic("Read the payload of another variant.")),
+            },
+            InstKind::Tuple(elems) => Value::Tuple(values(self, elems).into()),
+            InstKind::Extract(value, index) => match self.get(*value) {
+                Value::Tuple(elems) => elems[*index].clone(),
+                value => return Err(self.mismatch("extract", &[value])),
+            },
+            InstKind::Array(elems, _) => Value::Array(Rc::new(RefCell::new(values(self, elems)))),
+        };
+        Ok(Step::Value(value))
+    }
+
+    fn binary(&self, op: BinaryOp, a: Value, b: Value) -> Result<Value, Panic> {
+        Ok(match (&a, &b) {
+            (&Value::Int(a), &Value::Int(b)) => Value::Int(match op {
+                BinaryOp::Add => a.wrapping_add(b),
+                BinaryOp::Sub => a.wrapping_sub(b),
+                BinaryOp::Mul => a.wrapping_mul(b),
+                BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                    return Err(self.panic("Attempted to divide by zero."))
+                }
+                BinaryOp::Div => a
+                    .checked_div(b)
+                    .ok_or_else(|| self.panic("Attempted to divide with overflow."))?,
+                BinaryOp::Rem => a
+                    .checked_rem(b)
+                    .ok_or_else(|| self.panic("Attempted to divide with overflow."))?,
+                BinaryOp::Shl | BinaryOp::Shr => {
+                    let shift = u32::try_from(b)
+                        .ok()
+                        .filter(|&shift| shift < i64::BITS)
+                        .ok_or_else(|| self.panic(&format!("Attempted to shift by {b}.")))?;
+                    match op {
+                        BinaryOp::Shl => a << shift,
+                        _ => a >> shift,
+                    }
+                }
+            }),
+            (&Value::Float(a), &Value::Float(b)) => Value::Float(match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div => a / b,
+                BinaryOp::Rem => a % b,
+                BinaryOp::Shl | BinaryOp::Shr => {
+                    return Err(self.mismatch(op, &[a.into(), b.into()]))
+                }
+            }),
+            (Value::Str(a), Value::Str(b)) if op == BinaryOp::Add => {
+                Value::Str(Rc::from(format!("{a}{b}")))
+            }
+            _ => return Err(self.mismatch(op, &[a, b])),
+        })
+    }
+
+    fn native(
+        &mut self,
+        function: &Function,
+        name: &str,
+        args: &[(ValueId, Value)],
+    ) -> Result<Value, Panic> {
+        match (name, args) {
+            ("print" | "println", [(arg, value)]) => {
+                let mut text = display(self.module, &function.values[*arg], value, false);
+                if name == "println" {
+                    text.push('\n');
+                }
+                self.out
+                    .write_all(text.as_bytes())
+                    .map_err(|e| self.panic(&format!("Failed to print: {e}.")))?;
+                Ok(Value::Void)
+            }
+            ("panic", [(_, Value::Str(message))]) => Err(self.panic(message)),
+            ("read_file", [(_, Value::Str(path))]) => Ok(match std::fs::read_to_string(&**path) {
+                Ok(content) => Value::Variant(LEFT, Rc::new([Value::Str(content.into())])),
+                Err(e) => {
+                    let message = Value::Str(format!("Can't read {path}: {e}.").into());
+                    let error = Value::Struct(Rc::new(RefCell::new(vec![message])));
+                    Value::Variant(RIGHT, Rc::new([error]))
+                }
+            }),
+            ("array_length", [(_, Value::Array(elems))]) => {
+                Ok(Value::Int(elems.borrow().len() as i64))
+            }
+            ("array_get", [(_, Value::Array(elems)), (_, Value::Int(index))]) => {
+                let elem = usize::try_from(*index)
+                    .ok()
+                    .and_then(|index| elems.borrow().get(index).cloned());
+                Ok(option(elem))
+            }
+            ("array_push", [(_, Value::Array(elems)), (_, value)]) => {
+                elems.borrow_mut().push(value.clone());
+                Ok(Value::Void)
+            }
+            ("array_pop", [(_, Value::Array(elems))]) => Ok(option(elems.borrow_mut().pop())),
+            ("hash", [(_, value)]) => Ok(Value::Int(hash(value))),
+            ("continuation", [(_, stop)]) => Ok(stop.clone()),
+            _ => Err(self.panic(&format!("Unknown native function `{name}`."))),
+        }
+    }
+
+    /// A panic at the instruction or terminator running in every frame.
+    fn panic(&self, message: &str) -> Panic {
+        let frames = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = &self.module.functions[frame.func];
+                let block = &function.blocks[frame.block];
+                let (position, loc) = match block.insts.get(frame.index) {
+                    Some(inst) => (
+                        Position::Inst(frame.func, frame.block, frame.index),
+                        function.locs.get(&inst.result).cloned(),
+                    ),
+                    None => (Position::Terminator(frame.func, frame.block), None),
+                };
+                Frame {
+                    function: function.name.clone(),
+                    position,
+                    loc,
+                }
+            })
+            .collect();
+        Panic {
+            message: message.to_string(),
+            frames,
+        }
+    }
+
+    /// A panic for an operation on values it doesn't apply to, which verified PIR never has.
+    fn mismatch(&self, op: impl std::fmt::Display, values: &[Value]) -> Panic {
+        self.panic(&format!("`{op}` can't be applied to {values:?}."))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Float(n)
+    }
+}
+
+/// The text between `quote`s, escaping only the quote, backslashes and control characters.
+fn quoted(text: &str, quote: char) -> String {
+    let mut out = String::from(quote);
+    for c in text.chars() {
+        if c == quote || c == '\\' {
+            out.push('\\');
+            out.push(c);
+        } else if c.is_control() {
+            out.extend(c.escape_debug());
+        } else {
+            out.push(c);
+        }
+    }
+    out.push(quote);
+    out
+}
+
+/// The value as `print` writes it. Strings and characters are written as they are, unless they're
+/// `nested` in another value, where they're quoted.
+pub fn display(module: &Module, ty: &Type, value: &Value, nested: bool) -> String {
+    let list = |tys: &mut dyn Iterator<Item = &Type>, values: &[Value]| -> String {
+        tys.zip(values)
+            .map(|(ty, value)| display(module, ty, value, true))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match (ty, value) {
+        (_, Value::Void) => "void".to_string(),
+        (_, Value::Bool(b)) => b.to_string(),
+        (_, Value::Int(n)) => n.to_string(),
+        (_, Value::Float(n)) => format!("{n:?}"),
+        (_, Value::Char(c)) if nested => quoted(&c.to_string(), '\''),
+        (_, Value::Char(c)) => c.to_string(),
+        (_, Value::Str(s)) if nested => quoted(s, '"'),
+        (_, Value::Str(s)) => s.to_string(),
+        (_, Value::Ptr(_)) => "<pointer>".to_string(),
+        (Type::Adt(adt), Value::Struct(fields)) => {
+            let adt = &module.adts[*adt];
+            let AdtKind::Struct(defs) = &adt.kind else {
+                return "<struct>".to_string();
+            };
+            let fields: Vec<String> = defs
+                .iter()
+                .zip(fields.borrow().iter())
+                .map(|(def, value)| {
+                    format!("{}: {}", def.name, display(module, &def.ty, value, true))
+                })
+                .collect();
+            format!("{} {{ {} }}", adt.name, fields.join(", "))
+        }
+        (Type::Adt(adt), Value::Variant(variant, fields)) => {
+            let AdtKind::Enum(variants) = &module.adts[*adt].kind else {
+                return "<enum>".to_string();
+            };
+            let def = &variants[*variant];
+            if fields.is_empty() {
+                def.name.clone()
+            } else {
+                format!("{}({})", def.name, list(&mut def.fields.iter(), fields))
+            }
+        }
+        (Type::Tuple(tys), Value::Tuple(elems)) => format!("({})", list(&mut tys.iter(), elems)),
+        (Type::Array(ty), Value::Array(elems)) => {
+            format!("[{}]", list(&mut std::iter::repeat(&**ty), &elems.borrow()))
+        }
+        (_, Value::Closure(func, _)) => format!("<function {}>", module.functions[*func].name),
+        _ => format!("{value:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::lower::lower;
+    use crate::sema::builtins::PRELUDE;
+    use crate::sema::modules::ModuleLoader;
+    use std::path::Path;
+
+    fn run_source(source: &str) -> (String, Result<(), Panic>) {
+        let graph = ModuleLoader::new(vec![])
+            .load_source(Path::new("main.paca"), source.to_string())
+            .unwrap();
+        let (analysis, diagnostics) = crate::sema::analyze(&graph);
+        let analysis = analysis.unwrap_or_else(|| panic!("{diagnostics:?}"));
+        let module = lower(&graph, &analysis);
+        let mut out = Vec::new();
+        let result = run(&module, &mut out);
+        (String::from_utf8(out).unwrap(), result)
+    }
+
+    #[test]
+    fn runs_structs_enums_closures_and_loops() {
+        let (out, result) = run_source(
+            "struct Point { $x: int, y: float }\n\n\
+             enum Shape { Dot(Point), Line(Point, Point), Empty }\n\n\
+             def apply(n: int, f: def(int) int) int {\n    return f(n);\n}\n\n\
+             def main() void {\n    let p = Point { x => -1, y => 2.5 };\n    \
+             p->x = p->x * 2;\n    let mut n = 0;\n    while n < 3 { n += 1; }\n    \
+             match Shape::Line(p, p) {\n        Shape::Line(a, _) => println(a),\n        \
+             _ => println(\"none\"),\n    }\n    \
+             println(apply(n) { (x) = x + n });\n    println((\"a\" + \"b\", 'c', [1, 2]));\n    \
+             println(Option::Some(n / 2));\n}\n",
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(
+            out,
+            "Point { x: -2, y: 2.5 }\n6\n(\"ab\", 'c', [1, 2])\nSome(1)\n"
+        );
+    }
+
+    #[test]
+    fn stops_iterating_at_continuations() {
+        let (out, result) = run_source(
+            "import std::collections::Array;\n\n\
+             def main() void {\n    let mut seen = 0;\n    \
+             Array::for_each([1, 2, 3, 4]) { (quit, n) =>\n        \
+             if n == 3 {\n            quit();\n        }\n        seen += n;\n    };\n    \
+             println(seen);\n}\n",
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(out, "3\n");
+    }
+
+    #[test]
+    fn calls_operator_methods_of_primitives() {
+        let (out, result) = run_source(
+            "import std::ops::(Add, AddAssign, Negate);\n\
+             import std::cmp::Order;\n\n\
+             def twice<T: Add>(x: T) T {\n    return x->add(x);\n}\n\n\
+             def bump<T: AddAssign>(x: T, y: T) T {\n    let mut z = x;\n    \
+             z->add_assign(y);\n    return z;\n}\n\n\
+             def less<T: Order>(x: T, y: T) bool {\n    return x->less(y);\n}\n\n\
+             def main() void {\n    println(twice(3));\n    println(twice(1.5));\n    \
+             println(bump(4, 5));\n    println(less(2, 1));\n}\n",
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(out, "6\n3.0\n9\nfalse\n");
+    }
+
+    #[test]
+    fn hashes_primitives() {
+        let (out, result) = run_source(
+            "import std::hash::Hashable;\n\n\
+             def same<T: Hashable>(a: T, b: T) bool {\n    return a->hash() == b->hash();\n}\n\n\
+             def main() void {\n    println(same(3, 3));\n    println(same(\"key\", \"key\"));\n    \
+             println(same(\"key\", \"yek\"));\n    println(same('a', 'b'));\n    \
+             println(same(true, true));\n}\n",
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(out, "true\ntrue\nfalse\nfalse\ntrue\n");
+    }
+
+    #[test]
+    fn quotes_nested_strings() {
+        let (out, result) = run_source(
+            "struct Error { message: str, quote: char }\n\n\
+             def main() void {\n    \
+             println(Error { message => \"Can't say \\\"hi\\\"\\n\", quote => '\\'' });\n    \
+             println((\"it's\", '\"'));\n}\n",
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(
+            out,
+            "Error { message: \"Can't say \\\"hi\\\"\\n\", quote: '\\'' }\n(\"it's\", '\"')\n"
+        );
+    }
+
+    #[test]
+    fn traces_panics_to_the_source() {
+        let source = "def first(o: Option<int>) int {\n    return o->unwrap();\n}\n\n\
+                      def main() void {\n    println(first(Option::Some(1)));\n    \
+                      println(first(Option::None));\n}\n";
+        let (out, result) = run_source(source);
+        assert_eq!(out, "1\n");
+        let panic = result.unwrap_err();
+        assert_eq!(panic.message, "called `Option::unwrap` on a `None` value");
+        let frames: Vec<(&str, usize)> = panic
+            .frames
+            .iter()
+            .map(|f| {
+                (
+                    f.function.as_str(),
+                    f.loc.as_ref().map_or(0, |loc| loc.line),
+                )
+            })
+            .collect();
+        assert_eq!(
+            frames,
+            [("Option<int>::unwrap", 29), ("first", 2), ("main", 7)]
+        );
+        let rendered = panic.render(|_| PRELUDE);
+        assert!(
+            rendered.starts_with("Error at <prelude>:29:29:"),
+            "{rendered}"
+        );
+        assert!(rendered.contains("Option::None => panic("), "{rendered}");
+        assert!(
+            rendered.ends_with(
+                "Panic in `Option<int>::unwrap`: called `Option::unwrap` on a `None` value\n\
+                 Stack trace:\n    at Option<int>::unwrap (<prelude>:29:29)\n    \
+                 at first (main.paca:2:12)\n    at main (main.paca:7:13)"
+            ),
+            "{rendered}"
+        );
+    }
+
+    #[test]
+    fn collapses_repeated_frames() {
+        let frame = |function: &str| Frame {
+            function: function.to_string(),
+            position: Position::Function(0),
+            loc: None,
+        };
+        let mut frames = vec![frame("d"); 99_999];
+        frames.extend(
+            [frame("even"), frame("odd")]
+                .iter()
+                .cycle()
+                .take(6)
+                .cloned(),
+        );
+        frames.push(frame("main"));
+        let panic = Panic {
+            message: "Stack overflow.".to_string(),
+            frames,
+        };
+        assert_eq!(
+            panic.render(|_| ""),
+            "Panic in `d`: Stack overflow.
+Stack trace:
+    at d
+    ... 99,998 more frames of `d`
+    at even
+    at odd
+    ... 4 more frames repeating the 2 above
+    at main"
+        );
+    }
+}
@@ -0,0 +1,484 @@
+//! The memory layout of PIR types: the size and alignment of their values, where their fields are
+//! and how enums store which variant they are, for a target with 8-byte pointers.
+//!
+//! Structs are references, so a struct value is a pointer to its fields. Strings and arrays are
+//! pointers too. Every heap allocation is aligned to 8 bytes, and pointers to it are never null.
+//! Tuples and closures lay their parts out in order, like C structs. Enums are values, except that
+//! an enum containing itself through its variants is boxed like a struct, as it would have no
+//! finite size otherwise.
+//!
+//! The invalid values of a scalar, like the null pointer or the values of a `bool` other than 0
+//! and 1, are its niche. An enum with one variant carrying data and others carrying none stores
+//! the others as values of a niche in the data, so `Option` of a struct is just a nullable
+//! pointer. An enum whose variants are each a single pointer stores its tag in the low bits of the
+//! pointer, which alignment leaves zero. Other enums store their discriminant in the smallest
+//! integer that fits it, before the fields, and the values it doesn't take are a niche in turn.
+
+use super::{AdtId, AdtKind, Module, Type};
+use std::fmt::Write;
+
+pub const POINTER_SIZE: u64 = 8;
+
+/// How many low bits of a pointer to a heap allocation are always zero.
+const POINTER_FREE_BITS: u32 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u64,
+    pub align: u64,
+    /// The offsets of the elements of a tuple, the function and environment of a closure, or the
+    /// fields of a struct's allocation.
+    pub fields: Vec<u64>,
+    /// How an enum stores its variants.
+    pub variants: Option<Variants>,
+    /// The scalar with the most invalid values in the value, for an enum around it to use.
+    pub niche: Option<Niche>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variants {
+    pub tag: Tag,
+    /// The offsets of the fields of every variant.
+    pub fields: Vec<Vec<u64>>,
+}
+
+/// Where an enum keeps which variant it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tag {
+    /// There is at most one variant.
+    None,
+    /// An integer of `size` bytes at `offset` holds the discriminant.
+    Direct { offset: u64, size: u64 },
+    /// Only the variant `dataful` carries data, and each of the others, in order, is an invalid
+    /// value of the scalar of `niche`, starting from `first`.
+    Niche {
+        dataful: usize,
+        niche: Niche,
+        first: u128,
+    },
+    /// Every variant is a single pointer, whose low `bits` bits hold the index of the variant.
+    PointerBits { bits: u32 },
+}
+
+/// An unsigned integer scalar of `size` bytes at `offset` whose valid values are `valid_start` to
+/// `valid_end`, so the values around them are free.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Niche {
+    pub offset: u64,
+    pub size: u64,
+    pub valid_start: u128,
+    pub valid_end: u128,
+}
+
+impl Niche {
+    fn max(&self) -> u128 {
+        u128::MAX >> (128 - 8 * self.size)
+    }
+
+    /// How many invalid values the scalar has.
+    pub fn available(&self) -> u128 {
+        self.max() - self.valid_end + self.valid_start
+    }
+
+    /// Take `count` invalid values, above the valid ones if they fit or below them otherwise,
+    /// returning the first value taken and what is left of the niche.
+    fn reserve(&self, count: u128) -> Option<(u128, Niche)> {
+        if count <= self.max() - self.valid_end {
+            let first = self.valid_end + 1;
+            let valid_end = self.valid_end + count;
+            Some((first, Niche { valid_end, ..*self }))
+        } else if count <= self.valid_start {
+            let first = self.valid_start - count;
+            Some((
+                first,
+                Niche {
+                    valid_start: first,
+                    ..*self
+                },
+            ))
+        } else {
+            None
+        }
+    }
+
+    fn at(self, offset: u64) -> Niche {
+        Niche {
+            offset: self.offset + offset,
+            ..self
+        }
+    }
+}
+
+/// The layouts of the types of a module.
+#[derive(Clone, Debug)]
+pub struct Layouts<'a> {
+    module: &'a Module,
+    boxed: Vec<bool>,
+    /// The layout of every enum value, or of the allocation of every struct and boxed enum.
+    adts: Vec<Layout>,
+}
+
+impl<'a> Layouts<'a> {
+    pub fn new(module: &'a Module) -> Self {
+        let boxed = (0..module.adts.len())
+            .map(|adt| match module.adts[adt].kind {
+                AdtKind::Struct(_) => true,
+                AdtKind::Enum(_) => contains(module, adt, adt, &mut Vec::new()),
+            })
+            .collect();
+        let mut layouts = Self {
+            module,
+            boxed,
+            adts: Vec::new(),
+        };
+        let mut adts: Vec<Option<Layout>> = vec![None; module.adts.len()];
+        for adt in 0..module.adts.len() {
+            layouts.adt(adt, &mut adts);
+        }
+        layouts.adts = adts.into_iter().map(Option::unwrap).collect();
+        layouts
+    }
+
+    pub fn layout(&self, ty: &Type) -> Layout {
+        self.layout_with(ty, &mut |adt| self.adts[adt].clone())
+    }
+
+    pub fn size_of(&self, ty: &Type) -> u64 {
+        self.layout(ty).size
+    }
+
+    pub fn align_of(&self, ty: &Type) -> u64 {
+        self.layout(ty).align
+    }
+
+    /// Whether values of the struct or enum are pointers to an allocation.
+    pub fn is_boxed(&self, adt: AdtId) -> bool {
+        self.boxed[adt]
+    }
+
+    /// The layout of the allocation a struct or boxed enum points to.
+    pub fn allocation(&self, adt: AdtId) -> Option<&Layout> {
+        self.boxed[adt].then(|| &self.adts[adt])
+    }
+
+    /// The layout of the type, getting those of enum values from `adt`.
+    fn layout_with(&self, ty: &Type, adt: &mut dyn FnMut(AdtId) -> Layout) -> Layout {
+        match ty {
+            Type::Void => scalar(0, None),
+            Type::Bool => scalar(1, Some((0, 1))),
+            Type::Int | Type::Float => scalar(8, None),
+            Type::Char => scalar(4, Some((0, char::MAX as u128))),
+            Type::Str | Type::Ptr(_) | Type::Array(_) => pointer(),
+            Type::Adt(id) if self.boxed[*id] => pointer(),
+            Type::Adt(id) => adt(*id),
+            Type::Tuple(elems) => {
+                let elems: Vec<Layout> = elems.iter().map(|e| self.layout_with(e, adt)).collect();
+                record(0, &elems)
+            }
+            Type::Fn(_) => record(0, &[pointer(), pointer()]),
+        }
+    }
+
+    /// The layout of the enum value or struct allocation, after those of the enums it holds,
+    /// which can't hold it in turn since it would be boxed then.
+    fn adt(&self, id: AdtId, adts: &mut [Option<Layout>]) -> Layout {
+        if let Some(layout) = &adts[id] {
+            return layout.clone();
+        }
+        let mut field = |ty: &Type| self.layout_with(ty, &mut |inner| self.adt(inner, adts));
+        let layout = match &self.module.adts[id].kind {
+            AdtKind::Struct(fields) => {
+                let fields: Vec<Layout> = fields.iter().map(|f| field(&f.ty)).collect();
+                record(0, &fields)
+            }
+            AdtKind::Enum(variants) => {
+                let fields: Vec<Vec<Layout>> = variants
+                    .iter()
+                    .map(|v| v.fields.iter().map(&mut field).collect())
+                    .collect();
+                let discriminants: Vec<i64> = variants.iter().map(|v| v.discriminant).collect();
+                let single_pointers = variants.iter().all(|v| match &v.fields[..] {
+                    [Type::Str | Type::Array(_)] => true,
+                    [Type::Adt(adt)] => self.boxed[*adt],
+                    _ => false,
+                });
+                enum_layout(&fields, &discriminants, single_pointers)
+            }
+        };
+        adts[id] = Some(layout.clone());
+        layout
+    }
+}
+
+/// Whether the enum holds a value of the `target` enum without going through a pointer, given
+/// the enums already looked into.
+fn contains(module: &Module, adt: AdtId, target: AdtId, seen: &mut Vec<AdtId>) -> bool {
+    let AdtKind::Enum(variants) = &module.adts[adt].kind else {
+        return false;
+    };
+    if seen.contains(&adt) {
+        return false;
+    }
+    seen.push(adt);
+    let inner: Vec<AdtId> = variants
+        .iter()
+        .flat_map(|variant| &variant.fields)
+        .flat_map(inline_adts)
+        .collect();
+    inner
+        .into_iter()
+        .any(|inner| inner == target || contains(module, inner, target, seen))
+}
+
+/// The structs and enums the type holds without going through a pointer.
+fn inline_adts(ty: &Type) -> Box<dyn Iterator<Item = AdtId> + '_> {
+    match ty {
+        Type::Adt(adt) => Box::new(std::iter::once(*adt)),
+        Type::Tuple(elems) => Box::new(elems.iter().flat_map(inline_adts)),
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+fn scalar(size: u64, valid: Option<(u128, u128)>) -> Layout {
+    Layout {
+        size,
+        align: size.max(1),
+        fields: Vec::new(),
+        variants: None,
+        niche: valid.map(|(valid_start, valid_end)| Niche {
+            offset: 0,
+            size,
+            valid_start,
+            valid_end,
+        }),
+    }
+}
+
+fn pointer() -> Layout {
+    scalar(POINTER_SIZE, Some((1, u64::MAX as u128)))
+}
+
+fn align_to(offset: u64, align: u64) -> u64 {
+    offset.div_ceil(align) * align
+}
+
+/// The fields laid out in order from `start`, keeping the niche with the most free values.
+fn record(start: u64, fields: &[Layout]) -> Layout {
+    let mut offset = start;
+    let mut align = 1;
+    let mut offsets = Vec::new();
+    let mut niche: Option<Niche> = None;
+    for field in fields {
+        offset = align_to(offset, field.align);
+        offsets.push(offset);
+        if let Some(inner) = field.niche {
+            if niche.is_none_or(|n| inner.available() > n.available()) {
+                niche = Some(inner.at(offset));
+            }
+        }
+        offset += field.size;
+        align = align.max(field.align);
+    }
+    Layout {
+        size: align_to(offset, align),
+        align,
+        fields: offsets,
+        variants: None,
+        niche,
+    }
+}
+
+/// The layout of an enum with the fields of its variants, where `single_pointers` tells whether
+/// every variant is a single pointer to a heap allocation.
+fn enum_layout(variants: &[Vec<Layout>], discriminants: &[i64], single_pointers: bool) -> Layout {
+    let records: Vec<Layout> = variants.iter().map(|fields| record(0, fields)).collect();
+    let offsets = |records: &[Layout]| records.iter().map(|r| r.fields.clone()).collect();
+    if records.len() <= 1 {
+        let mut layout = records
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| scalar(0, None));
+        layout.variants = Some(Variants {
+            tag: Tag::None,
+            fields: vec![layout.fields.clone(); variants.len()],
+        });
+        layout.fields = Vec::new();
+        return layout;
+    }
+
+    let dataful: Vec<usize> = (0..records.len())
+        .filter(|&v| records[v].size > 0)
+        .collect();
+    if let [dataful] = dataful[..] {
+        let others = records.len() as u128 - 1;
+        if let Some((first, niche)) = records[dataful].niche.and_then(|n| n.reserve(others)) {
+            let record = &records[dataful];
+            return Layout {
+                size: record.size,
+                align: record.align,
+                fields: Vec::new(),
+                variants: Some(Variants {
+                    tag: Tag::Niche {
+                        dataful,
+                        niche: record.niche.unwrap(),
+                        first,
+                    },
+                    fields: offsets(&records),
+                }),
+                niche: Some(niche),
+            };
+        }
+    }
+
+    let bits = u64::BITS - (records.len() as u64 - 1).leading_zeros();
+    if single_pointers && bits <= POINTER_FREE_BITS {
+        return Layout {
+            variants: Some(Variants {
+                tag: Tag::PointerBits { bits },
+                fields: offsets(&records),
+            }),
+            ..scalar(POINTER_SIZE, None)
+        };
+    }
+
+    let min = discriminants.iter().copied().min().unwrap_or(0);
+    let max = discriminants.iter().copied().max().unwrap_or(0);
+    let tag_size = [1, 2, 4, 8]
+        .into_iter()
+        .find(|&size: &u64| {
+            let bits = 8 * size as u32;
+            let (low, high) = if min < 0 {
+                (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1)
+            } else {
+                (0, (1i128 << bits) - 1)
+            };
+            low <= min as i128 && max as i128 <= high
+        })
+        .unwrap_or(8);
+    let records: Vec<Layout> = variants
+        .iter()
+        .map(|fields| record(tag_size, fields))
+        .collect();
+    let align = records.iter().map(|r| r.align).fold(tag_size, u64::max);
+    let size = records.iter().map(|r| r.size).fold(tag_size, u64::max);
+    Layout {
+        size: align_to(size, align),
+        align,
+        fields: Vec::new(),
+        variants: Some(Variants {
+            tag: Tag::Direct {
+                offset: 0,
+                size: tag_size,
+            },
+            fields: offsets(&records),
+        }),
+        niche: (min >= 0).then_some(Niche {
+            offset: 0,
+            size: tag_size,
+            valid_start: min as u128,
+            valid_end: max as u128,
+        }),
+    }
+}
+
+/// Describe the layout of every struct and enum of the module, one per line.
+pub fn describe(module: &Module) -> String {
+    let layouts = Layouts::new(module);
+    let mut out = String::new();
+    for (id, adt) in module.adts.iter().enumerate() {
+        let value = layouts.layout(&Type::Adt(id));
+        write!(
+            out,
+            "{}: size {}, align {}",
+            adt.name, value.size, value.align
+        )
+        .unwrap();
+        let layout = layouts.allocation(id).unwrap_or(&value);
+        if layouts.is_boxed(id) {
+            write!(out, ", pointing to {} bytes", layout.size).unwrap();
+        }
+        let names: Vec<&str> = match &adt.kind {
+            AdtKind::Enum(variants) => variants.iter().map(|v| v.name.as_str()).collect(),
+            AdtKind::Struct(_) => Vec::new(),
+        };
+        match layout.variants.as_ref().map(|v| &v.tag) {
+            Some(Tag::Direct { offset, size }) => {
+                write!(out, ", {size}-byte tag at {offset}").unwrap()
+            }
+            Some(Tag::Niche {
+                dataful,
+                niche,
+                first,
+            }) => {
+                let others: Vec<String> = (0..names.len())
+                    .filter(|v| v != dataful)
+                    .zip(*first..)
+                    .map(|(v, value)| format!("{} = {value}", names[v]))
+                    .collect();
+                write!(
+                    out,
+                    ", tag in the niche of the {}-byte scalar at {} ({})",
+                    niche.size,
+                    niche.offset,
+                    others.join(", ")
+                )
+                .unwrap()
+            }
+            Some(Tag::PointerBits { bits }) => {
+                write!(out, ", tag in the low {bits} bits of the pointer").unwrap()
+            }
+            Some(Tag::None) | None => {}
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn fills_niches() {
+        let module = parse_module(
+            None,
+            "struct Point { x: int, y: int }
+enum \"Option<int>\" { Some(int) = 0, None = 1 }
+enum \"Option<Point>\" { Some(Point) = 0, None = 1 }
+enum \"Option<bool>\" { Some(bool) = 0, None = 1 }
+enum \"Option<Option<bool>>\" { Some(\"Option<bool>\") = 0, None = 1 }
+enum \"Either<Point, str>\" { Left(Point) = 0, Right(str) = 1 }
+enum Shape { Dot(char, bool) = 0, Line(Point, Point) = 1, Empty = 2 }
+enum List { Cons((int, List)) = 0, Nil = 1 }
+",
+        )
+        .unwrap();
+        let layouts = Layouts::new(&module);
+        let size_align = |adt: AdtId| {
+            let layout = layouts.layout(&Type::Adt(adt));
+            (layout.size, layout.align)
+        };
+        assert_eq!(size_align(0), (8, 8));
+        assert_eq!(layouts.allocation(0).unwrap().fields, [0, 8]);
+        assert_eq!(size_align(1), (16, 8));
+        assert_eq!(size_align(2), (8, 8));
+        assert_eq!(size_align(3), (1, 1));
+        assert_eq!(size_align(4), (1, 1));
+        let Tag::Niche { first, .. } = layouts.layout(&Type::Adt(4)).variants.unwrap().tag else {
+            panic!("expected a niche");
+        };
+        assert_eq!(first, 3);
+        assert_eq!(size_align(5), (8, 8));
+        assert_eq!(size_align(6), (24, 8));
+        assert_eq!(
+            layouts.layout(&Type::Adt(6)).variants.unwrap().fields,
+            [vec![4, 8], vec![8, 16], vec![]]
+        );
+        assert_eq!(size_align(7), (8, 8));
+        assert!(layouts.is_boxed(7));
+        assert_eq!(layouts.allocation(7).unwrap().size, 16);
+        let tuple = Type::Tuple(vec![Type::Bool, Type::Int, Type::Char]);
+        assert_eq!((layouts.size_of(&tuple), layouts.align_of(&tuple)), (24, 8));
+    }
+}
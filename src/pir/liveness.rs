@@ -0,0 +1,129 @@
+//! Liveness of values at the edges of blocks.
+//!
+//! A value is live at a point if a path from there uses it before the end of the function. Block
+//! parameters are defined at the start of their block, and the arguments of a jump are used at
+//! the end of the block jumping, so a value passed along a back edge is live around the loop only
+//! up to the jump.
+
+use super::dom::{predecessors, reverse_postorder};
+use super::{BlockId, Function, ValueId};
+use std::collections::BTreeSet;
+
+#[derive(Clone, Debug)]
+pub struct Liveness {
+    live_in: Vec<BTreeSet<ValueId>>,
+    live_out: Vec<BTreeSet<ValueId>>,
+}
+
+impl Liveness {
+    pub fn new(function: &Function) -> Self {
+        let count = function.blocks.len();
+        // The values every block uses before defining them, and the values it defines.
+        let mut uses = vec![BTreeSet::new(); count];
+        let mut defs = vec![BTreeSet::new(); count];
+        for (id, block) in function.blocks.iter().enumerate() {
+            defs[id].extend(&block.params);
+            for inst in &block.insts {
+                for value in inst.kind.operands() {
+                    if !defs[id].contains(&value) {
+                        uses[id].insert(value);
+                    }
+                }
+                defs[id].insert(inst.result);
+            }
+            let mut term = block.term.clone();
+            for value in term.operands_mut() {
+                if !defs[id].contains(value) {
+                    uses[id].insert(*value);
+                }
+            }
+        }
+
+        let preds = predecessors(function);
+        let mut live_in = uses.clone();
+        let mut live_out = vec![BTreeSet::new(); count];
+        // Going backwards over the reverse postorder visits successors first, apart from back
+        // edges, which the worklist picks up.
+        let mut worklist: Vec<BlockId> = reverse_postorder(function);
+        let mut queued = vec![false; count];
+        for &block in &worklist {
+            queued[block] = true;
+        }
+        while let Some(block) = worklist.pop() {
+            queued[block] = false;
+            let out: BTreeSet<ValueId> = function.blocks[block]
+                .term
+                .targets()
+                .into_iter()
+                .flat_map(|target| live_in[target.block].iter().copied())
+                .collect();
+            let new_in: BTreeSet<ValueId> = uses[block]
+                .iter()
+                .copied()
+                .chain(out.difference(&defs[block]).copied())
+                .collect();
+            live_out[block] = out;
+            if new_in != live_in[block] {
+                live_in[block] = new_in;
+                for &pred in &preds[block] {
+                    if !std::mem::replace(&mut queued[pred], true) {
+                        worklist.push(pred);
+                    }
+                }
+            }
+        }
+        Self { live_in, live_out }
+    }
+
+    /// The values live at the start of the block, not counting its parameters.
+    pub fn live_in(&self, block: BlockId) -> &BTreeSet<ValueId> {
+        &self.live_in[block]
+    }
+
+    /// The values live after the block's terminator, not counting the arguments it passes.
+    pub fn live_out(&self, block: BlockId) -> &BTreeSet<ValueId> {
+        &self.live_out[block]
+    }
+
+    pub fn is_live_out(&self, block: BlockId, value: ValueId) -> bool {
+        self.live_out[block].contains(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn computes_liveness_around_loops() {
+        let module = parse_module(
+            None,
+            "def f(%0: int, %1: int) int {
+bb0:
+    %2: int = const 0
+    jump bb1(%2)
+bb1(%3: int):
+    %4: bool = lt %3, %0
+    branch %4, bb2, bb3
+bb2:
+    %5: int = add %3, %1
+    jump bb1(%5)
+bb3:
+    return %3
+}
+",
+        )
+        .unwrap();
+        let liveness = Liveness::new(&module.functions[0]);
+        let set = |values: &[ValueId]| values.iter().copied().collect::<BTreeSet<_>>();
+        assert!(liveness.live_in(0).is_empty());
+        assert_eq!(liveness.live_out(0), &set(&[0, 1]));
+        assert_eq!(liveness.live_in(1), &set(&[0, 1]));
+        assert_eq!(liveness.live_out(1), &set(&[0, 1, 3]));
+        assert_eq!(liveness.live_in(2), &set(&[0, 1, 3]));
+        assert_eq!(liveness.live_out(2), &set(&[0, 1]));
+        assert_eq!(liveness.live_in(3), &set(&[3]));
+        assert!(liveness.live_out(3).is_empty() && !liveness.is_live_out(0, 2));
+    }
+}
@@ -0,0 +1,142 @@
+//! Natural loops and how they nest.
+//!
+//! Every edge to a block that dominates its source is a back edge, and the block a loop header.
+//! The loop of a header holds the blocks that reach a back edge to it without going through it.
+//! Loops sharing a header are one loop, so two loops are either disjoint or one is inside the
+//! other.
+
+use super::dom::{predecessors, DomTree};
+use super::{BlockId, Function};
+
+pub type LoopId = usize;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loop {
+    pub header: BlockId,
+    /// The blocks of the loop, header first, including those of the loops inside it.
+    pub blocks: Vec<BlockId>,
+    /// The blocks jumping back to the header.
+    pub latches: Vec<BlockId>,
+    /// The innermost loop around this one.
+    pub parent: Option<LoopId>,
+    /// How many loops this one is in, counting itself, so an outermost loop has a depth of 1.
+    pub depth: usize,
+}
+
+/// The loops of a function, inner loops before the loops around them.
+#[derive(Clone, Debug)]
+pub struct LoopInfo {
+    pub loops: Vec<Loop>,
+    /// The innermost loop of every block.
+    innermost: Vec<Option<LoopId>>,
+}
+
+impl LoopInfo {
+    pub fn new(function: &Function, dom: &DomTree) -> Self {
+        let preds = predecessors(function);
+        let mut loops = Vec::new();
+        for header in 0..function.blocks.len() {
+            let latches: Vec<BlockId> = preds[header]
+                .iter()
+                .copied()
+                .filter(|&pred| dom.dominates(header, pred))
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut blocks = vec![header];
+            let mut stack = latches.clone();
+            while let Some(block) = stack.pop() {
+                if !blocks.contains(&block) {
+                    blocks.push(block);
+                    stack.extend(preds[block].iter().copied());
+                }
+            }
+            loops.push(Loop {
+                header,
+                blocks,
+                latches,
+                parent: None,
+                depth: 1,
+            });
+        }
+        // A loop inside another has fewer blocks, so sorting by size puts inner loops first, and
+        // the first bigger loop containing a loop's header is its parent.
+        loops.sort_by_key(|l| l.blocks.len());
+        for inner in 0..loops.len() {
+            let header = loops[inner].header;
+            loops[inner].parent = (inner + 1..loops.len()).find(|&outer| {
+                loops[outer].header != header && loops[outer].blocks.contains(&header)
+            });
+        }
+        for id in (0..loops.len()).rev() {
+            if let Some(parent) = loops[id].parent {
+                loops[id].depth = loops[parent].depth + 1;
+            }
+        }
+        let mut innermost = vec![None; function.blocks.len()];
+        for (id, l) in loops.iter().enumerate() {
+            for &block in &l.blocks {
+                innermost[block].get_or_insert(id);
+            }
+        }
+        Self { loops, innermost }
+    }
+
+    /// The innermost loop the block is in.
+    pub fn loop_of(&self, block: BlockId) -> Option<LoopId> {
+        self.innermost[block]
+    }
+
+    /// How many loops the block is in.
+    pub fn depth(&self, block: BlockId) -> usize {
+        self.loop_of(block).map_or(0, |l| self.loops[l].depth)
+    }
+
+    /// Whether the jump from `from` to `to` goes back to the header of a loop.
+    pub fn is_back_edge(&self, from: BlockId, to: BlockId) -> bool {
+        self.loops
+            .iter()
+            .any(|l| l.header == to && l.latches.contains(&from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn finds_nested_loops() {
+        let module = parse_module(
+            None,
+            "def f(%0: bool) void {
+bb0:
+    jump outer
+outer:
+    branch %0, inner, exit
+inner:
+    branch %0, body, latch
+body:
+    jump inner
+latch:
+    jump outer
+exit:
+    return
+}
+",
+        )
+        .unwrap();
+        let function = &module.functions[0];
+        let info = LoopInfo::new(function, &DomTree::new(function));
+        assert_eq!(info.loops.len(), 2);
+        let (inner, outer) = (&info.loops[0], &info.loops[1]);
+        assert_eq!((inner.header, inner.blocks.len(), inner.depth), (2, 2, 2));
+        assert_eq!((outer.header, outer.blocks.len(), outer.depth), (1, 4, 1));
+        assert_eq!(inner.parent, Some(1));
+        assert_eq!(outer.latches, [4]);
+        let depths: Vec<_> = (0..6).map(|b| info.depth(b)).collect();
+        assert_eq!(depths, [0, 1, 2, 2, 1, 0]);
+        assert!(info.is_back_edge(3, 2) && !info.is_back_edge(2, 3));
+    }
+}
@@ -0,0 +1,1557 @@
+//! Lowering of a checked program to PIR.
+//!
+//! Every instance found by monomorphization becomes a function, and so does every instance the
+//! lowered code calls that monomorphization doesn't list, such as the methods of operator impls on
+//! generic structs. Generic parameters are replaced by the instance's arguments throughout, so the
+//! types of the PIR are concrete.
+//!
+//! Variables declared `mut` and those declared without a value live in memory; every other binding
+//! is the value it's bound to. Operators on primitive types become instructions, and those on
+//! structs and enums calls to the methods of their operator impls. A `match` tests the arms in
+//! order, each pattern branching to the next arm as soon as a part of it doesn't match.
+
+use super::{
+    AdtDef, AdtId, AdtKind, Alloc, BinaryOp, BlockId, CompareOp, Const, Field, FnType, FuncId,
+    Function, InstKind, Module, Target, Terminator, Type, UnaryOp, ValueId, Variant,
+};
+use crate::parse::ast::{
+    self, Block, Closure, ClosureBody, Expr, ExprKind, Literal, Path, Pattern, PatternKind, Stmt,
+    StmtKind,
+};
+use crate::parse::SourceCodeLocation;
+use crate::sema::builtins::{self, Native, Primitive};
+use crate::sema::closures::{CaptureMode, Var};
+use crate::sema::consts::ConstValue;
+use crate::sema::modules::{ModuleGraph, ModuleId};
+use crate::sema::mono::{self, Callee, Instance};
+use crate::sema::ops::{assign_trait, binary_dispatch, unary_trait, HASHABLE};
+use crate::sema::resolve::{LocalId, Res};
+use crate::sema::ty::Ty;
+use crate::sema::typeck::{self, AdtKind as SemaAdtKind, MethodId};
+use crate::sema::Analysis;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Lower every instance of a program that passed the semantic checks.
+pub fn lower(graph: &ModuleGraph, analysis: &Analysis) -> Module {
+    let mut lowerer = Lowerer {
+        graph,
+        analysis,
+        module: Module::default(),
+        adts: HashMap::new(),
+        instances: HashMap::new(),
+        wrappers: HashMap::new(),
+        names: HashSet::new(),
+        queue: VecDeque::new(),
+    };
+    for instance in &analysis.instances.list {
+        lowerer.instance(instance.clone());
+    }
+    while let Some((func, instance)) = lowerer.queue.pop_front() {
+        lowerer.body(func, &instance);
+    }
+    lowerer.module
+}
+
+/// A function made to use something that isn't a closure as a value of a function type.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Wrapper {
+    Fn(FuncId),
+    Variant(AdtId, usize),
+    Native(&'static str, FnType),
+}
+
+struct Lowerer<'a> {
+    graph: &'a ModuleGraph,
+    analysis: &'a Analysis,
+    module: Module,
+    /// The struct or enum made for every instantiated type.
+    adts: HashMap<Ty, AdtId>,
+    instances: HashMap<Instance, FuncId>,
+    wrappers: HashMap<Wrapper, FuncId>,
+    /// The names of the functions so far, to keep them unique.
+    names: HashSet<String>,
+    /// The instances whose functions are declared but don't have a body yet.
+    queue: VecDeque<(FuncId, Instance)>,
+}
+
+impl Lowerer<'_> {
+    /// The function of an instance, declared and queued for lowering the first time it's used.
+    fn instance(&mut self, instance: Instance) -> FuncId {
+        if let Some(&func) = self.instances.get(&instance) {
+            return func;
+        }
+        let items = &self.analysis.results.items;
+        let substs = mono::substs(items, &instance);
+        let (sig, name) = match instance.callee {
+            Callee::Fn(def) => {
+                let sig = &items.fns[&def];
+                let module = &self.graph.modules[def.module];
+                let mut name = mono::decl(self.graph, items, instance.callee)
+                    .1
+                    .name
+                    .name
+                    .clone();
+                if def.module != 0 {
+                    name = format!("{}::{name}", module.display_name());
+                }
+                (sig, name + &generic_args(&instance.args))
+            }
+            Callee::Method(id) => {
+                let method = items.method(id);
+                let imp = &items.impls[&id.imp];
+                let own = &instance.args[imp.generics.len().min(instance.args.len())..];
+                let name = format!(
+                    "{}::{}{}",
+                    imp.target.subst(&substs),
+                    method.name,
+                    generic_args(own)
+                );
+                (&method.sig, name)
+            }
+        };
+        let mut params = Vec::new();
+        if let Callee::Method(id) = instance.callee {
+            if sig.has_self {
+                params.push(self.ty(&items.impls[&id.imp].target.subst(&substs)));
+            }
+        }
+        for param in &sig.params {
+            params.push(self.ty(&param.subst(&substs)));
+        }
+        let ret = self.ty(&sig.ret.subst(&substs));
+        let func = self.declare(name, params, ret);
+        self.instances.insert(instance.clone(), func);
+        self.queue.push_back((func, instance));
+        func
+    }
+
+    /// Add a function without a body under a unique name.
+    fn declare(&mut self, name: String, params: Vec<Type>, ret: Type) -> FuncId {
+        let mut unique = name.clone();
+        let mut count = 1;
+        while !self.names.insert(unique.clone()) {
+            count += 1;
+            unique = format!("{name}.{count}");
+        }
+        self.module
+            .functions
+            .push(Function::new(unique, params, ret));
+        self.module.functions.len() - 1
+    }
+
+    fn body(&mut self, func: FuncId, instance: &Instance) {
+        let items = &self.analysis.results.items;
+        let (module, decl) = mono::decl(self.graph, items, instance.callee);
+        let substs = mono::substs(items, instance);
+        self.module.functions[func].tailcall = decl.tailcall.clone();
+        let mut lowerer = FnLowerer::new(self, func, module, substs);
+        let mut params = lowerer.function().params().to_vec().into_iter();
+        if decl.self_param.is_some() {
+            lowerer.self_value = params.next();
+        }
+        for (param, value) in decl.params.iter().zip(params) {
+            lowerer.irrefutable(&param.pattern, value);
+        }
+        let body = decl.body.as_ref().expect("instances have a body");
+        lowerer.block(body);
+        lowerer.finish();
+    }
+
+    /// The PIR type of a concrete type.
+    fn ty(&mut self, ty: &Ty) -> Type {
+        match ty {
+            Ty::Primitive(primitive) => match primitive {
+                Primitive::Int => Type::Int,
+                Primitive::Float => Type::Float,
+                Primitive::Str => Type::Str,
+                Primitive::Char => Type::Char,
+                Primitive::Bool => Type::Bool,
+                Primitive::Void | Primitive::Never => Type::Void,
+            },
+            Ty::Adt { .. } => Type::Adt(self.adt(ty)),
+            Ty::Array(elem) => Type::Array(Box::new(self.ty(elem))),
+            Ty::Tuple(elems) => Type::Tuple(elems.iter().map(|e| self.ty(e)).collect()),
+            Ty::Fn(fn_ty) => Type::Fn(Box::new(FnType {
+                params: fn_ty.params.iter().map(|p| self.ty(p)).collect(),
+                ret: self.ty(&fn_ty.ret),
+            })),
+            // Only values nothing can be done with, such as the elements of an empty array, have
+            // types that are still unknown after substitution.
+            Ty::Param(_) | Ty::Var(_) | Ty::Error => Type::Void,
+        }
+    }
+
+    /// The struct or enum of an instantiated type, made the first time it's used.
+    fn adt(&mut self, ty: &Ty) -> AdtId {
+        if let Some(&id) = self.adts.get(ty) {
+            return id;
+        }
+        let Ty::Adt { def, args, .. } = ty else {
+            unreachable!("only structs and enums are ADTs");
+        };
+        let id = self.module.adts.len();
+        // The placeholder lets the fields refer to the type itself.
+        self.module.adts.push(AdtDef {
+            name: ty.to_string(),
+            kind: AdtKind::Struct(Vec::new()),
+        });
+        self.adts.insert(ty.clone(), id);
+
+        let sema_adt = &self.analysis.results.items.adts[def];
+        let substs = sema_adt
+            .generics
+            .iter()
+            .map(|g| g.name.clone())
+            .zip(args.iter().cloned())
+            .collect::<HashMap<_, _>>();
+        let kind = match &sema_adt.kind {
+            SemaAdtKind::Struct(fields) => AdtKind::Struct(
+                fields
+                    .iter()
+                    .map(|field| Field {
+                        name: field.name.clone(),
+                        ty: self.ty(&field.ty.subst(&substs)),
+                    })
+                    .collect(),
+            ),
+            SemaAdtKind::Enum(variants) => {
+                let discriminants = self.analysis.consts.discriminants(*def);
+                AdtKind::Enum(
+                    variants
+                        .iter()
+                        .enumerate()
+                        .map(|(i, variant)| Variant {
+                            name: variant.name.clone(),
+                            discriminant: discriminants.map_or(i as i64, |d| d[i]),
+                            fields: variant
+                                .fields
+                                .iter()
+                                .map(|f| self.ty(&f.subst(&substs)))
+                                .collect(),
+                        })
+                        .collect(),
+                )
+            }
+        };
+        self.module.adts[id].kind = kind;
+        id
+    }
+
+    /// A function with an empty environment first, so that `call` can be used as a closure.
+    fn wrapper(
+        &mut self,
+        key: Wrapper,
+        name: String,
+        fn_ty: FnType,
+        call: impl FnOnce(Vec<ValueId>) -> InstKind,
+        diverges: bool,
+    ) -> FuncId {
+        if let Some(&func) = self.wrappers.get(&key) {
+            return func;
+        }
+        let mut params = vec![Type::Tuple(Vec::new())];
+        params.extend(fn_ty.params);
+        let func = self.declare(name, params, fn_ty.ret.clone());
+        let function = &mut self.module.functions[func];
+        let args = function.params()[1..].to_vec();
+        let result = function.push(0, call(args), fn_ty.ret.clone());
+        function.blocks[0].term = if diverges {
+            Terminator::Unreachable
+        } else if fn_ty.ret == Type::Void {
+            Terminator::Return(None)
+        } else {
+            Terminator::Return(Some(result))
+        };
+        self.wrappers.insert(key, func);
+        func
+    }
+}
+
+/// `<int, str>`, or nothing without arguments.
+fn generic_args(args: &[Ty]) -> String {
+    if args.is_empty() {
+        return String::new();
+    }
+    let args = args.iter().map(Ty::to_string).collect::<Vec<_>>();
+    format!("<{}>", args.join(", "))
+}
+
+/// Where the value of a local is.
+#[derive(Clone, Copy, Debug)]
+enum Place {
+    Value(ValueId),
+    /// The address of the memory holding it.
+    Memory(ValueId),
+}
+
+/// Lowers the body of one function or closure.
+struct FnLowerer<'l, 'a> {
+    cx: &'l mut Lowerer<'a>,
+    func: FuncId,
+    module: ModuleId,
+    substs: HashMap<String, Ty>,
+    /// The block instructions are added to, `None` after a terminator.
+    current: Option<BlockId>,
+    locals: HashMap<LocalId, Place>,
+    self_value: Option<ValueId>,
+    /// The blocks `continue` and `break` jump to in the enclosing loops, innermost last.
+    loops: Vec<(BlockId, BlockId)>,
+    /// How many closures the function has so far, to name them.
+    closures: usize,
+    /// The location of the innermost expression or statement being lowered.
+    loc: Option<SourceCodeLocation>,
+}
+
+impl<'l, 'a> FnLowerer<'l, 'a> {
+    fn new(
+        cx: &'l mut Lowerer<'a>,
+        func: FuncId,
+        module: ModuleId,
+        substs: HashMap<String, Ty>,
+    ) -> Self {
+        Self {
+            cx,
+            func,
+            module,
+            substs,
+            current: Some(0),
+            locals: HashMap::new(),
+            self_value: None,
+            loops: Vec::new(),
+            closures: 0,
+            loc: None,
+        }
+    }
+
+    fn function(&mut self) -> &mut Function {
+        &mut self.cx.module.functions[self.func]
+    }
+
+    /// Return at the end of a body that falls off it, which only `void` functions do.
+    fn finish(&mut self) {
+        if self.function().ret == Type::Void {
+            self.terminate(Terminator::Return(None));
+        } else {
+            self.terminate(Terminator::Unreachable);
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.function().add_block()
+    }
+
+    /// The current block, or a new one without predecessors for code after a terminator, such as
+    /// the rest of the arguments after one that calls `panic`.
+    fn open_block(&mut self) -> BlockId {
+        match self.current {
+            Some(block) => block,
+            None => {
+                let block = self.new_block();
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    fn emit(&mut self, kind: InstKind, ty: Type) -> ValueId {
+        let block = self.open_block();
+        let loc = self.loc.clone();
+        let function = self.function();
+        let value = function.push(block, kind, ty);
+        if let Some(loc) = loc {
+            function.locs.insert(value, loc);
+        }
+        value
+    }
+
+    fn terminate(&mut self, term: Terminator) {
+        if let Some(block) = self.current.take() {
+            self.function().blocks[block].term = term;
+        }
+    }
+
+    /// Jump to `target` if the current block is open, making the target on the first jump.
+    fn jump_to(&mut self, target: &mut Option<BlockId>) {
+        if self.current.is_some() {
+            let block =
+                *target.get_or_insert_with(|| self.cx.module.functions[self.func].add_block());
+            self.terminate(Terminator::Jump(Target::new(block, Vec::new())));
+        }
+    }
+
+    /// Continue in `next` if `cond` holds, and branch to `fail` otherwise.
+    fn test(&mut self, cond: ValueId, fail: &mut Option<BlockId>) {
+        let next = self.new_block();
+        let fail = *fail.get_or_insert_with(|| self.cx.module.functions[self.func].add_block());
+        self.terminate(Terminator::Branch {
+            cond,
+            then_target: Target::new(next, Vec::new()),
+            else_target: Target::new(fail, Vec::new()),
+        });
+        self.current = Some(next);
+    }
+
+    fn value_ty(&mut self, value: ValueId) -> Type {
+        self.function().values[value].clone()
+    }
+
+    /// The type of an expression with the generic arguments of the instance substituted.
+    fn sema_ty(&self, expr: &Expr) -> Ty {
+        self.cx
+            .analysis
+            .results
+            .expr_ty(self.module, expr)
+            .map_or(Ty::Error, |ty| ty.subst(&self.substs))
+    }
+
+    fn expr_ty(&mut self, expr: &Expr) -> Type {
+        let ty = self.sema_ty(expr);
+        self.cx.ty(&ty)
+    }
+
+    fn res(&self, path: &Path) -> Option<Res> {
+        let last = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        self.cx.analysis.resolutions.get(self.module, &last.ident)
+    }
+
+    /// The native function a path names, either one every module can use or a `@native` one of std.
+    fn native(&self, path: &Path) -> Option<&'static Native> {
+        match self.res(path)? {
+            Res::Native(native) => Some(native),
+            Res::Def(def) => self.cx.analysis.results.items.natives.get(&def).copied(),
+            _ => None,
+        }
+    }
+
+    fn constant(&mut self, value: Const) -> ValueId {
+        let ty = match &value {
+            Const::Int(_) => Type::Int,
+            Const::Float(_) => Type::Float,
+            Const::Bool(_) => Type::Bool,
+            Const::Char(_) => Type::Char,
+            Const::Str(_) => Type::Str,
+            Const::Void => Type::Void,
+        };
+        self.emit(InstKind::Const(value), ty)
+    }
+
+    fn literal(&mut self, literal: &Literal) -> ValueId {
+        self.constant(match literal {
+            Literal::Int(n) => Const::Int(*n),
+            Literal::Float(n) => Const::Float(*n),
+            Literal::Str(s) => Const::Str(s.clone()),
+            Literal::Char(c) => Const::Char(*c),
+            Literal::Bool(b) => Const::Bool(*b),
+        })
+    }
+
+    fn const_value(&mut self, value: &ConstValue) -> ValueId {
+        match value {
+            ConstValue::Int(n) => self.constant(Const::Int(*n)),
+            ConstValue::Float(n) => self.constant(Const::Float(*n)),
+            ConstValue::Bool(b) => self.constant(Const::Bool(*b)),
+            ConstValue::Char(c) => self.constant(Const::Char(*c)),
+            ConstValue::Str(s) => self.constant(Const::Str(s.clone())),
+            ConstValue::Tuple(values) => {
+                let values = values
+                    .iter()
+                    .map(|v| self.const_value(v))
+                    .collect::<Vec<_>>();
+                let ty = Type::Tuple(values.iter().map(|&v| self.value_ty(v)).collect());
+                self.emit(InstKind::Tuple(values), ty)
+            }
+            ConstValue::Void => self.constant(Const::Void),
+        }
+    }
+
+    fn local_ty(&mut self, local: LocalId) -> Type {
+        let ty = self.cx.analysis.results.locals[local].subst(&self.substs);
+        self.cx.ty(&ty)
+    }
+
+    /// Bind a local to a value, storing it in memory if the local may be assigned to.
+    fn bind(&mut self, local: LocalId, value: ValueId) {
+        let place = if self.cx.analysis.resolutions.locals[local].mutable {
+            let ty = self.local_ty(local);
+            let address = self.emit(InstKind::Alloca(ty.clone()), Type::Ptr(Box::new(ty)));
+            self.emit(InstKind::Store(address, value), Type::Void);
+            Place::Memory(address)
+        } else {
+            Place::Value(value)
+        };
+        self.locals.insert(local, place);
+    }
+
+    fn read(&mut self, local: LocalId) -> ValueId {
+        match self.locals[&local] {
+            Place::Value(value) => value,
+            Place::Memory(address) => {
+                let ty = self.local_ty(local);
+                self.emit(InstKind::Load(address), ty)
+            }
+        }
+    }
+
+    /// The memory of a local, for a local declared without a value or captured by reference.
+    fn address(&mut self, local: LocalId) -> ValueId {
+        match self.locals[&local] {
+            Place::Memory(address) => address,
+            Place::Value(value) => {
+                let ty = self.local_ty(local);
+                let address = self.emit(InstKind::Alloca(ty.clone()), Type::Ptr(Box::new(ty)));
+                self.emit(InstKind::Store(address, value), Type::Void);
+                self.locals.insert(local, Place::Memory(address));
+                address
+            }
+        }
+    }
+
+    fn pattern_local(&self, name: &ast::Ident) -> LocalId {
+        match self.cx.analysis.resolutions.get(self.module, name) {
+            Some(Res::Local(local)) => local,
+            _ => unreachable!("bindings resolve to locals"),
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            if self.current.is_none() {
+                // The rest is unreachable, which flow analysis warns about.
+                break;
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        let outer = self.loc.replace(stmt.loc.clone());
+        self.stmt_kind(stmt);
+        self.loc = outer;
+    }
+
+    fn stmt_kind(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let {
+                pattern,
+                value: Some(value),
+                ..
+            } => {
+                let value = self.expr(value);
+                self.irrefutable(pattern, value);
+            }
+            StmtKind::Let { pattern, .. } => {
+                let mut locals = Vec::new();
+                bindings(pattern, &mut locals);
+                for name in locals {
+                    let local = self.pattern_local(name);
+                    let ty = self.local_ty(local);
+                    let address = self.emit(InstKind::Alloca(ty.clone()), Type::Ptr(Box::new(ty)));
+                    self.locals.insert(local, Place::Memory(address));
+                }
+            }
+            StmtKind::Assign { target, op, value } => self.assign(target, *op, value),
+            StmtKind::Expr(expr) => {
+                self.expr(expr);
+            }
+            StmtKind::Return(value) => {
+                let value = value.as_ref().map(|v| self.expr(v));
+                let value = value.filter(|_| self.function().ret != Type::Void);
+                self.terminate(Terminator::Return(value));
+            }
+            StmtKind::Break => {
+                let (_, exit) = *self.loops.last().expect("`break` is inside a loop");
+                self.terminate(Terminator::Jump(Target::new(exit, Vec::new())));
+            }
+            StmtKind::Continue => {
+                let (header, _) = *self.loops.last().expect("`continue` is inside a loop");
+                self.terminate(Terminator::Jump(Target::new(header, Vec::new())));
+            }
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                let cond = self.expr(cond);
+                let (then_entry, else_entry) = (self.new_block(), self.new_block());
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_target: Target::new(then_entry, Vec::new()),
+                    else_target: Target::new(else_entry, Vec::new()),
+                });
+                let mut join = None;
+                self.current = Some(then_entry);
+                self.block(then_block);
+                self.jump_to(&mut join);
+                self.current = Some(else_entry);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+                self.jump_to(&mut join);
+                self.current = join;
+            }
+            StmtKind::While { cond, body } => {
+                let header = self.new_block();
+                self.terminate(Terminator::Jump(Target::new(header, Vec::new())));
+                self.current = Some(header);
+                let cond = self.expr(cond);
+                let (entry, exit) = (self.new_block(), self.new_block());
+                self.terminate(Terminator::Branch {
+                    cond,
+                    then_target: Target::new(entry, Vec::new()),
+                    else_target: Target::new(exit, Vec::new()),
+                });
+                self.loops.push((header, exit));
+                self.current = Some(entry);
+                self.block(body);
+                self.terminate(Terminator::Jump(Target::new(header, Vec::new())));
+                self.loops.pop();
+                self.current = Some(exit);
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                let scrutinee = self.expr(scrutinee);
+                let mut exit = None;
+                for arm in arms {
+                    let mut fail = None;
+                    self.pattern(&arm.pattern, scrutinee, &mut fail);
+                    if let Some(guard) = &arm.guard {
+                        let cond = self.expr(guard);
+                        self.test(cond, &mut fail);
+                    }
+                    self.stmt(&arm.body);
+                    self.jump_to(&mut exit);
+                    self.current = fail;
+                    if fail.is_none() {
+                        break;
+                    }
+                }
+                // Matches are exhaustive, so no value gets past the last arm.
+                self.terminate(Terminator::Unreachable);
+                self.current = exit;
+            }
+            StmtKind::Block(block) => self.block(block),
+        }
+    }
+
+    fn assign(&mut self, target: &Expr, op: Option<ast::BinaryOp>, value: &Expr) {
+        match &target.kind {
+            ExprKind::Path(path) => {
+                let Some(Res::Local(local)) = self.res(path) else {
+                    unreachable!("only locals and fields are assigned to");
+                };
+                let value = match op {
+                    Some(op) => {
+                        let old = self.read(local);
+                        match self.compound(op, target, old, value) {
+                            Some(value) => value,
+                            None => return,
+                        }
+                    }
+                    None => self.expr(value),
+                };
+                let address = self.address(local);
+                self.emit(InstKind::Store(address, value), Type::Void);
+            }
+            ExprKind::Field { base, field } => {
+                let base = self.expr(base);
+                let index = self.field_index(base, &field.name);
+                let value = match op {
+                    Some(op) => {
+                        let ty = self.expr_ty(target);
+                        let old = self.emit(InstKind::GetField(base, index), ty);
+                        match self.compound(op, target, old, value) {
+                            Some(value) => value,
+                            None => return,
+                        }
+                    }
+                    None => self.expr(value),
+                };
+                self.emit(InstKind::SetField(base, index, value), Type::Void);
+            }
+            _ => unreachable!("only locals and fields are assigned to"),
+        }
+    }
+
+    /// The new value of `target op= value`, or `None` when an in-place method already changed it.
+    fn compound(
+        &mut self,
+        op: ast::BinaryOp,
+        target: &Expr,
+        old: ValueId,
+        value: &Expr,
+    ) -> Option<ValueId> {
+        let ty = self.sema_ty(target);
+        let rhs = self.expr(value);
+        if let Ty::Adt { .. } = &ty {
+            let in_place = assign_trait(op).expect("compound assignments are arithmetic");
+            let items = &self.cx.analysis.results.items;
+            if let Some(id) = items.operator_method(in_place, &ty) {
+                let func = self.operator_method(&ty, id);
+                self.emit(InstKind::Call(func, vec![old, rhs]), Type::Void);
+                return None;
+            }
+        }
+        Some(self.binary_values(op, &ty, old, rhs))
+    }
+
+    /// Bind a pattern that always matches, such as the pattern of a parameter or a `let`.
+    fn irrefutable(&mut self, pattern: &Pattern, value: ValueId) {
+        let mut fail = None;
+        self.pattern(pattern, value, &mut fail);
+        if let Some(fail) = fail {
+            // Single-variant enums are still tested, and can't fail.
+            let current = self.current.take();
+            self.current = Some(fail);
+            self.terminate(Terminator::Unreachable);
+            self.current = current;
+        }
+    }
+
+    /// Test a value against a pattern in the current block, binding its locals and continuing in
+    /// the current block if it matches, and branching to `fail` if it doesn't.
+    fn pattern(&mut self, pattern: &Pattern, value: ValueId, fail: &mut Option<BlockId>) {
+        match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Rest => {}
+            PatternKind::Binding { name, sub, .. } => {
+                let local = self.pattern_local(name);
+                self.bind(local, value);
+                if let Some(sub) = sub {
+                    self.pattern(sub, value, fail);
+                }
+            }
+            PatternKind::Literal(literal) => {
+                let expected = self.literal(literal);
+                let cond = self.emit(
+                    InstKind::Compare(CompareOp::Eq, value, expected),
+                    Type::Bool,
+                );
+                self.test(cond, fail);
+            }
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let start = self.literal(start);
+                let cond = self.emit(InstKind::Compare(CompareOp::Ge, value, start), Type::Bool);
+                self.test(cond, fail);
+                let end = self.literal(end);
+                let op = if *inclusive {
+                    CompareOp::Le
+                } else {
+                    CompareOp::Lt
+                };
+                let cond = self.emit(InstKind::Compare(op, value, end), Type::Bool);
+                self.test(cond, fail);
+            }
+            PatternKind::Tuple(patterns) => {
+                let Type::Tuple(elems) = self.value_ty(value) else {
+                    unreachable!("tuple patterns match tuples");
+                };
+                for (index, pattern) in positions(patterns, elems.len()) {
+                    let elem = self.emit(InstKind::Extract(value, index), elems[index].clone());
+                    self.pattern(pattern, elem, fail);
+                }
+            }
+            PatternKind::Variant { path, fields } => {
+                let Type::Adt(adt) = self.value_ty(value) else {
+                    unreachable!("variant patterns match enums");
+                };
+                let name = &path
+                    .segments
+                    .last()
+                    .expect("paths have a segment")
+                    .ident
+                    .name;
+                let (index, variant) = self.variant(adt, name);
+                let tag = self.emit(InstKind::Tag(value), Type::Int);
+                let expected = self.constant(Const::Int(variant.discriminant));
+                let cond = self.emit(InstKind::Compare(CompareOp::Eq, tag, expected), Type::Bool);
+                self.test(cond, fail);
+                for (field, pattern) in
+                    positions(fields.as_deref().unwrap_or(&[]), variant.fields.len())
+                {
+                    let ty = variant.fields[field].clone();
+                    let payload = self.emit(InstKind::Payload(value, index, field), ty);
+                    self.pattern(pattern, payload, fail);
+                }
+            }
+            PatternKind::Struct { fields, .. } => {
+                for field in fields {
+                    let index = self.field_index(value, &field.name.name);
+                    let ty = self.field_ty(value, index);
+                    let field_value = self.emit(InstKind::GetField(value, index), ty);
+                    self.pattern(&field.pattern, field_value, fail);
+                }
+            }
+            PatternKind::Or(alternatives) => self.or_pattern(pattern, alternatives, value, fail),
+        }
+    }
+
+    /// Try the alternatives in order, all of them continuing in one block that takes the places of
+    /// the locals they bind as parameters.
+    fn or_pattern(
+        &mut self,
+        pattern: &Pattern,
+        alternatives: &[Pattern],
+        value: ValueId,
+        fail: &mut Option<BlockId>,
+    ) {
+        let mut names = Vec::new();
+        bindings(pattern, &mut names);
+        let mut locals = Vec::new();
+        for name in names {
+            let local = self.pattern_local(name);
+            if !locals.contains(&local) {
+                locals.push(local);
+            }
+        }
+        let join = self.new_block();
+        for &local in &locals {
+            let mut ty = self.local_ty(local);
+            if self.cx.analysis.resolutions.locals[local].mutable {
+                ty = Type::Ptr(Box::new(ty));
+            }
+            self.function().add_param(join, ty);
+        }
+        for (i, alternative) in alternatives.iter().enumerate() {
+            let mut next = None;
+            let alternative_fail = if i + 1 == alternatives.len() {
+                &mut *fail
+            } else {
+                &mut next
+            };
+            self.pattern(alternative, value, alternative_fail);
+            let args = locals
+                .iter()
+                .map(|local| match self.locals[local] {
+                    Place::Value(value) | Place::Memory(value) => value,
+                })
+                .collect();
+            self.terminate(Terminator::Jump(Target::new(join, args)));
+            if i + 1 < alternatives.len() {
+                match next {
+                    Some(next) => self.current = Some(next),
+                    // The alternative always matches, so the ones after it never run.
+                    None => break,
+                }
+            }
+        }
+        let params = self.function().blocks[join].params.clone();
+        for (&local, param) in locals.iter().zip(params) {
+            let place = if self.cx.analysis.resolutions.locals[local].mutable {
+                Place::Memory(param)
+            } else {
+                Place::Value(param)
+            };
+            self.locals.insert(local, place);
+        }
+        self.current = Some(join);
+    }
+
+    fn variant(&self, adt: AdtId, name: &str) -> (usize, Variant) {
+        let AdtKind::Enum(variants) = &self.cx.module.adts[adt].kind else {
+            unreachable!("variants belong to enums");
+        };
+        let index = variants
+            .iter()
+            .position(|v| v.name == name)
+            .expect("variants are resolved");
+        (index, variants[index].clone())
+    }
+
+    fn field_index(&mut self, value: ValueId, name: &str) -> usize {
+        let Type::Adt(adt) = self.value_ty(value) else {
+            unreachable!("fields belong to structs");
+        };
+        let AdtKind::Struct(fields) = &self.cx.module.adts[adt].kind else {
+            unreachable!("fields belong to structs");
+        };
+        fields
+            .iter()
+            .position(|f| f.name == name)
+            .expect("fields are resolved")
+    }
+
+    fn field_ty(&mut self, value: ValueId, index: usize) -> Type {
+        let Type::Adt(adt) = self.value_ty(value) else {
+            unreachable!("fields belong to structs");
+        };
+        let AdtKind::Struct(fields) = &self.cx.module.adts[adt].kind else {
+            unreachable!("fields belong to structs");
+        };
+        fields[index].ty.clone()
+    }
+
+    fn expr(&mut self, expr: &Expr) -> ValueId {
+        let outer = self.loc.replace(expr.loc.clone());
+        let value = self.expr_kind(expr);
+        self.loc = outer;
+        value
+    }
+
+    fn expr_kind(&mut self, expr: &Expr) -> ValueId {
+        match &expr.kind {
+            ExprKind::Literal(literal) => self.literal(literal),
+            ExprKind::Path(path) => self.path(expr, path),
+            ExprKind::SelfValue => self.self_value.expect("`self` is only used in methods"),
+            ExprKind::Tuple(elems) => {
+                let elems = elems.iter().map(|e| self.expr(e)).collect();
+                let ty = self.expr_ty(expr);
+                self.emit(InstKind::Tuple(elems), ty)
+            }
+            ExprKind::Array(elems) => {
+                let elems = elems.iter().map(|e| self.expr(e)).collect();
+                let ty = self.expr_ty(expr);
+                self.emit(InstKind::Array(elems, Alloc::Heap), ty)
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let ty = self.sema_ty(operand);
+                let value = self.expr(operand);
+                if let Ty::Adt { .. } = &ty {
+                    let items = &self.cx.analysis.results.items;
+                    let id = items
+                        .operator_method(unary_trait(*op), &ty)
+                        .expect("operators are implemented");
+                    let func = self.operator_method(&ty, id);
+                    let ret = self.cx.module.functions[func].ret.clone();
+                    return self.emit(InstKind::Call(func, vec![value]), ret);
+                }
+                let op = match op {
+                    ast::UnaryOp::Neg => UnaryOp::Neg,
+                    ast::UnaryOp::Not => UnaryOp::Not,
+                };
+                let ty = self.value_ty(value);
+                self.emit(InstKind::Unary(op, value), ty)
+            }
+            ExprKind::Binary { op, lhs, rhs } => match op {
+                ast::BinaryOp::And | ast::BinaryOp::Or => self.short_circuit(*op, lhs, rhs),
+                _ => {
+                    let ty = self.sema_ty(lhs);
+                    let lhs = self.expr(lhs);
+                    let rhs = self.expr(rhs);
+                    self.binary_values(*op, &ty, lhs, rhs)
+                }
+            },
+            ExprKind::Call { callee, args } => self.call(expr, callee, args),
+            ExprKind::MethodCall { receiver, args, .. } => {
+                let Some(instance) = mono::instance_of(
+                    self.module,
+                    &self.cx.analysis.resolutions,
+                    &self.cx.analysis.results,
+                    &self.substs,
+                    expr,
+                ) else {
+                    return self.builtin_method(expr, receiver, args);
+                };
+                let mut values = vec![self.expr(receiver)];
+                values.extend(args.iter().map(|a| self.expr(a)));
+                self.direct_call(expr, instance, values)
+            }
+            ExprKind::Field { base, field } => {
+                let base = self.expr(base);
+                let index = self.field_index(base, &field.name);
+                let ty = self.field_ty(base, index);
+                self.emit(InstKind::GetField(base, index), ty)
+            }
+            ExprKind::StructLit { fields, .. } => {
+                let ty = self.expr_ty(expr);
+                let Type::Adt(adt) = ty else {
+                    unreachable!("struct literals make structs");
+                };
+                let AdtKind::Struct(declared) = &self.cx.module.adts[adt].kind else {
+                    unreachable!("struct literals make structs");
+                };
+                let order = fields
+                    .iter()
+                    .map(|f| {
+                        declared
+                            .iter()
+                            .position(|d| d.name == f.name.name)
+                            .expect("fields are resolved")
+                    })
+                    .collect::<Vec<_>>();
+                // The values are computed in source order and passed in declaration order.
+                let mut values = vec![None; order.len()];
+                for (field, index) in fields.iter().zip(order) {
+                    values[index] = Some(self.expr(&field.value));
+                }
+                let values = values
+                    .into_iter()
+                    .map(|v| v.expect("every field is initialized"))
+                    .collect();
+                self.emit(InstKind::New(adt, values, Alloc::Heap), ty)
+            }
+            ExprKind::Closure(closure) => self.closure(expr, closure),
+        }
+    }
+
+    fn short_circuit(&mut self, op: ast::BinaryOp, lhs: &Expr, rhs: &Expr) -> ValueId {
+        let lhs = self.expr(lhs);
+        let (rhs_block, join) = (self.new_block(), self.new_block());
+        let result = self.function().add_param(join, Type::Bool);
+        let rhs_target = Target::new(rhs_block, Vec::new());
+        let done = Target::new(join, vec![lhs]);
+        let (then_target, else_target) = match op {
+            ast::BinaryOp::And => (rhs_target, done),
+            _ => (done, rhs_target),
+        };
+        self.terminate(Terminator::Branch {
+            cond: lhs,
+            then_target,
+            else_target,
+        });
+        self.current = Some(rhs_block);
+        let rhs = self.expr(rhs);
+        self.terminate(Terminator::Jump(Target::new(join, vec![rhs])));
+        self.current = Some(join);
+        result
+    }
+
+    /// A trait method called on a generic parameter that stands for a primitive type, which
+    /// implements its operator traits and `Hashable` built in: `x->add(y)` is `x + y`,
+    /// `x->add_assign(y)` is `x += y` and `x->hash()` calls the `hash` native.
+    fn builtin_method(&mut self, expr: &Expr, receiver: &Expr, args: &[Expr]) -> ValueId {
+        const BINARY: [ast::BinaryOp; 9] = [
+            ast::BinaryOp::Add,
+            ast::BinaryOp::Sub,
+            ast::BinaryOp::Mul,
+            ast::BinaryOp::Div,
+            ast::BinaryOp::Rem,
+            ast::BinaryOp::Shl,
+            ast::BinaryOp::Shr,
+            ast::BinaryOp::Eq,
+            ast::BinaryOp::Less,
+        ];
+        let results = &self.cx.analysis.results;
+        let (id, _) = results
+            .trait_call(self.module, expr)
+            .expect("method calls are resolved");
+        let op_trait = results.items.builtin_traits[&id.trait_def];
+        let ty = self.sema_ty(receiver);
+        if let Some(op) = BINARY
+            .into_iter()
+            .find(|&op| assign_trait(op) == Some(op_trait))
+        {
+            if let ExprKind::Path(_) | ExprKind::Field { .. } = receiver.kind {
+                self.assign(receiver, Some(op), &args[0]);
+            } else {
+                let lhs = self.expr(receiver);
+                let rhs = self.expr(&args[0]);
+                self.binary_values(op, &ty, lhs, rhs);
+            }
+            return self.constant(Const::Void);
+        }
+        let value = self.expr(receiver);
+        if op_trait == &HASHABLE {
+            let native = builtins::native("hash").expect("`hash` is a native");
+            return self.emit(InstKind::CallNative(native, vec![value]), Type::Int);
+        }
+        if let Some(op) = BINARY
+            .into_iter()
+            .find(|&op| binary_dispatch(op).is_some_and(|d| d.op_trait == op_trait))
+        {
+            let rhs = self.expr(&args[0]);
+            return self.binary_values(op, &ty, value, rhs);
+        }
+        let op = if op_trait == unary_trait(ast::UnaryOp::Neg) {
+            UnaryOp::Neg
+        } else {
+            UnaryOp::Not
+        };
+        let ty = self.value_ty(value);
+        self.emit(InstKind::Unary(op, value), ty)
+    }
+
+    /// `lhs op rhs` where the left-hand side has type `ty`, through the operator impl of structs and
+    /// enums.
+    fn binary_values(&mut self, op: ast::BinaryOp, ty: &Ty, lhs: ValueId, rhs: ValueId) -> ValueId {
+        if let Ty::Adt { .. } = ty {
+            let dispatch = binary_dispatch(op).expect("`&&` and `||` short-circuit");
+            let items = &self.cx.analysis.results.items;
+            let id = items
+                .operator_method(dispatch.op_trait, ty)
+                .expect("operators are implemented");
+            let func = self.operator_method(ty, id);
+            let ret = self.cx.module.functions[func].ret.clone();
+            let args = if dispatch.swap {
+                vec![rhs, lhs]
+            } else {
+                vec![lhs, rhs]
+            };
+            let result = self.emit(InstKind::Call(func, args), ret.clone());
+            return if dispatch.negate {
+                self.emit(InstKind::Unary(UnaryOp::Not, result), ret)
+            } else {
+                result
+            };
+        }
+        let kind = match op {
+            ast::BinaryOp::Add => InstKind::Binary(BinaryOp::Add, lhs, rhs),
+            ast::BinaryOp::Sub => InstKind::Binary(BinaryOp::Sub, lhs, rhs),
+            ast::BinaryOp::Mul => InstKind::Binary(BinaryOp::Mul, lhs, rhs),
+            ast::BinaryOp::Div => InstKind::Binary(BinaryOp::Div, lhs, rhs),
+            ast::BinaryOp::Rem => InstKind::Binary(BinaryOp::Rem, lhs, rhs),
+            ast::BinaryOp::Shl => InstKind::Binary(BinaryOp::Shl, lhs, rhs),
+            ast::BinaryOp::Shr => InstKind::Binary(BinaryOp::Shr, lhs, rhs),
+            ast::BinaryOp::Eq => InstKind::Compare(CompareOp::Eq, lhs, rhs),
+            ast::BinaryOp::NotEq => InstKind::Compare(CompareOp::Ne, lhs, rhs),
+            ast::BinaryOp::Less => InstKind::Compare(CompareOp::Lt, lhs, rhs),
+            ast::BinaryOp::LessEq => InstKind::Compare(CompareOp::Le, lhs, rhs),
+            ast::BinaryOp::Greater => InstKind::Compare(CompareOp::Gt, lhs, rhs),
+            ast::BinaryOp::GreaterEq => InstKind::Compare(CompareOp::Ge, lhs, rhs),
+            ast::BinaryOp::And | ast::BinaryOp::Or => unreachable!("`&&` and `||` short-circuit"),
+        };
+        let ty = match kind {
+            InstKind::Compare(..) => Type::Bool,
+            _ => self.value_ty(lhs),
+        };
+        self.emit(kind, ty)
+    }
+
+    /// The function of an operator method of a struct or enum type.
+    fn operator_method(&mut self, ty: &Ty, id: MethodId) -> FuncId {
+        let imp = &self.cx.analysis.results.items.impls[&id.imp];
+        let mut substs = HashMap::new();
+        typeck::match_ty(&imp.target, ty, &mut substs);
+        let args = imp
+            .generics
+            .iter()
+            .map(|g| substs.get(&g.name).cloned().unwrap_or(Ty::Error))
+            .collect();
+        self.cx.instance(Instance {
+            callee: Callee::Method(id),
+            args,
+        })
+    }
+
+    fn path(&mut self, expr: &Expr, path: &Path) -> ValueId {
+        if let Some(instance) = self.instance_of(expr) {
+            let func = self.cx.instance(instance);
+            return self.fn_value(func);
+        }
+        if let Some(native) = self.native(path) {
+            let Type::Fn(fn_ty) = self.expr_ty(expr) else {
+                unreachable!("natives are functions");
+            };
+            let name = format!("{}.value", native.name);
+            let diverges = native.ret == Primitive::Never.name();
+            let func = self.cx.wrapper(
+                Wrapper::Native(native.name, (*fn_ty).clone()),
+                name,
+                *fn_ty,
+                |args| InstKind::CallNative(native, args),
+                diverges,
+            );
+            return self.closure_value(func);
+        }
+        match self.res(path) {
+            Some(Res::Local(local)) => return self.read(local),
+            Some(Res::Def(def)) => {
+                if let Some(value) = self.cx.analysis.consts.get(def) {
+                    return self.const_value(&value.clone());
+                }
+            }
+            _ => {}
+        }
+        // A variant, either without fields or used as the function making it.
+        let name = &path
+            .segments
+            .last()
+            .expect("paths have a segment")
+            .ident
+            .name;
+        match self.expr_ty(expr) {
+            Type::Adt(adt) => {
+                let (index, _) = self.variant(adt, name);
+                self.emit(InstKind::Variant(adt, index, Vec::new()), Type::Adt(adt))
+            }
+            Type::Fn(fn_ty) => {
+                let Type::Adt(adt) = fn_ty.ret else {
+                    unreachable!("paths of other types are resolved");
+                };
+                let (index, _) = self.variant(adt, name);
+                let name = format!("{}::{name}", self.cx.module.adts[adt].name);
+                let func = self.cx.wrapper(
+                    Wrapper::Variant(adt, index),
+                    name,
+                    *fn_ty,
+                    |args| InstKind::Variant(adt, index, args),
+                    false,
+                );
+                self.closure_value(func)
+            }
+            _ => unreachable!("paths of other types are resolved"),
+        }
+    }
+
+    fn instance_of(&self, expr: &Expr) -> Option<Instance> {
+        mono::instance_of(
+            self.module,
+            &self.cx.analysis.resolutions,
+            &self.cx.analysis.results,
+            &self.substs,
+            expr,
+        )
+    }
+
+    /// A function as a closure value, through a wrapper taking an empty environment.
+    fn fn_value(&mut self, func: FuncId) -> ValueId {
+        let function = &self.cx.module.functions[func];
+        let fn_ty = FnType {
+            params: function
+                .params()
+                .iter()
+                .map(|&p| function.values[p].clone())
+                .collect(),
+            ret: function.ret.clone(),
+        };
+        let name = format!("{}.value", function.name);
+        let wrapper = self.cx.wrapper(
+            Wrapper::Fn(func),
+            name,
+            fn_ty,
+            |args| InstKind::Call(func, args),
+            false,
+        );
+        self.closure_value(wrapper)
+    }
+
+    /// A closure of a wrapper, whose environment is empty.
+    fn closure_value(&mut self, func: FuncId) -> ValueId {
+        let env = self.emit(InstKind::Tuple(Vec::new()), Type::Tuple(Vec::new()));
+        let function = &self.cx.module.functions[func];
+        let fn_ty = FnType {
+            params: function.params()[1..]
+                .iter()
+                .map(|&p| function.values[p].clone())
+                .collect(),
+            ret: function.ret.clone(),
+        };
+        self.emit(InstKind::Closure(func, env), Type::Fn(Box::new(fn_ty)))
+    }
+
+    fn call(&mut self, expr: &Expr, callee: &Expr, args: &[Expr]) -> ValueId {
+        if let Some(instance) = self.instance_of(callee) {
+            let values = args.iter().map(|a| self.expr(a)).collect();
+            return self.direct_call(expr, instance, values);
+        }
+        if let ExprKind::Path(path) = &callee.kind {
+            if let Some(native) = self.native(path) {
+                let values = args.iter().map(|a| self.expr(a)).collect();
+                return self.native_call(expr, native, values);
+            }
+            match self.res(path) {
+                Some(Res::Local(_)) => {}
+                _ => {
+                    if let Type::Adt(adt) = self.expr_ty(expr) {
+                        let name = &path
+                            .segments
+                            .last()
+                            .expect("paths have a segment")
+                            .ident
+                            .name;
+                        let (index, _) = self.variant(adt, name);
+                        let values = args.iter().map(|a| self.expr(a)).collect();
+                        return self.emit(InstKind::Variant(adt, index, values), Type::Adt(adt));
+                    }
+                }
+            }
+        }
+        let closure = self.expr(callee);
+        let values = args.iter().map(|a| self.expr(a)).collect();
+        let variadic = matches!(self.sema_ty(callee), Ty::Fn(fn_ty) if fn_ty.variadic);
+        let Type::Fn(fn_ty) = self.value_ty(closure) else {
+            unreachable!("only functions are called");
+        };
+        let values = self.pack_rest(values, variadic, &fn_ty.params);
+        let ty = self.expr_ty(expr);
+        let result = self.emit(InstKind::CallIndirect(closure, values), ty);
+        // A function value that never returns may be a continuation, which returns from here.
+        if self.sema_ty(expr) == Ty::NEVER {
+            self.terminate(Terminator::Return(None));
+        }
+        result
+    }
+
+    /// Collect the arguments of a variadic call that go to its last parameter into an array.
+    fn pack_rest(
+        &mut self,
+        mut args: Vec<ValueId>,
+        variadic: bool,
+        params: &[Type],
+    ) -> Vec<ValueId> {
+        if variadic {
+            let rest = args.split_off(params.len() - 1);
+            let ty = params[params.len() - 1].clone();
+            args.push(self.emit(InstKind::Array(rest, Alloc::Heap), ty));
+        }
+        args
+    }
+
+    fn direct_call(&mut self, expr: &Expr, instance: Instance, args: Vec<ValueId>) -> ValueId {
+        let items = &self.cx.analysis.results.items;
+        let variadic = match instance.callee {
+            Callee::Fn(def) => items.fns[&def].variadic,
+            Callee::Method(id) => items.method(id).sig.variadic,
+        };
+        let func = self.cx.instance(instance);
+        let function = &self.cx.module.functions[func];
+        let params = function
+            .params()
+            .iter()
+            .map(|&p| function.values[p].clone())
+            .collect::<Vec<_>>();
+        let ret = function.ret.clone();
+        let args = self.pack_rest(args, variadic, &params);
+        let result = self.emit(InstKind::Call(func, args), ret);
+        self.diverge_after(expr);
+        result
+    }
+
+    fn native_call(&mut self, expr: &Expr, native: &'static Native, args: Vec<ValueId>) -> ValueId {
+        let ty = self.expr_ty(expr);
+        let result = self.emit(InstKind::CallNative(native, args), ty);
+        self.diverge_after(expr);
+        result
+    }
+
+    /// End the block after a call that never returns.
+    fn diverge_after(&mut self, call: &Expr) {
+        if self.sema_ty(call) == Ty::NEVER {
+            self.terminate(Terminator::Unreachable);
+        }
+    }
+
+    fn closure(&mut self, expr: &Expr, closure: &Closure) -> ValueId {
+        let layout = self
+            .cx
+            .analysis
+            .closures
+            .get(self.module, expr)
+            .expect("closures are analyzed")
+            .clone();
+        let mut env = Vec::new();
+        for capture in &layout.captures {
+            env.push(match (capture.var, capture.mode) {
+                (Var::SelfValue, _) => self.self_value.expect("`self` is only used in methods"),
+                (Var::Local(local), CaptureMode::ByValue) => self.read(local),
+                (Var::Local(local), CaptureMode::ByRef) => self.address(local),
+            });
+        }
+        let env_ty = Type::Tuple(env.iter().map(|&v| self.value_ty(v)).collect());
+        let env = self.emit(InstKind::Tuple(env), env_ty.clone());
+
+        let Type::Fn(fn_ty) = self
+            .cx
+            .ty(&Ty::Fn(layout.fn_ty.clone()).subst(&self.substs))
+        else {
+            unreachable!("closures are functions");
+        };
+        let mut params = vec![env_ty];
+        params.extend(fn_ty.params.iter().cloned());
+        let count = self.closures;
+        let name = format!("{}.closure{count}", self.function().name);
+        self.closures += 1;
+        let func = self.cx.declare(name, params, fn_ty.ret.clone());
+
+        let mut lowerer = FnLowerer::new(self.cx, func, self.module, self.substs.clone());
+        let params = lowerer.function().params().to_vec();
+        let env_value = params[0];
+        for (index, capture) in layout.captures.iter().enumerate() {
+            let ty = lowerer.function().values[env_value].clone();
+            let Type::Tuple(elems) = ty else {
+                unreachable!("environments are tuples");
+            };
+            let field = lowerer.emit(InstKind::Extract(env_value, index), elems[index].clone());
+            match (capture.var, capture.mode) {
+                (Var::SelfValue, _) => lowerer.self_value = Some(field),
+                (Var::Local(local), CaptureMode::ByValue) => {
+                    lowerer.locals.insert(local, Place::Value(field));
+                }
+                (Var::Local(local), CaptureMode::ByRef) => {
+                    lowerer.locals.insert(local, Place::Memory(field));
+                }
+            }
+        }
+        for (param, &value) in closure.params.iter().zip(&params[1..]) {
+            lowerer.irrefutable(&param.pattern, value);
+        }
+        match &closure.body {
+            ClosureBody::Expr(body) => {
+                let value = lowerer.expr(body);
+                let value = Some(value).filter(|_| fn_ty.ret != Type::Void);
+                lowerer.terminate(Terminator::Return(value));
+            }
+            ClosureBody::Block(body) => {
+                lowerer.block(body);
+                lowerer.finish();
+            }
+        }
+        self.emit(InstKind::Closure(func, env), Type::Fn(fn_ty))
+    }
+}
+
+/// The names a pattern binds, in order.
+fn bindings<'p>(pattern: &'p Pattern, names: &mut Vec<&'p ast::Ident>) {
+    match &pattern.kind {
+        PatternKind::Wildcard
+        | PatternKind::Rest
+        | PatternKind::Literal(_)
+        | PatternKind::Range { .. } => {}
+        PatternKind::Binding { name, sub, .. } => {
+            names.push(name);
+            if let Some(sub) = sub {
+                bindings(sub, names);
+            }
+        }
+        PatternKind::Tuple(patterns) => patterns.iter().for_each(|p| bindings(p, names)),
+        PatternKind::Variant { fields, .. } => {
+            fields.iter().flatten().for_each(|p| bindings(p, names));
+        }
+        PatternKind::Struct { fields, .. } => {
+            fields.iter().for_each(|f| bindings(&f.pattern, names));
+        }
+        // Every alternative binds the same locals.
+        PatternKind::Or(alternatives) => {
+            if let Some(first) = alternatives.first() {
+                bindings(first, names);
+            }
+        }
+    }
+}
+
+/// The index of the element each of the patterns of a tuple or variant matches, `..` standing for
+/// as many elements as the others leave.
+fn positions(patterns: &[Pattern], len: usize) -> Vec<(usize, &Pattern)> {
+    match patterns
+        .iter()
+        .position(|p| matches!(p.kind, PatternKind::Rest))
+    {
+        Some(rest) => {
+            let after = patterns.len() - rest - 1;
+            patterns[..rest]
+                .iter()
+                .enumerate()
+                .chain(
+                    patterns[rest + 1..]
+                        .iter()
+                        .enumerate()
+                        .map(|(i, p)| (len - after + i, p)),
+                )
+                .collect()
+        }
+        None => patterns.iter().enumerate().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::modules::ModuleLoader;
+    use std::path::Path;
+
+    fn lower_source(src: &str) -> Module {
+        let graph = ModuleLoader::new(vec![])
+            .load_source(Path::new("main.paca"), src.to_string())
+            .unwrap();
+        let (analysis, diagnostics) = crate::sema::analyze(&graph);
+        let analysis = analysis.unwrap_or_else(|| panic!("{diagnostics:?}"));
+        lower(&graph, &analysis)
+    }
+
+    fn insts(function: &Function) -> Vec<&InstKind> {
+        function
+            .blocks
+            .iter()
+            .flat_map(|b| &b.insts)
+            .map(|i| &i.kind)
+            .collect()
+    }
+
+    #[test]
+    fn lowers_loops_and_short_circuits_to_blocks() {
+        let module = lower_source(
+            "def count(n: int) int {\n    let mut i = 0;\n    \
+             while i < n && i != 7 {\n        i += 1;\n    }\n    return i;\n}\n",
+        );
+        let count = module.function("count").unwrap();
+        assert_eq!(count.values[count.params()[0]], Type::Int);
+        // The entry, the loop header, the right-hand side of `&&` and where it joins, the body and
+        // the exit.
+        assert_eq!(count.blocks.len(), 6);
+        let join = count.blocks[1..]
+            .iter()
+            .find(|b| !b.params.is_empty())
+            .unwrap();
+        assert_eq!(count.values[join.params[0]], Type::Bool);
+        assert!(matches!(join.term, Terminator::Branch { .. }));
+        let insts = insts(count);
+        assert!(matches!(insts[1], InstKind::Alloca(Type::Int)));
+        assert!(insts
+            .iter()
+            .any(|i| matches!(i, InstKind::Binary(BinaryOp::Add, ..))));
+        assert!(count
+            .blocks
+            .iter()
+            .any(|b| matches!(b.term, Terminator::Return(Some(_)))));
+    }
+
+    #[test]
+    fn instantiates_generics_and_operator_impls() {
+        let module = lower_source(
+            "import std::ops::Add;\n\nstruct Pair<T> {\n    first: T,\n    second: T,\n}\n\n\
+             impl Add for Pair<T> {\n    def add(self, other: Self) Self {\n        return self;\n    }\n}\n\n\
+             def map<T, U>(value: Option<T>, f: def(T) U) Option<U> {\n    match value {\n        \
+             Option::Some(x) => return Option::Some(f(x)),\n        \
+             Option::None => return Option::None,\n    }\n}\n\n\
+             def main() void {\n    let pair = Pair { first => 1, second => 2 } + Pair { first => 3, second => 4 };\n    \
+             println(map(Option::Some(pair->first)) { (x) = x > 2 });\n}\n",
+        );
+        let mut names = module
+            .functions
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            ["Pair<int>::add", "main", "main.closure0", "map<int, bool>"]
+        );
+        let mut adts = module
+            .adts
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect::<Vec<_>>();
+        adts.sort();
+        assert_eq!(adts, ["Option<bool>", "Option<int>", "Pair<int>"]);
+        let map = module.function("map<int, bool>").unwrap();
+        assert!(insts(map).iter().any(|i| matches!(i, InstKind::Tag(_))));
+    }
+
+    #[test]
+    fn passes_captures_through_environments() {
+        let module = lower_source(
+            "def each(f: def(int) void) void {\n    f(1);\n}\n\n\
+             def main() void {\n    let step = 2;\n    let mut total = 0;\n    \
+             each() { (n) => total += n * step; };\n    println(total);\n}\n",
+        );
+        let closure = module.function("main.closure0").unwrap();
+        let env = Type::Tuple(vec![Type::Ptr(Box::new(Type::Int)), Type::Int]);
+        assert_eq!(closure.values[closure.params()[0]], env);
+        assert!(insts(closure)
+            .iter()
+            .any(|i| matches!(i, InstKind::Store(..))));
+        let main = module.function("main").unwrap();
+        let func = module
+            .functions
+            .iter()
+            .position(|f| f.name == "main.closure0")
+            .unwrap();
+        assert!(insts(main)
+            .iter()
+            .any(|i| matches!(i, InstKind::Closure(f, _) if *f == func)));
+    }
+}
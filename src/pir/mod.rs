@@ -0,0 +1,601 @@
+//! The Paca intermediate representation (PIR), a typed SSA form of a whole program that sits
+//! between the checked AST and the backends.
+//!
+//! A module holds the structs and enums of the program, one per list of generic arguments, and its
+//! functions, one per instance of a generic function or method. A function is a list of basic
+//! blocks, the first one being the entry, whose parameters are the function's. Every value is
+//! defined exactly once, by a block parameter or an instruction, and has a type. Instead of phi
+//! nodes, blocks take parameters and every jump passes the arguments for them.
+//!
+//! Values are immutable. Variables that are assigned to live in memory made by `alloca`, and the
+//! optimizer promotes them to values. Structs are shared by reference, like in Paca, so `new`
+//! allocates one and `get_field`/`set_field` go through the reference, while tuples and enums are
+//! plain values taken apart by `extract` and `payload`.
+//!
+//! Closures are functions whose first parameter is their environment, a tuple of the captured
+//! values and of pointers to the captured variables they assign to. A value of a function type
+//! is the pair of such a function and its environment, so every indirect call passes the
+//! environment first; functions used as values get a wrapper taking an empty environment.
+//!
+//! # Textual form
+//!
+//! `printer` writes a module as text and `parser` reads it back, using the tokens of Paca source
+//! code. The structs and enums come first, then the functions:
+//!
+//! ```text
+//! struct Point {
+//!     x: int,
+//!     y: int,
+//! }
+//!
+//! enum "Option<int>" {
+//!     Some(int) = 0,
+//!     None = 1,
+//! }
+//!
+//! def sum(%0: Point) int {
+//! bb0:
+//!     %1: int = get_field %0, x
+//!     %2: int = get_field %0, y
+//!     %3: int = add %1, %2
+//!     %4: bool = gt %3, %1
+//!     branch %4, bb1(%3), bb1(%1)
+//! bb1(%5: int):
+//!     return %5
+//! }
+//! ```
+//!
+//! Names that aren't identifiers, or that are keywords, are written as strings. Types are written
+//! as in Paca, with `*T` for pointers and `def(A, B) R` for closures. A function lists the
+//! parameters of its entry block in its header; the other blocks list theirs after their label.
+//! Every instruction gives its value and the value's type, e.g. `%3: int = const -1`, and calls
+//! name functions with `@`, e.g. `call @"Option<int>::unwrap"(%1)`. Fields and variants are named,
+//! or numbered when the value isn't a struct or an enum. Constants that can't be written as
+//! literals are `min` for the smallest `int`, and `nan`, `inf` and `-inf` for `float`s.
+
+pub mod analysis;
+pub mod dom;
+pub mod dot;
+pub mod escape;
+pub mod interp;
+pub mod layout;
+pub mod liveness;
+pub mod loops;
+pub mod lower;
+pub mod opt;
+pub mod parser;
+pub mod printer;
+pub mod profile;
+pub mod tail;
+pub mod verify;
+
+use crate::parse::SourceCodeLocation;
+use crate::sema::builtins::Native;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+pub type FuncId = usize;
+pub type AdtId = usize;
+pub type BlockId = usize;
+/// A value of a function, numbered from 0 in each function.
+pub type ValueId = usize;
+
+/// A whole program.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Module {
+    pub adts: Vec<AdtDef>,
+    pub functions: Vec<Function>,
+}
+
+impl Module {
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+}
+
+/// A struct or enum with concrete types for its generic parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdtDef {
+    /// The name with the generic arguments, e.g. `Option<int>`.
+    pub name: String,
+    pub kind: AdtKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdtKind {
+    Struct(Vec<Field>),
+    Enum(Vec<Variant>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    /// The value `tag` gives for the variant.
+    pub discriminant: i64,
+    pub fields: Vec<Type>,
+}
+
+/// The type of a value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
+    /// The type of values that carry nothing, such as the result of `store` or of a call to a
+    /// function returning `void` or `never`.
+    Void,
+    Bool,
+    Int,
+    Float,
+    Char,
+    Str,
+    /// The address of a value of the type, made by `alloca`.
+    Ptr(Box<Type>),
+    /// A struct, which is a reference, or an enum.
+    Adt(AdtId),
+    Tuple(Vec<Type>),
+    Array(Box<Type>),
+    /// A closure, called with its environment before the parameters.
+    Fn(Box<FnType>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FnType {
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+/// A function in SSA form.
+#[derive(Clone, Debug)]
+pub struct Function {
+    /// The unique name of the function, e.g. `main`, `Option<int>::unwrap` or `main.closure0`.
+    pub name: String,
+    pub ret: Type,
+    /// The type of every value, indexed by `ValueId`.
+    pub values: Vec<Type>,
+    /// The basic blocks, the first one being the entry.
+    pub blocks: Vec<Block>,
+    /// Where in the Paca source the instructions defining the values come from, for reporting
+    /// panics. It isn't part of the textual form, and passes may leave entries of removed values.
+    pub locs: HashMap<ValueId, SourceCodeLocation>,
+    /// Where the Paca source annotates the function with `@tailcall`, which requires its recursive
+    /// calls to be tail calls. It isn't part of the textual form either.
+    pub tailcall: Option<SourceCodeLocation>,
+    /// How many times the function was called in the profile given with `--profile-use`. Along
+    /// with the counts of the targets, it isn't part of the textual form.
+    pub entry_count: Option<u64>,
+}
+
+/// Functions are equal if they have the same code, wherever it comes from.
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.ret == other.ret
+            && self.values == other.values
+            && self.blocks == other.blocks
+    }
+}
+
+impl Function {
+    pub fn new(name: impl Into<String>, params: Vec<Type>, ret: Type) -> Self {
+        let mut function = Self {
+            name: name.into(),
+            ret,
+            values: Vec::new(),
+            blocks: Vec::new(),
+            locs: HashMap::new(),
+            tailcall: None,
+            entry_count: None,
+        };
+        let entry = function.add_block();
+        for ty in params {
+            function.add_param(entry, ty);
+        }
+        function
+    }
+
+    /// The parameters, which are those of the entry block.
+    pub fn params(&self) -> &[ValueId] {
+        &self.blocks[0].params
+    }
+
+    pub fn new_value(&mut self, ty: Type) -> ValueId {
+        self.values.push(ty);
+        self.values.len() - 1
+    }
+
+    /// Add an empty block ending in `unreachable`.
+    pub fn add_block(&mut self) -> BlockId {
+        self.blocks.push(Block::default());
+        self.blocks.len() - 1
+    }
+
+    pub fn add_param(&mut self, block: BlockId, ty: Type) -> ValueId {
+        let value = self.new_value(ty);
+        self.blocks[block].params.push(value);
+        value
+    }
+
+    /// Replace every use of the values in the map, following chains of replacements.
+    pub fn replace_uses(&mut self, map: &HashMap<ValueId, ValueId>) {
+        if map.is_empty() {
+            return;
+        }
+        for block in &mut self.blocks {
+            for inst in &mut block.insts {
+                inst.kind.rename(map);
+            }
+            block.term.rename(map);
+        }
+    }
+
+    /// Append an instruction to the block, returning the value it defines.
+    pub fn push(&mut self, block: BlockId, kind: InstKind, ty: Type) -> ValueId {
+        let result = self.new_value(ty);
+        self.blocks[block].insts.push(Inst { result, kind });
+        result
+    }
+}
+
+/// The value a value is replaced with, after following the map as far as it goes.
+fn resolve(map: &HashMap<ValueId, ValueId>, mut value: ValueId) -> ValueId {
+    while let Some(&next) = map.get(&value) {
+        value = next;
+    }
+    value
+}
+
+/// A basic block: parameters, straight-line instructions and the terminator leaving it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Block {
+    pub params: Vec<ValueId>,
+    pub insts: Vec<Inst>,
+    pub term: Terminator,
+}
+
+/// An instruction and the value it defines.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Inst {
+    pub result: ValueId,
+    pub kind: InstKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InstKind {
+    Const(Const),
+    Unary(UnaryOp, ValueId),
+    /// Arithmetic on two `int`s or two `float`s, or `add` on two `str`s to concatenate them.
+    Binary(BinaryOp, ValueId, ValueId),
+    /// A comparison of two values of the same primitive type, giving a `bool`.
+    Compare(CompareOp, ValueId, ValueId),
+    /// Reserve memory for a value of the type for the rest of the call, giving its address.
+    Alloca(Type),
+    Load(ValueId),
+    /// `store address, value`
+    Store(ValueId, ValueId),
+    Call(FuncId, Vec<ValueId>),
+    CallNative(&'static Native, Vec<ValueId>),
+    /// Call a closure, passing its environment before the arguments.
+    CallIndirect(ValueId, Vec<ValueId>),
+    /// Make a closure of a function and its environment.
+    Closure(FuncId, ValueId),
+    /// Allocate a struct with the values of its fields.
+    New(AdtId, Vec<ValueId>, Alloc),
+    /// `get_field struct, index`
+    GetField(ValueId, usize),
+    /// `set_field struct, index, value`
+    SetField(ValueId, usize, ValueId),
+    /// Make a variant of an enum, by index, with the values of its fields.
+    Variant(AdtId, usize, Vec<ValueId>),
+    /// The discriminant of an enum value, as an `int`.
+    Tag(ValueId),
+    /// `payload enum, variant, field` reads a field of an enum value known to be the variant.
+    Payload(ValueId, usize, usize),
+    Tuple(Vec<ValueId>),
+    /// `extract tuple, index`
+    Extract(ValueId, usize),
+    Array(Vec<ValueId>, Alloc),
+    /// Add one to a counter of the profile of a function, which `--profile-generate` puts in
+    /// every block and on every branch.
+    Count(FuncId, Counter),
+}
+
+/// A counter of `count`, written `entry 123`, `block 1` or `edge 1, 0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Counter {
+    /// How many times the function was called, with the checksum of its code when the counters
+    /// were put in, to tell whether a profile is of the same program.
+    Entry(u32),
+    /// How many times the block ran.
+    Block(BlockId),
+    /// How many times the branch ending the block went to its then (0) or else (1) target.
+    Edge(BlockId, usize),
+}
+
+/// Where `new` and `array` put the memory they allocate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Alloc {
+    #[default]
+    Heap,
+    /// In the frame of the call, freed when it returns. Escape analysis proves when a struct or
+    /// array can go there.
+    Stack,
+}
+
+impl InstKind {
+    /// The values the instruction uses, in order.
+    pub fn operands(&self) -> Vec<ValueId> {
+        let mut kind = self.clone();
+        kind.operands_mut().into_iter().map(|v| *v).collect()
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            InstKind::Const(_) | InstKind::Alloca(_) | InstKind::Count(..) => Vec::new(),
+            InstKind::Unary(_, value)
+            | InstKind::Load(value)
+            | InstKind::Closure(_, value)
+            | InstKind::GetField(value, _)
+            | InstKind::Tag(value)
+            | InstKind::Payload(value, _, _)
+            | InstKind::Extract(value, _) => vec![value],
+            InstKind::Binary(_, a, b)
+            | InstKind::Compare(_, a, b)
+            | InstKind::Store(a, b)
+            | InstKind::SetField(a, _, b) => vec![a, b],
+            InstKind::Call(_, args)
+            | InstKind::CallNative(_, args)
+            | InstKind::New(_, args, _)
+            | InstKind::Variant(_, _, args)
+            | InstKind::Tuple(args)
+            | InstKind::Array(args, _) => args.iter_mut().collect(),
+            InstKind::CallIndirect(closure, args) => {
+                let mut values = vec![closure];
+                values.extend(args);
+                values
+            }
+        }
+    }
+
+    /// Whether the instruction does something besides giving its value, so it can't be removed
+    /// when the value isn't used. An integer division may stop the program by dividing by zero.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            InstKind::Store(..)
+                | InstKind::SetField(..)
+                | InstKind::Call(..)
+                | InstKind::CallNative(..)
+                | InstKind::CallIndirect(..)
+                | InstKind::Count(..)
+                | InstKind::Binary(BinaryOp::Div | BinaryOp::Rem, ..)
+        )
+    }
+
+    /// Whether the instruction always gives the same value for the same operands and does nothing
+    /// else, so two of them can share a value. Structs, arrays and `alloca`s are new memory every
+    /// time, so making or reading them isn't pure.
+    pub fn is_pure(&self) -> bool {
+        !self.has_side_effects()
+            && !matches!(
+                self,
+                InstKind::Alloca(_)
+                    | InstKind::Load(_)
+                    | InstKind::New(..)
+                    | InstKind::GetField(..)
+                    | InstKind::Array(..)
+            )
+    }
+
+    /// Replace the values the instruction uses according to the map.
+    pub fn rename(&mut self, map: &HashMap<ValueId, ValueId>) {
+        for value in self.operands_mut() {
+            *value = resolve(map, *value);
+        }
+    }
+}
+
+/// A constant. Floats are compared bit by bit, so a `nan` equals itself and `0.0` differs from
+/// `-0.0`.
+#[derive(Clone, Debug)]
+pub enum Const {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Void,
+}
+
+impl PartialEq for Const {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Const::Int(a), Const::Int(b)) => a == b,
+            (Const::Float(a), Const::Float(b)) => a.to_bits() == b.to_bits(),
+            (Const::Bool(a), Const::Bool(b)) => a == b,
+            (Const::Char(a), Const::Char(b)) => a == b,
+            (Const::Str(a), Const::Str(b)) => a == b,
+            (Const::Void, Const::Void) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Const {}
+
+impl Hash for Const {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Const::Int(n) => n.hash(state),
+            Const::Float(n) => n.to_bits().hash(state),
+            Const::Bool(b) => b.hash(state),
+            Const::Char(c) => c.hash(state),
+            Const::Str(s) => s.hash(state),
+            Const::Void => {}
+        }
+    }
+}
+
+impl Const {
+    pub fn ty(&self) -> Type {
+        match self {
+            Const::Int(_) => Type::Int,
+            Const::Float(_) => Type::Float,
+            Const::Bool(_) => Type::Bool,
+            Const::Char(_) => Type::Char,
+            Const::Str(_) => Type::Str,
+            Const::Void => Type::Void,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// How a block is left.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Terminator {
+    Jump(Target),
+    Branch {
+        cond: ValueId,
+        then_target: Target,
+        else_target: Target,
+    },
+    /// Return from the function, without a value for `void`.
+    Return(Option<ValueId>),
+    /// Control never gets here, e.g. after a call to `panic`.
+    #[default]
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn targets_mut(&mut self) -> Vec<&mut Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then_target,
+                else_target,
+                ..
+            } => vec![then_target, else_target],
+            Terminator::Return(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    /// The values the terminator uses, including the arguments of its targets.
+    pub fn operands_mut(&mut self) -> Vec<&mut ValueId> {
+        match self {
+            Terminator::Jump(target) => target.args.iter_mut().collect(),
+            Terminator::Branch {
+                cond,
+                then_target,
+                else_target,
+            } => {
+                let mut values = vec![cond];
+                values.extend(&mut then_target.args);
+                values.extend(&mut else_target.args);
+                values
+            }
+            Terminator::Return(value) => value.iter_mut().collect(),
+            Terminator::Unreachable => Vec::new(),
+        }
+    }
+
+    /// Replace the values the terminator uses according to the map.
+    pub fn rename(&mut self, map: &HashMap<ValueId, ValueId>) {
+        for value in self.operands_mut() {
+            *value = resolve(map, *value);
+        }
+    }
+
+    pub fn targets(&self) -> Vec<&Target> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch {
+                then_target,
+                else_target,
+                ..
+            } => vec![then_target, else_target],
+            Terminator::Return(_) | Terminator::Unreachable => Vec::new(),
+        }
+    }
+}
+
+/// A place in a module that a problem can be reported at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Position {
+    Function(FuncId),
+    Block(FuncId, BlockId),
+    /// An instruction, by its index in the block.
+    Inst(FuncId, BlockId, usize),
+    Terminator(FuncId, BlockId),
+}
+
+/// Where the functions, blocks, instructions and terminators of a module are in its textual form.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Spans {
+    locs: HashMap<Position, SourceCodeLocation>,
+}
+
+impl Spans {
+    pub fn get(&self, position: Position) -> Option<&SourceCodeLocation> {
+        self.locs.get(&position)
+    }
+
+    pub fn insert(&mut self, position: Position, loc: SourceCodeLocation) {
+        self.locs.insert(position, loc);
+    }
+}
+
+/// A block jumped to, with the arguments for its parameters.
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub block: BlockId,
+    pub args: Vec<ValueId>,
+    /// How many times the jump was taken in the profile given with `--profile-use`.
+    pub count: Option<u64>,
+}
+
+impl Target {
+    pub fn new(block: BlockId, args: Vec<ValueId>) -> Self {
+        Self {
+            block,
+            args,
+            count: None,
+        }
+    }
+}
+
+/// Targets are equal if they jump to the same block with the same arguments, however often.
+impl PartialEq for Target {
+    fn eq(&self, other: &Self) -> bool {
+        self.block == other.block && self.args == other.args
+    }
+}
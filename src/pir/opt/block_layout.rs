@@ -0,0 +1,134 @@
+//! Block layout: order the blocks of functions with a profile so the paths that run most fall
+//! through from block to block.
+//!
+//! Starting at the entry, every block is followed by the successor its profile took most, unless
+//! that one is laid out already. When a chain of blocks ends, the next one starts at the block
+//! left that ran most. Blocks keep their order otherwise, so functions without a profile don't
+//! change.
+
+use super::Pass;
+use crate::pir::analysis::Analyses;
+use crate::pir::profile::block_count;
+use crate::pir::{BlockId, Function, Module};
+
+pub const NAME: &str = "block-layout";
+
+pub struct BlockLayout;
+
+impl Pass for BlockLayout {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn run(&mut self, module: &mut Module, _: &mut Analyses) -> bool {
+        let mut changed = false;
+        for function in &mut module.functions {
+            if function.entry_count.is_none() {
+                continue;
+            }
+            let order = layout(function);
+            if order.iter().enumerate().any(|(i, &block)| i != block) {
+                reorder(function, &order);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// The blocks of the function in the order to lay them out.
+fn layout(function: &Function) -> Vec<BlockId> {
+    let counts: Vec<u64> = (0..function.blocks.len())
+        .map(|block| block_count(function, block).unwrap_or(0))
+        .collect();
+    let mut placed = vec![false; function.blocks.len()];
+    let mut order = Vec::with_capacity(function.blocks.len());
+    let mut next = Some(0);
+    while let Some(block) = next {
+        placed[block] = true;
+        order.push(block);
+        next = function.blocks[block]
+            .term
+            .targets()
+            .into_iter()
+            .filter(|target| !placed[target.block])
+            .max_by_key(|target| target.count.unwrap_or(0))
+            .map(|target| target.block);
+        if next.is_none() {
+            // The first of the hottest blocks left, since `max_by_key` takes the last.
+            next = (0..function.blocks.len())
+                .filter(|&block| !placed[block])
+                .rev()
+                .max_by_key(|&block| counts[block]);
+        }
+    }
+    order
+}
+
+/// Move the blocks of the function to the positions of the order, keeping the entry first.
+fn reorder(function: &mut Function, order: &[BlockId]) {
+    let mut ids = vec![0; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        ids[old] = new;
+    }
+    let mut blocks: Vec<_> = std::mem::take(&mut function.blocks)
+        .into_iter()
+        .map(Some)
+        .collect();
+    for &old in order {
+        let mut block = blocks[old].take().expect("every block is laid out once");
+        for target in block.term.targets_mut() {
+            target.block = ids[target.block];
+        }
+        function.blocks.push(block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+    use crate::pir::profile::{annotate, Profile};
+    use crate::pir::verify::verify;
+
+    #[test]
+    fn lays_out_hot_paths_first() {
+        let source = "def main(%0: bool) int {
+bb0:
+    branch %0, bb1, bb2
+bb1:
+    %1: int = const 1
+    return %1
+bb2:
+    %2: int = const 2
+    jump bb3(%2)
+bb3(%3: int):
+    return %3
+}
+";
+        let mut module = parse_module(None, source).unwrap();
+        assert!(!BlockLayout.run(&mut module, &mut Analyses::default()));
+        let profile =
+            Profile::parse("block\tmain\t0\t10\nedge\tmain\t0\t0\t1\nedge\tmain\t0\t1\t9\n");
+        annotate(&mut module, &profile.unwrap());
+        assert!(BlockLayout.run(&mut module, &mut Analyses::default()));
+        verify(&module).unwrap();
+        assert_eq!(
+            print_module(&module),
+            "def main(%0: bool) int {
+bb0:
+    branch %0, bb3, bb1
+bb1:
+    %2: int = const 2
+    jump bb2(%2)
+bb2(%3: int):
+    return %3
+bb3:
+    %1: int = const 1
+    return %1
+}
+"
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Constant folding and propagation: instructions on constants become constants, instructions
+//! that give one of their operands are replaced by it, and branches on constants become jumps.
+//!
+//! Values are SSA, so a constant is propagated to every use of its value for free. Across blocks,
+//! a block parameter that is passed the same constant by every jump becomes that constant. Enums
+//! and tuples made in the function are looked through by `tag`, `payload` and `extract`.
+
+use super::Pass;
+use crate::pir::analysis::Analyses;
+use crate::pir::dom::{predecessors, reverse_postorder};
+use crate::pir::{
+    AdtDef, AdtKind, BinaryOp, CompareOp, Const, Function, Inst, InstKind, Module, Target,
+    Terminator, Type, UnaryOp, ValueId,
+};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+pub const NAME: &str = "const-fold";
+
+pub struct ConstFold;
+
+impl Pass for ConstFold {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        for function in &mut module.functions {
+            while fold(&module.adts, function) {
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// What an instruction folds to.
+enum Folded {
+    Const(Const),
+    Value(ValueId),
+}
+
+/// Fold what can be folded in one walk over the function, returning whether anything was.
+fn fold(adts: &[AdtDef], function: &mut Function) -> bool {
+    let mut changed = fold_params(function);
+    // The instructions defining the values, for the constants, enums and tuples among them.
+    let mut defs: HashMap<ValueId, InstKind> = HashMap::new();
+    let mut replaced = HashMap::new();
+    for block in reverse_postorder(function) {
+        let mut insts = Vec::new();
+        for mut inst in std::mem::take(&mut function.blocks[block].insts) {
+            inst.kind.rename(&replaced);
+            match fold_inst(adts, &defs, &inst.kind, &function.values[inst.result]) {
+                Some(Folded::Value(value)) => {
+                    replaced.insert(inst.result, value);
+                    changed = true;
+                    continue;
+                }
+                Some(Folded::Const(value)) => {
+                    inst.kind = InstKind::Const(value);
+                    changed = true;
+                }
+                None => {}
+            }
+            defs.insert(inst.result, inst.kind.clone());
+            insts.push(inst);
+        }
+        function.blocks[block].insts = insts;
+
+        let term = &mut function.blocks[block].term;
+        term.rename(&replaced);
+        if let Terminator::Branch {
+            cond,
+            then_target,
+            else_target,
+        } = term
+        {
+            if let Some(InstKind::Const(Const::Bool(cond))) = defs.get(cond) {
+                let target = if *cond { then_target } else { else_target };
+                *term = Terminator::Jump(std::mem::replace(target, Target::new(0, vec![])));
+                changed = true;
+            }
+        }
+    }
+    function.replace_uses(&replaced);
+    changed
+}
+
+/// Replace the parameters of blocks that every jump passes the same constant with the constant.
+fn fold_params(function: &mut Function) -> bool {
+    let consts: HashMap<ValueId, Const> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match &inst.kind {
+            InstKind::Const(value) => Some((inst.result, value.clone())),
+            _ => None,
+        })
+        .collect();
+    let mut used = vec![false; function.values.len()];
+    for block in &mut function.blocks {
+        for inst in &block.insts {
+            for value in inst.kind.operands() {
+                used[value] = true;
+            }
+        }
+        for value in block.term.operands_mut() {
+            used[*value] = true;
+        }
+    }
+    let preds = predecessors(function);
+    let mut changed = false;
+    for (block, preds) in preds.iter().enumerate().skip(1) {
+        for i in 0..function.blocks[block].params.len() {
+            let param = function.blocks[block].params[i];
+            if !used[param] {
+                continue;
+            }
+            let mut value = None;
+            let incoming = preds.iter().flat_map(|&pred| {
+                function.blocks[pred]
+                    .term
+                    .targets()
+                    .into_iter()
+                    .filter(|target| target.block == block)
+                    .map(|target| target.args[i])
+                    .collect::<Vec<_>>()
+            });
+            let mut all_same = true;
+            for arg in incoming {
+                if arg == param {
+                    continue;
+                }
+                match (consts.get(&arg), &value) {
+                    (Some(arg), None) => value = Some(arg.clone()),
+                    (Some(arg), Some(value)) if arg == value => {}
+                    _ => all_same = false,
+                }
+            }
+            let (true, Some(value)) = (all_same, value) else {
+                continue;
+            };
+            // The parameter stays, unused, for `dce` to remove, and a constant defines its value.
+            let result = function.new_value(value.ty());
+            function.blocks[block].insts.insert(
+                0,
+                Inst {
+                    result,
+                    kind: InstKind::Const(value),
+                },
+            );
+            function.replace_uses(&HashMap::from([(param, result)]));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The constant or value the instruction gives, if it can be known.
+fn fold_inst(
+    adts: &[AdtDef],
+    defs: &HashMap<ValueId, InstKind>,
+    kind: &InstKind,
+    ty: &Type,
+) -> Option<Folded> {
+    let constant = |value: &ValueId| match defs.get(value) {
+        Some(InstKind::Const(value)) => Some(value),
+        _ => None,
+    };
+    match kind {
+        InstKind::Unary(op, value) => fold_unary(*op, constant(value)?).map(Folded::Const),
+        InstKind::Binary(op, a, b) => match (constant(a), constant(b)) {
+            (Some(a), Some(b)) => fold_binary(*op, a, b).map(Folded::Const),
+            (a_const, b_const) => simplify_binary(*op, (*a, a_const), (*b, b_const), ty),
+        },
+        InstKind::Compare(op, a, b) => match (constant(a), constant(b)) {
+            (Some(a), Some(b)) => fold_compare(*op, a, b).map(|b| Folded::Const(Const::Bool(b))),
+            _ => None,
+        },
+        InstKind::Tag(value) => match defs.get(value)? {
+            InstKind::Variant(adt, variant, _) => match &adts.get(*adt)?.kind {
+                AdtKind::Enum(variants) => Some(Folded::Const(Const::Int(
+                    variants.get(*variant)?.discriminant,
+                ))),
+                AdtKind::Struct(_) => None,
+            },
+            _ => None,
+        },
+        InstKind::Payload(value, variant, field) => match defs.get(value)? {
+            InstKind::Variant(_, made, args) if made == variant => {
+                args.get(*field).copied().map(Folded::Value)
+            }
+            _ => None,
+        },
+        InstKind::Extract(value, index) => match defs.get(value)? {
+            InstKind::Tuple(args) => args.get(*index).copied().map(Folded::Value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn fold_unary(op: UnaryOp, value: &Const) -> Option<Const> {
+    Some(match (op, value) {
+        (UnaryOp::Neg, Const::Int(n)) => Const::Int(n.wrapping_neg()),
+        (UnaryOp::Neg, Const::Float(n)) => Const::Float(-n),
+        (UnaryOp::Not, Const::Bool(b)) => Const::Bool(!b),
+        _ => return None,
+    })
+}
+
+/// Fold arithmetic on constants. Integer division by zero is left to fail when the program runs.
+fn fold_binary(op: BinaryOp, a: &Const, b: &Const) -> Option<Const> {
+    Some(match (a, b) {
+        (&Const::Int(a), &Const::Int(b)) => Const::Int(match op {
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Sub => a.wrapping_sub(b),
+            BinaryOp::Mul => a.wrapping_mul(b),
+            BinaryOp::Div => a.checked_div(b)?,
+            BinaryOp::Rem => a.checked_rem(b)?,
+            BinaryOp::Shl => a.checked_shl(b.try_into().ok()?)?,
+            BinaryOp::Shr => a.checked_shr(b.try_into().ok()?)?,
+        }),
+        (&Const::Float(a), &Const::Float(b)) => Const::Float(match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Rem => a % b,
+            BinaryOp::Shl | BinaryOp::Shr => return None,
+        }),
+        (Const::Str(a), Const::Str(b)) if op == BinaryOp::Add => Const::Str(format!("{a}{b}")),
+        _ => return None,
+    })
+}
+
+/// Simplify arithmetic on integers with a constant operand that leaves the other operand as it
+/// is, e.g. `x + 0`, or gives zero, e.g. `x * 0`.
+fn simplify_binary(
+    op: BinaryOp,
+    (a, a_const): (ValueId, Option<&Const>),
+    (b, b_const): (ValueId, Option<&Const>),
+    ty: &Type,
+) -> Option<Folded> {
+    if *ty != Type::Int {
+        return None;
+    }
+    let is = |value: Option<&Const>, n: i64| value == Some(&Const::Int(n));
+    match op {
+        BinaryOp::Add if is(a_const, 0) => Some(Folded::Value(b)),
+        BinaryOp::Add | BinaryOp::Sub | BinaryOp::Shl | BinaryOp::Shr if is(b_const, 0) => {
+            Some(Folded::Value(a))
+        }
+        BinaryOp::Mul if is(a_const, 1) => Some(Folded::Value(b)),
+        BinaryOp::Mul | BinaryOp::Div if is(b_const, 1) => Some(Folded::Value(a)),
+        BinaryOp::Mul if is(a_const, 0) || is(b_const, 0) => Some(Folded::Const(Const::Int(0))),
+        BinaryOp::Sub if a == b => Some(Folded::Const(Const::Int(0))),
+        _ => None,
+    }
+}
+
+fn fold_compare(op: CompareOp, a: &Const, b: &Const) -> Option<bool> {
+    let ordering = match (a, b) {
+        (Const::Int(a), Const::Int(b)) => a.cmp(b),
+        (Const::Float(a), Const::Float(b)) => a.partial_cmp(b)?,
+        (Const::Bool(a), Const::Bool(b)) => a.cmp(b),
+        (Const::Char(a), Const::Char(b)) => a.cmp(b),
+        (Const::Str(a), Const::Str(b)) => a.cmp(b),
+        _ => return None,
+    };
+    Some(match op {
+        CompareOp::Eq => ordering == Ordering::Equal,
+        CompareOp::Ne => ordering != Ordering::Equal,
+        CompareOp::Lt => ordering == Ordering::Less,
+        CompareOp::Le => ordering != Ordering::Greater,
+        CompareOp::Gt => ordering == Ordering::Greater,
+        CompareOp::Ge => ordering != Ordering::Less,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    fn run(source: &str) -> String {
+        let mut module = parse_module(None, source).unwrap();
+        ConstFold.run(&mut module, &mut Analyses::default());
+        print_module(&module)
+    }
+
+    #[test]
+    fn folds_arithmetic_and_branches() {
+        let folded = run("def f(%0: int) int {
+bb0:
+    %1: int = const 6
+    %2: int = const 7
+    %3: int = mul %1, %2
+    %4: int = const 0
+    %5: int = add %0, %4
+    %6: int = div %5, %4
+    %7: bool = gt %3, %2
+    branch %7, bb1, bb2
+bb1:
+    %8: int = add %3, %6
+    return %8
+bb2:
+    return %5
+}
+");
+        assert_eq!(
+            folded,
+            "def f(%0: int) int {
+bb0:
+    %1: int = const 6
+    %2: int = const 7
+    %3: int = const 42
+    %4: int = const 0
+    %6: int = div %0, %4
+    %7: bool = const true
+    jump bb1
+bb1:
+    %8: int = add %3, %6
+    return %8
+bb2:
+    return %0
+}
+"
+        );
+    }
+
+    #[test]
+    fn propagates_through_params_and_enums() {
+        let folded = run("enum E {
+    A(int) = 3,
+    B = 5,
+}
+
+def f(%0: bool) int {
+bb0:
+    %1: int = const 1
+    %2: E = variant E::A(%1)
+    branch %0, bb1(%1), bb2
+bb1(%3: int):
+    %4: int = tag %2
+    %5: int = payload %2, A, 0
+    %6: int = add %4, %5
+    %7: int = add %6, %3
+    return %7
+bb2:
+    %8: int = const 1
+    jump bb1(%8)
+}
+");
+        assert!(
+            folded.ends_with(
+                "bb1(%3: int):
+    %9: int = const 1
+    %4: int = const 3
+    %6: int = const 4
+    %7: int = const 5
+    return %7
+bb2:
+    %8: int = const 1
+    jump bb1(%8)
+}
+"
+            ),
+            "{folded}"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! Common subexpression elimination: a pure instruction that gives the same value as one that
+//! dominates it is removed, and its value replaced with the earlier one.
+//!
+//! The blocks are walked down the dominator tree with a table of the instructions seen on the way,
+//! so an instruction is only matched with the ones dominating it.
+
+use super::Pass;
+use crate::pir::analysis::{Analyses, Preserved};
+use crate::pir::dom::DomTree;
+use crate::pir::{BlockId, Function, InstKind, Module, Type, ValueId};
+use std::collections::HashMap;
+
+pub const NAME: &str = "cse";
+
+pub struct Cse;
+
+impl Pass for Cse {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn preserves(&self) -> Preserved {
+        Preserved::Cfg
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        for id in 0..module.functions.len() {
+            let dom = analyses.dom(module, id);
+            changed |= eliminate(&mut module.functions[id], dom);
+        }
+        changed
+    }
+}
+
+fn eliminate(function: &mut Function, dom: &DomTree) -> bool {
+    let mut available: HashMap<(InstKind, Type), ValueId> = HashMap::new();
+    let mut replaced = HashMap::new();
+    // Each entry is a block to visit, or the keys to forget when leaving a block's subtree.
+    let mut stack: Vec<Result<BlockId, Vec<(InstKind, Type)>>> = vec![Ok(0)];
+    while let Some(entry) = stack.pop() {
+        let block = match entry {
+            Ok(block) => block,
+            Err(keys) => {
+                for key in keys {
+                    available.remove(&key);
+                }
+                continue;
+            }
+        };
+        let mut added = Vec::new();
+        let mut insts = Vec::new();
+        for mut inst in std::mem::take(&mut function.blocks[block].insts) {
+            inst.kind.rename(&replaced);
+            if inst.kind.is_pure() {
+                let key = (inst.kind.clone(), function.values[inst.result].clone());
+                if let Some(&value) = available.get(&key) {
+                    replaced.insert(inst.result, value);
+                    continue;
+                }
+                available.insert(key.clone(), inst.result);
+                added.push(key);
+            }
+            insts.push(inst);
+        }
+        function.blocks[block].insts = insts;
+        stack.push(Err(added));
+        stack.extend(dom.children(block).iter().rev().map(|&child| Ok(child)));
+    }
+    function.replace_uses(&replaced);
+    !replaced.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    #[test]
+    fn shares_values_of_dominating_instructions() {
+        let mut module = parse_module(
+            None,
+            "struct S {
+    x: int,
+}
+
+def f(%0: bool, %1: int, %2: S) int {
+bb0:
+    %3: int = add %1, %1
+    %4: int = get_field %2, x
+    branch %0, bb1, bb2
+bb1:
+    %5: int = add %1, %1
+    %6: int = mul %5, %3
+    %7: int = get_field %2, x
+    jump bb3(%6)
+bb2:
+    %8: int = add %1, %1
+    %9: int = mul %3, %8
+    jump bb3(%9)
+bb3(%10: int):
+    %11: int = mul %3, %3
+    return %11
+}
+",
+        )
+        .unwrap();
+        assert!(Cse.run(&mut module, &mut Analyses::default()));
+        let printed = print_module(&module);
+        let body = &printed[printed.find("bb1:").unwrap()..];
+        assert_eq!(
+            body,
+            "bb1:
+    %6: int = mul %3, %3
+    %7: int = get_field %2, x
+    jump bb3(%6)
+bb2:
+    %9: int = mul %3, %3
+    jump bb3(%9)
+bb3(%10: int):
+    %11: int = mul %3, %3
+    return %11
+}
+"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Dead code elimination: remove the instructions whose values aren't needed and that have no
+//! side effects, and the block parameters that aren't needed, along with their arguments.
+//!
+//! A value is needed if an instruction with side effects or a terminator uses it, or if a needed
+//! value is computed from it. Values used only to compute themselves, e.g. a counter of a loop
+//! that nothing reads, aren't needed.
+
+use super::Pass;
+use crate::pir::analysis::{Analyses, Preserved};
+use crate::pir::{BlockId, Function, Module, Terminator, ValueId};
+use std::collections::HashMap;
+
+pub const NAME: &str = "dce";
+
+pub struct Dce;
+
+impl Pass for Dce {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn preserves(&self) -> Preserved {
+        Preserved::Cfg
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        for function in &mut module.functions {
+            changed |= remove_dead(function);
+        }
+        changed
+    }
+}
+
+/// Where a value is defined.
+#[derive(Clone, Copy)]
+enum Def {
+    Param(BlockId, usize),
+    Inst(BlockId, usize),
+}
+
+fn remove_dead(function: &mut Function) -> bool {
+    let mut defs = HashMap::new();
+    // The blocks jumping to every block, with the index of the target.
+    let mut incoming = vec![Vec::new(); function.blocks.len()];
+    let mut live = vec![false; function.values.len()];
+    let mut worklist = Vec::new();
+    for (id, block) in function.blocks.iter().enumerate() {
+        for (i, &param) in block.params.iter().enumerate() {
+            defs.insert(param, Def::Param(id, i));
+        }
+        for (i, inst) in block.insts.iter().enumerate() {
+            defs.insert(inst.result, Def::Inst(id, i));
+            if inst.kind.has_side_effects() {
+                worklist.push(inst.result);
+            }
+        }
+        for (i, target) in block.term.targets().into_iter().enumerate() {
+            incoming[target.block].push((id, i));
+        }
+        match block.term {
+            Terminator::Branch { cond, .. } => worklist.push(cond),
+            Terminator::Return(Some(value)) => worklist.push(value),
+            _ => {}
+        }
+    }
+    // The parameters of the entry block are those of the function.
+    worklist.extend(function.params());
+
+    while let Some(value) = worklist.pop() {
+        if std::mem::replace(&mut live[value], true) {
+            continue;
+        }
+        match defs.get(&value) {
+            Some(&Def::Inst(block, i)) => {
+                worklist.extend(function.blocks[block].insts[i].kind.operands());
+            }
+            Some(&Def::Param(block, i)) => {
+                for &(pred, target) in &incoming[block] {
+                    worklist.push(function.blocks[pred].term.targets()[target].args[i]);
+                }
+            }
+            None => {}
+        }
+    }
+
+    let mut changed = false;
+    for block in 1..function.blocks.len() {
+        let dead: Vec<usize> = (0..function.blocks[block].params.len())
+            .filter(|&i| !live[function.blocks[block].params[i]])
+            .collect();
+        if dead.is_empty() {
+            continue;
+        }
+        changed = true;
+        for &(pred, target) in &incoming[block] {
+            let target = &mut function.blocks[pred].term.targets_mut()[target];
+            target.args = retain_indices(&target.args, &dead);
+        }
+        let params = &mut function.blocks[block].params;
+        *params = retain_indices(params, &dead);
+    }
+    for block in &mut function.blocks {
+        let count = block.insts.len();
+        block.insts.retain(|inst| live[inst.result]);
+        changed |= block.insts.len() != count;
+    }
+    changed
+}
+
+/// The values without the ones at the indices.
+fn retain_indices(values: &[ValueId], removed: &[usize]) -> Vec<ValueId> {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !removed.contains(i))
+        .map(|(_, &value)| value)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    #[test]
+    fn removes_unused_values_and_loop_counters() {
+        let mut module = parse_module(
+            None,
+            "def f(%0: bool) int {
+bb0:
+    %1: int = const 0
+    %2: int = const 1
+    %3: *int = alloca int
+    jump bb1(%1, %1)
+bb1(%4: int, %5: int):
+    %6: int = add %4, %2
+    %7: int = add %5, %2
+    %8: void = call_native println(%7)
+    %9: int = div %2, %1
+    branch %0, bb1(%6, %7), bb2
+bb2:
+    return %2
+}
+",
+        )
+        .unwrap();
+        assert!(Dce.run(&mut module, &mut Analyses::default()));
+        assert_eq!(
+            print_module(&module),
+            "def f(%0: bool) int {
+bb0:
+    %1: int = const 0
+    %2: int = const 1
+    jump bb1(%1)
+bb1(%5: int):
+    %7: int = add %5, %2
+    %8: void = call_native println(%7)
+    %9: int = div %2, %1
+    branch %0, bb1(%7), bb2
+bb2:
+    return %2
+}
+"
+        );
+        assert!(!Dce.run(&mut module, &mut Analyses::default()));
+    }
+}
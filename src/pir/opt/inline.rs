@@ -0,0 +1,317 @@
+//! Function inlining: calls to small functions are replaced with a copy of their body.
+//!
+//! The cost of inlining a call is the number of instructions and blocks of the callee, less a
+//! bonus for every constant argument, which `const-fold` is likely to fold away in the copy.
+//! Calls cheaper than the threshold are inlined. Callees are inlined into their callers before
+//! the callers are into theirs, so small call chains collapse in one run. Functions that call
+//! themselves are never inlined, and a caller stops inlining once it has grown too big.
+//!
+//! With a profile, calls that never ran aren't inlined, and calls that run at least a tenth as
+//! often as the most called function get a higher threshold. The copy of an inlined body gets the
+//! counts of the callee scaled down to the call.
+
+use super::Pass;
+use crate::pir::analysis::Analyses;
+use crate::pir::profile::block_count;
+use crate::pir::{
+    BlockId, Const, FuncId, Function, Inst, InstKind, Module, Target, Terminator, Type, ValueId,
+};
+use std::collections::HashMap;
+
+pub const NAME: &str = "inline";
+
+/// The threshold of `O2`.
+pub const O2_THRESHOLD: usize = 30;
+/// The threshold of `O3`.
+pub const O3_THRESHOLD: usize = 100;
+/// How much cheaper a constant argument makes a call.
+const CONST_ARG_BONUS: usize = 5;
+/// How many times its threshold a caller may grow to by inlining.
+const GROWTH_LIMIT: usize = 20;
+/// How many times the threshold is for hot calls.
+const HOT_BONUS: usize = 3;
+/// How many times less often than the most called function a hot call may run.
+const HOT_FRACTION: u64 = 10;
+
+pub struct Inline {
+    threshold: usize,
+}
+
+impl Inline {
+    pub fn new(threshold: usize) -> Self {
+        Self { threshold }
+    }
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        let hottest = module.functions.iter().filter_map(|f| f.entry_count).max();
+        for caller in bottom_up(module) {
+            let mut function = module.functions[caller].clone();
+            let mut inlined = false;
+            while let Some((block, index, callee)) =
+                self.next_call(module, caller, &function, hottest)
+            {
+                inline_call(&mut function, block, index, &module.functions[callee]);
+                inlined = true;
+            }
+            if inlined {
+                module.functions[caller] = function;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+impl Inline {
+    /// The first call in the function worth inlining, as its block, index and callee.
+    fn next_call(
+        &self,
+        module: &Module,
+        caller: FuncId,
+        function: &Function,
+        hottest: Option<u64>,
+    ) -> Option<(BlockId, usize, FuncId)> {
+        if size(function) > self.threshold * GROWTH_LIMIT {
+            return None;
+        }
+        let consts: Vec<ValueId> = function
+            .blocks
+            .iter()
+            .flat_map(|block| &block.insts)
+            .filter(|inst| matches!(inst.kind, InstKind::Const(_)))
+            .map(|inst| inst.result)
+            .collect();
+        for (id, block) in function.blocks.iter().enumerate() {
+            let threshold = match (block_count(function, id), hottest) {
+                (Some(0), _) => continue,
+                (Some(count), Some(hottest)) if count >= hottest / HOT_FRACTION => {
+                    self.threshold * HOT_BONUS
+                }
+                _ => self.threshold,
+            };
+            for (index, inst) in block.insts.iter().enumerate() {
+                let InstKind::Call(callee, args) = &inst.kind else {
+                    continue;
+                };
+                if *callee == caller || calls(&module.functions[*callee], *callee) {
+                    continue;
+                }
+                let bonus = CONST_ARG_BONUS * args.iter().filter(|a| consts.contains(a)).count();
+                if size(&module.functions[*callee]).saturating_sub(bonus) < threshold {
+                    return Some((id, index, *callee));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The size of a function, in instructions and blocks.
+fn size(function: &Function) -> usize {
+    function.blocks.iter().map(|b| b.insts.len() + 1).sum()
+}
+
+/// Whether the function calls the given function directly.
+fn calls(function: &Function, callee: FuncId) -> bool {
+    function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .any(|inst| matches!(inst.kind, InstKind::Call(id, _) if id == callee))
+}
+
+/// The functions in postorder of the call graph, so that callees come before their callers except
+/// in cycles.
+fn bottom_up(module: &Module) -> Vec<FuncId> {
+    let callees: Vec<Vec<FuncId>> = module
+        .functions
+        .iter()
+        .map(|function| {
+            let mut callees: Vec<FuncId> = function
+                .blocks
+                .iter()
+                .flat_map(|block| &block.insts)
+                .filter_map(|inst| match inst.kind {
+                    InstKind::Call(callee, _) => Some(callee),
+                    _ => None,
+                })
+                .collect();
+            callees.dedup();
+            callees
+        })
+        .collect();
+    let mut visited = vec![false; module.functions.len()];
+    let mut order = Vec::new();
+    for root in 0..module.functions.len() {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut stack = vec![(root, 0)];
+        while let Some((function, next)) = stack.pop() {
+            match callees[function].get(next) {
+                Some(&callee) => {
+                    stack.push((function, next + 1));
+                    if !visited[callee] {
+                        visited[callee] = true;
+                        stack.push((callee, 0));
+                    }
+                }
+                None => order.push(function),
+            }
+        }
+    }
+    order
+}
+
+/// Replace the call at the index of the block with a copy of the callee's blocks. The block is
+/// split after the call into a continuation taking the value returned, which keeps the call's
+/// value id, so the uses of the call don't change.
+fn inline_call(function: &mut Function, block: BlockId, index: usize, callee: &Function) {
+    let count = block_count(function, block);
+    let scale = |n: Option<u64>| match (n, count, callee.entry_count) {
+        (Some(n), Some(count), Some(entry)) if entry > 0 => {
+            Some((n as u128 * count as u128 / entry as u128) as u64)
+        }
+        (Some(_), Some(_), Some(_)) => Some(0),
+        _ => None,
+    };
+    let call = function.blocks[block].insts.remove(index);
+    let InstKind::Call(_, args) = call.kind else {
+        unreachable!("only calls are inlined")
+    };
+    let rest = function.blocks[block].insts.split_off(index);
+    let cont = function.add_block();
+    function.blocks[cont].params.push(call.result);
+    function.blocks[cont].insts = rest;
+    function.blocks[cont].term = std::mem::take(&mut function.blocks[block].term);
+
+    let first = copy_body(function, callee);
+    for copy in first..function.blocks.len() {
+        for target in function.blocks[copy].term.targets_mut() {
+            target.count = scale(target.count);
+        }
+        let value = match function.blocks[copy].term {
+            Terminator::Return(Some(value)) => value,
+            Terminator::Return(None) => {
+                let void = function.new_value(Type::Void);
+                function.blocks[copy].insts.push(Inst {
+                    result: void,
+                    kind: InstKind::Const(Const::Void),
+                });
+                void
+            }
+            _ => continue,
+        };
+        let mut target = Target::new(cont, vec![value]);
+        target.count = scale(block_count(callee, copy - first));
+        function.blocks[copy].term = Terminator::Jump(target);
+    }
+    let mut target = Target::new(first, args);
+    target.count = count;
+    function.blocks[block].term = Terminator::Jump(target);
+}
+
+/// Append a copy of the callee's blocks to the function, returning the block the copy starts at.
+/// The callee's values are renumbered all at once rather than with `rename`, whose chains would
+/// mix up old and new ids.
+pub(super) fn copy_body(function: &mut Function, callee: &Function) -> BlockId {
+    let values: HashMap<ValueId, ValueId> = callee
+        .values
+        .iter()
+        .enumerate()
+        .map(|(value, ty)| (value, function.new_value(ty.clone())))
+        .collect();
+    for (value, loc) in &callee.locs {
+        if let Some(&value) = values.get(value) {
+            function.locs.insert(value, loc.clone());
+        }
+    }
+    let first = function.blocks.len();
+    for source in &callee.blocks {
+        let mut block = source.clone();
+        block.params = source.params.iter().map(|v| values[v]).collect();
+        for inst in &mut block.insts {
+            inst.result = values[&inst.result];
+            for operand in inst.kind.operands_mut() {
+                *operand = values[operand];
+            }
+        }
+        for operand in block.term.operands_mut() {
+            *operand = values[operand];
+        }
+        for target in block.term.targets_mut() {
+            target.block += first;
+        }
+        function.blocks.push(block);
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    #[test]
+    fn inlines_small_callees_bottom_up() {
+        let mut module = parse_module(
+            None,
+            "def main() void {
+bb0:
+    %0: int = const 2
+    %1: int = call @twice(%0)
+    %2: void = call_native println(%1)
+    %3: int = call @fact(%1)
+    return
+}
+
+def twice(%0: int) int {
+bb0:
+    %1: int = call @double(%0)
+    %2: int = call @double(%1)
+    return %2
+}
+
+def double(%0: int) int {
+bb0:
+    %1: int = add %0, %0
+    return %1
+}
+
+def fact(%0: int) int {
+bb0:
+    %1: int = const 1
+    %2: bool = le %0, %1
+    branch %2, bb1, bb2
+bb1:
+    return %1
+bb2:
+    %3: int = sub %0, %1
+    %4: int = call @fact(%3)
+    %5: int = mul %0, %4
+    return %5
+}
+",
+        )
+        .unwrap();
+        assert!(Inline::new(O2_THRESHOLD).run(&mut module, &mut Analyses::default()));
+        crate::pir::verify::verify(&module).unwrap();
+        let printed = print_module(&module);
+        let main = &printed[..printed.find("def twice").unwrap()];
+        assert!(
+            !main.contains("@twice") && !main.contains("@double"),
+            "{main}"
+        );
+        assert!(main.contains("call @fact"), "{main}");
+        assert!(printed.contains("%4: int = call @fact(%3)"), "{printed}");
+    }
+}
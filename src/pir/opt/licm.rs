@@ -0,0 +1,187 @@
+//! Loop-invariant code motion: pure instructions in a loop whose operands are all defined outside
+//! of it are moved out, to the block entering the loop, so they run once instead of every time
+//! around.
+//!
+//! A loop is found from each edge going back to a block that dominates its source, the header.
+//! The instructions are moved to the single block jumping to the header from outside the loop, or
+//! to a new block made to do so, the preheader. Instructions that may fail when run, like a
+//! division or reading the payload of another variant, are never moved, since the loop might not
+//! have run them.
+
+use super::Pass;
+use crate::pir::analysis::Analyses;
+use crate::pir::dom::predecessors;
+use crate::pir::{BlockId, Function, InstKind, Module, Target, Terminator, ValueId};
+use std::collections::HashSet;
+
+pub const NAME: &str = "licm";
+
+pub struct Licm;
+
+impl Pass for Licm {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        for id in 0..module.functions.len() {
+            // The loops as their headers and blocks, inner loops first.
+            let mut loops: Vec<(BlockId, Vec<BlockId>)> = analyses
+                .loops(module, id)
+                .loops
+                .iter()
+                .map(|l| (l.header, l.blocks.clone()))
+                .collect();
+            let function = &mut module.functions[id];
+            for i in 0..loops.len() {
+                let (header, body) = loops[i].clone();
+                let Some(preheader) = hoist(function, header, &body) else {
+                    continue;
+                };
+                changed = true;
+                // A new preheader is in the loops around this one.
+                for (_, outer) in &mut loops[i + 1..] {
+                    if outer.contains(&header) && !outer.contains(&preheader) {
+                        outer.push(preheader);
+                    }
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Move the invariant instructions of the loop out, returning the block they were moved to.
+fn hoist(function: &mut Function, header: BlockId, body: &[BlockId]) -> Option<BlockId> {
+    let mut defined_inside: HashSet<ValueId> = HashSet::new();
+    for &block in body {
+        defined_inside.extend(&function.blocks[block].params);
+        defined_inside.extend(function.blocks[block].insts.iter().map(|inst| inst.result));
+    }
+    let mut hoisted = Vec::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in body {
+            let insts = std::mem::take(&mut function.blocks[block].insts);
+            let (moved, kept): (Vec<_>, Vec<_>) = insts.into_iter().partition(|inst| {
+                let invariant = is_speculatable(&inst.kind)
+                    && inst
+                        .kind
+                        .operands()
+                        .iter()
+                        .all(|value| !defined_inside.contains(value));
+                if invariant {
+                    defined_inside.remove(&inst.result);
+                }
+                invariant
+            });
+            changed |= !moved.is_empty();
+            hoisted.extend(moved);
+            function.blocks[block].insts = kept;
+        }
+    }
+    if hoisted.is_empty() {
+        return None;
+    }
+    let preheader = preheader(function, header, body);
+    function.blocks[preheader].insts.extend(hoisted);
+    Some(preheader)
+}
+
+/// Whether the instruction can be run where the program wouldn't run it.
+fn is_speculatable(kind: &InstKind) -> bool {
+    kind.is_pure() && !matches!(kind, InstKind::Payload(..))
+}
+
+/// The block that all jumps from outside the loop to its header come from, made if there isn't
+/// one.
+fn preheader(function: &mut Function, header: BlockId, body: &[BlockId]) -> BlockId {
+    let outside: Vec<BlockId> = predecessors(function)[header]
+        .iter()
+        .copied()
+        .filter(|pred| !body.contains(pred))
+        .collect();
+    if let [pred] = outside[..] {
+        if matches!(function.blocks[pred].term, Terminator::Jump(_)) {
+            return pred;
+        }
+    }
+    let preheader = function.add_block();
+    let params: Vec<ValueId> = function.blocks[header]
+        .params
+        .iter()
+        .map(|&param| function.values[param].clone())
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|ty| function.add_param(preheader, ty))
+        .collect();
+    function.blocks[preheader].term = Terminator::Jump(Target::new(header, params));
+    for pred in outside {
+        for target in function.blocks[pred].term.targets_mut() {
+            if target.block == header {
+                target.block = preheader;
+            }
+        }
+    }
+    preheader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    #[test]
+    fn hoists_invariants_to_a_preheader() {
+        let mut module = parse_module(
+            None,
+            "def f(%0: int, %1: bool) int {
+bb0:
+    %2: int = const 0
+    branch %1, bb1(%2), bb3
+bb1(%3: int):
+    %4: int = mul %0, %0
+    %5: int = const 1
+    %6: int = add %4, %5
+    %7: int = div %0, %6
+    %8: int = add %3, %6
+    %9: bool = lt %8, %7
+    branch %9, bb1(%8), bb2
+bb2:
+    return %8
+bb3:
+    return %2
+}
+",
+        )
+        .unwrap();
+        assert!(Licm.run(&mut module, &mut Analyses::default()));
+        crate::pir::verify::verify(&module).unwrap();
+        assert_eq!(
+            print_module(&module),
+            "def f(%0: int, %1: bool) int {
+bb0:
+    %2: int = const 0
+    branch %1, bb4(%2), bb3
+bb1(%3: int):
+    %7: int = div %0, %6
+    %8: int = add %3, %6
+    %9: bool = lt %8, %7
+    branch %9, bb1(%8), bb2
+bb2:
+    return %8
+bb3:
+    return %2
+bb4(%10: int):
+    %4: int = mul %0, %0
+    %5: int = const 1
+    %6: int = add %4, %5
+    jump bb1(%10)
+}
+"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+//! Promote variables in memory to values: an `alloca` that is only loaded from and stored to is
+//! replaced by the value last stored, passed from block to block as a block parameter.
+//!
+//! Every block dominated by the `alloca` gets a parameter for the variable, most of which
+//! `simplify-cfg` and `dce` remove afterwards. Variables that may be read before they are written,
+//! and functions with unreachable blocks, are left alone.
+
+use super::Pass;
+use crate::pir::analysis::{Analyses, Preserved};
+use crate::pir::dom::DomTree;
+use crate::pir::{BlockId, Function, InstKind, Module, Type, ValueId};
+use std::collections::HashMap;
+
+pub const NAME: &str = "mem2reg";
+
+pub struct Mem2Reg;
+
+impl Pass for Mem2Reg {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn preserves(&self) -> Preserved {
+        Preserved::Cfg
+    }
+
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        for id in 0..module.functions.len() {
+            let dom = analyses.dom(module, id);
+            changed |= promote_all(&mut module.functions[id], dom);
+        }
+        changed
+    }
+}
+
+fn promote_all(function: &mut Function, dom: &DomTree) -> bool {
+    if (0..function.blocks.len()).any(|block| !dom.is_reachable(block)) {
+        return false;
+    }
+    let allocas: Vec<(BlockId, ValueId)> = function
+        .blocks
+        .iter()
+        .enumerate()
+        .flat_map(|(id, block)| {
+            block
+                .insts
+                .iter()
+                .filter(|inst| matches!(inst.kind, InstKind::Alloca(_)))
+                .map(move |inst| (id, inst.result))
+        })
+        .collect();
+    let mut changed = false;
+    for (block, alloca) in allocas {
+        if is_promotable(function, alloca) {
+            changed |= promote(function, dom, block, alloca);
+        }
+    }
+    changed
+}
+
+/// Whether the address is only used to load and store, and not e.g. stored or captured.
+fn is_promotable(function: &Function, alloca: ValueId) -> bool {
+    function.blocks.iter().all(|block| {
+        let mut term = block.term.clone();
+        block.insts.iter().all(|inst| match &inst.kind {
+            InstKind::Load(_) => true,
+            InstKind::Store(_, value) => *value != alloca,
+            kind => !kind.operands().contains(&alloca),
+        }) && !term
+            .operands_mut()
+            .into_iter()
+            .any(|value| *value == alloca)
+    })
+}
+
+/// Replace the loads of the variable with the values stored, if every load and every jump within
+/// the blocks it's live in has one.
+fn promote(function: &mut Function, dom: &DomTree, home: BlockId, alloca: ValueId) -> bool {
+    let Type::Ptr(ty) = function.values[alloca].clone() else {
+        return false;
+    };
+    let dominated: Vec<BlockId> = (0..function.blocks.len())
+        .filter(|&block| block != home && dom.dominates(home, block))
+        .collect();
+    // The parameter every dominated block would get, numbered after the existing values.
+    let params: HashMap<BlockId, ValueId> = dominated
+        .iter()
+        .enumerate()
+        .map(|(i, &block)| (block, function.values.len() + i))
+        .collect();
+
+    let mut loads = HashMap::new();
+    let mut args: Vec<(BlockId, usize, ValueId)> = Vec::new();
+    for block in std::iter::once(home).chain(dominated.iter().copied()) {
+        let mut current = params.get(&block).copied();
+        for inst in &function.blocks[block].insts {
+            match inst.kind {
+                InstKind::Alloca(_) if inst.result == alloca => current = None,
+                InstKind::Load(address) if address == alloca => match current {
+                    Some(value) => {
+                        loads.insert(inst.result, value);
+                    }
+                    None => return false,
+                },
+                InstKind::Store(address, value) if address == alloca => {
+                    current = Some(value);
+                }
+                _ => {}
+            }
+        }
+        for (i, target) in function.blocks[block]
+            .term
+            .targets()
+            .into_iter()
+            .enumerate()
+        {
+            if params.contains_key(&target.block) {
+                match current {
+                    Some(value) => args.push((block, i, value)),
+                    None => return false,
+                }
+            }
+        }
+    }
+
+    for &block in &dominated {
+        function.add_param(block, (*ty).clone());
+    }
+    for (block, i, value) in args {
+        function.blocks[block].term.targets_mut()[i]
+            .args
+            .push(value);
+    }
+    for block in &mut function.blocks {
+        block.insts.retain(|inst| match inst.kind {
+            InstKind::Alloca(_) => inst.result != alloca,
+            InstKind::Load(address) | InstKind::Store(address, _) => address != alloca,
+            _ => true,
+        });
+    }
+    function.replace_uses(&loads);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    #[test]
+    fn promotes_variables_assigned_in_branches() {
+        let mut module = parse_module(
+            None,
+            "def f(%0: bool) int {
+bb0:
+    %1: *int = alloca int
+    %2: int = const 1
+    %3: void = store %1, %2
+    branch %0, bb1, bb2
+bb1:
+    %4: int = const 2
+    %5: void = store %1, %4
+    jump bb2
+bb2:
+    %6: int = load %1
+    return %6
+}
+
+def g() int {
+bb0:
+    %0: *int = alloca int
+    %1: int = load %0
+    return %1
+}
+",
+        )
+        .unwrap();
+        assert!(Mem2Reg.run(&mut module, &mut Analyses::default()));
+        assert_eq!(
+            print_module(&module),
+            "def f(%0: bool) int {
+bb0:
+    %2: int = const 1
+    branch %0, bb1(%2), bb2(%2)
+bb1(%7: int):
+    %4: int = const 2
+    jump bb2(%4)
+bb2(%8: int):
+    return %8
+}
+
+def g() int {
+bb0:
+    %0: *int = alloca int
+    %1: int = load %0
+    return %1
+}
+"
+        );
+    }
+}
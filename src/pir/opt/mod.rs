@@ -0,0 +1,307 @@
+//! The optimizer: passes that rewrite a module into a faster one with the same behavior, and the
+//! pass manager running them in the pipeline of an optimization level.
+//!
+//! Passes share the analyses of the module through `analysis::Analyses`, which the pass manager
+//! clears of what a pass invalidated after it changed the module. Every pass keeps the module
+//! valid PIR. With `verify_each`, the pass manager runs the verifier
+//! after every pass, so a broken pass is caught right where it breaks the module.
+
+pub mod block_layout;
+pub mod const_fold;
+pub mod cse;
+pub mod dce;
+pub mod inline;
+pub mod licm;
+pub mod mem2reg;
+pub mod simplify_cfg;
+pub mod stack_alloc;
+pub mod tail_call;
+
+use super::analysis::{Analyses, Preserved};
+use super::verify::{verify, PirError};
+use super::Module;
+use crate::parse::SourceCodeLocation;
+use std::fmt;
+
+/// The names of every pass, e.g. for `--print-after`.
+pub const PASSES: [&str; 10] = [
+    mem2reg::NAME,
+    const_fold::NAME,
+    dce::NAME,
+    simplify_cfg::NAME,
+    cse::NAME,
+    inline::NAME,
+    licm::NAME,
+    stack_alloc::NAME,
+    tail_call::NAME,
+    block_layout::NAME,
+];
+
+/// A transformation of a module.
+pub trait Pass {
+    fn name(&self) -> &'static str;
+
+    /// What the pass keeps valid when it changes the module.
+    fn preserves(&self) -> Preserved {
+        Preserved::Nothing
+    }
+
+    /// Rewrite the module, returning whether anything changed. The analyses are those of the module
+    /// as the pass got it.
+    fn run(&mut self, module: &mut Module, analyses: &mut Analyses) -> bool;
+
+    /// What the pass did in its last run that is worth telling the user, e.g. with `--remarks`.
+    fn remarks(&mut self) -> Vec<Remark> {
+        Vec::new()
+    }
+}
+
+/// A note from a pass about a change it made to the program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Remark {
+    pub pass: &'static str,
+    pub function: String,
+    /// Where in the Paca source the changed code comes from, if it is known.
+    pub loc: Option<SourceCodeLocation>,
+    pub message: String,
+}
+
+impl fmt::Display for Remark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(loc) = &self.loc {
+            let file = loc.filename.as_deref().unwrap_or("unknown");
+            write!(f, "{file}:{}:{}: ", loc.line, loc.column)?;
+        }
+        write!(f, "in `{}`: {}", self.function, self.message)
+    }
+}
+
+/// How hard to optimize, from `O0`, which doesn't, to `O3`. Every level eliminates tail calls,
+/// which `@tailcall` functions rely on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Only eliminate tail calls.
+    #[default]
+    O0,
+    /// Promote variables to values, fold constants and clean up.
+    O1,
+    /// Also inline small functions, take allocations that don't escape off the heap, share common
+    /// subexpressions, hoist loop invariants and lay out blocks by the profile, if there is one.
+    O2,
+    /// Like `O2`, but inline bigger functions and clean up once more.
+    O3,
+}
+
+/// A pass that broke the module, with what the verifier found.
+#[derive(Clone, Debug)]
+pub struct PassError {
+    pub pass: &'static str,
+    pub errors: Vec<PirError>,
+}
+
+/// Runs passes in order.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+    print_after: Vec<String>,
+    verify_each: bool,
+    remarks: Vec<Remark>,
+}
+
+impl PassManager {
+    /// A pass manager with the pipeline of the level.
+    pub fn new(level: OptLevel) -> Self {
+        let mut manager = Self::default().with_pass(tail_call::TailCall);
+        if level == OptLevel::O0 {
+            return manager;
+        }
+        manager = manager
+            .with_pass(simplify_cfg::SimplifyCfg)
+            .with_pass(mem2reg::Mem2Reg)
+            .with_pass(const_fold::ConstFold)
+            .with_pass(simplify_cfg::SimplifyCfg)
+            .with_pass(dce::Dce);
+        if level == OptLevel::O1 {
+            return manager;
+        }
+        let threshold = match level {
+            OptLevel::O3 => inline::O3_THRESHOLD,
+            _ => inline::O2_THRESHOLD,
+        };
+        manager = manager
+            .with_pass(inline::Inline::new(threshold))
+            .with_pass(simplify_cfg::SimplifyCfg)
+            .with_pass(stack_alloc::StackAlloc::default())
+            .with_pass(mem2reg::Mem2Reg)
+            .with_pass(const_fold::ConstFold)
+            .with_pass(cse::Cse)
+            .with_pass(licm::Licm)
+            .with_pass(simplify_cfg::SimplifyCfg)
+            .with_pass(dce::Dce);
+        if level == OptLevel::O3 {
+            manager = manager
+                .with_pass(const_fold::ConstFold)
+                .with_pass(cse::Cse)
+                .with_pass(simplify_cfg::SimplifyCfg)
+                .with_pass(dce::Dce);
+        }
+        manager.with_pass(block_layout::BlockLayout)
+    }
+
+    pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    /// Hand the module to the `print` callback of `run` after every pass with one of the names.
+    pub fn print_after(mut self, names: Vec<String>) -> Self {
+        self.print_after = names;
+        self
+    }
+
+    /// Verify the module after every pass.
+    pub fn verify_each(mut self, verify_each: bool) -> Self {
+        self.verify_each = verify_each;
+        self
+    }
+
+    /// The names of the passes, in order.
+    pub fn pipeline(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    /// The remarks of the passes that have run, in order.
+    pub fn remarks(&self) -> &[Remark] {
+        &self.remarks
+    }
+
+    /// Run the passes on the module, calling `print` with the name of a pass and the module after
+    /// the passes given to `print_after`.
+    pub fn run(
+        &mut self,
+        module: &mut Module,
+        mut print: impl FnMut(&str, &Module),
+    ) -> Result<(), PassError> {
+        let mut analyses = Analyses::default();
+        for pass in &mut self.passes {
+            let changed = pass.run(module, &mut analyses);
+            log::trace!("Ran {}, which changed {}.", pass.name(), changed);
+            self.remarks.extend(pass.remarks());
+            if changed {
+                analyses.invalidate_all(pass.preserves());
+            }
+            if self.verify_each {
+                verify(module).map_err(|errors| PassError {
+                    pass: pass.name(),
+                    errors,
+                })?;
+            }
+            if self.print_after.iter().any(|name| name == pass.name()) {
+                print(pass.name(), module);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::lower::lower;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+    use crate::sema::modules::ModuleLoader;
+    use std::path::Path;
+
+    #[test]
+    fn keeps_lowered_programs_valid_at_every_level() {
+        for fixture in ["arrays", "consts", "either_option", "patterns", "traits"] {
+            let path = format!("tests/fixtures/{fixture}.paca");
+            let graph = ModuleLoader::new(vec![])
+                .load(Path::new(&path))
+                .unwrap_or_else(|e| panic!("{}", e.message()));
+            let (analysis, diagnostics) = crate::sema::analyze(&graph);
+            let analysis = analysis.unwrap_or_else(|| panic!("{diagnostics:?}"));
+            for level in [OptLevel::O1, OptLevel::O2, OptLevel::O3] {
+                let mut module = lower(&graph, &analysis);
+                let result = PassManager::new(level)
+                    .verify_each(true)
+                    .run(&mut module, |_, _| {});
+                assert!(result.is_ok(), "{fixture} at {level:?}: {result:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn optimizes_loops_at_o2() {
+        let source = "def sq(%0: int) int {
+bb0:
+    %1: int = mul %0, %0
+    return %1
+}
+
+def main() void {
+bb0:
+    %0: int = const 0
+    %1: *int = alloca int
+    %2: void = store %1, %0
+    %3: int = const 3
+    %4: int = const 4
+    %5: int = add %3, %4
+    jump bb1
+bb1:
+    %6: int = load %1
+    %7: int = const 10
+    %8: bool = lt %6, %7
+    branch %8, bb2, bb3
+bb2:
+    %9: int = call @sq(%5)
+    %10: int = load %1
+    %11: int = add %9, %10
+    %12: void = call_native println(%11)
+    %13: int = load %1
+    %14: int = const 1
+    %15: int = add %13, %14
+    %16: void = store %1, %15
+    jump bb1
+bb3:
+    return
+}
+";
+        let mut module = parse_module(None, source).unwrap();
+        let mut printed = Vec::new();
+        PassManager::new(OptLevel::O2)
+            .verify_each(true)
+            .print_after(vec![mem2reg::NAME.to_string()])
+            .run(&mut module, |pass, module| {
+                printed.push((pass.to_string(), print_module(module)))
+            })
+            .unwrap();
+        assert_eq!(printed.len(), 2);
+        assert!(printed.iter().all(|(_, text)| !text.contains("alloca")));
+        let main = print_module(&module);
+        let main = &main[main.find("def main").unwrap()..];
+        assert_eq!(
+            main,
+            "def main() void {
+bb0:
+    %0: int = const 0
+    %7: int = const 10
+    %21: int = const 49
+    %14: int = const 1
+    jump bb1(%0)
+bb1(%17: int):
+    %8: bool = lt %17, %7
+    branch %8, bb3, bb2
+bb2:
+    return
+bb3:
+    %11: int = add %21, %17
+    %12: void = call_native println(%11)
+    %15: int = add %17, %14
+    jump bb1(%15)
+}
+"
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! CFG simplification: remove unreachable blocks, merge a block into the one jumping to it when
+//! nothing else does, skip blocks that only jump on, turn branches with the same two targets into
+//! jumps and remove the parameters every jump passes the same value.
+
+use super::Pass;
+use crate::pir::analysis::Analyses;
+use crate::pir::dom::{predecessors, reverse_postorder};
+use crate::pir::{Function, Module, Terminator, ValueId};
+use std::collections::HashMap;
+
+pub const NAME: &str = "simplify-cfg";
+
+pub struct SimplifyCfg;
+
+impl Pass for SimplifyCfg {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn run(&mut self, module: &mut Module, _analyses: &mut Analyses) -> bool {
+        let mut changed = false;
+        for function in &mut module.functions {
+            changed |= simplify(function);
+        }
+        changed
+    }
+}
+
+fn simplify(function: &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let round = fold_branches(function)
+            | remove_unreachable(function)
+            | thread_jumps(function)
+            | merge_blocks(function)
+            | remove_trivial_params(function);
+        if !round {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+/// Turn branches whose targets are the same into jumps.
+fn fold_branches(function: &mut Function) -> bool {
+    let mut changed = false;
+    for block in &mut function.blocks {
+        if let Terminator::Branch {
+            then_target,
+            else_target,
+            ..
+        } = &block.term
+        {
+            if then_target == else_target {
+                let mut target = then_target.clone();
+                target.count = then_target.count.zip(else_target.count).map(|(a, b)| a + b);
+                block.term = Terminator::Jump(target);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Remove the blocks that can't be reached from the entry, numbering the rest in order.
+pub(super) fn remove_unreachable(function: &mut Function) -> bool {
+    let mut reachable = vec![false; function.blocks.len()];
+    for block in reverse_postorder(function) {
+        reachable[block] = true;
+    }
+    if reachable.iter().all(|&r| r) {
+        return false;
+    }
+    let mut ids = vec![usize::MAX; function.blocks.len()];
+    let mut next = 0;
+    for (block, &reachable) in reachable.iter().enumerate() {
+        if reachable {
+            ids[block] = next;
+            next += 1;
+        }
+    }
+    let blocks = std::mem::take(&mut function.blocks);
+    for (block, mut data) in blocks.into_iter().enumerate() {
+        if reachable[block] {
+            for target in data.term.targets_mut() {
+                target.block = ids[target.block];
+            }
+            function.blocks.push(data);
+        }
+    }
+    true
+}
+
+/// Make the jumps to blocks that only jump on, without parameters or instructions, go straight to
+/// where those blocks jump.
+fn thread_jumps(function: &mut Function) -> bool {
+    let forward: Vec<Option<_>> = function
+        .blocks
+        .iter()
+        .enumerate()
+        .map(|(id, block)| match &block.term {
+            Terminator::Jump(target)
+                if id != 0
+                    && target.block != id
+                    && block.params.is_empty()
+                    && block.insts.is_empty() =>
+            {
+                Some(target.clone())
+            }
+            _ => None,
+        })
+        .collect();
+    let mut changed = false;
+    for block in &mut function.blocks {
+        for target in block.term.targets_mut() {
+            if let Some(forward) = &forward[target.block] {
+                // Only one step, so a cycle of such blocks doesn't loop forever.
+                let count = target.count;
+                *target = forward.clone();
+                target.count = count;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Append the blocks that only one jump goes to to the block jumping there. The merged blocks are
+/// left without predecessors for `remove_unreachable`.
+fn merge_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+    let mut preds = predecessors(function);
+    for block in 0..function.blocks.len() {
+        let Terminator::Jump(target) = &function.blocks[block].term else {
+            continue;
+        };
+        let succ = target.block;
+        if succ == 0 || succ == block || preds[succ].len() != 1 {
+            continue;
+        }
+        let args = target.args.clone();
+        let succ_block = std::mem::take(&mut function.blocks[succ]);
+        let map: HashMap<ValueId, ValueId> = succ_block.params.iter().copied().zip(args).collect();
+        for target in succ_block.term.targets() {
+            for pred in &mut preds[target.block] {
+                if *pred == succ {
+                    *pred = block;
+                }
+            }
+        }
+        preds[succ].clear();
+        let merged = &mut function.blocks[block];
+        merged.insts.extend(succ_block.insts);
+        merged.term = succ_block.term;
+        function.replace_uses(&map);
+        changed = true;
+    }
+    changed
+}
+
+/// Remove the parameters that every jump passes the same value, or the parameter itself, and use
+/// that value instead.
+fn remove_trivial_params(function: &mut Function) -> bool {
+    let preds = predecessors(function);
+    let mut map = HashMap::new();
+    for (block, preds) in preds.iter().enumerate().skip(1) {
+        let mut trivial = Vec::new();
+        for (i, &param) in function.blocks[block].params.iter().enumerate() {
+            let mut value = None;
+            let mut unique = true;
+            for &pred in preds {
+                for target in function.blocks[pred].term.targets() {
+                    if target.block != block || target.args[i] == param {
+                        continue;
+                    }
+                    match value {
+                        None => value = Some(target.args[i]),
+                        Some(value) if value == target.args[i] => {}
+                        Some(_) => unique = false,
+                    }
+                }
+            }
+            if let (true, Some(value)) = (unique, value) {
+                map.insert(param, value);
+                trivial.push(i);
+            }
+        }
+        if trivial.is_empty() {
+            continue;
+        }
+        for &pred in preds {
+            for target in function.blocks[pred].term.targets_mut() {
+                if target.block == block {
+                    target.args = without(&target.args, &trivial);
+                }
+            }
+        }
+        let params = &mut function.blocks[block].params;
+        *params = without(params, &trivial);
+    }
+    function.replace_uses(&map);
+    !map.is_empty()
+}
+
+fn without(values: &[ValueId], removed: &[usize]) -> Vec<ValueId> {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !removed.contains(i))
+        .map(|(_, &value)| value)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+
+    #[test]
+    fn merges_threads_and_removes_blocks() {
+        let mut module = parse_module(
+            None,
+            "def f(%0: bool, %1: int) int {
+bb0:
+    branch %0, bb1, bb1
+bb1:
+    jump bb2(%1)
+bb2(%2: int):
+    %3: int = add %2, %2
+    branch %0, bb3, bb4(%3, %1)
+bb3:
+    jump bb4(%3, %1)
+bb4(%4: int, %5: int):
+    %6: int = add %4, %5
+    return %6
+bb5:
+    jump bb4(%1, %1)
+}
+",
+        )
+        .unwrap();
+        assert!(SimplifyCfg.run(&mut module, &mut Analyses::default()));
+        assert_eq!(
+            print_module(&module),
+            "def f(%0: bool, %1: int) int {
+bb0:
+    %3: int = add %1, %1
+    %6: int = add %3, %1
+    return %6
+}
+"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Take structs and arrays that escape analysis proves die with their call off the heap.
+//!
+//! A struct whose fields are only read and written is replaced by an `alloca` per field, which
+//! `mem2reg` then promotes to values. The other structs and arrays that don't escape are allocated
+//! on the stack. Every allocation taken off the heap gets a remark.
+
+use super::{Pass, Remark};
+use crate::pir::analysis::{Analyses, Preserved};
+use crate::pir::escape::{Escape, EscapeInfo};
+use crate::pir::{AdtId, AdtKind, Alloc, Function, Inst, InstKind, Module, Type, ValueId};
+use std::collections::HashMap;
+
+pub const NAME: &str = "stack-alloc";
+
+#[derive(Default)]
+pub struct StackAlloc {
+    remarks: Vec<Remark>,
+}
+
+impl Pass for StackAlloc {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn preserves(&self) -> Preserved {
+        Preserved::Cfg
+    }
+
+    fn run(&mut self, module: &mut Module, _: &mut Analyses) -> bool {
+        let info = EscapeInfo::new(module);
+        let mut changed = false;
+        for id in 0..module.functions.len() {
+            let mut replaced = HashMap::new();
+            let function = &module.functions[id];
+            for inst in function.blocks.iter().flat_map(|block| &block.insts) {
+                let (adt, alloc) = match &inst.kind {
+                    InstKind::New(adt, _, alloc) => (Some(*adt), *alloc),
+                    InstKind::Array(_, alloc) => (None, *alloc),
+                    _ => continue,
+                };
+                if alloc == Alloc::Stack {
+                    continue;
+                }
+                let what = adt.map_or("The array".to_string(), |adt| {
+                    format!("`{}`", module.adts[adt].name)
+                });
+                let escape = info.allocation(module, id, inst.result);
+                let message = match escape {
+                    Escape::Escapes => continue,
+                    Escape::Frame => format!("{what} is allocated on the stack."),
+                    Escape::Fields => format!("{what} is replaced by a variable per field."),
+                };
+                self.remarks.push(Remark {
+                    pass: NAME,
+                    function: function.name.clone(),
+                    loc: function.locs.get(&inst.result).cloned(),
+                    message,
+                });
+                replaced.insert(inst.result, escape);
+            }
+            if replaced.is_empty() {
+                continue;
+            }
+            let fields: Vec<Vec<Type>> = module
+                .adts
+                .iter()
+                .map(|adt| match &adt.kind {
+                    AdtKind::Struct(fields) => fields.iter().map(|f| f.ty.clone()).collect(),
+                    _ => Vec::new(),
+                })
+                .collect();
+            rewrite(&mut module.functions[id], &replaced, &fields);
+            changed = true;
+        }
+        changed
+    }
+
+    fn remarks(&mut self) -> Vec<Remark> {
+        std::mem::take(&mut self.remarks)
+    }
+}
+
+/// Allocate the structs and arrays marked `Frame` on the stack, and split those marked `Fields`
+/// into an `alloca` per field of the struct types in `fields`. The `alloca`s are made first, since
+/// after inlining a block may use a struct made in a block numbered after it.
+fn rewrite(function: &mut Function, replaced: &HashMap<ValueId, Escape>, fields: &[Vec<Type>]) {
+    let split: Vec<(ValueId, AdtId)> = function
+        .blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter_map(|inst| match (&inst.kind, replaced.get(&inst.result)) {
+            (InstKind::New(adt, ..), Some(Escape::Fields)) => Some((inst.result, *adt)),
+            _ => None,
+        })
+        .collect();
+    let mut slots: HashMap<ValueId, Vec<ValueId>> = HashMap::new();
+    for (value, adt) in split {
+        let allocas = fields[adt]
+            .iter()
+            .map(|ty| function.new_value(Type::Ptr(Box::new(ty.clone()))))
+            .collect();
+        slots.insert(value, allocas);
+    }
+    for block in 0..function.blocks.len() {
+        let insts = std::mem::take(&mut function.blocks[block].insts);
+        let mut rewritten = Vec::with_capacity(insts.len());
+        for mut inst in insts {
+            match (&mut inst.kind, replaced.get(&inst.result)) {
+                (InstKind::New(_, _, alloc) | InstKind::Array(_, alloc), Some(Escape::Frame)) => {
+                    *alloc = Alloc::Stack;
+                }
+                (InstKind::New(adt, values, _), Some(Escape::Fields)) => {
+                    let loc = function.locs.get(&inst.result).cloned();
+                    for ((ty, &value), &alloca) in fields[*adt]
+                        .iter()
+                        .zip(values.iter())
+                        .zip(&slots[&inst.result])
+                    {
+                        let store = function.new_value(Type::Void);
+                        rewritten.push(Inst {
+                            result: alloca,
+                            kind: InstKind::Alloca(ty.clone()),
+                        });
+                        rewritten.push(Inst {
+                            result: store,
+                            kind: InstKind::Store(alloca, value),
+                        });
+                        if let Some(loc) = &loc {
+                            function.locs.insert(alloca, loc.clone());
+                            function.locs.insert(store, loc.clone());
+                        }
+                    }
+                    continue;
+                }
+                (InstKind::GetField(value, index), _) if slots.contains_key(value) => {
+                    inst.kind = InstKind::Load(slots[value][*index]);
+                }
+                (InstKind::SetField(target, index, value), _) if slots.contains_key(target) => {
+                    inst.kind = InstKind::Store(slots[target][*index], *value);
+                }
+                _ => {}
+            }
+            rewritten.push(inst);
+        }
+        function.blocks[block].insts = rewritten;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::interp;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+    use crate::pir::verify::verify;
+
+    #[test]
+    fn takes_allocations_off_the_heap() {
+        let source = "struct Point { x: int, y: int }
+
+def norm(%0: Point) int {
+bb0:
+    %1: int = get_field %0, x
+    return %1
+}
+
+def main() Point {
+bb0:
+    %0: int = const 1
+    %1: Point = new Point(%0, %0)
+    %2: int = get_field %1, y
+    %3: void = set_field %1, x, %2
+    %4: Point = new Point(%0, %2)
+    %5: int = call @norm(%4)
+    %6: []int = array[%5]
+    %7: void = call_native println(%6)
+    %8: Point = new Point(%0, %0)
+    return %8
+}
+";
+        let mut module = parse_module(None, source).unwrap();
+        let mut pass = StackAlloc::default();
+        assert!(pass.run(&mut module, &mut Analyses::default()));
+        let text = print_module(&module);
+        let main = &text[text.find("def main").unwrap()..];
+        assert_eq!(
+            main,
+            "def main() Point {
+bb0:
+    %0: int = const 1
+    %9: *int = alloca int
+    %11: void = store %9, %0
+    %10: *int = alloca int
+    %12: void = store %10, %0
+    %2: int = load %10
+    %3: void = store %9, %2
+    %4: Point = new stack Point(%0, %2)
+    %5: int = call @norm(%4)
+    %6: []int = array stack [%5]
+    %7: void = call_native println(%6)
+    %8: Point = new Point(%0, %0)
+    return %8
+}
+"
+        );
+        assert_eq!(print_module(&parse_module(None, &text).unwrap()), text);
+        let remarks: Vec<String> = pass.remarks().iter().map(|r| r.message.clone()).collect();
+        assert_eq!(
+            remarks,
+            [
+                "`Point` is replaced by a variable per field.",
+                "`Point` is allocated on the stack.",
+                "The array is allocated on the stack.",
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_structs_used_in_earlier_blocks() {
+        let source = "struct Point { x: int, y: int }
+
+def main() int {
+bb0:
+    %0: int = const 1
+    jump bb2
+bb1:
+    %2: int = get_field %1, x
+    return %2
+bb2:
+    %1: Point = new Point(%0, %0)
+    %3: void = set_field %1, x, %0
+    jump bb1
+}
+";
+        let mut module = parse_module(None, source).unwrap();
+        assert!(StackAlloc::default().run(&mut module, &mut Analyses::default()));
+        verify(&module).unwrap();
+        let text = print_module(&module);
+        assert!(
+            !text.contains("get_field") && !text.contains("new"),
+            "{text}"
+        );
+        let mut out = Vec::new();
+        interp::run(&module, &mut out).unwrap();
+    }
+}
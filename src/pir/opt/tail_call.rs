@@ -0,0 +1,156 @@
+//! Tail-call elimination: tail calls between functions that are recursive with each other become
+//! jumps, so recursion in tail position runs in constant stack space.
+//!
+//! A tail call of a function to itself jumps back to a copy of its entry block, whose parameters
+//! take the arguments. A tail call to another function of its cycle jumps to a copy of that
+//! function's body made in the caller, once per callee, whose own tail calls back become jumps
+//! too. The pass runs at every level, since `@tailcall` functions rely on it.
+
+use super::inline::copy_body;
+use super::simplify_cfg::remove_unreachable;
+use super::Pass;
+use crate::pir::analysis::Analyses;
+use crate::pir::tail::{recursion, tail_call};
+use crate::pir::{BlockId, FuncId, Function, Module, Target, Terminator};
+use std::collections::{HashMap, HashSet};
+
+pub const NAME: &str = "tail-call";
+
+pub struct TailCall;
+
+impl Pass for TailCall {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn run(&mut self, module: &mut Module, _: &mut Analyses) -> bool {
+        let recursion = recursion(module);
+        let original = module.functions.clone();
+        let mut changed = false;
+        for (id, cycle) in recursion.iter().enumerate() {
+            changed |= eliminate(&mut module.functions[id], id, cycle, &original);
+        }
+        changed
+    }
+}
+
+/// Turn the tail calls of the function to those of its cycle into jumps, copying the bodies of
+/// the callees from `original`.
+fn eliminate(
+    function: &mut Function,
+    id: FuncId,
+    cycle: &HashSet<FuncId>,
+    original: &[Function],
+) -> bool {
+    let mut entries: HashMap<FuncId, BlockId> = HashMap::new();
+    let mut changed = false;
+    let mut block = 0;
+    while block < function.blocks.len() {
+        let Some((callee, args)) = tail_call(function, block) else {
+            block += 1;
+            continue;
+        };
+        if !cycle.contains(&callee) {
+            block += 1;
+            continue;
+        }
+        let args = args.to_vec();
+        // Making the loop header moves the entry block, and a call in it along with it.
+        let (entry, at) = match entries.get(&callee) {
+            Some(&entry) => (entry, block),
+            None if callee == id => {
+                let header = loop_header(function);
+                (header, if block == 0 { header } else { block })
+            }
+            None => (copy_body(function, &original[callee]), block),
+        };
+        entries.insert(callee, entry);
+        function.blocks[at].insts.pop();
+        function.blocks[at].term = Terminator::Jump(Target::new(entry, args));
+        changed = true;
+    }
+    if changed {
+        remove_unreachable(function);
+    }
+    changed
+}
+
+/// Move the entry block of the function to a new block, which the entry jumps to with new
+/// parameters, and return it.
+fn loop_header(function: &mut Function) -> BlockId {
+    let header = function.add_block();
+    let entry = std::mem::take(&mut function.blocks[0]);
+    let params: Vec<_> = entry
+        .params
+        .iter()
+        .map(|&param| function.new_value(function.values[param].clone()))
+        .collect();
+    function.blocks[header] = entry;
+    function.blocks[0].params = params.clone();
+    function.blocks[0].term = Terminator::Jump(Target::new(header, params));
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::interp;
+    use crate::pir::parser::parse_module;
+    use crate::pir::printer::print_module;
+    use crate::pir::verify::verify;
+
+    #[test]
+    fn turns_mutual_recursion_into_loops() {
+        let mut module = parse_module(
+            None,
+            "def main() void {
+bb0:
+    %0: int = const 1000001
+    %1: bool = call @is_even(%0)
+    %2: void = call_native println(%1)
+    return
+}
+
+def is_even(%0: int) bool {
+bb0:
+    %1: int = const 0
+    %2: bool = eq %0, %1
+    branch %2, bb1, bb2
+bb1:
+    %3: bool = const true
+    return %3
+bb2:
+    %4: int = const 1
+    %5: int = sub %0, %4
+    %6: bool = call @is_odd(%5)
+    return %6
+}
+
+def is_odd(%0: int) bool {
+bb0:
+    %1: int = const 0
+    %2: bool = eq %0, %1
+    branch %2, bb1, bb2
+bb1:
+    %3: bool = const false
+    return %3
+bb2:
+    %4: int = const 1
+    %5: int = sub %0, %4
+    %6: bool = call @is_even(%5)
+    return %6
+}
+",
+        )
+        .unwrap();
+        assert!(TailCall.run(&mut module, &mut Analyses::default()));
+        verify(&module).unwrap();
+        let printed = print_module(&module);
+        let is_even =
+            &printed[printed.find("def is_even").unwrap()..printed.find("def is_odd").unwrap()];
+        assert!(!is_even.contains("call"), "{is_even}");
+        let mut out = Vec::new();
+        interp::run(&module, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "false\n");
+    }
+}
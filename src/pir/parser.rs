@@ -0,0 +1,921 @@
+use super::*;
+use crate::parse::{
+    Keyword, Lexer, ParseError, ParseErrorType, SourceCodeLocation, SyntaxError, Token, TokenKind,
+    Tokenize,
+};
+use crate::sema::builtins;
+use log::debug;
+use std::collections::HashMap;
+
+type ParseResult<T> = Result<T, ParseError>;
+
+/// Tokenize and parse PIR in its textual form.
+pub fn parse_module(filename: Option<String>, source: &str) -> Result<Module, SyntaxError> {
+    Ok(parse_module_with_spans(filename, source)?.0)
+}
+
+/// Same as `parse_module`, but also return where everything is in the source code, to report
+/// problems found in the module later.
+pub fn parse_module_with_spans(
+    filename: Option<String>,
+    source: &str,
+) -> Result<(Module, Spans), SyntaxError> {
+    let tokens = Lexer::new(filename, source)
+        .tokenize()
+        .map_err(SyntaxError::Lex)?;
+    Parser::new(tokens).parse().map_err(SyntaxError::Parse)
+}
+
+/// A recursive descent parser turning tokens into a PIR `Module`.
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    adts: HashMap<String, AdtId>,
+    functions: HashMap<String, FuncId>,
+    /// The structs and enums parsed so far, to look up fields and variants by name.
+    adt_defs: Vec<AdtDef>,
+    spans: Spans,
+}
+
+/// The function being parsed.
+#[derive(Default)]
+struct Body {
+    values: Vec<Option<Type>>,
+    blocks: Vec<Block>,
+    labels: HashMap<String, BlockId>,
+    /// Every value used, to check that it is defined somewhere in the function.
+    uses: Vec<(ValueId, SourceCodeLocation)>,
+    /// Names that can only be looked up once the whole function is parsed.
+    fixups: Vec<Fixup>,
+}
+
+/// A name that refers to something defined later in the function, or that depends on the type of
+/// such a value.
+enum Fixup {
+    /// The label of the target of a block's terminator, by its position among the targets.
+    Target(BlockId, usize, String, SourceCodeLocation),
+    /// The field of `get_field` or `set_field`, or the variant of `payload`, of an instruction.
+    Member(BlockId, usize, String, SourceCodeLocation),
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens,
+            current: 0,
+            adts: HashMap::new(),
+            functions: HashMap::new(),
+            adt_defs: Vec::new(),
+            spans: Spans::default(),
+        }
+    }
+
+    fn parse(mut self) -> ParseResult<(Module, Spans)> {
+        debug!("Starting parsing PIR...");
+        self.declare()?;
+
+        // Functions name the fields and variants of types defined anywhere, so they are parsed
+        // once all the types are.
+        let mut functions = Vec::new();
+        while !self.is_end() {
+            match self.peek() {
+                Some(TokenKind::Keyword(Keyword::Struct | Keyword::Enum)) => {
+                    let adt = self.adt()?;
+                    self.adt_defs.push(adt);
+                }
+                Some(TokenKind::Keyword(Keyword::Def)) => {
+                    functions.push(self.current);
+                    self.skip_function();
+                }
+                _ => return Err(self.error(vec!["`struct`", "`enum`", "`def`"])),
+            }
+        }
+        let mut module = Module::default();
+        for (id, start) in functions.into_iter().enumerate() {
+            self.current = start;
+            module.functions.push(self.function(id)?);
+        }
+        module.adts = self.adt_defs;
+
+        debug!("Finished parsing PIR.");
+        Ok((module, self.spans))
+    }
+
+    /// Number the structs, enums and functions in order, so they can be used before they are
+    /// defined.
+    fn declare(&mut self) -> ParseResult<()> {
+        let mut depth = 0usize;
+        for (i, token) in self.tokens.iter().enumerate() {
+            match &token.kind {
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace => depth = depth.saturating_sub(1),
+                TokenKind::Keyword(keyword @ (Keyword::Struct | Keyword::Enum | Keyword::Def))
+                    if depth == 0 =>
+                {
+                    let Some(next) = self.tokens.get(i + 1) else {
+                        continue;
+                    };
+                    let (TokenKind::Ident(name) | TokenKind::Str(name)) = &next.kind else {
+                        continue;
+                    };
+                    let (names, kind) = if *keyword == Keyword::Def {
+                        (&mut self.functions, "function")
+                    } else {
+                        (&mut self.adts, "type")
+                    };
+                    if names.contains_key(name) {
+                        return Err(ParseError::new(
+                            ParseErrorType::DuplicateName(kind, name.clone()),
+                            next.loc.clone(),
+                        ));
+                    }
+                    let id = names.len();
+                    names.insert(name.clone(), id);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Move past the function starting at the current token, up to its closing brace.
+    fn skip_function(&mut self) {
+        let mut depth = 0;
+        while !self.is_end() {
+            match self.advance().kind {
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace if depth <= 1 => return,
+                TokenKind::RightBrace => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    fn adt(&mut self) -> ParseResult<AdtDef> {
+        let is_struct = self.eat(&TokenKind::Keyword(Keyword::Struct));
+        if !is_struct {
+            self.expect(&TokenKind::Keyword(Keyword::Enum), "`enum`")?;
+        }
+        let name = self.name()?;
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+        let kind = if is_struct {
+            AdtKind::Struct(self.comma_separated(&TokenKind::RightBrace, |p| {
+                let name = p.name()?;
+                p.expect(&TokenKind::Colon, "`:`")?;
+                Ok(Field { name, ty: p.ty()? })
+            })?)
+        } else {
+            AdtKind::Enum(self.comma_separated(&TokenKind::RightBrace, |p| {
+                let name = p.name()?;
+                let fields = if p.eat(&TokenKind::LeftParen) {
+                    p.comma_separated(&TokenKind::RightParen, |p| p.ty())?
+                } else {
+                    Vec::new()
+                };
+                p.expect(&TokenKind::Eq, "`=`")?;
+                let negative = p.eat(&TokenKind::Minus);
+                let discriminant = p.int()? as i64;
+                Ok(Variant {
+                    name,
+                    discriminant: if negative {
+                        -discriminant
+                    } else {
+                        discriminant
+                    },
+                    fields,
+                })
+            })?)
+        };
+        Ok(AdtDef { name, kind })
+    }
+
+    fn function(&mut self, func: FuncId) -> ParseResult<Function> {
+        let start = self.loc();
+        self.expect(&TokenKind::Keyword(Keyword::Def), "`def`")?;
+        let name = self.name()?;
+        let mut body = Body::default();
+        self.expect(&TokenKind::LeftParen, "`(`")?;
+        let params = self.block_params(&mut body)?;
+        let ret = self.ty()?;
+        self.spans
+            .insert(Position::Function(func), self.loc_from(&start));
+        self.expect(&TokenKind::LeftBrace, "`{`")?;
+
+        loop {
+            let loc = self.loc();
+            let label = self.ident()?;
+            let id = body.blocks.len();
+            if body.labels.insert(label.clone(), id).is_some() {
+                return Err(ParseError::new(
+                    ParseErrorType::DuplicateName("block", label),
+                    loc,
+                ));
+            }
+            let mut block = Block::default();
+            if id == 0 {
+                block.params = params.clone();
+            } else if self.eat(&TokenKind::LeftParen) {
+                block.params = self.block_params(&mut body)?;
+            }
+            self.spans
+                .insert(Position::Block(func, id), self.loc_from(&loc));
+            self.expect(&TokenKind::Colon, "`:`")?;
+            body.blocks.push(block);
+
+            while self.check(&TokenKind::Rem) {
+                let start = self.loc();
+                let inst = self.inst(&mut body, id)?;
+                let index = body.blocks[id].insts.len();
+                self.spans
+                    .insert(Position::Inst(func, id, index), self.loc_from(&start));
+                body.blocks[id].insts.push(inst);
+            }
+            let start = self.loc();
+            body.blocks[id].term = self.terminator(&mut body, id)?;
+            self.spans
+                .insert(Position::Terminator(func, id), self.loc_from(&start));
+            if self.eat(&TokenKind::RightBrace) {
+                break;
+            }
+        }
+
+        self.finish(&mut body)?;
+        Ok(Function {
+            name,
+            ret,
+            values: body
+                .values
+                .into_iter()
+                .map(|ty| ty.unwrap_or(Type::Void))
+                .collect(),
+            blocks: body.blocks,
+            locs: HashMap::new(),
+            tailcall: None,
+            entry_count: None,
+        })
+    }
+
+    /// Parse `%0: int, %1: bool)`, defining the values.
+    fn block_params(&mut self, body: &mut Body) -> ParseResult<Vec<ValueId>> {
+        self.comma_separated(&TokenKind::RightParen, |p| {
+            let loc = p.loc();
+            let value = p.value_id()?;
+            p.expect(&TokenKind::Colon, "`:`")?;
+            let ty = p.ty()?;
+            p.define(body, value, ty, loc)?;
+            Ok(value)
+        })
+    }
+
+    fn define(
+        &self,
+        body: &mut Body,
+        value: ValueId,
+        ty: Type,
+        loc: SourceCodeLocation,
+    ) -> ParseResult<()> {
+        if body.values.len() <= value {
+            body.values.resize(value + 1, None);
+        }
+        if body.values[value].is_some() {
+            return Err(ParseError::new(
+                ParseErrorType::DuplicateName("value", format!("%{value}")),
+                loc,
+            ));
+        }
+        body.values[value] = Some(ty);
+        Ok(())
+    }
+
+    /// Resolve the names used before their definition and check every used value is defined.
+    fn finish(&self, body: &mut Body) -> ParseResult<()> {
+        for (value, loc) in &body.uses {
+            if body.values.get(*value).is_none_or(|ty| ty.is_none()) {
+                return Err(ParseError::new(
+                    ParseErrorType::UnknownName("value", format!("%{value}")),
+                    loc.clone(),
+                ));
+            }
+        }
+        for fixup in std::mem::take(&mut body.fixups) {
+            match fixup {
+                Fixup::Target(block, index, label, loc) => {
+                    let Some(&id) = body.labels.get(&label) else {
+                        return Err(ParseError::new(
+                            ParseErrorType::UnknownName("block", label),
+                            loc,
+                        ));
+                    };
+                    let term = &mut body.blocks[block].term;
+                    let target = match (term, index) {
+                        (Terminator::Jump(target), _) => target,
+                        (Terminator::Branch { then_target, .. }, 0) => then_target,
+                        (Terminator::Branch { else_target, .. }, _) => else_target,
+                        _ => unreachable!("only jumps and branches have targets"),
+                    };
+                    target.block = id;
+                }
+                Fixup::Member(block, index, name, loc) => {
+                    let inst = &mut body.blocks[block].insts[index];
+                    let (value, member, kind) = match &mut inst.kind {
+                        InstKind::GetField(value, field) | InstKind::SetField(value, field, _) => {
+                            (*value, field, "field")
+                        }
+                        InstKind::Payload(value, variant, _) => (*value, variant, "variant"),
+                        _ => unreachable!("only field and payload instructions name members"),
+                    };
+                    let adt = match &body.values[value] {
+                        Some(Type::Adt(adt)) => self.adt_defs.get(*adt),
+                        _ => None,
+                    };
+                    let names: Vec<&str> = match adt.map(|adt| &adt.kind) {
+                        Some(AdtKind::Struct(fields)) if kind == "field" => {
+                            fields.iter().map(|f| f.name.as_str()).collect()
+                        }
+                        Some(AdtKind::Enum(variants)) if kind == "variant" => {
+                            variants.iter().map(|v| v.name.as_str()).collect()
+                        }
+                        _ => Vec::new(),
+                    };
+                    let Some(position) = names.iter().position(|n| *n == name) else {
+                        return Err(ParseError::new(
+                            ParseErrorType::UnknownName(kind, name),
+                            loc,
+                        ));
+                    };
+                    *member = position;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn inst(&mut self, body: &mut Body, block: BlockId) -> ParseResult<Inst> {
+        let loc = self.loc();
+        let result = self.value_id()?;
+        self.expect(&TokenKind::Colon, "`:`")?;
+        let ty = self.ty()?;
+        self.define(body, result, ty, loc)?;
+        self.expect(&TokenKind::Eq, "`=`")?;
+
+        if self.eat(&TokenKind::Keyword(Keyword::Const)) {
+            return Ok(Inst {
+                result,
+                kind: InstKind::Const(self.constant()?),
+            });
+        }
+        let op_loc = self.loc();
+        let op = self.ident()?;
+        let index = body.blocks[block].insts.len();
+        let kind = match op.as_str() {
+            "neg" => InstKind::Unary(UnaryOp::Neg, self.value(body)?),
+            "not" => InstKind::Unary(UnaryOp::Not, self.value(body)?),
+            "alloca" => InstKind::Alloca(self.ty()?),
+            "load" => InstKind::Load(self.value(body)?),
+            "store" => {
+                let address = self.value(body)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                InstKind::Store(address, self.value(body)?)
+            }
+            "call" => {
+                let func = self.function_ref()?;
+                InstKind::Call(func, self.args(body)?)
+            }
+            "call_native" => {
+                let loc = self.loc();
+                let name = self.ident()?;
+                let Some(native) = builtins::native(&name) else {
+                    return Err(ParseError::new(
+                        ParseErrorType::UnknownName("native function", name),
+                        loc,
+                    ));
+                };
+                InstKind::CallNative(native, self.args(body)?)
+            }
+            "call_indirect" => {
+                let closure = self.value(body)?;
+                InstKind::CallIndirect(closure, self.args(body)?)
+            }
+            "closure" => {
+                let func = self.function_ref()?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                InstKind::Closure(func, self.value(body)?)
+            }
+            "new" => {
+                let alloc = self.alloc();
+                let adt = self.adt_ref()?;
+                InstKind::New(adt, self.args(body)?, alloc)
+            }
+            "get_field" => {
+                let value = self.value(body)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                let field = self.member(body, block, index)?;
+                InstKind::GetField(value, field)
+            }
+            "set_field" => {
+                let value = self.value(body)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                let field = self.member(body, block, index)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                InstKind::SetField(value, field, self.value(body)?)
+            }
+            "variant" => {
+                let adt = self.adt_ref()?;
+                self.expect(&TokenKind::DoubleColon, "`::`")?;
+                let variant = self.variant(adt)?;
+                let fields = if self.check(&TokenKind::LeftParen) {
+                    self.args(body)?
+                } else {
+                    Vec::new()
+                };
+                InstKind::Variant(adt, variant, fields)
+            }
+            "tag" => InstKind::Tag(self.value(body)?),
+            "payload" => {
+                let value = self.value(body)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                let variant = self.member(body, block, index)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                InstKind::Payload(value, variant, self.int()?)
+            }
+            "tuple" => InstKind::Tuple(self.args(body)?),
+            "extract" => {
+                let value = self.value(body)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                InstKind::Extract(value, self.int()?)
+            }
+            "count" => {
+                let func = self.function_ref()?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                let loc = self.loc();
+                let counter = match self.ident()?.as_str() {
+                    "entry" => Counter::Entry(self.int()? as u32),
+                    "block" => Counter::Block(self.int()?),
+                    "edge" => {
+                        let block = self.int()?;
+                        self.expect(&TokenKind::Comma, "`,`")?;
+                        Counter::Edge(block, self.int()?)
+                    }
+                    name => {
+                        return Err(ParseError::new(
+                            ParseErrorType::UnknownName("counter", name.to_string()),
+                            loc,
+                        ))
+                    }
+                };
+                InstKind::Count(func, counter)
+            }
+            "array" => {
+                let alloc = self.alloc();
+                self.expect(&TokenKind::LeftBracket, "`[`")?;
+                InstKind::Array(self.values(body, &TokenKind::RightBracket)?, alloc)
+            }
+            op => {
+                let operands = |p: &mut Self, body: &mut Body| -> ParseResult<_> {
+                    let a = p.value(body)?;
+                    p.expect(&TokenKind::Comma, "`,`")?;
+                    Ok((a, p.value(body)?))
+                };
+                if let Some(op) = BinaryOp::ALL.into_iter().find(|o| o.name() == op) {
+                    let (a, b) = operands(self, body)?;
+                    InstKind::Binary(op, a, b)
+                } else if let Some(op) = CompareOp::ALL.into_iter().find(|o| o.name() == op) {
+                    let (a, b) = operands(self, body)?;
+                    InstKind::Compare(op, a, b)
+                } else {
+                    return Err(ParseError::new(
+                        ParseErrorType::UnexpectedToken(vec!["instruction"]),
+                        op_loc,
+                    ));
+                }
+            }
+        };
+        Ok(Inst { result, kind })
+    }
+
+    /// Parse the literal of a `const`.
+    fn constant(&mut self) -> ParseResult<Const> {
+        let negative = self.eat(&TokenKind::Minus);
+        let value = match self.peek() {
+            Some(TokenKind::Int(n)) => Const::Int(if negative { -n } else { *n }),
+            Some(TokenKind::Float(n)) => Const::Float(if negative { -n } else { *n }),
+            Some(TokenKind::Ident(name)) if name == "inf" => Const::Float(if negative {
+                f64::NEG_INFINITY
+            } else {
+                f64::INFINITY
+            }),
+            Some(TokenKind::Ident(name)) if !negative && name == "nan" => Const::Float(f64::NAN),
+            Some(TokenKind::Ident(name)) if !negative && name == "min" => Const::Int(i64::MIN),
+            Some(TokenKind::Ident(name)) if !negative && name == "void" => Const::Void,
+            Some(TokenKind::Keyword(Keyword::True)) if !negative => Const::Bool(true),
+            Some(TokenKind::Keyword(Keyword::False)) if !negative => Const::Bool(false),
+            Some(TokenKind::Char(c)) if !negative => Const::Char(*c),
+            Some(TokenKind::Str(s)) if !negative => Const::Str(s.clone()),
+            _ => return Err(self.error(vec!["constant"])),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    fn terminator(&mut self, body: &mut Body, block: BlockId) -> ParseResult<Terminator> {
+        if self.eat(&TokenKind::Keyword(Keyword::Return)) {
+            return Ok(Terminator::Return(if self.check(&TokenKind::Rem) {
+                Some(self.value(body)?)
+            } else {
+                None
+            }));
+        }
+        let loc = self.loc();
+        match self.ident() {
+            Ok(name) if name == "jump" => Ok(Terminator::Jump(self.target(body, block, 0)?)),
+            Ok(name) if name == "branch" => {
+                let cond = self.value(body)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                let then_target = self.target(body, block, 0)?;
+                self.expect(&TokenKind::Comma, "`,`")?;
+                let else_target = self.target(body, block, 1)?;
+                Ok(Terminator::Branch {
+                    cond,
+                    then_target,
+                    else_target,
+                })
+            }
+            Ok(name) if name == "unreachable" => Ok(Terminator::Unreachable),
+            _ => Err(ParseError::new(
+                ParseErrorType::UnexpectedToken(vec![
+                    "`%`",
+                    "`jump`",
+                    "`branch`",
+                    "`return`",
+                    "`unreachable`",
+                ]),
+                loc,
+            )),
+        }
+    }
+
+    /// Parse `label` or `label(%0, %1)`, leaving the label to be resolved by `finish`.
+    fn target(&mut self, body: &mut Body, block: BlockId, index: usize) -> ParseResult<Target> {
+        let loc = self.loc();
+        let label = self.ident()?;
+        body.fixups.push(Fixup::Target(block, index, label, loc));
+        let args = if self.check(&TokenKind::LeftParen) {
+            self.args(body)?
+        } else {
+            Vec::new()
+        };
+        Ok(Target::new(BlockId::MAX, args))
+    }
+
+    /// Parse a variant of the enum, either by index or by name.
+    fn variant(&mut self, adt: AdtId) -> ParseResult<usize> {
+        if let Some(TokenKind::Int(_)) = self.peek() {
+            return self.int();
+        }
+        let loc = self.loc();
+        let name = self.name()?;
+        let variants = match self.adt_defs.get(adt).map(|adt| &adt.kind) {
+            Some(AdtKind::Enum(variants)) => variants.as_slice(),
+            _ => &[],
+        };
+        variants
+            .iter()
+            .position(|v| v.name == name)
+            .ok_or_else(|| ParseError::new(ParseErrorType::UnknownName("variant", name), loc))
+    }
+
+    /// Parse a field or variant, either by index or by a name resolved by `finish`.
+    fn member(&mut self, body: &mut Body, block: BlockId, index: usize) -> ParseResult<usize> {
+        if let Some(TokenKind::Int(_)) = self.peek() {
+            return self.int();
+        }
+        let loc = self.loc();
+        let name = self.name()?;
+        body.fixups.push(Fixup::Member(block, index, name, loc));
+        Ok(0)
+    }
+
+    fn ty(&mut self) -> ParseResult<Type> {
+        let loc = self.loc();
+        let ty = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Int)) => Type::Int,
+            Some(TokenKind::Keyword(Keyword::Float)) => Type::Float,
+            Some(TokenKind::Keyword(Keyword::Str)) => Type::Str,
+            Some(TokenKind::Ident(name)) if name == "bool" => Type::Bool,
+            Some(TokenKind::Ident(name)) if name == "char" => Type::Char,
+            Some(TokenKind::Ident(name)) if name == "void" => Type::Void,
+            Some(TokenKind::Ident(name) | TokenKind::Str(name)) => {
+                let Some(&adt) = self.adts.get(name) else {
+                    return Err(ParseError::new(
+                        ParseErrorType::UnknownName("type", name.clone()),
+                        loc,
+                    ));
+                };
+                Type::Adt(adt)
+            }
+            Some(TokenKind::Mul) => {
+                self.advance();
+                return Ok(Type::Ptr(Box::new(self.ty()?)));
+            }
+            Some(TokenKind::LeftBracket) => {
+                self.advance();
+                self.expect(&TokenKind::RightBracket, "`]`")?;
+                return Ok(Type::Array(Box::new(self.ty()?)));
+            }
+            Some(TokenKind::LeftParen) => {
+                self.advance();
+                return Ok(Type::Tuple(
+                    self.comma_separated(&TokenKind::RightParen, |p| p.ty())?,
+                ));
+            }
+            Some(TokenKind::Keyword(Keyword::Def)) => {
+                self.advance();
+                self.expect(&TokenKind::LeftParen, "`(`")?;
+                let params = self.comma_separated(&TokenKind::RightParen, |p| p.ty())?;
+                let ret = self.ty()?;
+                return Ok(Type::Fn(Box::new(FnType { params, ret })));
+            }
+            _ => return Err(self.error(vec!["type"])),
+        };
+        self.advance();
+        Ok(ty)
+    }
+
+    fn adt_ref(&mut self) -> ParseResult<AdtId> {
+        let loc = self.loc();
+        let name = self.name()?;
+        self.adts
+            .get(&name)
+            .copied()
+            .ok_or_else(|| ParseError::new(ParseErrorType::UnknownName("type", name), loc))
+    }
+
+    /// Parse the `stack` of `new stack` and `array stack`. A struct may be named `stack` too, so
+    /// it's only the keyword when a name or `[` follows.
+    fn alloc(&mut self) -> Alloc {
+        let is_keyword = matches!(self.peek(), Some(TokenKind::Ident(name)) if name == "stack")
+            && matches!(
+                self.tokens.get(self.current + 1).map(|t| &t.kind),
+                Some(TokenKind::Ident(_) | TokenKind::Str(_) | TokenKind::LeftBracket)
+            );
+        if is_keyword {
+            self.advance();
+            Alloc::Stack
+        } else {
+            Alloc::Heap
+        }
+    }
+
+    /// Parse `@name`.
+    fn function_ref(&mut self) -> ParseResult<FuncId> {
+        self.expect(&TokenKind::At, "`@`")?;
+        let loc = self.loc();
+        let name = self.name()?;
+        self.functions
+            .get(&name)
+            .copied()
+            .ok_or_else(|| ParseError::new(ParseErrorType::UnknownName("function", name), loc))
+    }
+
+    /// Parse `(%0, %1)`.
+    fn args(&mut self, body: &mut Body) -> ParseResult<Vec<ValueId>> {
+        self.expect(&TokenKind::LeftParen, "`(`")?;
+        self.values(body, &TokenKind::RightParen)
+    }
+
+    fn values(&mut self, body: &mut Body, end: &TokenKind) -> ParseResult<Vec<ValueId>> {
+        self.comma_separated(end, |p| p.value(body))
+    }
+
+    /// Parse a use of a value.
+    fn value(&mut self, body: &mut Body) -> ParseResult<ValueId> {
+        let loc = self.loc();
+        let value = self.value_id()?;
+        body.uses.push((value, loc));
+        Ok(value)
+    }
+
+    /// Parse `%0`.
+    fn value_id(&mut self) -> ParseResult<ValueId> {
+        self.expect(&TokenKind::Rem, "`%`")?;
+        self.int()
+    }
+
+    /// Parse a non-negative integer, such as an index.
+    fn int(&mut self) -> ParseResult<usize> {
+        match self.peek() {
+            Some(TokenKind::Int(n)) => {
+                let n = *n as usize;
+                self.advance();
+                Ok(n)
+            }
+            _ => Err(self.error(vec!["number"])),
+        }
+    }
+
+    /// Parse a name, which is an identifier or a string for names that aren't identifiers.
+    fn name(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(TokenKind::Ident(name) | TokenKind::Str(name)) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error(vec!["name"])),
+        }
+    }
+
+    fn ident(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(TokenKind::Ident(name)) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error(vec!["identifier"])),
+        }
+    }
+
+    /// Parse items separated by commas, with an optional trailing comma, up to and including `end`.
+    fn comma_separated<T>(
+        &mut self,
+        end: &TokenKind,
+        mut item: impl FnMut(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        let mut items = Vec::new();
+        while !self.eat(end) {
+            items.push(item(self)?);
+            if !self.eat(&TokenKind::Comma) {
+                self.expect(end, "closing bracket")?;
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    #[inline]
+    fn is_end(&self) -> bool {
+        self.current >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.current).map(|t| &t.kind)
+    }
+
+    fn check(&self, kind: &TokenKind) -> bool {
+        self.peek() == Some(kind)
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.current].clone();
+        self.current += 1;
+        token
+    }
+
+    /// Consume the current token if it is `kind`.
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.check(kind) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind, description: &'static str) -> ParseResult<Token> {
+        if self.check(kind) {
+            Ok(self.advance())
+        } else {
+            Err(self.error(vec![description]))
+        }
+    }
+
+    /// Create an error for the current token.
+    fn error(&self, expected: Vec<&'static str>) -> ParseError {
+        if self.is_end() {
+            ParseError::new(ParseErrorType::UnexpectedEnd(expected), self.loc())
+        } else {
+            ParseError::new(ParseErrorType::UnexpectedToken(expected), self.loc())
+        }
+    }
+
+    /// A location spanning from `start` to the end of the previous token.
+    fn loc_from(&self, start: &SourceCodeLocation) -> SourceCodeLocation {
+        match self.current.checked_sub(1).and_then(|i| self.tokens.get(i)) {
+            Some(prev) => start.to(&prev.loc),
+            None => start.clone(),
+        }
+    }
+
+    /// The location of the current token, or of the last one at the end.
+    fn loc(&self) -> SourceCodeLocation {
+        self.tokens
+            .get(self.current)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.loc.clone())
+            .unwrap_or_else(|| SourceCodeLocation::new(1, 1, 0, 0, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::lower::lower;
+    use crate::pir::printer::print_module;
+    use crate::sema::modules::ModuleLoader;
+    use crate::util::GenerateErrorMessage;
+    use std::path::Path;
+
+    fn parse(src: &str) -> Module {
+        parse_module(None, src).unwrap_or_else(|e| panic!("{e:?}"))
+    }
+
+    #[test]
+    fn round_trips_lowered_programs() {
+        let graph = ModuleLoader::new(vec![])
+            .load_source(
+                Path::new("main.paca"),
+                "struct Point { $x: int, y: float }\n\n\
+                 enum Shape { Dot(Point), Line(Point, Point), Empty }\n\n\
+                 def apply(n: int, f: def(int) int) int {\n    return f(n);\n}\n\n\
+                 def main() void {\n    let p = Point { x => -1, y => 2.5 };\n    \
+                 p->x = p->x * 2;\n    let mut n = 0;\n    while n < 3 && !false { n += 1; }\n    \
+                 let shape = Shape::Line(p, p);\n    \
+                 match shape {\n        Shape::Line(a, _) => println(a->y),\n        \
+                 _ => println(\"none\\n\"),\n    }\n    \
+                 println(apply(n) { (x) = x + n });\n    println((1, 'c'));\n}\n"
+                    .to_string(),
+            )
+            .unwrap();
+        let (analysis, diagnostics) = crate::sema::analyze(&graph);
+        let analysis = analysis.unwrap_or_else(|| panic!("{diagnostics:?}"));
+        let module = lower(&graph, &analysis);
+
+        let text = print_module(&module);
+        let parsed = parse(&text);
+        assert_eq!(parsed, module);
+        assert_eq!(print_module(&parsed), text);
+    }
+
+    #[test]
+    fn parses_hand_written_ir() {
+        // Labels are numbered in order of definition, and functions and types can be used before
+        // they are defined.
+        let module = parse(
+            "def main() void {\n\
+             entry:\n    %0: Pair = call @make()\n    %1: int = get_field %0, b\n    \
+             %2: bool = lt %1, %1\n    branch %2, done, loop(%1)\n\
+             loop(%3: int):\n    %4: float = const -inf\n    %5: int = const min\n    \
+             %6: void = set_field %0, 0, %5\n    jump done\n\
+             done:\n    return\n}\n\n\
+             def make() Pair {\nstart:\n    %0: int = const 1\n    \
+             %1: Pair = new Pair(%0, %0)\n    return %1\n}\n\n\
+             struct Pair { a: int, b: int, }\n",
+        );
+        assert_eq!(module.adts[0].name, "Pair");
+        let main = &module.functions[0];
+        assert_eq!(main.blocks.len(), 3);
+        assert_eq!(main.blocks[0].insts[0].kind, InstKind::Call(1, vec![]));
+        assert_eq!(main.blocks[0].insts[1].kind, InstKind::GetField(0, 1));
+        assert_eq!(
+            main.blocks[0].term,
+            Terminator::Branch {
+                cond: 2,
+                then_target: Target::new(2, vec![]),
+                else_target: Target::new(1, vec![1]),
+            }
+        );
+        assert_eq!(main.blocks[1].params, [3]);
+        assert_eq!(
+            main.blocks[1].insts[0].kind,
+            InstKind::Const(Const::Float(f64::NEG_INFINITY))
+        );
+        assert_eq!(
+            main.blocks[1].insts[1].kind,
+            InstKind::Const(Const::Int(i64::MIN))
+        );
+        assert_eq!(
+            print_module(&parse(&print_module(&module))),
+            print_module(&module)
+        );
+    }
+
+    #[test]
+    fn reports_unknown_names() {
+        let error = |src: &str| match parse_module(None, src) {
+            Err(SyntaxError::Parse(e)) => e.generate_error_message(src),
+            result => panic!("expected a parse error, got {result:?}"),
+        };
+        assert!(error("def f() int {\nbb0:\n    return %0\n}\n").contains("Unknown value `%0`."));
+        assert!(error("def f() void {\nbb0:\n    jump bb1\n}\n").contains("Unknown block `bb1`."));
+        assert!(error(
+            "struct S { a: int }\n\ndef f(%0: S) int {\nbb0:\n    %1: int = get_field %0, b\n    \
+             return %1\n}\n"
+        )
+        .contains("Unknown field `b`."));
+        assert!(error(
+            "def f() void {\nbb0:\n    return\n}\n\ndef f() void {\nbb0:\n    return\n}\n"
+        )
+        .contains("The function `f` is defined more than once."));
+    }
+}
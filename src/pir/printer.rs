@@ -0,0 +1,481 @@
+use super::*;
+use crate::parse::ast::Literal;
+use crate::parse::printer::literal_str;
+use crate::parse::{Keyword, SourceCodeLocation};
+use std::fmt;
+
+/// Turn a module into the textual form of PIR, which `parser::parse_module` reads back into an equal
+/// module.
+pub fn print_module(module: &Module) -> String {
+    print_module_with_spans(module).0
+}
+
+/// Same as `print_module`, but also return where everything is in the text, to report problems
+/// in a module that has no source.
+pub fn print_module_with_spans(module: &Module) -> (String, Spans) {
+    let mut printer = Printer::new(module);
+    printer.module();
+    (printer.out, printer.spans)
+}
+
+/// Turn a single function of the module into the textual form of PIR.
+pub fn print_function(module: &Module, function: &Function) -> String {
+    let mut printer = Printer::new(module);
+    printer.function(None, function);
+    printer.out
+}
+
+/// Turn a block of a function into the textual form of PIR, from its label to its terminator.
+pub fn print_block(module: &Module, function: &Function, block: BlockId) -> String {
+    let mut printer = Printer::new(module);
+    printer.block(None, function, block);
+    printer.out
+}
+
+/// Turn a type into the textual form of PIR, naming structs and enums as the module does.
+pub fn print_type(module: &Module, ty: &Type) -> String {
+    let mut printer = Printer::new(module);
+    printer.ty(ty);
+    printer.out
+}
+
+/// The name as written in PIR: plain if it is an identifier, quoted otherwise, e.g. for
+/// `Option<int>::unwrap`.
+pub fn name_str(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_ident = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+        && TryInto::<Keyword>::try_into(name.to_string()).is_err()
+        && !matches!(name, "bool" | "char" | "void");
+    if is_ident {
+        name.to_string()
+    } else {
+        literal_str(&Literal::Str(name.to_string()))
+    }
+}
+
+/// The source representation of a constant, without its type.
+pub fn const_str(value: &Const) -> String {
+    match value {
+        // `9223372036854775808` doesn't fit an `int`, so `-9223372036854775808` can't be written.
+        Const::Int(i64::MIN) => "min".to_string(),
+        Const::Int(n) => n.to_string(),
+        Const::Float(n) if n.is_nan() => "nan".to_string(),
+        Const::Float(n) if n.is_infinite() => if *n > 0.0 { "inf" } else { "-inf" }.to_string(),
+        Const::Float(n) => literal_str(&Literal::Float(*n)),
+        Const::Bool(b) => b.to_string(),
+        Const::Char(c) => literal_str(&Literal::Char(*c)),
+        Const::Str(s) => literal_str(&Literal::Str(s.clone())),
+        Const::Void => "void".to_string(),
+    }
+}
+
+impl UnaryOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "neg",
+            UnaryOp::Not => "not",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub const ALL: [BinaryOp; 7] = [
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Mul,
+        BinaryOp::Div,
+        BinaryOp::Rem,
+        BinaryOp::Shl,
+        BinaryOp::Shr,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Rem => "rem",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Shr => "shr",
+        }
+    }
+}
+
+impl CompareOp {
+    pub const ALL: [CompareOp; 6] = [
+        CompareOp::Eq,
+        CompareOp::Ne,
+        CompareOp::Lt,
+        CompareOp::Le,
+        CompareOp::Gt,
+        CompareOp::Ge,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CompareOp::Eq => "eq",
+            CompareOp::Ne => "ne",
+            CompareOp::Lt => "lt",
+            CompareOp::Le => "le",
+            CompareOp::Gt => "gt",
+            CompareOp::Ge => "ge",
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for CompareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+struct Printer<'a> {
+    module: &'a Module,
+    out: String,
+    spans: Spans,
+    /// The line and column of the next character, and the number of characters written.
+    line: usize,
+    column: usize,
+    offset: usize,
+}
+
+impl<'a> Printer<'a> {
+    fn new(module: &'a Module) -> Self {
+        Self {
+            module,
+            out: String::new(),
+            spans: Spans::default(),
+            line: 1,
+            column: 1,
+            offset: 0,
+        }
+    }
+
+    fn write(&mut self, s: &str) {
+        for c in s.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+            self.offset += 1;
+        }
+        self.out.push_str(s);
+    }
+
+    /// The location of the next character, to be passed to `span` once the thing there is written.
+    fn mark(&self) -> SourceCodeLocation {
+        SourceCodeLocation::new(self.line, self.column, self.offset, 0, None)
+    }
+
+    /// Record that the thing of the function at the position spans from `start` to here.
+    fn span(&mut self, position: Option<Position>, start: SourceCodeLocation) {
+        if let Some(position) = position {
+            let length = self.offset - start.offset;
+            self.spans
+                .insert(position, SourceCodeLocation { length, ..start });
+        }
+    }
+
+    fn module(&mut self) {
+        let mut first = true;
+        for adt in &self.module.adts {
+            if !first {
+                self.write("\n");
+            }
+            first = false;
+            self.adt(adt);
+        }
+        for (id, function) in self.module.functions.iter().enumerate() {
+            if !first {
+                self.write("\n");
+            }
+            first = false;
+            self.function(Some(id), function);
+        }
+    }
+
+    fn adt(&mut self, adt: &AdtDef) {
+        match &adt.kind {
+            AdtKind::Struct(fields) => {
+                self.write(&format!("struct {} {{\n", name_str(&adt.name)));
+                for field in fields {
+                    self.write(&format!("    {}: ", name_str(&field.name)));
+                    self.ty(&field.ty);
+                    self.write(",\n");
+                }
+            }
+            AdtKind::Enum(variants) => {
+                self.write(&format!("enum {} {{\n", name_str(&adt.name)));
+                for variant in variants {
+                    self.write(&format!("    {}", name_str(&variant.name)));
+                    if !variant.fields.is_empty() {
+                        self.tys(&variant.fields);
+                    }
+                    self.write(&format!(" = {},\n", variant.discriminant));
+                }
+            }
+        }
+        self.write("}\n");
+    }
+
+    /// Write the function, recording where its parts are if its id is given.
+    fn function(&mut self, id: Option<FuncId>, function: &Function) {
+        let start = self.mark();
+        self.write(&format!("def {}(", name_str(&function.name)));
+        self.params(function, function.params());
+        self.write(") ");
+        self.ty(&function.ret);
+        self.span(id.map(Position::Function), start);
+        self.write(" {\n");
+        for block_id in 0..function.blocks.len() {
+            self.block(id, function, block_id);
+        }
+        self.write("}\n");
+    }
+
+    fn block(&mut self, id: Option<FuncId>, function: &Function, block_id: BlockId) {
+        let block = &function.blocks[block_id];
+        let start = self.mark();
+        self.write(&format!("bb{block_id}"));
+        if block_id != 0 && !block.params.is_empty() {
+            self.write("(");
+            self.params(function, &block.params);
+            self.write(")");
+        }
+        self.span(id.map(|id| Position::Block(id, block_id)), start);
+        self.write(":\n");
+        for (index, inst) in block.insts.iter().enumerate() {
+            self.write("    ");
+            let start = self.mark();
+            self.write(&format!("%{}: ", inst.result));
+            self.value_ty(function, inst.result);
+            self.write(" = ");
+            self.inst(function, &inst.kind);
+            self.span(id.map(|id| Position::Inst(id, block_id, index)), start);
+            self.write("\n");
+        }
+        self.write("    ");
+        let start = self.mark();
+        self.terminator(&block.term);
+        self.span(id.map(|id| Position::Terminator(id, block_id)), start);
+        self.write("\n");
+    }
+
+    fn params(&mut self, function: &Function, params: &[ValueId]) {
+        for (i, &param) in params.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.write(&format!("%{param}: "));
+            self.value_ty(function, param);
+        }
+    }
+
+    fn inst(&mut self, function: &Function, kind: &InstKind) {
+        let member = |value: ValueId, index: usize, of_struct: bool| {
+            self.member(function.values.get(value), index, of_struct)
+        };
+        let text = match kind {
+            InstKind::Const(value) => format!("const {}", const_str(value)),
+            InstKind::Unary(op, value) => format!("{op} %{value}"),
+            InstKind::Binary(op, a, b) => format!("{op} %{a}, %{b}"),
+            InstKind::Compare(op, a, b) => format!("{op} %{a}, %{b}"),
+            InstKind::Alloca(ty) => format!("alloca {}", print_type(self.module, ty)),
+            InstKind::Load(address) => format!("load %{address}"),
+            InstKind::Store(address, value) => format!("store %{address}, %{value}"),
+            InstKind::Call(func, args) => format!(
+                "call @{}{}",
+                self.function_name(*func),
+                values(args, "(", ")")
+            ),
+            InstKind::CallNative(native, args) => {
+                format!("call_native {}{}", native.name, values(args, "(", ")"))
+            }
+            InstKind::CallIndirect(closure, args) => {
+                format!("call_indirect %{closure}{}", values(args, "(", ")"))
+            }
+            InstKind::Closure(func, env) => {
+                format!("closure @{}, %{env}", self.function_name(*func))
+            }
+            InstKind::Count(func, counter) => {
+                let counter = match counter {
+                    Counter::Entry(checksum) => format!("entry {checksum}"),
+                    Counter::Block(block) => format!("block {block}"),
+                    Counter::Edge(block, index) => format!("edge {block}, {index}"),
+                };
+                format!("count @{}, {counter}", self.function_name(*func))
+            }
+            InstKind::New(adt, fields, alloc) => format!(
+                "new {}{}{}",
+                stack(*alloc),
+                self.adt_name(*adt),
+                values(fields, "(", ")")
+            ),
+            InstKind::GetField(value, index) => {
+                format!("get_field %{value}, {}", member(*value, *index, true))
+            }
+            InstKind::SetField(value, index, field) => format!(
+                "set_field %{value}, {}, %{field}",
+                member(*value, *index, true)
+            ),
+            InstKind::Variant(adt, index, fields) => {
+                let variant = self.member(Some(&Type::Adt(*adt)), *index, false);
+                let fields = if fields.is_empty() {
+                    String::new()
+                } else {
+                    values(fields, "(", ")")
+                };
+                format!("variant {}::{variant}{fields}", self.adt_name(*adt))
+            }
+            InstKind::Tag(value) => format!("tag %{value}"),
+            InstKind::Payload(value, variant, field) => format!(
+                "payload %{value}, {}, {field}",
+                member(*value, *variant, false)
+            ),
+            InstKind::Tuple(elems) => format!("tuple{}", values(elems, "(", ")")),
+            InstKind::Extract(value, index) => format!("extract %{value}, {index}"),
+            InstKind::Array(elems, alloc) => {
+                let space = if *alloc == Alloc::Stack { " " } else { "" };
+                format!("array{space}{}{}", stack(*alloc), values(elems, "[", "]"))
+            }
+        };
+        self.write(&text);
+    }
+
+    fn terminator(&mut self, term: &Terminator) {
+        let text = match term {
+            Terminator::Jump(target) => format!("jump {}", target_str(target)),
+            Terminator::Branch {
+                cond,
+                then_target,
+                else_target,
+            } => format!(
+                "branch %{cond}, {}, {}",
+                target_str(then_target),
+                target_str(else_target)
+            ),
+            Terminator::Return(Some(value)) => format!("return %{value}"),
+            Terminator::Return(None) => "return".to_string(),
+            Terminator::Unreachable => "unreachable".to_string(),
+        };
+        self.write(&text);
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Void => self.write("void"),
+            Type::Bool => self.write("bool"),
+            Type::Int => self.write("int"),
+            Type::Float => self.write("float"),
+            Type::Char => self.write("char"),
+            Type::Str => self.write("str"),
+            Type::Ptr(ty) => {
+                self.write("*");
+                self.ty(ty);
+            }
+            Type::Adt(adt) => {
+                let name = self.adt_name(*adt);
+                self.write(&name);
+            }
+            Type::Tuple(elems) => self.tys(elems),
+            Type::Array(elem) => {
+                self.write("[]");
+                self.ty(elem);
+            }
+            Type::Fn(fn_ty) => {
+                self.write("def");
+                self.tys(&fn_ty.params);
+                self.write(" ");
+                self.ty(&fn_ty.ret);
+            }
+        }
+    }
+
+    /// Write the type of the value, or `?` if it has none.
+    fn value_ty(&mut self, function: &Function, value: ValueId) {
+        match function.values.get(value) {
+            Some(ty) => self.ty(ty),
+            None => self.write("?"),
+        }
+    }
+
+    /// The name of a struct or enum, or its id if there is no such type.
+    fn adt_name(&self, adt: AdtId) -> String {
+        self.module
+            .adts
+            .get(adt)
+            .map_or(adt.to_string(), |adt| name_str(&adt.name))
+    }
+
+    /// The name of a function, or its id if there is no such function.
+    fn function_name(&self, func: FuncId) -> String {
+        self.module
+            .functions
+            .get(func)
+            .map_or(func.to_string(), |function| name_str(&function.name))
+    }
+
+    /// The name of a field of the struct, or of a variant of the enum, or the index if the type
+    /// has no such member.
+    fn member(&self, ty: Option<&Type>, index: usize, of_struct: bool) -> String {
+        let adt = match ty {
+            Some(Type::Adt(adt)) => self.module.adts.get(*adt),
+            _ => None,
+        };
+        let name = match adt.map(|adt| &adt.kind) {
+            Some(AdtKind::Struct(fields)) if of_struct => fields.get(index).map(|f| &f.name),
+            Some(AdtKind::Enum(variants)) if !of_struct => variants.get(index).map(|v| &v.name),
+            _ => None,
+        };
+        name.map_or(index.to_string(), |name| name_str(name))
+    }
+
+    /// Write the types in parentheses.
+    fn tys(&mut self, tys: &[Type]) {
+        self.write("(");
+        for (i, ty) in tys.iter().enumerate() {
+            if i > 0 {
+                self.write(", ");
+            }
+            self.ty(ty);
+        }
+        self.write(")");
+    }
+}
+
+/// The keyword of a stack allocation, with its space.
+fn stack(alloc: Alloc) -> &'static str {
+    match alloc {
+        Alloc::Heap => "",
+        Alloc::Stack => "stack ",
+    }
+}
+
+fn values(values: &[ValueId], open: &str, close: &str) -> String {
+    let values: Vec<_> = values.iter().map(|v| format!("%{v}")).collect();
+    format!("{open}{}{close}", values.join(", "))
+}
+
+fn target_str(target: &Target) -> String {
+    if target.args.is_empty() {
+        format!("bb{}", target.block)
+    } else {
+        format!("bb{}{}", target.block, values(&target.args, "(", ")"))
+    }
+}
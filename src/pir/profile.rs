@@ -0,0 +1,410 @@
+//! Profiles: how many times the blocks and branches of a program ran, to optimize for the paths
+//! that run most.
+//!
+//! With `--profile-generate`, `instrument` puts a `count` instruction at the start of every block
+//! and on both targets of every branch, in a block of its own between the branch and its target.
+//! Running the program then adds up the counters, and `paca run` writes them to a file. The counts
+//! of the jumps follow from those of the blocks they end, so they need no counters of their own.
+//! A counter names the function and block it counts, so it keeps counting for them when it's
+//! inlined into another function.
+//!
+//! With `--profile-use`, `annotate` gives the targets of the freshly lowered program the counts of
+//! the profile, which is made from the same program before it's optimized. The blocks are then
+//! numbered the same way, so the counters still name them. The counter of the entry of a function
+//! has a checksum of its code, so that the counts of a function that changed since are left out
+//! with a warning. Passes read the counts back with `block_count` and `likely_target`.
+//!
+//! # File format
+//!
+//! A profile is a line per function and counter that ran, with fields separated by tabs, shown as
+//! spaces here:
+//!
+//! ```text
+//! function  fib  9f1c02d4
+//! block     fib  0  177
+//! block     fib  2  88
+//! edge      fib  0  1  88
+//! ```
+//!
+//! A `function` line has the function and the checksum of its code in hex, a `block` line has the
+//! function, the block and its count, and an `edge` line has the function, the block, the target
+//! of the branch ending it, 0 for then and 1 for else, and its count. Counters that don't appear
+//! never ran.
+
+use super::printer::print_function;
+use super::{BlockId, Counter, FuncId, Function, Inst, InstKind, Module, Target, Terminator, Type};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// The counts of the `count` instructions run, by the function and counter.
+pub type Counts = HashMap<(FuncId, Counter), u64>;
+
+/// Put a counter at the start of every block and on both targets of every branch. The counter of
+/// the entry has the checksum of the function.
+pub fn instrument(module: &mut Module) {
+    let checksums: Vec<u32> = module
+        .functions
+        .iter()
+        .map(|function| checksum(module, function))
+        .collect();
+    for (id, function) in module.functions.iter_mut().enumerate() {
+        let blocks = function.blocks.len();
+        for block in 0..blocks {
+            let count = function.new_value(Type::Void);
+            let counter = match block {
+                0 => Counter::Entry(checksums[id]),
+                _ => Counter::Block(block),
+            };
+            function.blocks[block].insts.insert(
+                0,
+                Inst {
+                    result: count,
+                    kind: InstKind::Count(id, counter),
+                },
+            );
+            if !matches!(function.blocks[block].term, Terminator::Branch { .. }) {
+                continue;
+            }
+            for index in 0..2 {
+                let edge = function.add_block();
+                let count = function.new_value(Type::Void);
+                function.blocks[edge].insts.push(Inst {
+                    result: count,
+                    kind: InstKind::Count(id, Counter::Edge(block, index)),
+                });
+                let target = &mut function.blocks[block].term.targets_mut()[index];
+                let mut split = Target::new(edge, Vec::new());
+                split.count = target.count;
+                let target = std::mem::replace(*target, split);
+                function.blocks[edge].term = Terminator::Jump(target);
+            }
+        }
+    }
+}
+
+/// A checksum of the code of the function, the 32-bit FNV-1a hash of its textual form.
+pub fn checksum(module: &Module, function: &Function) -> u32 {
+    print_function(module, function)
+        .bytes()
+        .fold(0x811c9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x01000193)
+        })
+}
+
+/// The counts of a run of a program, by the names of its functions.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    functions: BTreeMap<String, FunctionProfile>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FunctionProfile {
+    /// The checksum of the function the counts are of, if the profile has it.
+    checksum: Option<u32>,
+    /// The counts by counter, with the calls of the function as the count of its entry block.
+    counts: BTreeMap<Counter, u64>,
+}
+
+impl Profile {
+    /// The profile of the counts of a run of the module.
+    pub fn new(module: &Module, counts: &Counts) -> Self {
+        let mut profile = Self::default();
+        for (&(func, counter), &count) in counts {
+            let name = module.functions[func].name.clone();
+            let function = profile.functions.entry(name).or_default();
+            let counter = match counter {
+                Counter::Entry(checksum) => {
+                    function.checksum = Some(checksum);
+                    Counter::Block(0)
+                }
+                counter => counter,
+            };
+            *function.counts.entry(counter).or_default() += count;
+        }
+        profile
+    }
+
+    /// Read a profile in the format of the module documentation.
+    pub fn parse(text: &str) -> Result<Self, ProfileError> {
+        let mut profile = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let error = |message: &str| ProfileError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split('\t').collect();
+            let number = |field: &str| -> Result<u64, ProfileError> {
+                field
+                    .parse()
+                    .map_err(|_| error(&format!("`{field}` isn't a count.")))
+            };
+            if let ["function", name, checksum] = fields.as_slice() {
+                let checksum = u32::from_str_radix(checksum, 16)
+                    .map_err(|_| error(&format!("`{checksum}` isn't a checksum.")))?;
+                profile
+                    .functions
+                    .entry(name.to_string())
+                    .or_default()
+                    .checksum = Some(checksum);
+                continue;
+            }
+            let (name, counter, count) = match fields.as_slice() {
+                ["block", name, block, count] => {
+                    (name, Counter::Block(number(block)? as BlockId), count)
+                }
+                ["edge", name, block, index @ ("0" | "1"), count] => (
+                    name,
+                    Counter::Edge(number(block)? as BlockId, number(index)? as usize),
+                    count,
+                ),
+                _ => {
+                    return Err(error(
+                        "Expected a function, or a `block` or `edge` counter.",
+                    ))
+                }
+            };
+            *profile
+                .functions
+                .entry(name.to_string())
+                .or_default()
+                .counts
+                .entry(counter)
+                .or_default() += number(count)?;
+        }
+        Ok(profile)
+    }
+
+    pub fn count(&self, function: &str, counter: Counter) -> u64 {
+        self.functions
+            .get(function)
+            .and_then(|function| function.counts.get(&counter))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (function, profile) in &self.functions {
+            if let Some(checksum) = profile.checksum {
+                writeln!(f, "function\t{function}\t{checksum:08x}")?;
+            }
+            for (counter, count) in &profile.counts {
+                match counter {
+                    Counter::Entry(_) => {}
+                    Counter::Block(block) => writeln!(f, "block\t{function}\t{block}\t{count}")?,
+                    Counter::Edge(block, index) => {
+                        writeln!(f, "edge\t{function}\t{block}\t{index}\t{count}")?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line of a profile that can't be read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProfileError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Give the functions and targets of the module the counts of the profile, returning warnings
+/// about what doesn't match. A jump is taken as often as its block runs, and a branch as often as
+/// the counter of its target. Functions whose checksum differs from the profile's, or that lack
+/// blocks it counts, get no counts, and neither do functions of a profile that doesn't match at
+/// all.
+pub fn annotate(module: &mut Module, profile: &Profile) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for name in profile.functions.keys() {
+        if module.function(name).is_none() {
+            warnings.push(format!(
+                "The profile has counts for `{name}`, which isn't in the program."
+            ));
+        }
+    }
+    let matches: Vec<bool> = module
+        .functions
+        .iter()
+        .map(|function| {
+            let Some(counts) = profile.functions.get(&function.name) else {
+                return true;
+            };
+            let blocks = function.blocks.len();
+            counts
+                .checksum
+                .is_none_or(|c| c == checksum(module, function))
+                && counts.counts.keys().all(|counter| match counter {
+                    Counter::Block(block) | Counter::Edge(block, _) => *block < blocks,
+                    Counter::Entry(_) => true,
+                })
+        })
+        .collect();
+    for (function, &matches) in module.functions.iter().zip(&matches) {
+        if !matches {
+            warnings.push(format!(
+                "The profile of `{}` is of different code, so it's ignored.",
+                function.name
+            ));
+        }
+    }
+    let used = module
+        .functions
+        .iter()
+        .zip(&matches)
+        .any(|(function, &matches)| matches && profile.functions.contains_key(&function.name));
+    if !profile.functions.is_empty() && !used {
+        warnings.push("The profile isn't of this program, so it's ignored.".to_string());
+        return warnings;
+    }
+    for (function, matches) in module.functions.iter_mut().zip(matches) {
+        if !matches {
+            continue;
+        }
+        let name = function.name.clone();
+        function.entry_count = Some(profile.count(&name, Counter::Block(0)));
+        for (block, data) in function.blocks.iter_mut().enumerate() {
+            match &mut data.term {
+                Terminator::Jump(target) => {
+                    target.count = Some(profile.count(&name, Counter::Block(block)));
+                }
+                term => {
+                    for (index, target) in term.targets_mut().into_iter().enumerate() {
+                        target.count = Some(profile.count(&name, Counter::Edge(block, index)));
+                    }
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// How many times the block ran: the count of the function for its entry, and the sum of the
+/// counts of the jumps to it for the others. It's unknown when a jump to it has no count.
+pub fn block_count(function: &Function, block: BlockId) -> Option<u64> {
+    if block == 0 {
+        return function.entry_count;
+    }
+    let mut jumps = function
+        .blocks
+        .iter()
+        .flat_map(|data| data.term.targets())
+        .filter(|target| target.block == block)
+        .peekable();
+    jumps.peek()?;
+    jumps.map(|target| target.count).sum()
+}
+
+/// The target of the branch ending the block that the profile took more often, 0 for then and 1
+/// for else, e.g. to lay it out right after the block or hint it to the C compiler.
+pub fn likely_target(function: &Function, block: BlockId) -> Option<usize> {
+    let Terminator::Branch {
+        then_target,
+        else_target,
+        ..
+    } = &function.blocks[block].term
+    else {
+        return None;
+    };
+    match (then_target.count?, else_target.count?) {
+        (then, other) if then > other => Some(0),
+        (then, other) if then < other => Some(1),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::interp;
+    use crate::pir::parser::parse_module;
+    use crate::pir::verify::verify;
+
+    #[test]
+    fn counts_blocks_and_branches() {
+        let source = "def main() void {
+bb0:
+    %0: int = const 0
+    %1: int = const 10
+    jump bb1(%0)
+bb1(%2: int):
+    %3: bool = lt %2, %1
+    branch %3, bb2, bb3
+bb2:
+    %4: int = const 1
+    %5: int = add %2, %4
+    jump bb1(%5)
+bb3:
+    return
+}
+";
+        let mut module = parse_module(None, source).unwrap();
+        let sum = checksum(&module, &module.functions[0]);
+        instrument(&mut module);
+        verify(&module).unwrap();
+        let mut counts = Counts::new();
+        interp::run_counting(&module, &mut Vec::new(), &mut counts).unwrap();
+        let profile = Profile::new(&module, &counts);
+        let text = profile.to_string();
+        assert_eq!(
+            text,
+            format!("function\tmain\t{sum:08x}\n")
+                + "block\tmain\t0\t1
+block\tmain\t1\t11
+block\tmain\t2\t10
+block\tmain\t3\t1
+edge\tmain\t1\t0\t10
+edge\tmain\t1\t1\t1
+"
+        );
+        assert_eq!(Profile::parse(&text), Ok(profile.clone()));
+        assert_eq!(Profile::parse("block\tmain\t0\tmany").unwrap_err().line, 1);
+
+        let mut module = parse_module(None, source).unwrap();
+        assert!(annotate(&mut module, &profile).is_empty());
+        let main = &module.functions[0];
+        assert_eq!(block_count(main, 1), Some(11));
+        assert_eq!(block_count(main, 2), Some(10));
+        assert_eq!(likely_target(main, 1), Some(0));
+    }
+
+    #[test]
+    fn ignores_profiles_of_other_code() {
+        let source = "def main() void {
+bb0:
+    return
+}
+
+def other() void {
+bb0:
+    return
+}
+";
+        let profile = Profile::parse(
+            "function\tmain\t00000000\nblock\tmain\t0\t1\nblock\tgone\t0\t1\n\
+             block\tother\t3\t1\n",
+        )
+        .unwrap();
+        let mut module = parse_module(None, source).unwrap();
+        assert_eq!(
+            annotate(&mut module, &profile),
+            [
+                "The profile has counts for `gone`, which isn't in the program.",
+                "The profile of `main` is of different code, so it's ignored.",
+                "The profile of `other` is of different code, so it's ignored.",
+                "The profile isn't of this program, so it's ignored.",
+            ]
+        );
+        assert_eq!(module.functions[0].entry_count, None);
+    }
+}
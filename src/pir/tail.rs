@@ -0,0 +1,165 @@
+//! Tail calls: calls whose value the caller returns right away, so the callee can take over the
+//! caller's frame instead of getting one of its own.
+//!
+//! A call is a tail call when it ends its block and the block returns its value, possibly through
+//! jumps to blocks without instructions that pass the value on. For `void` calls the value doesn't
+//! matter, only that nothing else happens before the return. The `tail-call` pass turns the tail
+//! calls between functions that are recursive with each other into jumps, and `check` makes sure
+//! that the functions annotated with `@tailcall` have no other recursive calls.
+
+use super::{BlockId, FuncId, Function, InstKind, Module, Terminator, Type, ValueId};
+use crate::sema::Diagnostic;
+use std::collections::HashSet;
+
+/// The callee and arguments of the call ending the block, if it is a tail call.
+pub fn tail_call(function: &Function, block: BlockId) -> Option<(FuncId, &[ValueId])> {
+    let inst = function.blocks[block].insts.last()?;
+    let InstKind::Call(callee, args) = &inst.kind else {
+        return None;
+    };
+    let void = function.values[inst.result] == Type::Void;
+    let mut values = vec![inst.result];
+    let mut term = &function.blocks[block].term;
+    for _ in 0..function.blocks.len() {
+        match term {
+            Terminator::Return(None) => return void.then_some((*callee, args.as_slice())),
+            Terminator::Return(Some(value)) => {
+                return values.contains(value).then_some((*callee, args.as_slice()))
+            }
+            Terminator::Jump(target) if function.blocks[target.block].insts.is_empty() => {
+                let next = &function.blocks[target.block];
+                for (arg, &param) in target.args.iter().zip(&next.params) {
+                    if values.contains(arg) {
+                        values.push(param);
+                    }
+                }
+                term = &next.term;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The functions every function is recursive with: those it calls, directly or not, that call it
+/// back. A function calling itself is recursive with itself.
+pub fn recursion(module: &Module) -> Vec<HashSet<FuncId>> {
+    let callees: Vec<HashSet<FuncId>> = module
+        .functions
+        .iter()
+        .map(|function| {
+            function
+                .blocks
+                .iter()
+                .flat_map(|block| &block.insts)
+                .filter_map(|inst| match inst.kind {
+                    InstKind::Call(callee, _) => Some(callee),
+                    _ => None,
+                })
+                .collect()
+        })
+        .collect();
+    let reachable: Vec<HashSet<FuncId>> = (0..module.functions.len())
+        .map(|root| {
+            let mut reached = HashSet::new();
+            let mut stack: Vec<FuncId> = callees[root].iter().copied().collect();
+            while let Some(function) = stack.pop() {
+                if reached.insert(function) {
+                    stack.extend(&callees[function]);
+                }
+            }
+            reached
+        })
+        .collect();
+    (0..module.functions.len())
+        .map(|function| {
+            reachable[function]
+                .iter()
+                .copied()
+                .filter(|&other| reachable[other].contains(&function))
+                .collect()
+        })
+        .collect()
+}
+
+/// Report the recursive calls of the functions annotated with `@tailcall` that aren't tail calls,
+/// and so can't be turned into jumps.
+pub fn check(module: &Module) -> Vec<Diagnostic> {
+    let recursion = recursion(module);
+    let mut diagnostics = Vec::new();
+    for (id, function) in module.functions.iter().enumerate() {
+        let Some(annotation) = &function.tailcall else {
+            continue;
+        };
+        for (block, data) in function.blocks.iter().enumerate() {
+            for (index, inst) in data.insts.iter().enumerate() {
+                let InstKind::Call(callee, _) = inst.kind else {
+                    continue;
+                };
+                let is_tail = index + 1 == data.insts.len() && tail_call(function, block).is_some();
+                if !recursion[id].contains(&callee) || is_tail {
+                    continue;
+                }
+                let loc = function.locs.get(&inst.result).unwrap_or(annotation);
+                let message = format!(
+                    "This recursive call to `{}` isn't a tail call, but `{}` is `@tailcall`.",
+                    module.functions[callee].name, function.name
+                );
+                diagnostics.push(
+                    Diagnostic::error(message, loc.clone())
+                        .with_help("return the value of the call right away."),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::SourceCodeLocation;
+    use crate::pir::parser::parse_module;
+
+    #[test]
+    fn finds_tail_calls_through_jumps() {
+        let mut module = parse_module(
+            None,
+            "def fact(%0: int, %1: int) int {
+bb0:
+    %2: int = const 1
+    %3: bool = le %0, %2
+    branch %3, bb1, bb2
+bb1:
+    return %1
+bb2:
+    %4: int = sub %0, %2
+    %5: int = mul %0, %1
+    %6: int = call @fact(%4, %5)
+    jump bb3(%6)
+bb3(%7: int):
+    return %7
+}
+
+def sum(%0: int) int {
+bb0:
+    %1: int = const 1
+    %2: int = sub %0, %1
+    %3: int = call @sum(%2)
+    %4: int = add %0, %3
+    return %4
+}
+",
+        )
+        .unwrap();
+        assert_eq!(tail_call(&module.functions[0], 2), Some((0, &[4, 5][..])));
+        assert_eq!(tail_call(&module.functions[1], 0), None);
+        assert!(check(&module).is_empty());
+
+        let loc = SourceCodeLocation::new(1, 1, 0, 9, None);
+        module.functions[1].tailcall = Some(loc.clone());
+        let diagnostics = check(&module);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].loc, loc);
+    }
+}
@@ -0,0 +1,782 @@
+//! The verifier, which checks that a module follows the rules of PIR: every value is defined once,
+//! by a block parameter or an instruction that dominates its uses, every instruction gets operands
+//! of the types it works on and gives a value of the type it's declared with, and every terminator
+//! passes its targets as many arguments as they take, of their types.
+//!
+//! Lowering and the optimizer only make modules that pass, so a violation is a bug in them, and
+//! the verifier runs after each of them in debug builds. PIR read from text is always verified.
+
+use super::dom::DomTree;
+use super::printer::{name_str, print_type};
+use super::*;
+use crate::util::GenerateErrorMessage;
+
+/// A violation of the rules of PIR.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PirError {
+    /// The name of the function the violation is in.
+    pub function: String,
+    pub position: Position,
+    pub message: String,
+    /// Where the violation is in the text of the module, once it's known.
+    pub loc: Option<SourceCodeLocation>,
+}
+
+impl PirError {
+    /// Point the error at the text the spans were taken from.
+    pub fn locate(mut self, spans: &Spans) -> Self {
+        self.loc = spans.get(self.position).cloned();
+        self
+    }
+}
+
+impl GenerateErrorMessage for PirError {
+    fn generate_error_message(self, source_code: &str) -> String {
+        let message = format!(
+            "IR Error in `{}`: {}",
+            name_str(&self.function),
+            self.message
+        );
+        match self.loc {
+            Some(loc) => loc.line_in_source_code(source_code) + "\n" + &message,
+            None => message,
+        }
+    }
+}
+
+/// Check every function of the module, returning all the violations found.
+pub fn verify(module: &Module) -> Result<(), Vec<PirError>> {
+    let mut errors = Vec::new();
+    for (id, function) in module.functions.iter().enumerate() {
+        FnVerifier::new(module, id, function, &mut errors).verify();
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Where a value is defined.
+#[derive(Clone, Copy)]
+enum Def {
+    Param(BlockId),
+    /// An instruction, by its index in the block.
+    Inst(BlockId, usize),
+}
+
+struct FnVerifier<'a> {
+    module: &'a Module,
+    id: FuncId,
+    function: &'a Function,
+    defs: Vec<Option<Def>>,
+    dom: Option<DomTree>,
+    errors: &'a mut Vec<PirError>,
+}
+
+impl<'a> FnVerifier<'a> {
+    fn new(
+        module: &'a Module,
+        id: FuncId,
+        function: &'a Function,
+        errors: &'a mut Vec<PirError>,
+    ) -> Self {
+        Self {
+            module,
+            id,
+            function,
+            defs: vec![None; function.values.len()],
+            dom: None,
+            errors,
+        }
+    }
+
+    fn verify(&mut self) {
+        if self.function.blocks.is_empty() {
+            self.error(Position::Function(self.id), "The function has no blocks.");
+            return;
+        }
+        // Targets out of range would break the dominator tree, so they are reported first.
+        let mut targets_exist = true;
+        for (id, block) in self.function.blocks.iter().enumerate() {
+            for target in block.term.targets() {
+                if target.block >= self.function.blocks.len() {
+                    self.error(
+                        Position::Terminator(self.id, id),
+                        format!("`bb{}` doesn't exist.", target.block),
+                    );
+                    targets_exist = false;
+                }
+            }
+        }
+        if !targets_exist {
+            return;
+        }
+        self.dom = Some(DomTree::new(self.function));
+
+        for (id, block) in self.function.blocks.iter().enumerate() {
+            for &param in &block.params {
+                self.define(param, Def::Param(id), Position::Block(self.id, id));
+            }
+            for (index, inst) in block.insts.iter().enumerate() {
+                let position = Position::Inst(self.id, id, index);
+                self.define(inst.result, Def::Inst(id, index), position);
+                if let InstKind::Alloca(ty) = &inst.kind {
+                    self.check_type(position, ty);
+                }
+            }
+        }
+        for id in 0..self.function.blocks.len() {
+            for index in 0..self.function.blocks[id].insts.len() {
+                self.inst(id, index);
+            }
+            self.terminator(id);
+        }
+    }
+
+    fn error(&mut self, position: Position, message: impl Into<String>) {
+        self.errors.push(PirError {
+            function: self.function.name.clone(),
+            position,
+            message: message.into(),
+            loc: None,
+        });
+    }
+
+    fn define(&mut self, value: ValueId, def: Def, position: Position) {
+        match self.defs.get_mut(value) {
+            None => self.error(position, format!("`%{value}` has no type.")),
+            Some(Some(_)) => self.error(position, format!("`%{value}` is defined more than once.")),
+            Some(slot) => {
+                *slot = Some(def);
+                let ty = &self.function.values[value];
+                self.check_type(position, ty);
+            }
+        }
+    }
+
+    /// Check that the structs and enums the type names exist.
+    fn check_type(&mut self, position: Position, ty: &Type) {
+        match ty {
+            Type::Adt(adt) if *adt >= self.module.adts.len() => self.error(
+                position,
+                format!("There is no struct or enum with id {adt}."),
+            ),
+            Type::Ptr(ty) | Type::Array(ty) => self.check_type(position, ty),
+            Type::Tuple(tys) => tys.iter().for_each(|ty| self.check_type(position, ty)),
+            Type::Fn(fn_ty) => {
+                fn_ty
+                    .params
+                    .iter()
+                    .for_each(|ty| self.check_type(position, ty));
+                self.check_type(position, &fn_ty.ret);
+            }
+            _ => {}
+        }
+    }
+
+    fn ty_str(&self, ty: &Type) -> String {
+        print_type(self.module, ty)
+    }
+
+    /// The type of a value used at the index of the block's instructions, the terminator being at
+    /// the end, or `None` if it isn't defined where it's used.
+    fn use_value(
+        &mut self,
+        position: Position,
+        value: ValueId,
+        block: BlockId,
+        index: usize,
+    ) -> Option<Type> {
+        let Some(def) = self.defs.get(value).copied().flatten() else {
+            self.error(position, format!("`%{value}` is never defined."));
+            return None;
+        };
+        let dom = self.dom.as_ref().expect("the dominators are computed");
+        // Unreachable blocks are never run, so any value can be used in them.
+        let dominates = !dom.is_reachable(block)
+            || match def {
+                Def::Param(def_block) => dom.dominates(def_block, block),
+                Def::Inst(def_block, def_index) if def_block == block => def_index < index,
+                Def::Inst(def_block, _) => dom.dominates(def_block, block),
+            };
+        if !dominates {
+            self.error(
+                position,
+                format!("The definition of `%{value}` doesn't dominate this use."),
+            );
+            return None;
+        }
+        Some(self.function.values[value].clone())
+    }
+
+    /// Check that the value is of the expected type.
+    fn operand(&mut self, position: Position, value: ValueId, ty: Option<&Type>, expected: &Type) {
+        if let Some(ty) = ty.filter(|ty| *ty != expected) {
+            let message = format!(
+                "Expected `%{value}` to be `{}`, but it is `{}`.",
+                self.ty_str(expected),
+                self.ty_str(ty)
+            );
+            self.error(position, message);
+        }
+    }
+
+    /// Check that the values passed to something taking parameters of the types match them.
+    fn arguments(
+        &mut self,
+        position: Position,
+        callee: &str,
+        args: &[ValueId],
+        tys: &[Option<Type>],
+        params: &[Type],
+    ) {
+        if args.len() != params.len() {
+            let message = format!(
+                "{callee} takes {} but {} given.",
+                plural(params.len(), "argument"),
+                were(args.len())
+            );
+            self.error(position, message);
+            return;
+        }
+        for ((&arg, ty), param) in args.iter().zip(tys).zip(params) {
+            self.operand(position, arg, ty.as_ref(), param);
+        }
+    }
+
+    fn inst(&mut self, block: BlockId, index: usize) {
+        let position = Position::Inst(self.id, block, index);
+        let function = self.function;
+        let inst = &function.blocks[block].insts[index];
+        let operands = inst.kind.operands();
+        let tys: Vec<Option<Type>> = operands
+            .iter()
+            .map(|&value| self.use_value(position, value, block, index))
+            .collect();
+        // Types are only checked once every operand is defined where it's used.
+        if tys.iter().any(Option::is_none) {
+            return;
+        }
+        let ty_of = |i: usize| tys[i].clone().expect("operands are defined");
+
+        let result = match &inst.kind {
+            InstKind::Const(value) => Some(match value {
+                Const::Int(_) => Type::Int,
+                Const::Float(_) => Type::Float,
+                Const::Bool(_) => Type::Bool,
+                Const::Char(_) => Type::Char,
+                Const::Str(_) => Type::Str,
+                Const::Void => Type::Void,
+            }),
+            InstKind::Unary(op, value) => {
+                let ty = ty_of(0);
+                let (allowed, result) = match op {
+                    UnaryOp::Neg => (matches!(ty, Type::Int | Type::Float), ty.clone()),
+                    UnaryOp::Not => (ty == Type::Bool, Type::Bool),
+                };
+                if !allowed {
+                    self.error(
+                        position,
+                        format!(
+                            "`{op}` can't be applied to `%{value}` of type `{}`.",
+                            self.ty_str(&ty)
+                        ),
+                    );
+                }
+                Some(result)
+            }
+            InstKind::Binary(op, a, _) => {
+                let ty = ty_of(0);
+                self.operand(position, operands[1], Some(&ty_of(1)), &ty);
+                let allowed = match op {
+                    BinaryOp::Add => matches!(ty, Type::Int | Type::Float | Type::Str),
+                    BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+                        matches!(ty, Type::Int | Type::Float)
+                    }
+                    BinaryOp::Shl | BinaryOp::Shr => ty == Type::Int,
+                };
+                if !allowed {
+                    self.error(
+                        position,
+                        format!(
+                            "`{op}` can't be applied to `%{a}` of type `{}`.",
+                            self.ty_str(&ty)
+                        ),
+                    );
+                }
+                Some(ty)
+            }
+            InstKind::Compare(op, a, _) => {
+                let ty = ty_of(0);
+                self.operand(position, operands[1], Some(&ty_of(1)), &ty);
+                if !matches!(
+                    ty,
+                    Type::Bool | Type::Int | Type::Float | Type::Char | Type::Str
+                ) {
+                    self.error(
+                        position,
+                        format!(
+                            "`{op}` can't be applied to `%{a}` of type `{}`.",
+                            self.ty_str(&ty)
+                        ),
+                    );
+                }
+                Some(Type::Bool)
+            }
+            InstKind::Alloca(ty) => Some(Type::Ptr(Box::new(ty.clone()))),
+            InstKind::Load(address) => self.pointee(position, *address, &ty_of(0)),
+            InstKind::Store(address, value) => {
+                if let Some(pointee) = self.pointee(position, *address, &ty_of(0)) {
+                    self.operand(position, *value, Some(&ty_of(1)), &pointee);
+                }
+                Some(Type::Void)
+            }
+            InstKind::Call(func, args) => match self.module.functions.get(*func) {
+                Some(callee) => {
+                    let params: Vec<Type> = callee
+                        .params()
+                        .iter()
+                        .map(|&param| value_type(callee, param))
+                        .collect();
+                    let name = format!("`@{}`", name_str(&callee.name));
+                    self.arguments(position, &name, args, &tys, &params);
+                    Some(callee.ret.clone())
+                }
+                None => {
+                    self.error(position, format!("There is no function with id {func}."));
+                    None
+                }
+            },
+            InstKind::CallNative(native, args) => {
+                if args.len() != native.params.len() {
+                    let message = format!(
+                        "`{}` takes {} but {} given.",
+                        native.name,
+                        plural(native.params.len(), "argument"),
+                        were(args.len())
+                    );
+                    self.error(position, message);
+                } else {
+                    for ((&arg, ty), param) in args.iter().zip(&tys).zip(native.params) {
+                        if let Some(param) = native_type(param) {
+                            self.operand(position, arg, ty.as_ref(), &param);
+                        }
+                    }
+                }
+                native_type(native.ret)
+            }
+            InstKind::CallIndirect(closure, args) => match ty_of(0) {
+                Type::Fn(fn_ty) => {
+                    self.arguments(
+                        position,
+                        &format!("`%{closure}`"),
+                        args,
+                        &tys[1..],
+                        &fn_ty.params,
+                    );
+                    Some(fn_ty.ret.clone())
+                }
+                ty => {
+                    self.error(
+                        position,
+                        format!(
+                            "`%{closure}` of type `{}` can't be called.",
+                            self.ty_str(&ty)
+                        ),
+                    );
+                    None
+                }
+            },
+            InstKind::Closure(func, env) => match self.module.functions.get(*func) {
+                Some(callee) => match callee.params().split_first() {
+                    Some((&first, rest)) => {
+                        self.operand(position, *env, Some(&ty_of(0)), &value_type(callee, first));
+                        Some(Type::Fn(Box::new(FnType {
+                            params: rest.iter().map(|&p| value_type(callee, p)).collect(),
+                            ret: callee.ret.clone(),
+                        })))
+                    }
+                    None => {
+                        self.error(
+                            position,
+                            format!(
+                                "`@{}` takes no environment, so it can't be a closure.",
+                                name_str(&callee.name)
+                            ),
+                        );
+                        None
+                    }
+                },
+                None => {
+                    self.error(position, format!("There is no function with id {func}."));
+                    None
+                }
+            },
+            InstKind::Count(func, _) => {
+                if *func >= self.module.functions.len() {
+                    self.error(position, format!("There is no function with id {func}."));
+                }
+                Some(Type::Void)
+            }
+            InstKind::New(adt, fields, _) => match self.module.adts.get(*adt).map(|a| &a.kind) {
+                Some(AdtKind::Struct(decls)) => {
+                    let params: Vec<Type> = decls.iter().map(|f| f.ty.clone()).collect();
+                    let name = format!("`{}`", self.ty_str(&Type::Adt(*adt)));
+                    self.arguments(position, &name, fields, &tys, &params);
+                    Some(Type::Adt(*adt))
+                }
+                _ => {
+                    self.error(position, format!("There is no struct with id {adt}."));
+                    None
+                }
+            },
+            InstKind::GetField(value, field) => self.field(position, *value, &ty_of(0), *field),
+            InstKind::SetField(value, field, _) => {
+                if let Some(field_ty) = self.field(position, *value, &ty_of(0), *field) {
+                    self.operand(position, operands[1], Some(&ty_of(1)), &field_ty);
+                }
+                Some(Type::Void)
+            }
+            InstKind::Variant(adt, variant, fields) => {
+                match self.variant(position, &Type::Adt(*adt), *variant) {
+                    Some(decl) => {
+                        let name = format!(
+                            "`{}::{}`",
+                            self.ty_str(&Type::Adt(*adt)),
+                            name_str(&decl.name)
+                        );
+                        self.arguments(position, &name, fields, &tys, &decl.fields);
+                        Some(Type::Adt(*adt))
+                    }
+                    None => None,
+                }
+            }
+            InstKind::Tag(value) => {
+                let ty = ty_of(0);
+                let is_enum = match ty {
+                    Type::Adt(adt) => self
+                        .module
+                        .adts
+                        .get(adt)
+                        .is_some_and(|adt| matches!(adt.kind, AdtKind::Enum(_))),
+                    _ => false,
+                };
+                if !is_enum {
+                    self.error(
+                        position,
+                        format!("`%{value}` of type `{}` isn't an enum.", self.ty_str(&ty)),
+                    );
+                }
+                Some(Type::Int)
+            }
+            InstKind::Payload(_, variant, field) => {
+                match self.variant(position, &ty_of(0), *variant) {
+                    Some(decl) => match decl.fields.get(*field) {
+                        Some(ty) => Some(ty.clone()),
+                        None => {
+                            let message = format!(
+                                "The variant `{}` has no field {field}.",
+                                name_str(&decl.name)
+                            );
+                            self.error(position, message);
+                            None
+                        }
+                    },
+                    None => None,
+                }
+            }
+            InstKind::Tuple(_) => Some(Type::Tuple(tys.iter().flatten().cloned().collect())),
+            InstKind::Extract(value, index) => match ty_of(0) {
+                Type::Tuple(elems) if *index < elems.len() => Some(elems[*index].clone()),
+                ty => {
+                    self.error(
+                        position,
+                        format!(
+                            "`%{value}` of type `{}` has no element {index}.",
+                            self.ty_str(&ty)
+                        ),
+                    );
+                    None
+                }
+            },
+            InstKind::Array(elems, _) => match value_type(function, inst.result) {
+                Type::Array(elem) => {
+                    for (&value, ty) in elems.iter().zip(&tys) {
+                        self.operand(position, value, ty.as_ref(), &elem);
+                    }
+                    None
+                }
+                ty => {
+                    let message = format!(
+                        "The instruction gives an array, but `%{}` is declared as `{}`.",
+                        inst.result,
+                        self.ty_str(&ty)
+                    );
+                    self.error(position, message);
+                    None
+                }
+            },
+        };
+
+        // The declared type of the result is checked against the one the instruction gives.
+        let Some(declared) = function.values.get(inst.result) else {
+            return;
+        };
+        if let Some(result) = result.filter(|result| result != declared) {
+            let message = format!(
+                "The instruction gives `{}`, but `%{}` is declared as `{}`.",
+                self.ty_str(&result),
+                inst.result,
+                self.ty_str(declared)
+            );
+            self.error(position, message);
+        }
+    }
+
+    /// The type a value of the pointer type points to.
+    fn pointee(&mut self, position: Position, address: ValueId, ty: &Type) -> Option<Type> {
+        match ty {
+            Type::Ptr(pointee) => Some((**pointee).clone()),
+            _ => {
+                let message = format!(
+                    "`%{address}` of type `{}` isn't a pointer.",
+                    self.ty_str(ty)
+                );
+                self.error(position, message);
+                None
+            }
+        }
+    }
+
+    /// The type of a field of the struct type.
+    fn field(
+        &mut self,
+        position: Position,
+        value: ValueId,
+        ty: &Type,
+        field: usize,
+    ) -> Option<Type> {
+        let fields = match ty {
+            Type::Adt(adt) => match self.module.adts.get(*adt).map(|adt| &adt.kind) {
+                Some(AdtKind::Struct(fields)) => Some(fields),
+                _ => None,
+            },
+            _ => None,
+        };
+        match fields.map(|fields| fields.get(field)) {
+            Some(Some(decl)) => Some(decl.ty.clone()),
+            Some(None) => {
+                let message = format!("`{}` has no field {field}.", self.ty_str(ty));
+                self.error(position, message);
+                None
+            }
+            None => {
+                let message = format!("`%{value}` of type `{}` isn't a struct.", self.ty_str(ty));
+                self.error(position, message);
+                None
+            }
+        }
+    }
+
+    /// The variant of the enum type.
+    fn variant(&mut self, position: Position, ty: &Type, variant: usize) -> Option<&'a Variant> {
+        let module = self.module;
+        let variants = match ty {
+            Type::Adt(adt) => match module.adts.get(*adt).map(|adt| &adt.kind) {
+                Some(AdtKind::Enum(variants)) => Some(variants),
+                _ => None,
+            },
+            _ => None,
+        };
+        match variants.map(|variants| variants.get(variant)) {
+            Some(Some(decl)) => Some(decl),
+            Some(None) => {
+                let message = format!("`{}` has no variant {variant}.", self.ty_str(ty));
+                self.error(position, message);
+                None
+            }
+            None => {
+                let message = format!("`{}` isn't an enum.", self.ty_str(ty));
+                self.error(position, message);
+                None
+            }
+        }
+    }
+
+    fn terminator(&mut self, block: BlockId) {
+        let position = Position::Terminator(self.id, block);
+        let function = self.function;
+        let end = function.blocks[block].insts.len();
+        match &function.blocks[block].term {
+            Terminator::Jump(target) => self.target(position, block, target),
+            Terminator::Branch {
+                cond,
+                then_target,
+                else_target,
+            } => {
+                let ty = self.use_value(position, *cond, block, end);
+                self.operand(position, *cond, ty.as_ref(), &Type::Bool);
+                self.target(position, block, then_target);
+                self.target(position, block, else_target);
+            }
+            Terminator::Return(Some(value)) => {
+                let ty = self.use_value(position, *value, block, end);
+                self.operand(position, *value, ty.as_ref(), &function.ret);
+            }
+            Terminator::Return(None) if function.ret != Type::Void => {
+                let message = format!(
+                    "`return` needs a value of type `{}`.",
+                    self.ty_str(&function.ret)
+                );
+                self.error(position, message);
+            }
+            Terminator::Return(None) | Terminator::Unreachable => {}
+        }
+    }
+
+    /// Check the arguments passed to a target at the end of the block.
+    fn target(&mut self, position: Position, block: BlockId, target: &Target) {
+        let end = self.function.blocks[block].insts.len();
+        let tys: Vec<Option<Type>> = target
+            .args
+            .iter()
+            .map(|&arg| self.use_value(position, arg, block, end))
+            .collect();
+        let params: Vec<Type> = self.function.blocks[target.block]
+            .params
+            .iter()
+            .map(|&param| value_type(self.function, param))
+            .collect();
+        let name = format!("`bb{}`", target.block);
+        self.arguments(position, &name, &target.args, &tys, &params);
+    }
+}
+
+/// The type of a value, or `void` for a value without one, which is reported where it's defined.
+fn value_type(function: &Function, value: ValueId) -> Type {
+    function.values.get(value).cloned().unwrap_or(Type::Void)
+}
+
+/// The PIR type of a type written in the signature of a native function, or `None` for a generic
+/// parameter.
+fn native_type(name: &str) -> Option<Type> {
+    match name {
+        "void" | "never" => Some(Type::Void),
+        "bool" => Some(Type::Bool),
+        "int" => Some(Type::Int),
+        "float" => Some(Type::Float),
+        "char" => Some(Type::Char),
+        "str" => Some(Type::Str),
+        _ => None,
+    }
+}
+
+/// `1 was` or `2 were`, as in "takes 1 argument but 2 were given".
+fn were(n: usize) -> String {
+    if n == 1 {
+        "1 was".to_string()
+    } else {
+        format!("{n} were")
+    }
+}
+
+fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("1 {word}")
+    } else {
+        format!("{n} {word}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pir::lower::lower;
+    use crate::pir::parser::parse_module_with_spans;
+    use crate::pir::printer::print_module_with_spans;
+    use crate::sema::modules::ModuleLoader;
+    use std::path::Path;
+
+    fn errors(src: &str) -> Vec<String> {
+        let (module, spans) = parse_module_with_spans(None, src).unwrap();
+        verify(&module)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.locate(&spans).generate_error_message(src))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_lowered_programs() {
+        for fixture in ["arrays", "consts", "either_option", "patterns", "traits"] {
+            let path = format!("tests/fixtures/{fixture}.paca");
+            let graph = ModuleLoader::new(vec![])
+                .load(Path::new(&path))
+                .unwrap_or_else(|e| panic!("{}", e.message()));
+            let (analysis, diagnostics) = crate::sema::analyze(&graph);
+            let analysis = analysis.unwrap_or_else(|| panic!("{diagnostics:?}"));
+            let module = lower(&graph, &analysis);
+            assert_eq!(verify(&module), Ok(()), "{fixture}");
+        }
+    }
+
+    #[test]
+    fn reports_violations_at_their_text() {
+        let errors = errors(
+            "struct P {\n    x: int,\n}\n\n\
+             def f(%0: int, %1: P) int {\nbb0:\n    %2: bool = lt %0, %0\n    \
+             branch %2, bb1, bb2(%0, %0)\n\
+             bb1:\n    %3: float = get_field %1, x\n    %4: int = add %0, %5\n    jump bb2(%4)\n\
+             bb2(%5: int):\n    %6: int = call @f(%5)\n    return %3\n}\n",
+        );
+        let expected = [
+            (8, "`bb2` takes 1 argument but 2 were given."),
+            (
+                10,
+                "The instruction gives `int`, but `%3` is declared as `float`.",
+            ),
+            (11, "The definition of `%5` doesn't dominate this use."),
+            (14, "`@f` takes 2 arguments but 1 was given."),
+            (15, "The definition of `%3` doesn't dominate this use."),
+        ];
+        assert_eq!(errors.len(), expected.len(), "{errors:#?}");
+        for (error, (line, message)) in errors.iter().zip(expected) {
+            assert!(
+                error.starts_with(&format!("Error at unknown:{line}:5:")),
+                "{error}"
+            );
+            assert!(
+                error.ends_with(&format!("IR Error in `f`: {message}")),
+                "{error}"
+            );
+        }
+    }
+
+    #[test]
+    fn locates_errors_in_printed_modules() {
+        let mut function = Function::new("main", vec![], Type::Void);
+        let value = function.push(0, InstKind::Const(Const::Int(1)), Type::Int);
+        function.push(0, InstKind::Unary(UnaryOp::Not, value), Type::Bool);
+        function.blocks[0].term = Terminator::Return(Some(value));
+        let module = Module {
+            adts: Vec::new(),
+            functions: vec![function],
+        };
+        let (text, spans) = print_module_with_spans(&module);
+        let errors: Vec<String> = verify(&module)
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.locate(&spans).generate_error_message(&text))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "Error at unknown:4:5:49\n\n    %1: bool = not %0\n    ^^^^^^^^^^^^^^^^^\n\
+                 IR Error in `main`: `not` can't be applied to `%0` of type `int`.",
+                "Error at unknown:5:5:71\n\n    return %0\n    ^^^^^^^^^\n\
+                 IR Error in `main`: Expected `%0` to be `void`, but it is `int`.",
+            ]
+        );
+    }
+}
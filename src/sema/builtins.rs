@@ -0,0 +1,154 @@
+//! The primitive types and native functions every module can use without importing them.
+
+/// The types built into the language.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Primitive {
+    Int,
+    Float,
+    Str,
+    Char,
+    Bool,
+    Void,
+    /// The type of calls that never return, such as `panic`. It fits wherever a value is expected.
+    Never,
+}
+
+impl Primitive {
+    pub const ALL: [Primitive; 7] = [
+        Primitive::Int,
+        Primitive::Float,
+        Primitive::Str,
+        Primitive::Char,
+        Primitive::Bool,
+        Primitive::Void,
+        Primitive::Never,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Primitive::Int => "int",
+            Primitive::Float => "float",
+            Primitive::Str => "str",
+            Primitive::Char => "char",
+            Primitive::Bool => "bool",
+            Primitive::Void => "void",
+            Primitive::Never => "never",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// A function implemented by the runtime rather than in Paca.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Native {
+    /// The std module declaring the native with `@native`, `None` for the natives every module can
+    /// use without importing them.
+    pub module: Option<&'static str>,
+    pub name: &'static str,
+    /// The types of the parameters, written as in Paca source code. Names that aren't primitive types
+    /// are generic parameters, which the std natives also use for the types their declarations
+    /// spell out.
+    pub params: &'static [&'static str],
+    pub ret: &'static str,
+}
+
+pub const NATIVES: [Native; 10] = [
+    Native {
+        module: None,
+        name: "print",
+        params: &["T"],
+        ret: "void",
+    },
+    Native {
+        module: None,
+        name: "println",
+        params: &["T"],
+        ret: "void",
+    },
+    Native {
+        module: None,
+        name: "panic",
+        params: &["str"],
+        ret: "never",
+    },
+    Native {
+        module: Some("std::fs"),
+        name: "read_file",
+        params: &["str"],
+        ret: "Either",
+    },
+    Native {
+        module: Some("std::collections"),
+        name: "array_length",
+        params: &["Array"],
+        ret: "int",
+    },
+    Native {
+        module: Some("std::collections"),
+        name: "array_get",
+        params: &["Array", "int"],
+        ret: "Option",
+    },
+    Native {
+        module: Some("std::collections"),
+        name: "array_push",
+        params: &["Array", "T"],
+        ret: "void",
+    },
+    Native {
+        module: Some("std::collections"),
+        name: "array_pop",
+        params: &["Array"],
+        ret: "Option",
+    },
+    Native {
+        module: Some("std::hash"),
+        name: "hash",
+        params: &["T"],
+        ret: "int",
+    },
+    Native {
+        module: Some("std::iter"),
+        name: "continuation",
+        params: &["F"],
+        ret: "F",
+    },
+];
+
+pub fn native(name: &str) -> Option<&'static Native> {
+    NATIVES.iter().find(|n| n.name == name)
+}
+
+/// The source of the prelude, the module whose exports are visible in every other module.
+pub const PRELUDE: &str = include_str!("prelude.paca");
+
+/// The name the prelude is loaded under.
+pub const PRELUDE_NAME: &str = "prelude";
+
+/// The sources of the std modules, which the loader falls back to when no source root has them.
+pub const STD: [(&str, &str); 10] = [
+    ("std::cmp", include_str!("std/cmp.paca")),
+    ("std::collections", include_str!("std/collections.paca")),
+    (
+        "std::collections::tuple",
+        include_str!("std/collections/tuple.paca"),
+    ),
+    ("std::convert", include_str!("std/convert.paca")),
+    ("std::fmt", include_str!("std/fmt.paca")),
+    ("std::fs", include_str!("std/fs.paca")),
+    ("std::hash", include_str!("std/hash.paca")),
+    ("std::io", include_str!("std/io.paca")),
+    ("std::iter", include_str!("std/iter.paca")),
+    ("std::ops", include_str!("std/ops.paca")),
+];
+
+/// The source of the std module with the given path, e.g. `["std", "io"]`.
+pub fn std_module(name: &[String]) -> Option<&'static str> {
+    let name = name.join("::");
+    STD.iter()
+        .find(|(path, _)| *path == name)
+        .map(|(_, source)| *source)
+}
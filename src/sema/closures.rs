@@ -0,0 +1,485 @@
+//! Capture analysis of closures, deciding how each one is lowered.
+//!
+//! A closure becomes a function that takes a pointer to its environment before its own parameters,
+//! and a closure value is the pair of that function pointer and the environment. The environment is
+//! a struct with a field per variable the closure uses from outside: a copy of the value, or the
+//! address of the variable when the closure assigns to it, so that `exit = true` in
+//! `Array::for_each(entries) { (quit, entry) => exit = true; }` is seen by the enclosing function.
+//!
+//! Pointing into the frame of the enclosing function is only sound while that frame lives. Closures
+//! are only written as trailing arguments, so they reach other code through function-typed
+//! parameters, and those don't escape: a function may call them or pass them on to another call, but
+//! not store or return them. Neither a closure nor such a parameter may be passed where the callee
+//! declares a generic parameter, e.g. `id<T>(x: T) T` or `array_push`, as the callee could return
+//! or store it through the generic type.
+//!
+//! A parameter that never returns, such as `quit`, can be a continuation from `std::iter`: calling
+//! it runs the closure the iterator wrapped with `continuation`, e.g. one setting a flag the loop
+//! checks, and then returns from the calling closure like `return;`.
+
+use super::modules::{ModuleGraph, ModuleId};
+use super::resolve::{LocalId, Res, Resolutions};
+use super::ty::{FnTy, Ty};
+use super::typeck::{AdtKind, TypeckResults};
+use super::Diagnostic;
+use crate::parse::ast::{Expr, ExprKind, FnDecl, Ident, Pattern, Stmt, StmtKind};
+use crate::parse::visit::{walk, Visitor};
+use std::collections::{HashMap, HashSet};
+
+/// A variable a closure can capture.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Var {
+    Local(LocalId),
+    SelfValue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// The environment holds a copy of the value taken when the closure is created.
+    ByValue,
+    /// The environment holds the address of the variable, which the closure assigns to.
+    ByRef,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capture {
+    pub var: Var,
+    pub mode: CaptureMode,
+    /// The type of the variable, which is behind a pointer for `ByRef` captures.
+    pub ty: Ty,
+}
+
+/// A closure lowered to an environment struct and a function pointer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClosureLayout {
+    /// The fields of the environment, in the order the closure first uses them.
+    pub captures: Vec<Capture>,
+    /// The type of the function without the environment parameter.
+    pub fn_ty: FnTy,
+}
+
+/// The layout of every closure of the program.
+#[derive(Debug, Default)]
+pub struct Closures {
+    layouts: HashMap<(ModuleId, usize), ClosureLayout>,
+}
+
+impl Closures {
+    pub fn get(&self, module: ModuleId, expr: &Expr) -> Option<&ClosureLayout> {
+        self.layouts.get(&(module, expr.loc.offset))
+    }
+}
+
+/// Decide the captures of every closure and check that function-typed parameters don't escape.
+pub fn analyze(
+    graph: &ModuleGraph,
+    res: &Resolutions,
+    results: &TypeckResults,
+) -> (Closures, Vec<Diagnostic>) {
+    let mut analyzer = CaptureAnalyzer {
+        module: 0,
+        res,
+        results,
+        open: Vec::new(),
+        fn_params: HashSet::new(),
+        in_pattern: false,
+        closures: Closures::default(),
+        diagnostics: Vec::new(),
+    };
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        analyzer.module = module;
+        analyzer.visit_module(&loaded.ast);
+    }
+    (analyzer.closures, analyzer.diagnostics)
+}
+
+/// A closure whose body is being walked.
+struct OpenClosure {
+    /// The locals declared inside the closure, including its parameters.
+    declared: HashSet<LocalId>,
+    captures: Vec<Capture>,
+}
+
+struct CaptureAnalyzer<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    results: &'a TypeckResults,
+    /// The closures around the current point, innermost last.
+    open: Vec<OpenClosure>,
+    /// The function-typed parameters of the current function and its closures.
+    fn_params: HashSet<LocalId>,
+    /// Whether the identifiers being visited are bindings of a pattern.
+    in_pattern: bool,
+    closures: Closures,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> CaptureAnalyzer<'a> {
+    /// Record a use of a variable in the closures it's captured by: those between the use and the
+    /// declaration of the variable.
+    fn use_var(&mut self, var: Var, ty: Ty, mode: CaptureMode) {
+        for closure in self.open.iter_mut().rev() {
+            if matches!(var, Var::Local(local) if closure.declared.contains(&local)) {
+                return;
+            }
+            match closure.captures.iter_mut().find(|c| c.var == var) {
+                Some(capture) if mode == CaptureMode::ByRef => capture.mode = mode,
+                Some(_) => {}
+                None => closure.captures.push(Capture {
+                    var,
+                    mode,
+                    ty: ty.clone(),
+                }),
+            }
+        }
+    }
+
+    fn local(&self, ident: &Ident) -> Option<LocalId> {
+        match self.res.get(self.module, ident) {
+            Some(Res::Local(local)) => Some(local),
+            _ => None,
+        }
+    }
+
+    /// The variable an expression names, if it's `self` or a plain local.
+    fn var<'e>(&self, expr: &'e Expr) -> Option<(Var, Option<&'e Ident>)> {
+        match &expr.kind {
+            ExprKind::SelfValue => Some((Var::SelfValue, None)),
+            ExprKind::Path(path) => match path.segments.as_slice() {
+                [segment] => self
+                    .local(&segment.ident)
+                    .map(|local| (Var::Local(local), Some(&segment.ident))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn var_ty(&self, var: Var, expr: &Expr) -> Ty {
+        match var {
+            Var::Local(local) => self.results.locals[local].clone(),
+            Var::SelfValue => self
+                .results
+                .expr_ty(self.module, expr)
+                .cloned()
+                .unwrap_or(Ty::Error),
+        }
+    }
+
+    /// Visit an expression in a position where a function-typed parameter may appear: the callee
+    /// or an argument of a call.
+    fn visit_callable(&mut self, expr: &Expr) {
+        match self.var(expr) {
+            Some((var, _)) => {
+                let ty = self.var_ty(var, expr);
+                self.use_var(var, ty, CaptureMode::ByValue);
+            }
+            None => self.visit_expr(expr),
+        }
+    }
+
+    /// The declared types of the parameters of the function a callee or a method call names, after
+    /// the arguments that aren't among them, `self` when a method is called by its path, and whether
+    /// the last parameter is variadic.
+    fn declared_params(&self, expr: &Expr) -> Option<(usize, &'a [Ty], bool)> {
+        let items = &self.results.items;
+        let method_call = matches!(expr.kind, ExprKind::MethodCall { .. });
+        let sig = if let Some(id) = self.results.method(self.module, expr) {
+            &items.method(id).sig
+        } else if let Some((id, _)) = self.results.trait_call(self.module, expr) {
+            &items.trait_method(*id).sig
+        } else {
+            let ExprKind::Path(path) = &expr.kind else {
+                return None;
+            };
+            let last = path.segments.last()?;
+            return match self.res.get(self.module, &last.ident)? {
+                Res::Def(def) => items
+                    .fns
+                    .get(&def)
+                    .map(|sig| (0, sig.params.as_slice(), sig.variadic)),
+                Res::Variant(def, index) => match &items.adts[&def].kind {
+                    AdtKind::Enum(variants) => Some((0, variants[index].fields.as_slice(), false)),
+                    AdtKind::Struct(_) => None,
+                },
+                _ => None,
+            };
+        };
+        let skipped = usize::from(sig.has_self && !method_call);
+        Some((skipped, &sig.params, sig.variadic))
+    }
+
+    /// Report a closure or a function-typed parameter passed where the callee declares a generic
+    /// parameter, through which it could store or return it.
+    fn check_generic_arg(&mut self, arg: &Expr, param: Option<&Ty>) {
+        let Some(Ty::Param(generic)) = param else {
+            return;
+        };
+        let message = match (&arg.kind, self.var(arg)) {
+            (ExprKind::Closure(_), _) => {
+                format!("Closures can't be passed as the generic `{generic}`.")
+            }
+            (_, Some((Var::Local(local), Some(ident)))) if self.fn_params.contains(&local) => {
+                format!(
+                    "`{}` is a function parameter, so it can't be passed as the generic \
+                     `{generic}`.",
+                    ident.name
+                )
+            }
+            _ => return,
+        };
+        self.diagnostics
+            .push(Diagnostic::error(message, arg.loc.clone()).with_help(
+                "the callee could store or return a value of a generic type, and closures passed \
+                 to a function only live during the call.",
+            ));
+    }
+
+    /// Visit the arguments of a call whose callee, or the method call itself, is `callee`.
+    fn visit_args(&mut self, callee: &Expr, args: &[Expr]) {
+        let params = self.declared_params(callee);
+        for (i, arg) in args.iter().enumerate() {
+            let param = params.and_then(|(skipped, params, variadic)| {
+                let i = i.checked_sub(skipped)?;
+                match params.last() {
+                    // The arguments of a variadic parameter are the elements of its array.
+                    Some(Ty::Array(elem)) if variadic && i + 1 >= params.len() => Some(&**elem),
+                    _ => params.get(i),
+                }
+            });
+            self.check_generic_arg(arg, param);
+            self.visit_callable(arg);
+        }
+    }
+
+    /// Remember the function-typed parameters a pattern binds.
+    fn fn_params(&mut self, pattern: &Pattern) {
+        let mut finder = BindingFinder {
+            module: self.module,
+            res: self.res,
+            locals: Vec::new(),
+        };
+        finder.visit_pattern(pattern);
+        for local in finder.locals {
+            if matches!(self.results.locals[local], Ty::Fn(_)) {
+                self.fn_params.insert(local);
+            }
+        }
+    }
+}
+
+impl Visitor for CaptureAnalyzer<'_> {
+    fn visit_fn_decl(&mut self, decl: &FnDecl) {
+        self.fn_params.clear();
+        for param in &decl.params {
+            self.fn_params(&param.pattern);
+        }
+        walk::walk_fn_decl(self, decl);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let StmtKind::Assign { target, .. } = &stmt.kind {
+            let mut place = target;
+            while let ExprKind::Field { base, .. } = &place.kind {
+                place = base;
+            }
+            if let Some((var, _)) = self.var(place) {
+                let ty = self.var_ty(var, place);
+                self.use_var(var, ty, CaptureMode::ByRef);
+            }
+        }
+        walk::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Some((var, ident)) = self.var(expr) {
+            if let (Var::Local(local), Some(ident)) = (var, ident) {
+                if self.fn_params.contains(&local) {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "`{}` is a function parameter, so it can only be called or passed \
+                                 to another call.",
+                                ident.name
+                            ),
+                            expr.loc.clone(),
+                        )
+                        .with_help(
+                            "closures passed to a function only live during the call, so they \
+                             can't be stored or returned.",
+                        ),
+                    );
+                }
+            }
+            let ty = self.var_ty(var, expr);
+            return self.use_var(var, ty, CaptureMode::ByValue);
+        }
+        match &expr.kind {
+            ExprKind::Call { callee, args } => {
+                self.visit_callable(callee);
+                self.visit_args(callee, args);
+            }
+            ExprKind::MethodCall { receiver, args, .. } => {
+                self.visit_expr(receiver);
+                self.visit_args(expr, args);
+            }
+            ExprKind::Closure(closure) => {
+                self.open.push(OpenClosure {
+                    declared: HashSet::new(),
+                    captures: Vec::new(),
+                });
+                for param in &closure.params {
+                    self.fn_params(&param.pattern);
+                }
+                walk::walk_closure(self, closure);
+                let OpenClosure { captures, .. } = self.open.pop().expect("pushed above");
+                let fn_ty = match self.results.expr_ty(self.module, expr) {
+                    Some(Ty::Fn(fn_ty)) => fn_ty.clone(),
+                    _ => FnTy {
+                        params: vec![Ty::Error; closure.params.len()],
+                        ret: Box::new(Ty::Error),
+                        variadic: false,
+                    },
+                };
+                self.closures.layouts.insert(
+                    (self.module, expr.loc.offset),
+                    ClosureLayout { captures, fn_ty },
+                );
+            }
+            _ => walk::walk_expr(self, expr),
+        }
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        let outer = std::mem::replace(&mut self.in_pattern, true);
+        walk::walk_pattern(self, pattern);
+        self.in_pattern = outer;
+    }
+
+    fn visit_ident(&mut self, ident: &Ident) {
+        if !self.in_pattern {
+            return;
+        }
+        if let (Some(local), Some(closure)) = (self.local(ident), self.open.last_mut()) {
+            closure.declared.insert(local);
+        }
+    }
+}
+
+/// Finds the locals a pattern binds.
+struct BindingFinder<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    locals: Vec<LocalId>,
+}
+
+impl Visitor for BindingFinder<'_> {
+    fn visit_ident(&mut self, ident: &Ident) {
+        if let Some(Res::Local(local)) = self.res.get(self.module, ident) {
+            self.locals.push(local);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::check_source;
+
+    /// The captures of each closure in source order, and the error messages.
+    fn analyze_source(src: &str) -> (Vec<Vec<String>>, Vec<String>) {
+        let (graph, res, results) = check_source(src);
+        let (closures, diagnostics) = analyze(&graph, &res, &results);
+        let mut layouts = closures
+            .layouts
+            .iter()
+            .filter(|((module, _), _)| *module == 0)
+            .collect::<Vec<_>>();
+        layouts.sort_by_key(|(key, _)| *key);
+        let captures = layouts
+            .into_iter()
+            .map(|(_, layout)| {
+                layout
+                    .captures
+                    .iter()
+                    .map(|capture| {
+                        let name = match capture.var {
+                            Var::Local(local) => res.locals[local].name.as_str(),
+                            Var::SelfValue => "self",
+                        };
+                        format!("{name}: {:?}", capture.mode)
+                    })
+                    .collect()
+            })
+            .collect();
+        (
+            captures,
+            diagnostics.into_iter().map(|d| d.message).collect(),
+        )
+    }
+
+    #[test]
+    fn decides_capture_modes() {
+        let (captures, errors) = analyze_source(
+            "def each(f: def(def() never, int) void) void {\n    return;\n}\n\n\
+             struct Counter {\n    count: int,\n}\n\n\
+             impl methods for Counter {\n    def sum(self) int {\n        \
+             let total = 0;\n        let seen = false;\n        \
+             each() { (quit, n) =>\n            total += n;\n            \
+             each() { (stop, m) =>\n                seen = true;\n                \
+             println(self->count + total + m);\n            };\n            \
+             quit();\n        };\n        \
+             return total;\n    }\n}\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            captures,
+            [
+                vec!["total: ByRef", "seen: ByRef", "self: ByValue"],
+                vec!["seen: ByRef", "self: ByValue", "total: ByValue"],
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_function_parameters_from_escaping() {
+        let (_, errors) = analyze_source(
+            "def twice(f: def(int) int) def(int) int {\n    \
+             let g = f;\n    f(f(1));\n    apply(f);\n    \
+             apply() { (x) = f(x) };\n    return f;\n}\n\n\
+             def apply(f: def(int) int) int {\n    return f(1);\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "`f` is a function parameter, so it can only be called or passed to another call.",
+                "`f` is a function parameter, so it can only be called or passed to another call.",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_closures_from_escaping_through_generics() {
+        let (_, errors) = analyze_source(
+            "import std::collections::Array;\n\n\
+             def id<T>(x: T) T {\n    return x;\n}\n\n\
+             def leak(f: def(int) int) def(int) int {\n    return id(f);\n}\n\n\
+             def store(f: def(int) int) void {\n    let fs: []def(int) int = [];\n    \
+             Array::append(fs, f);\n    Option::Some(f);\n}\n\n\
+             def keep<T>(x: T) T {\n    return x;\n}\n\n\
+             def mk() def(int) int {\n    let mut n = 1;\n    \
+             return leak() { (x) =>\n        n = n + x;\n        return n;\n    };\n}\n\n\
+             def kept() def(int) int {\n    let mut n = 1;\n    \
+             return keep() { (x) =>\n        n = n + x;\n        return n;\n    };\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "`f` is a function parameter, so it can't be passed as the generic `T`.",
+                "`f` is a function parameter, so it can't be passed as the generic `T`.",
+                "`f` is a function parameter, so it can't be passed as the generic `T`.",
+                "Closures can't be passed as the generic `T`.",
+            ]
+        );
+    }
+}
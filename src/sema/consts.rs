@@ -0,0 +1,788 @@
+//! Compile-time evaluation of constants and enum discriminants.
+//!
+//! The value of `const MAX: int = 1 << 10;` is computed by walking the AST of its expression. It may
+//! use literals, operators, tuples, other constants and calls to functions whose bodies only do the
+//! same, with `let`, assignments, `if`, `while` and `match` on plain values. Natives, methods,
+//! structs, arrays and closures are left to run time.
+//!
+//! Integer arithmetic is checked: an overflow or a division by zero is an error at the operator,
+//! whose span covers the operands down to the literals they come from.
+
+use super::modules::{ModuleGraph, ModuleId};
+use super::resolve::{DefId, LocalId, Res, Resolutions};
+use super::Diagnostic;
+use crate::parse::ast::*;
+use crate::parse::SourceCodeLocation;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// How deep calls may nest while evaluating a constant.
+const MAX_DEPTH: usize = 256;
+/// How many loop iterations and calls evaluating a constant may take.
+const MAX_STEPS: usize = 1_000_000;
+
+/// The value of a constant.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Tuple(Vec<ConstValue>),
+    Void,
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstValue::Int(n) => write!(f, "{n}"),
+            ConstValue::Float(n) => write!(f, "{n:?}"),
+            ConstValue::Bool(b) => write!(f, "{b}"),
+            ConstValue::Char(c) => write!(f, "{c:?}"),
+            ConstValue::Str(s) => write!(f, "{s:?}"),
+            ConstValue::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                if values.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            ConstValue::Void => write!(f, "void"),
+        }
+    }
+}
+
+/// The values of the constants and the discriminants of the enums of a program.
+#[derive(Debug, Default)]
+pub struct ConstValues {
+    values: HashMap<DefId, ConstValue>,
+    /// The discriminant of every variant of every enum, counting up from the previous one when it
+    /// isn't set.
+    discriminants: HashMap<DefId, Vec<i64>>,
+}
+
+impl ConstValues {
+    pub fn get(&self, def: DefId) -> Option<&ConstValue> {
+        self.values.get(&def)
+    }
+
+    pub fn discriminants(&self, def: DefId) -> Option<&[i64]> {
+        self.discriminants.get(&def).map(Vec::as_slice)
+    }
+}
+
+/// Evaluate every constant and discriminant of the program.
+pub fn evaluate(graph: &ModuleGraph, res: &Resolutions) -> (ConstValues, Vec<Diagnostic>) {
+    let mut evaluator = Evaluator {
+        graph,
+        res,
+        module: 0,
+        consts: ConstValues::default(),
+        evaluating: Vec::new(),
+        broken: HashSet::new(),
+        depth: 0,
+        steps: 0,
+        diagnostics: Vec::new(),
+    };
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        for (item, declaration) in loaded.ast.items.iter().enumerate() {
+            let def = DefId { module, item };
+            match &declaration.kind {
+                ItemKind::Const(_) => {
+                    // Failures are reported by `constant`, and dependents are marked broken.
+                    let _ = evaluator.constant(def);
+                }
+                ItemKind::Enum(decl) => evaluator.discriminants(def, decl),
+                _ => {}
+            }
+        }
+    }
+    (evaluator.consts, evaluator.diagnostics)
+}
+
+/// Why evaluation stopped before producing a value.
+enum Stop {
+    Error(Diagnostic),
+    /// A constant it depends on is already reported as broken.
+    Broken,
+}
+
+type Eval<T> = Result<T, Stop>;
+
+fn error<T>(message: impl Into<String>, loc: &SourceCodeLocation) -> Eval<T> {
+    Err(Stop::Error(Diagnostic::error(message, loc.clone())))
+}
+
+/// How a statement completes.
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(ConstValue),
+}
+
+struct Evaluator<'a> {
+    graph: &'a ModuleGraph,
+    res: &'a Resolutions,
+    /// The module of the code being evaluated.
+    module: ModuleId,
+    consts: ConstValues,
+    /// The constants whose evaluation is under way, to report cycles.
+    evaluating: Vec<DefId>,
+    broken: HashSet<DefId>,
+    depth: usize,
+    steps: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Evaluator<'_> {
+    /// Evaluate an expression at the top level of a constant or a discriminant, reporting what
+    /// stops it.
+    fn top_level(
+        &mut self,
+        expr: &Expr,
+        name: &str,
+        decl: &SourceCodeLocation,
+    ) -> Option<ConstValue> {
+        self.depth = 0;
+        self.steps = 0;
+        match self.expr(expr, &mut HashMap::new()) {
+            Ok(value) => Some(value),
+            Err(Stop::Error(mut diagnostic)) => {
+                // Errors inside the functions a constant calls point at those functions.
+                let inside = diagnostic.loc.filename == decl.filename
+                    && (decl.offset..decl.offset + decl.length).contains(&diagnostic.loc.offset);
+                if !inside && diagnostic.help.is_none() {
+                    diagnostic =
+                        diagnostic.with_help(format!("this is reached while evaluating `{name}`."));
+                }
+                self.diagnostics.push(diagnostic);
+                None
+            }
+            Err(Stop::Broken) => None,
+        }
+    }
+
+    fn constant(&mut self, def: DefId) -> Eval<ConstValue> {
+        if let Some(value) = self.consts.values.get(&def) {
+            return Ok(value.clone());
+        }
+        if self.broken.contains(&def) {
+            return Err(Stop::Broken);
+        }
+        let declaration = &self.graph.modules[def.module].ast.items[def.item];
+        let ItemKind::Const(decl) = &declaration.kind else {
+            unreachable!("only constants are evaluated by definition")
+        };
+        if let Some(start) = self.evaluating.iter().position(|&d| d == def) {
+            let names = self.evaluating[start..]
+                .iter()
+                .chain([&def])
+                .map(|&d| format!("`{}`", self.const_name(d)))
+                .collect::<Vec<_>>();
+            return error(
+                format!(
+                    "The value of `{}` depends on itself: {}.",
+                    decl.name.name,
+                    names.join(" -> ")
+                ),
+                &decl.name.loc,
+            );
+        }
+
+        let module = std::mem::replace(&mut self.module, def.module);
+        let (depth, steps) = (self.depth, self.steps);
+        self.evaluating.push(def);
+        let value = self.top_level(&decl.value, &decl.name.name, &declaration.loc);
+        self.evaluating.pop();
+        (self.depth, self.steps) = (depth, steps);
+        self.module = module;
+
+        match value {
+            Some(value) => {
+                self.consts.values.insert(def, value.clone());
+                Ok(value)
+            }
+            None => {
+                self.broken.insert(def);
+                Err(Stop::Broken)
+            }
+        }
+    }
+
+    fn const_name(&self, def: DefId) -> &str {
+        match &self.graph.modules[def.module].ast.items[def.item].kind {
+            ItemKind::Const(decl) => &decl.name.name,
+            _ => "",
+        }
+    }
+
+    fn discriminants(&mut self, def: DefId, decl: &EnumDecl) {
+        self.module = def.module;
+        let loc = &self.graph.modules[def.module].ast.items[def.item].loc;
+        let mut discriminants: Vec<i64> = Vec::new();
+        for variant in &decl.variants {
+            let value = match (&variant.discriminant, discriminants.last()) {
+                (Some(expr), _) => {
+                    match self.top_level(
+                        expr,
+                        &format!("{}::{}", decl.name.name, variant.name.name),
+                        loc,
+                    ) {
+                        Some(ConstValue::Int(n)) => n,
+                        _ => return,
+                    }
+                }
+                (None, None) => 0,
+                (None, Some(&previous)) => match previous.checked_add(1) {
+                    Some(n) => n,
+                    None => {
+                        self.diagnostics.push(
+                            Diagnostic::error(
+                                format!(
+                                    "The discriminant of `{}` overflows `int`: it comes after {previous}.",
+                                    variant.name.name
+                                ),
+                                variant.name.loc.clone(),
+                            )
+                            .with_help("give it a discriminant of its own with `= value`."),
+                        );
+                        return;
+                    }
+                },
+            };
+            if let Some(index) = discriminants.iter().position(|&d| d == value) {
+                self.diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` has the same discriminant as `{}`: {value}.",
+                        variant.name.name, decl.variants[index].name.name
+                    ),
+                    variant.name.loc.clone(),
+                ));
+                return;
+            }
+            discriminants.push(value);
+        }
+        self.consts.discriminants.insert(def, discriminants);
+    }
+
+    fn step(&mut self, loc: &SourceCodeLocation) -> Eval<()> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return error(
+                format!(
+                    "Evaluating this constant takes more than {MAX_STEPS} steps, it may never end."
+                ),
+                loc,
+            );
+        }
+        Ok(())
+    }
+
+    // Expressions.
+
+    fn expr(&mut self, expr: &Expr, frame: &mut HashMap<LocalId, ConstValue>) -> Eval<ConstValue> {
+        match &expr.kind {
+            ExprKind::Literal(literal) => Ok(literal_value(literal)),
+            ExprKind::Path(path) => {
+                let last = path.segments.last().expect("paths have at least one segment");
+                match self.res.get(self.module, &last.ident) {
+                    Some(Res::Local(local)) => frame.get(&local).cloned().ok_or(Stop::Broken),
+                    Some(Res::Def(def))
+                        if matches!(
+                            self.graph.modules[def.module].ast.items[def.item].kind,
+                            ItemKind::Const(_)
+                        ) =>
+                    {
+                        self.constant(def)
+                    }
+                    Some(Res::Error) | None => Err(Stop::Broken),
+                    Some(_) => unsupported("Functions and enum variants", &expr.loc),
+                }
+            }
+            ExprKind::Tuple(exprs) => Ok(ConstValue::Tuple(
+                exprs
+                    .iter()
+                    .map(|e| self.expr(e, frame))
+                    .collect::<Eval<_>>()?,
+            )),
+            ExprKind::Unary { op, expr: operand } => {
+                match (op, self.expr(operand, frame)?) {
+                    (UnaryOp::Neg, ConstValue::Int(n)) => match n.checked_neg() {
+                        Some(n) => Ok(ConstValue::Int(n)),
+                        None => error(
+                            format!("Overflow while evaluating a constant: `-({n})` doesn't fit in an `int`."),
+                            &expr.loc,
+                        ),
+                    },
+                    (UnaryOp::Neg, ConstValue::Float(n)) => Ok(ConstValue::Float(-n)),
+                    (UnaryOp::Not, ConstValue::Bool(b)) => Ok(ConstValue::Bool(!b)),
+                    _ => Err(Stop::Broken),
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => {
+                let lhs_value = self.expr(lhs, frame)?;
+                match (op, &lhs_value) {
+                    (BinaryOp::And, ConstValue::Bool(false)) => return Ok(lhs_value),
+                    (BinaryOp::Or, ConstValue::Bool(true)) => return Ok(lhs_value),
+                    (BinaryOp::And | BinaryOp::Or, _) => return self.expr(rhs, frame),
+                    _ => {}
+                }
+                let rhs_value = self.expr(rhs, frame)?;
+                binary(*op, lhs_value, rhs_value, &expr.loc)
+            }
+            ExprKind::Call { callee, args } => {
+                let ExprKind::Path(path) = &callee.kind else {
+                    return unsupported("Calls of function values", &expr.loc);
+                };
+                let last = path.segments.last().expect("paths have at least one segment");
+                match self.res.get(self.module, &last.ident) {
+                    Some(Res::Def(def)) => {
+                        let args = args
+                            .iter()
+                            .map(|a| self.expr(a, frame))
+                            .collect::<Eval<Vec<_>>>()?;
+                        self.call(def, args, &expr.loc)
+                    }
+                    Some(Res::Native(native)) => {
+                        let error = Diagnostic::error(
+                            format!("`{}` can't be called while evaluating a constant.", native.name),
+                            callee.loc.clone(),
+                        )
+                        .with_help("only functions written in Paca are run at compile time.");
+                        Err(Stop::Error(error))
+                    }
+                    Some(Res::Error) | None => Err(Stop::Broken),
+                    Some(_) => unsupported("Enum variants and calls of function values", &expr.loc),
+                }
+            }
+            ExprKind::SelfValue | ExprKind::MethodCall { .. } => {
+                unsupported("Methods", &expr.loc)
+            }
+            ExprKind::Field { .. } | ExprKind::StructLit { .. } => unsupported("Structs", &expr.loc),
+            ExprKind::Array(_) => unsupported("Arrays", &expr.loc),
+            ExprKind::Closure(_) => unsupported("Closures", &expr.loc),
+        }
+    }
+
+    fn call(
+        &mut self,
+        def: DefId,
+        args: Vec<ConstValue>,
+        loc: &SourceCodeLocation,
+    ) -> Eval<ConstValue> {
+        let ItemKind::Fn(decl) = &self.graph.modules[def.module].ast.items[def.item].kind else {
+            return Err(Stop::Broken);
+        };
+        if decl.params.iter().any(|p| p.variadic) {
+            return unsupported("Variadic functions", loc);
+        }
+        let Some(body) = &decl.body else {
+            return Err(Stop::Broken);
+        };
+        self.step(loc)?;
+        if self.depth == MAX_DEPTH {
+            return error(
+                format!("Evaluating this constant nests calls more than {MAX_DEPTH} deep."),
+                loc,
+            );
+        }
+
+        let module = std::mem::replace(&mut self.module, def.module);
+        self.depth += 1;
+        let mut frame = HashMap::new();
+        let result = (|| {
+            for (param, arg) in decl.params.iter().zip(args) {
+                self.bind(&param.pattern, arg, &mut frame)?;
+            }
+            match self.block(body, &mut frame)? {
+                Flow::Return(value) => Ok(value),
+                _ => Ok(ConstValue::Void),
+            }
+        })();
+        self.depth -= 1;
+        self.module = module;
+        result
+    }
+
+    // Statements.
+
+    fn block(&mut self, block: &Block, frame: &mut HashMap<LocalId, ConstValue>) -> Eval<Flow> {
+        for stmt in &block.stmts {
+            match self.stmt(stmt, frame)? {
+                Flow::Next => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, frame: &mut HashMap<LocalId, ConstValue>) -> Eval<Flow> {
+        match &stmt.kind {
+            StmtKind::Let { pattern, value, .. } => {
+                if let Some(value) = value {
+                    let value = self.expr(value, frame)?;
+                    self.bind(pattern, value, frame)?;
+                }
+            }
+            StmtKind::Assign { target, op, value } => {
+                let ExprKind::Path(path) = &target.kind else {
+                    return unsupported("Structs", &target.loc);
+                };
+                let Some(Res::Local(local)) = self.res.get(self.module, &path.segments[0].ident)
+                else {
+                    return Err(Stop::Broken);
+                };
+                let mut value = self.expr(value, frame)?;
+                if let Some(op) = op {
+                    let current = frame.get(&local).cloned().ok_or(Stop::Broken)?;
+                    value = binary(*op, current, value, &stmt.loc)?;
+                }
+                frame.insert(local, value);
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr, frame)?;
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(value) => self.expr(value, frame)?,
+                    None => ConstValue::Void,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Continue => return Ok(Flow::Continue),
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                if self.expr(cond, frame)? == ConstValue::Bool(true) {
+                    return self.block(then_block, frame);
+                } else if let Some(else_block) = else_block {
+                    return self.block(else_block, frame);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                while self.expr(cond, frame)? == ConstValue::Bool(true) {
+                    self.step(&stmt.loc)?;
+                    match self.block(body, frame)? {
+                        Flow::Break => break,
+                        Flow::Next | Flow::Continue => {}
+                        flow => return Ok(flow),
+                    }
+                }
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                let value = self.expr(scrutinee, frame)?;
+                for arm in arms {
+                    if !self.matches(&arm.pattern, &value, frame)? {
+                        continue;
+                    }
+                    if let Some(guard) = &arm.guard {
+                        if self.expr(guard, frame)? != ConstValue::Bool(true) {
+                            continue;
+                        }
+                    }
+                    return self.stmt(&arm.body, frame);
+                }
+            }
+            StmtKind::Block(block) => return self.block(block, frame),
+        }
+        Ok(Flow::Next)
+    }
+
+    // Patterns.
+
+    /// Bind an irrefutable pattern of a `let` or a parameter.
+    fn bind(
+        &mut self,
+        pattern: &Pattern,
+        value: ConstValue,
+        frame: &mut HashMap<LocalId, ConstValue>,
+    ) -> Eval<()> {
+        if self.matches(pattern, &value, frame)? {
+            Ok(())
+        } else {
+            Err(Stop::Broken)
+        }
+    }
+
+    /// Whether the pattern matches the value, binding its names if it does.
+    fn matches(
+        &mut self,
+        pattern: &Pattern,
+        value: &ConstValue,
+        frame: &mut HashMap<LocalId, ConstValue>,
+    ) -> Eval<bool> {
+        match (&pattern.kind, value) {
+            (PatternKind::Wildcard, _) => Ok(true),
+            (PatternKind::Binding { name, sub, .. }, _) => {
+                if let Some(sub) = sub {
+                    if !self.matches(sub, value, frame)? {
+                        return Ok(false);
+                    }
+                }
+                if let Some(Res::Local(local)) = self.res.get(self.module, name) {
+                    frame.insert(local, value.clone());
+                }
+                Ok(true)
+            }
+            (PatternKind::Literal(literal), _) => Ok(literal_value(literal) == *value),
+            (
+                PatternKind::Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                _,
+            ) => {
+                let above = compare(&literal_value(start), value).is_some_and(Ordering::is_le);
+                let below = match compare(value, &literal_value(end)) {
+                    Some(Ordering::Less) => true,
+                    Some(Ordering::Equal) => *inclusive,
+                    _ => false,
+                };
+                Ok(above && below)
+            }
+            (PatternKind::Tuple(patterns), ConstValue::Tuple(values)) => {
+                let Some(rest) = patterns.iter().position(|p| p.kind == PatternKind::Rest) else {
+                    for (pattern, value) in patterns.iter().zip(values) {
+                        if !self.matches(pattern, value, frame)? {
+                            return Ok(false);
+                        }
+                    }
+                    return Ok(true);
+                };
+                let after = patterns.len() - rest - 1;
+                let pairs = patterns[..rest].iter().zip(values).chain(
+                    patterns[rest + 1..]
+                        .iter()
+                        .zip(&values[values.len() - after..]),
+                );
+                for (pattern, value) in pairs {
+                    if !self.matches(pattern, value, frame)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (PatternKind::Or(alternatives), _) => {
+                for alternative in alternatives {
+                    if self.matches(alternative, value, frame)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            _ => unsupported("Enum and struct patterns", &pattern.loc),
+        }
+    }
+}
+
+fn unsupported<T>(what: &str, loc: &SourceCodeLocation) -> Eval<T> {
+    Err(Stop::Error(
+        Diagnostic::error(
+            format!("{what} can't be evaluated at compile time."),
+            loc.clone(),
+        )
+        .with_help(
+            "constants can use literals, operators, tuples, other constants and calls to \
+                 functions that only use those.",
+        ),
+    ))
+}
+
+fn literal_value(literal: &Literal) -> ConstValue {
+    match literal {
+        Literal::Int(n) => ConstValue::Int(*n),
+        Literal::Float(n) => ConstValue::Float(*n),
+        Literal::Str(s) => ConstValue::Str(s.clone()),
+        Literal::Char(c) => ConstValue::Char(*c),
+        Literal::Bool(b) => ConstValue::Bool(*b),
+    }
+}
+
+fn compare(a: &ConstValue, b: &ConstValue) -> Option<Ordering> {
+    match (a, b) {
+        (ConstValue::Int(a), ConstValue::Int(b)) => a.partial_cmp(b),
+        (ConstValue::Float(a), ConstValue::Float(b)) => a.partial_cmp(b),
+        (ConstValue::Char(a), ConstValue::Char(b)) => a.partial_cmp(b),
+        (ConstValue::Str(a), ConstValue::Str(b)) => a.partial_cmp(b),
+        _ => None,
+    }
+}
+
+fn binary(
+    op: BinaryOp,
+    lhs: ConstValue,
+    rhs: ConstValue,
+    loc: &SourceCodeLocation,
+) -> Eval<ConstValue> {
+    let ordering = compare(&lhs, &rhs);
+    let value = match (op, lhs, rhs) {
+        (BinaryOp::Eq, lhs, rhs) => ConstValue::Bool(lhs == rhs),
+        (BinaryOp::NotEq, lhs, rhs) => ConstValue::Bool(lhs != rhs),
+        (BinaryOp::Less, ..) => ConstValue::Bool(ordering.is_some_and(Ordering::is_lt)),
+        (BinaryOp::LessEq, ..) => ConstValue::Bool(ordering.is_some_and(Ordering::is_le)),
+        (BinaryOp::Greater, ..) => ConstValue::Bool(ordering.is_some_and(Ordering::is_gt)),
+        (BinaryOp::GreaterEq, ..) => ConstValue::Bool(ordering.is_some_and(Ordering::is_ge)),
+        (BinaryOp::Add, ConstValue::Str(a), ConstValue::Str(b)) => ConstValue::Str(a + &b),
+        (op, ConstValue::Int(a), ConstValue::Int(b)) => ConstValue::Int(int_op(op, a, b, loc)?),
+        (op, ConstValue::Float(a), ConstValue::Float(b)) => ConstValue::Float(match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Rem => a % b,
+            _ => return Err(Stop::Broken),
+        }),
+        _ => return Err(Stop::Broken),
+    };
+    Ok(value)
+}
+
+/// Apply an arithmetic operator to integers, reporting overflows and divisions by zero.
+fn int_op(op: BinaryOp, a: i64, b: i64, loc: &SourceCodeLocation) -> Eval<i64> {
+    if b == 0 && matches!(op, BinaryOp::Div | BinaryOp::Rem) {
+        return error(
+            format!(
+                "Division by zero while evaluating a constant: `{a} {} 0`.",
+                op.symbol()
+            ),
+            loc,
+        );
+    }
+    let result = match op {
+        BinaryOp::Add => a.checked_add(b),
+        BinaryOp::Sub => a.checked_sub(b),
+        BinaryOp::Mul => a.checked_mul(b),
+        BinaryOp::Div => a.checked_div(b),
+        BinaryOp::Rem => a.checked_rem(b),
+        // Bits shifted out of a left shift are an overflow too.
+        BinaryOp::Shl => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_shl(b))
+            .filter(|shifted| shifted >> b == a),
+        BinaryOp::Shr => u32::try_from(b).ok().and_then(|b| a.checked_shr(b)),
+        _ => return Err(Stop::Broken),
+    };
+    match result {
+        Some(n) => Ok(n),
+        None => error(
+            format!(
+                "Overflow while evaluating a constant: `{a} {} {b}` doesn't fit in an `int`.",
+                op.symbol()
+            ),
+            loc,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::check_source;
+
+    /// The values of the constants of the entry module by name, and the error messages.
+    fn evaluate_source(src: &str) -> (Vec<(String, String)>, Vec<String>, ConstValues) {
+        let (graph, res, _) = check_source(src);
+        let (consts, diagnostics) = evaluate(&graph, &res);
+        let values = graph.modules[0]
+            .ast
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(item, declaration)| match &declaration.kind {
+                ItemKind::Const(decl) => Some((
+                    decl.name.name.clone(),
+                    consts
+                        .get(DefId { module: 0, item })
+                        .map_or("-".to_string(), ToString::to_string),
+                )),
+                _ => None,
+            })
+            .collect();
+        let messages = diagnostics.into_iter().map(|d| d.message).collect();
+        (values, messages, consts)
+    }
+
+    #[test]
+    fn evaluates_constants_and_pure_calls() {
+        let (values, errors, _) = evaluate_source(
+            "const MAX: int = 1 << 10;\n\
+             const HALF: int = MAX / 2 - -1;\n\
+             const GREETING: str = \"hello, \" + NAME;\n\
+             const NAME: str = \"paca\";\n\
+             const FACT: int = factorial(10);\n\
+             const PAIR: (bool, char) = (MAX > HALF && !false, 'x');\n\n\
+             def factorial(n: int) int {\n    let mut result = 1;\n    let mut i = n;\n    \
+             while i > 1 {\n        result *= i;\n        i -= 1;\n    }\n    \
+             match result {\n        0..10 => return 0,\n        _ => return result,\n    }\n}\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            values,
+            [
+                ("MAX", "1024"),
+                ("HALF", "513"),
+                ("GREETING", "\"hello, paca\""),
+                ("NAME", "\"paca\""),
+                ("FACT", "3628800"),
+                ("PAIR", "(true, 'x')"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_overflows_and_divisions_by_zero() {
+        let (values, errors, _) = evaluate_source(
+            "const BIG: int = 9223372036854775807 + 1;\n\
+             const WIDE: int = 1 << 64;\n\
+             const LOST: int = 3 << 62;\n\
+             const ZERO: int = half(0);\n\
+             const USES_BIG: int = BIG * 2;\n\
+             const LOOP: int = LOOP + 1;\n\
+             const PRINTS: int = shout();\n\n\
+             def half(n: int) int {\n    return 10 / n;\n}\n\n\
+             def shout() int {\n    println(\"hi\");\n    return 1;\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "Overflow while evaluating a constant: `9223372036854775807 + 1` doesn't fit in an \
+                 `int`.",
+                "Overflow while evaluating a constant: `1 << 64` doesn't fit in an `int`.",
+                "Overflow while evaluating a constant: `3 << 62` doesn't fit in an `int`.",
+                "Division by zero while evaluating a constant: `10 / 0`.",
+                "The value of `LOOP` depends on itself: `LOOP` -> `LOOP`.",
+                "`println` can't be called while evaluating a constant.",
+            ]
+        );
+        assert!(values.iter().all(|(_, value)| value == "-"));
+    }
+
+    #[test]
+    fn numbers_enum_variants() {
+        let (_, errors, consts) = evaluate_source(
+            "const BASE: int = 4;\n\n\
+             enum Flag {\n    Read = 1,\n    Write = BASE >> 1,\n    Exec = BASE,\n    Sticky,\n}\n\n\
+             enum Clash {\n    A = 2,\n    B = 1,\n    C,\n}\n",
+        );
+        assert_eq!(errors, ["`C` has the same discriminant as `A`: 2."]);
+        assert_eq!(
+            consts.discriminants(DefId { module: 0, item: 1 }),
+            Some(&[1, 2, 4, 5][..])
+        );
+        assert_eq!(consts.discriminants(DefId { module: 0, item: 2 }), None);
+    }
+}
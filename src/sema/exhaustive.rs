@@ -0,0 +1,661 @@
+//! Exhaustiveness and reachability of `match` arms, with the pattern matrices of Maranget's
+//! "Warnings for pattern matching".
+//!
+//! The arms of a `match` are the rows of a matrix, and a value goes to the first row that matches
+//! it. An arm is unreachable when no value it matches gets past the rows above it, and the `match`
+//! is exhaustive when no value gets past all of them. `useful` answers both, building example
+//! patterns of the values that get through, which are reported as not covered.
+
+use super::builtins::Primitive;
+use super::modules::{ModuleGraph, ModuleId};
+use super::ty::Ty;
+use super::typeck::{AdtKind, TypeckResults};
+use super::Diagnostic;
+use crate::parse::ast::{Literal, MatchArm, Pattern, PatternKind, Stmt, StmtKind};
+use crate::parse::visit::{walk, Visitor};
+use crate::parse::SourceCodeLocation;
+use std::collections::HashMap;
+
+/// How many of the values a `match` doesn't cover are listed.
+const MAX_WITNESSES: usize = 3;
+
+/// The values of `char`, which are the Unicode scalar values.
+const CHAR_RANGES: [IntRange; 2] = [
+    IntRange { lo: 0, hi: 0xD7FF },
+    IntRange {
+        lo: 0xE000,
+        hi: 0x10FFFF,
+    },
+];
+
+/// Consecutive `int` or `char` values, both ends included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct IntRange {
+    lo: i128,
+    hi: i128,
+}
+
+impl IntRange {
+    fn contains(&self, other: &IntRange) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+}
+
+/// What a pattern checks about a value before matching its fields.
+#[derive(Clone, Debug, PartialEq)]
+enum Ctor {
+    /// Matches every value.
+    Wild,
+    /// The only constructor of tuples and structs.
+    Single,
+    /// An enum variant, by index.
+    Variant(usize),
+    Bool(bool),
+    Range(IntRange),
+    /// A `str` or `float` literal, whose type has too many values to list them.
+    Opaque(String),
+    /// An or-pattern, whose alternatives are the fields.
+    Or,
+}
+
+/// A pattern lowered for the matrix: bindings are wildcards, `..` is expanded into wildcards and
+/// struct fields are in declaration order.
+#[derive(Clone, Debug)]
+struct Pat {
+    ctor: Ctor,
+    fields: Vec<Pat>,
+    ty: Ty,
+}
+
+impl Pat {
+    fn wild(ty: &Ty) -> Self {
+        Pat {
+            ctor: Ctor::Wild,
+            fields: Vec::new(),
+            ty: ty.clone(),
+        }
+    }
+}
+
+/// Check every `match` of the program, which type checked without errors.
+pub fn check(graph: &ModuleGraph, results: &TypeckResults) -> Vec<Diagnostic> {
+    let mut checker = MatchChecker {
+        module: 0,
+        results,
+        diagnostics: Vec::new(),
+    };
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        checker.module = module;
+        checker.visit_module(&loaded.ast);
+    }
+    checker.diagnostics
+}
+
+struct MatchChecker<'a> {
+    module: ModuleId,
+    results: &'a TypeckResults,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Visitor for MatchChecker<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let StmtKind::Match { scrutinee, arms } = &stmt.kind {
+            if let Some(ty) = self.results.expr_ty(self.module, scrutinee) {
+                if !ty.any(&|ty| *ty == Ty::Error) {
+                    self.check_match(ty, arms, scrutinee.loc.clone());
+                }
+            }
+        }
+        walk::walk_stmt(self, stmt);
+    }
+}
+
+impl MatchChecker<'_> {
+    fn check_match(&mut self, ty: &Ty, arms: &[MatchArm], loc: SourceCodeLocation) {
+        let mut matrix = Vec::new();
+        for arm in arms {
+            let pat = self.lower(&arm.pattern, ty);
+            if self.useful(&matrix, std::slice::from_ref(&pat)).is_empty() {
+                self.diagnostics.push(Diagnostic::warning(
+                    "Unreachable pattern: the arms above already match every value it matches.",
+                    arm.pattern.loc.clone(),
+                ));
+            } else if let PatternKind::Or(alternatives) = &arm.pattern.kind {
+                // The alternatives before an alternative are tried first too.
+                let mut above = matrix.clone();
+                for (alternative, lowered) in alternatives.iter().zip(&pat.fields) {
+                    let row = vec![lowered.clone()];
+                    if self.useful(&above, &row).is_empty() {
+                        self.diagnostics.push(Diagnostic::warning(
+                            "Unreachable pattern: the patterns before it already match every \
+                             value it matches.",
+                            alternative.loc.clone(),
+                        ));
+                    }
+                    above.push(row);
+                }
+            }
+            // A guard can reject any value, so guarded arms don't cover anything.
+            if arm.guard.is_none() {
+                matrix.push(vec![pat]);
+            }
+        }
+
+        let witnesses = self.useful(&matrix, &[Pat::wild(ty)]);
+        if witnesses.is_empty() {
+            return;
+        }
+        let mut examples = witnesses
+            .iter()
+            .take(MAX_WITNESSES)
+            .map(|witness| format!("`{}`", self.show(&witness[0])))
+            .collect::<Vec<_>>();
+        let help = if witnesses.len() == 1 {
+            "add an arm for it, or a `_` arm to match every other value."
+        } else {
+            "add arms for them, or a `_` arm to match every other value."
+        };
+        let last = match witnesses.len() - examples.len() {
+            0 => examples.pop().expect("there is at least one witness"),
+            more => format!("{more} more"),
+        };
+        let listed = if examples.is_empty() {
+            last
+        } else {
+            format!("{} and {last}", examples.join(", "))
+        };
+        self.diagnostics.push(
+            Diagnostic::error(
+                format!("Non-exhaustive `match`: {listed} not covered."),
+                loc,
+            )
+            .with_help(help),
+        );
+    }
+
+    fn lower(&self, pattern: &Pattern, ty: &Ty) -> Pat {
+        let (ctor, fields) = match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Rest | PatternKind::Binding { sub: None, .. } => {
+                return Pat::wild(ty);
+            }
+            PatternKind::Binding { sub: Some(sub), .. } => return self.lower(sub, ty),
+            PatternKind::Literal(literal) => (literal_ctor(literal), Vec::new()),
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let (Ctor::Range(start), Ctor::Range(end)) =
+                    (literal_ctor(start), literal_ctor(end))
+                else {
+                    unreachable!("range patterns are checked to be `int` or `char`")
+                };
+                let hi = if *inclusive { end.hi } else { end.hi - 1 };
+                (Ctor::Range(IntRange { lo: start.lo, hi }), Vec::new())
+            }
+            PatternKind::Tuple(patterns) => {
+                (Ctor::Single, self.lower_fields(patterns, ty, &Ctor::Single))
+            }
+            PatternKind::Variant { path, fields } => {
+                let name = &path
+                    .segments
+                    .last()
+                    .expect("paths have at least one segment")
+                    .ident
+                    .name;
+                let Some(ctor) = self.variant(ty, name) else {
+                    return Pat::wild(ty);
+                };
+                let fields = self.lower_fields(fields.as_deref().unwrap_or_default(), ty, &ctor);
+                (ctor, fields)
+            }
+            PatternKind::Struct { fields, .. } => {
+                let Some(names) = self.field_names(ty) else {
+                    return Pat::wild(ty);
+                };
+                let tys = self.field_tys(ty, &Ctor::Single);
+                let fields = names
+                    .iter()
+                    .zip(&tys)
+                    .map(
+                        |(name, ty)| match fields.iter().find(|f| &f.name.name == name) {
+                            Some(field) => self.lower(&field.pattern, ty),
+                            None => Pat::wild(ty),
+                        },
+                    )
+                    .collect();
+                (Ctor::Single, fields)
+            }
+            PatternKind::Or(alternatives) => (
+                Ctor::Or,
+                alternatives.iter().map(|p| self.lower(p, ty)).collect(),
+            ),
+        };
+        Pat {
+            ctor,
+            fields,
+            ty: ty.clone(),
+        }
+    }
+
+    /// Lower the fields of a tuple or variant pattern, expanding `..` into wildcards.
+    fn lower_fields(&self, patterns: &[Pattern], ty: &Ty, ctor: &Ctor) -> Vec<Pat> {
+        let tys = self.field_tys(ty, ctor);
+        let rest = patterns.iter().position(|p| p.kind == PatternKind::Rest);
+        let (before, after) = match rest {
+            Some(index) => (&patterns[..index], &patterns[index + 1..]),
+            None => (patterns, &[][..]),
+        };
+        let skipped = tys.len().saturating_sub(before.len() + after.len());
+        let mut patterns = before
+            .iter()
+            .map(Some)
+            .chain(std::iter::repeat_n(None, skipped))
+            .chain(after.iter().map(Some));
+        tys.iter()
+            .map(|ty| match patterns.next().flatten() {
+                Some(pattern) => self.lower(pattern, ty),
+                None => Pat::wild(ty),
+            })
+            .collect()
+    }
+
+    fn variant(&self, ty: &Ty, name: &str) -> Option<Ctor> {
+        let Ty::Adt { def, .. } = ty else {
+            return None;
+        };
+        match &self.results.items.adts[def].kind {
+            AdtKind::Enum(variants) => variants
+                .iter()
+                .position(|v| v.name == name)
+                .map(Ctor::Variant),
+            AdtKind::Struct(_) => None,
+        }
+    }
+
+    fn field_names(&self, ty: &Ty) -> Option<Vec<String>> {
+        let Ty::Adt { def, .. } = ty else {
+            return None;
+        };
+        match &self.results.items.adts[def].kind {
+            AdtKind::Struct(fields) => Some(fields.iter().map(|f| f.name.clone()).collect()),
+            AdtKind::Enum(_) => None,
+        }
+    }
+
+    /// The types of the fields a constructor of the type has.
+    fn field_tys(&self, ty: &Ty, ctor: &Ctor) -> Vec<Ty> {
+        match (ty, ctor) {
+            (Ty::Tuple(tys), Ctor::Single) => tys.clone(),
+            (Ty::Adt { def, args, .. }, Ctor::Single | Ctor::Variant(_)) => {
+                let adt = &self.results.items.adts[def];
+                let substs = adt
+                    .generics
+                    .iter()
+                    .map(|g| g.name.clone())
+                    .zip(args.iter().cloned())
+                    .collect::<HashMap<_, _>>();
+                match (&adt.kind, ctor) {
+                    (AdtKind::Struct(fields), _) => {
+                        fields.iter().map(|f| f.ty.subst(&substs)).collect()
+                    }
+                    (AdtKind::Enum(variants), Ctor::Variant(index)) => variants[*index]
+                        .fields
+                        .iter()
+                        .map(|ty| ty.subst(&substs))
+                        .collect(),
+                    (AdtKind::Enum(_), _) => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Every constructor of the type, or `None` when there are too many to list.
+    fn all_ctors(&self, ty: &Ty) -> Option<Vec<Ctor>> {
+        match ty {
+            Ty::Primitive(Primitive::Bool) => Some(vec![Ctor::Bool(false), Ctor::Bool(true)]),
+            Ty::Primitive(Primitive::Int) => Some(vec![Ctor::Range(IntRange {
+                lo: i64::MIN.into(),
+                hi: i64::MAX.into(),
+            })]),
+            Ty::Primitive(Primitive::Char) => Some(CHAR_RANGES.map(Ctor::Range).to_vec()),
+            Ty::Primitive(Primitive::Void) | Ty::Tuple(_) => Some(vec![Ctor::Single]),
+            Ty::Adt { def, .. } => match &self.results.items.adts[def].kind {
+                AdtKind::Struct(_) => Some(vec![Ctor::Single]),
+                AdtKind::Enum(variants) => Some((0..variants.len()).map(Ctor::Variant).collect()),
+            },
+            _ => None,
+        }
+    }
+
+    /// The values `row` matches that no row of `matrix` does, each as a list of example patterns
+    /// for the columns. Empty when every value the row matches is matched above it.
+    fn useful(&self, matrix: &[Vec<Pat>], row: &[Pat]) -> Vec<Vec<Pat>> {
+        let Some((head, rest)) = row.split_first() else {
+            return if matrix.is_empty() {
+                vec![Vec::new()]
+            } else {
+                Vec::new()
+            };
+        };
+        if head.ctor == Ctor::Or {
+            return head
+                .fields
+                .iter()
+                .flat_map(|alternative| {
+                    let row = [std::slice::from_ref(alternative), rest].concat();
+                    self.useful(matrix, &row)
+                })
+                .collect();
+        }
+        let matrix = matrix
+            .iter()
+            .flat_map(|row| expand(row))
+            .collect::<Vec<_>>();
+        let column = matrix
+            .iter()
+            .map(|row| &row[0].ctor)
+            .filter(|&ctor| *ctor != Ctor::Wild)
+            .collect::<Vec<_>>();
+
+        if head.ctor != Ctor::Wild {
+            return split(&head.ctor, &column)
+                .into_iter()
+                .flat_map(|ctor| self.useful_ctor(&matrix, row, &ctor))
+                .collect();
+        }
+        if let Some(all) = self.all_ctors(&head.ty) {
+            let ctors = all
+                .iter()
+                .flat_map(|ctor| split(ctor, &column))
+                .collect::<Vec<_>>();
+            let missing = ctors
+                .iter()
+                .filter(|ctor| !column.iter().any(|c| covers(c, ctor)))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                return ctors
+                    .iter()
+                    .flat_map(|ctor| self.useful_ctor(&matrix, row, ctor))
+                    .collect();
+            }
+            // Only the rows starting with a wildcard match the missing constructors.
+            let defaults = defaults(&matrix);
+            let witnesses = self.useful(&defaults, rest);
+            if column.is_empty() {
+                return prepend(Pat::wild(&head.ty), witnesses);
+            }
+            return missing
+                .into_iter()
+                .flat_map(|ctor| {
+                    let head = Pat {
+                        ctor: ctor.clone(),
+                        fields: self
+                            .field_tys(&head.ty, ctor)
+                            .iter()
+                            .map(Pat::wild)
+                            .collect(),
+                        ty: head.ty.clone(),
+                    };
+                    prepend(head, witnesses.clone())
+                })
+                .collect();
+        }
+        let witnesses = self.useful(&defaults(&matrix), rest);
+        prepend(Pat::wild(&head.ty), witnesses)
+    }
+
+    /// `useful` for the values built by one constructor, which is covered by all or none of the
+    /// constructors in the first column.
+    fn useful_ctor(&self, matrix: &[Vec<Pat>], row: &[Pat], ctor: &Ctor) -> Vec<Vec<Pat>> {
+        let ty = row[0].ty.clone();
+        let tys = self.field_tys(&ty, ctor);
+        let matrix = matrix
+            .iter()
+            .filter_map(|row| specialize(row, ctor, &tys))
+            .collect::<Vec<_>>();
+        let Some(row) = specialize(row, ctor, &tys) else {
+            return Vec::new();
+        };
+        self.useful(&matrix, &row)
+            .into_iter()
+            .map(|mut witness| {
+                let fields = witness.drain(..tys.len()).collect();
+                let head = Pat {
+                    ctor: ctor.clone(),
+                    fields,
+                    ty: ty.clone(),
+                };
+                std::iter::once(head).chain(witness).collect()
+            })
+            .collect()
+    }
+
+    /// An example pattern written as in the source.
+    fn show(&self, pat: &Pat) -> String {
+        let fields = || pat.fields.iter().map(|f| self.show(f)).collect::<Vec<_>>();
+        match (&pat.ctor, &pat.ty) {
+            (Ctor::Wild, _) => "_".to_string(),
+            (Ctor::Bool(value), _) => value.to_string(),
+            (Ctor::Opaque(text), _) => text.clone(),
+            (Ctor::Range(range), ty) => {
+                let bound = |value: i128| match ty {
+                    Ty::Primitive(Primitive::Char) => {
+                        format!("{:?}", char::from_u32(value as u32).unwrap_or_default())
+                    }
+                    _ if value == i64::MIN.into() => "int::MIN".to_string(),
+                    _ if value == i64::MAX.into() => "int::MAX".to_string(),
+                    _ => value.to_string(),
+                };
+                if range.lo == range.hi {
+                    bound(range.lo)
+                } else {
+                    format!("{}..={}", bound(range.lo), bound(range.hi))
+                }
+            }
+            (Ctor::Variant(index), Ty::Adt { def, .. }) => {
+                let adt = &self.results.items.adts[def];
+                let AdtKind::Enum(variants) = &adt.kind else {
+                    unreachable!("variants belong to enums")
+                };
+                let name = format!("{}::{}", adt.name, variants[*index].name);
+                if pat.fields.is_empty() {
+                    name
+                } else {
+                    format!("{name}({})", fields().join(", "))
+                }
+            }
+            (Ctor::Single, Ty::Adt { def, .. }) => {
+                let adt = &self.results.items.adts[def];
+                let names = self.field_names(&pat.ty).unwrap_or_default();
+                let mut shown = names
+                    .iter()
+                    .zip(&pat.fields)
+                    .filter(|(_, field)| field.ctor != Ctor::Wild)
+                    .map(|(name, field)| format!("{name} => {}", self.show(field)))
+                    .collect::<Vec<_>>();
+                if shown.len() < names.len() {
+                    shown.push("..".to_string());
+                }
+                format!("{} {{ {} }}", adt.name, shown.join(", "))
+            }
+            (Ctor::Single, Ty::Tuple(_)) => format!("({})", fields().join(", ")),
+            _ => "_".to_string(),
+        }
+    }
+}
+
+fn literal_ctor(literal: &Literal) -> Ctor {
+    match literal {
+        Literal::Int(n) => Ctor::Range(IntRange {
+            lo: (*n).into(),
+            hi: (*n).into(),
+        }),
+        Literal::Char(c) => Ctor::Range(IntRange {
+            lo: (*c as u32).into(),
+            hi: (*c as u32).into(),
+        }),
+        Literal::Bool(value) => Ctor::Bool(*value),
+        Literal::Float(n) => Ctor::Opaque(n.to_string()),
+        Literal::Str(s) => Ctor::Opaque(format!("{s:?}")),
+    }
+}
+
+/// Whether every value `b` matches is matched by `a`.
+fn covers(a: &Ctor, b: &Ctor) -> bool {
+    match (a, b) {
+        (Ctor::Wild, _) => true,
+        (Ctor::Range(a), Ctor::Range(b)) => a.contains(b),
+        _ => a == b,
+    }
+}
+
+/// Split a constructor into pieces that each constructor of the column either covers or doesn't
+/// match at all. Only ranges need it: `0..=9` against `5` becomes `0..=4`, `5` and `6..=9`.
+fn split(ctor: &Ctor, column: &[&Ctor]) -> Vec<Ctor> {
+    let Ctor::Range(range) = ctor else {
+        return vec![ctor.clone()];
+    };
+    let mut bounds = vec![range.lo, range.hi + 1];
+    for ctor in column {
+        if let Ctor::Range(other) = ctor {
+            for bound in [other.lo, other.hi + 1] {
+                if range.lo < bound && bound <= range.hi {
+                    bounds.push(bound);
+                }
+            }
+        }
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+    bounds
+        .windows(2)
+        .map(|pair| {
+            Ctor::Range(IntRange {
+                lo: pair[0],
+                hi: pair[1] - 1,
+            })
+        })
+        .collect()
+}
+
+/// The rows of the alternatives of an or-pattern at the start of the row.
+fn expand(row: &[Pat]) -> Vec<Vec<Pat>> {
+    match row.first() {
+        Some(head) if head.ctor == Ctor::Or => head
+            .fields
+            .iter()
+            .flat_map(|alternative| {
+                expand(&[std::slice::from_ref(alternative), &row[1..]].concat())
+            })
+            .collect(),
+        _ => vec![row.to_vec()],
+    }
+}
+
+/// The row for the fields of a value built by the constructor, if the row matches such values.
+fn specialize(row: &[Pat], ctor: &Ctor, tys: &[Ty]) -> Option<Vec<Pat>> {
+    let (head, rest) = row.split_first().expect("specialized rows aren't empty");
+    let fields = if head.ctor == Ctor::Wild {
+        tys.iter().map(Pat::wild).collect()
+    } else if covers(&head.ctor, ctor) {
+        head.fields.clone()
+    } else {
+        return None;
+    };
+    Some([fields, rest.to_vec()].concat())
+}
+
+/// The rows that start with a wildcard, without it.
+fn defaults(matrix: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    matrix
+        .iter()
+        .filter(|row| row[0].ctor == Ctor::Wild)
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn prepend(head: Pat, witnesses: Vec<Vec<Pat>>) -> Vec<Vec<Pat>> {
+    witnesses
+        .into_iter()
+        .map(|witness| std::iter::once(head.clone()).chain(witness).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::check_source;
+
+    fn check_matches(src: &str) -> Vec<String> {
+        let (graph, _, results) = check_source(src);
+        check(&graph, &results)
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+    }
+
+    const ENUMS: &str = "enum Opt<T> {\n    Some(T),\n    None,\n}\n\n\
+                         enum Either<L, R> {\n    Left(L),\n    Right(R),\n}\n\n";
+
+    #[test]
+    fn reports_missing_variants() {
+        let errors = check_matches(&format!(
+            "{ENUMS}def main() void {{\n    \
+             let e: Either<Opt<int>, (bool, bool)> = Either::Right((true, false));\n    \
+             match e {{\n        \
+             Either::Left(Opt::Some(_)) => {{}}\n        \
+             Either::Right((true, _)) => {{}}\n        \
+             Either::Right((_, true)) if true => {{}}\n    \
+             }}\n    \
+             match Opt::Some(1) {{\n        Opt::Some(n) => {{}}\n    }}\n}}\n"
+        ));
+        assert_eq!(
+            errors,
+            [
+                "Non-exhaustive `match`: `Either::Left(Opt::None)` and \
+                 `Either::Right((false, _))` not covered.",
+                "Non-exhaustive `match`: `Opt::None` not covered.",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unreachable_arms() {
+        let errors = check_matches(&format!(
+            "{ENUMS}def main() void {{\n    \
+             match Opt::Some(true) {{\n        \
+             Opt::Some(true) | Opt::None => {{}}\n        \
+             Opt::Some(false) | Opt::None => {{}}\n        \
+             x => {{}}\n    \
+             }}\n}}\n"
+        ));
+        assert_eq!(
+            errors,
+            [
+                "Unreachable pattern: the patterns before it already match every value it \
+                 matches.",
+                "Unreachable pattern: the arms above already match every value it matches.",
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_literal_ranges() {
+        let errors = check_matches(
+            "def main() void {\n    \
+             match 4 {\n        0 => {}\n        1..=5 => {}\n        3 => {}\n        6..10 => {}\n    }\n    \
+             match 'c' {\n        'a'..='z' => {}\n    }\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "Unreachable pattern: the arms above already match every value it matches.",
+                "Non-exhaustive `match`: `int::MIN..=-1` and `10..=int::MAX` not covered.",
+                "Non-exhaustive `match`: `'\\0'..='`'`, `'{'..='\\u{d7ff}'` and \
+                 `'\\u{e000}'..='\\u{10ffff}'` not covered.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,666 @@
+//! Flow analysis of function and closure bodies: whether a body that returns a value can reach its
+//! end, whether a local declared without a value is read before it's assigned one, which statements
+//! can never run and which `while` loops can never end.
+//!
+//! The statements are walked in order with the locals that may still be unassigned at each point,
+//! or `None` where the point can't be reached. Where paths meet, as after an `if`, the sets are
+//! merged, so a local is only assigned after an `if` when both branches assign it.
+//!
+//! The locals that no path has assigned yet are tracked as well: an assignment to one of them is
+//! its initialization, which is allowed even when the local isn't `mut`, as in
+//! `let y: int; if c { y = 1; } else { y = 2; }`. An assignment in a loop only initializes a local
+//! declared before the loop when no path through the body comes back to the loop with it assigned.
+
+use super::modules::{ModuleGraph, ModuleId};
+use super::resolve::{DefId, LocalId, Res, Resolutions};
+use super::ty::Ty;
+use super::typeck::TypeckResults;
+use super::Diagnostic;
+use crate::parse::ast::{
+    Block, ClosureBody, Expr, ExprKind, Ident, ItemKind, Literal, Pattern, Stmt, StmtKind,
+};
+use crate::parse::visit::{walk, Visitor};
+use crate::parse::SourceCodeLocation;
+use std::collections::HashSet;
+
+/// The locals declared without a value at a point of a body.
+#[derive(Clone, Debug, Default)]
+struct Locals {
+    /// The locals that may be unassigned.
+    unassigned: HashSet<LocalId>,
+    /// The locals that are unassigned on every path.
+    fresh: HashSet<LocalId>,
+}
+
+/// The locals at a point of a body, or `None` when nothing reaches it.
+type State = Option<Locals>;
+
+/// The state where two paths meet.
+fn join(a: State, b: State) -> State {
+    match (a, b) {
+        (Some(mut a), Some(b)) => {
+            a.unassigned.extend(b.unassigned);
+            a.fresh.retain(|local| b.fresh.contains(local));
+            Some(a)
+        }
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// The assignments that give a local declared without a value its first value.
+#[derive(Debug, Default)]
+pub struct Initializations {
+    assignments: HashSet<(ModuleId, usize)>,
+}
+
+impl Initializations {
+    /// Whether the target of an assignment is a local assigned for the first time.
+    pub fn contains(&self, module: ModuleId, target: &Expr) -> bool {
+        self.assignments.contains(&(module, target.loc.offset))
+    }
+}
+
+/// A `while` loop around the current point.
+#[derive(Default)]
+struct Loop {
+    /// The states at the `break`s of the loop.
+    breaks: State,
+    /// The states at the `continue`s of the loop.
+    continues: State,
+    /// Whether a `break` or a `return` can leave the loop.
+    left: bool,
+}
+
+/// Check the bodies of every function, method and closure of the program, and find the
+/// assignments that initialize locals.
+pub fn check(
+    graph: &ModuleGraph,
+    res: &Resolutions,
+    results: &TypeckResults,
+) -> (Initializations, Vec<Diagnostic>) {
+    let mut checker = FlowChecker {
+        module: 0,
+        res,
+        results,
+        state: None,
+        dead_after: "",
+        loops: Vec::new(),
+        reported: HashSet::new(),
+        initializations: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    let items = &results.items;
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        checker.module = module;
+        for (item, declaration) in loaded.ast.items.iter().enumerate() {
+            let id = DefId { module, item };
+            let bodies = match &declaration.kind {
+                ItemKind::Fn(decl) => items
+                    .fns
+                    .get(&id)
+                    .map(|sig| vec![(decl.as_ref(), &sig.ret)])
+                    .unwrap_or_default(),
+                ItemKind::Impl(decl) => items
+                    .impls
+                    .get(&id)
+                    .map(|imp| decl.methods.iter().zip(&imp.methods).collect::<Vec<_>>())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(decl, method)| (decl, &method.sig.ret))
+                    .collect(),
+                ItemKind::Trait(decl) => items
+                    .traits
+                    .get(&id)
+                    .map(|def| decl.methods.iter().zip(&def.methods).collect::<Vec<_>>())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(decl, method)| (decl, &method.sig.ret))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            for (decl, ret) in bodies {
+                if let Some(body) = &decl.body {
+                    let name = Some(decl.name.name.as_str());
+                    checker.body(body, HashSet::new(), ret, name, &decl.name.loc);
+                }
+            }
+        }
+    }
+    let assignments = checker
+        .initializations
+        .into_iter()
+        .map(|(module, _, offset)| (module, offset))
+        .collect();
+    (Initializations { assignments }, checker.diagnostics)
+}
+
+struct FlowChecker<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    results: &'a TypeckResults,
+    state: State,
+    /// What makes the current point unreachable when `state` is `None`, e.g. "a `return`".
+    dead_after: &'static str,
+    loops: Vec<Loop>,
+    /// The locals already reported as read before being assigned, so each is reported once.
+    reported: HashSet<LocalId>,
+    /// The module, local and target offset of the assignments initializing a local.
+    initializations: Vec<(ModuleId, LocalId, usize)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl FlowChecker<'_> {
+    /// Check the body of the named function or method, or of a closure.
+    fn body(
+        &mut self,
+        body: &Block,
+        unassigned: HashSet<LocalId>,
+        ret: &Ty,
+        name: Option<&str>,
+        loc: &SourceCodeLocation,
+    ) {
+        let loops = std::mem::take(&mut self.loops);
+        // A closure may run any number of times, so it can't initialize the locals around it.
+        let outer = self.state.replace(Locals {
+            unassigned,
+            fresh: HashSet::new(),
+        });
+        self.block(body);
+        let ignored = *ret == Ty::VOID || ret.any(&|t| *t == Ty::Error);
+        if self.state.is_some() && !ignored {
+            let (what, end) = match name {
+                Some(name) => (format!("`{name}`"), format!("`{name}`")),
+                None => ("This closure".to_string(), "the closure".to_string()),
+            };
+            let (message, help) = if *ret == Ty::NEVER {
+                (
+                    format!("{what} never returns, but the end of its body can be reached."),
+                    format!("end {end} with a call that never returns, such as `panic`."),
+                )
+            } else {
+                (
+                    format!(
+                        "{what} returns `{ret}`, but the end of its body can be reached without \
+                         a `return`."
+                    ),
+                    format!("add a `return` at the end of {end}."),
+                )
+            };
+            self.diagnostics
+                .push(Diagnostic::error(message, loc.clone()).with_help(help));
+        }
+        self.state = outer;
+        self.loops = loops;
+    }
+
+    fn block(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            if self.state.is_none() {
+                self.diagnostics.push(Diagnostic::warning(
+                    format!("Unreachable code: it comes after {}.", self.dead_after),
+                    stmt.loc.clone(),
+                ));
+                return;
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    /// Mark the rest of the path as unreachable.
+    fn diverge(&mut self, after: &'static str) {
+        self.state = None;
+        self.dead_after = after;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { pattern, value, .. } => match value {
+                Some(value) => self.visit_expr(value),
+                None => {
+                    let locals = self.bindings(pattern);
+                    if let Some(state) = &mut self.state {
+                        state.unassigned.extend(locals.iter().copied());
+                        state.fresh.extend(locals);
+                    }
+                }
+            },
+            StmtKind::Assign { target, op, value } => {
+                self.visit_expr(value);
+                match self.local(target) {
+                    Some((local, ident)) => {
+                        if op.is_some() {
+                            self.read(local, ident);
+                        }
+                        if let Some(state) = &mut self.state {
+                            state.unassigned.remove(&local);
+                            if state.fresh.remove(&local) && op.is_none() {
+                                self.initializations
+                                    .push((self.module, local, target.loc.offset));
+                            }
+                        }
+                    }
+                    None => self.visit_expr(target),
+                }
+            }
+            StmtKind::Expr(expr) => {
+                self.visit_expr(expr);
+                if self.results.expr_ty(self.module, expr) == Some(&Ty::NEVER) {
+                    self.diverge("a call that never returns");
+                }
+            }
+            StmtKind::Return(value) => {
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                for outer in &mut self.loops {
+                    outer.left = true;
+                }
+                self.diverge("a `return`");
+            }
+            StmtKind::Break => {
+                match self.loops.last_mut() {
+                    Some(innermost) => {
+                        innermost.breaks = join(innermost.breaks.take(), self.state.clone());
+                        innermost.left = true;
+                    }
+                    None => self.outside_loop("break", stmt),
+                }
+                self.diverge("a `break`");
+            }
+            StmtKind::Continue => {
+                match self.loops.last_mut() {
+                    Some(innermost) => {
+                        innermost.continues = join(innermost.continues.take(), self.state.clone());
+                    }
+                    None => self.outside_loop("continue", stmt),
+                }
+                self.diverge("a `continue`");
+            }
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.visit_expr(cond);
+                let before = self.state.clone();
+                self.block(then_block);
+                let after_then = std::mem::replace(&mut self.state, before);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+                self.state = join(after_then, self.state.take());
+            }
+            StmtKind::While { cond, body } => {
+                self.visit_expr(cond);
+                let entry = self.state.clone();
+                let initialized = self.initializations.len();
+                self.loops.push(Loop::default());
+                self.block(body);
+                let inner = self.loops.pop().expect("pushed above");
+                let back = join(self.state.take(), inner.continues);
+                if let (Some(entry), Some(back)) = (&entry, &back) {
+                    // The next iteration may run an assignment again, so it only initializes a
+                    // local from before the loop that no path back to the loop assigns.
+                    let assignments = self.initializations.split_off(initialized);
+                    self.initializations
+                        .extend(assignments.into_iter().filter(|(_, local, _)| {
+                            !entry.fresh.contains(local) || back.fresh.contains(local)
+                        }));
+                }
+                let forever = matches!(cond.kind, ExprKind::Literal(Literal::Bool(true)));
+                if !inner.left {
+                    self.check_termination(cond, body, forever);
+                }
+                self.state = if forever {
+                    inner.breaks
+                } else {
+                    join(join(entry, back), inner.breaks)
+                };
+                if self.state.is_none() {
+                    self.dead_after = "a loop that never ends";
+                }
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                self.visit_expr(scrutinee);
+                let entry = self.state.take();
+                let mut exit = None;
+                for arm in arms {
+                    self.state = entry.clone();
+                    if let Some(guard) = &arm.guard {
+                        self.visit_expr(guard);
+                    }
+                    self.stmt(&arm.body);
+                    exit = join(exit, self.state.take());
+                }
+                self.state = exit;
+                if arms.is_empty() {
+                    self.dead_after = "a `match` without arms";
+                }
+            }
+            StmtKind::Block(block) => self.block(block),
+        }
+    }
+
+    fn outside_loop(&mut self, keyword: &str, stmt: &Stmt) {
+        self.diagnostics.push(Diagnostic::error(
+            format!("`{keyword}` can only be used inside a `while` loop."),
+            stmt.loc.clone(),
+        ));
+    }
+
+    /// Warn about a loop that nothing leaves when its condition can't become false either.
+    fn check_termination(&mut self, cond: &Expr, body: &Block, forever: bool) {
+        let help = "add a `break` or a `return`, or change the condition inside the loop.";
+        if forever {
+            self.diagnostics.push(
+                Diagnostic::warning(
+                    "This loop never ends: its condition is always true and nothing in it leaves \
+                     the loop.",
+                    cond.loc.clone(),
+                )
+                .with_help(help),
+            );
+            return;
+        }
+        let mut finder = LocalFinder {
+            module: self.module,
+            res: self.res,
+            locals: Vec::new(),
+            opaque: false,
+        };
+        finder.visit_expr(cond);
+        if finder.opaque || finder.locals.is_empty() {
+            return;
+        }
+        let mut changes = ChangeFinder {
+            module: self.module,
+            res: self.res,
+            changed: HashSet::new(),
+        };
+        changes.visit_block(body);
+        if finder
+            .locals
+            .iter()
+            .any(|(local, _)| changes.changed.contains(local))
+        {
+            return;
+        }
+        let mut names = finder
+            .locals
+            .iter()
+            .map(|(_, name)| format!("`{name}`"))
+            .collect::<Vec<_>>();
+        names.dedup();
+        let last = names.pop().expect("the condition reads locals");
+        let names = if names.is_empty() {
+            last
+        } else {
+            format!("{} or {last}", names.join(", "))
+        };
+        self.diagnostics.push(
+            Diagnostic::warning(
+                format!(
+                    "This loop never ends once it starts: nothing in it changes {names} or leaves \
+                     the loop."
+                ),
+                cond.loc.clone(),
+            )
+            .with_help(help),
+        );
+    }
+
+    /// The locals a pattern binds.
+    fn bindings(&self, pattern: &Pattern) -> Vec<LocalId> {
+        let mut finder = LocalFinder {
+            module: self.module,
+            res: self.res,
+            locals: Vec::new(),
+            opaque: false,
+        };
+        finder.visit_pattern(pattern);
+        finder.locals.into_iter().map(|(local, _)| local).collect()
+    }
+
+    /// The local an expression names, if it's a plain variable.
+    fn local<'e>(&self, expr: &'e Expr) -> Option<(LocalId, &'e Ident)> {
+        let ExprKind::Path(path) = &expr.kind else {
+            return None;
+        };
+        let [segment] = path.segments.as_slice() else {
+            return None;
+        };
+        match self.res.get(self.module, &segment.ident) {
+            Some(Res::Local(local)) => Some((local, &segment.ident)),
+            _ => None,
+        }
+    }
+
+    fn read(&mut self, local: LocalId, ident: &Ident) {
+        let unassigned = self
+            .state
+            .as_ref()
+            .is_some_and(|s| s.unassigned.contains(&local));
+        if unassigned && self.reported.insert(local) {
+            let decl = &self.res.locals[local];
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("`{}` is used before it's assigned a value.", ident.name),
+                    ident.loc.clone(),
+                )
+                .with_help(format!(
+                    "`{}` is declared without a value on line {}, so assign one on every path \
+                     that reaches this use.",
+                    ident.name, decl.loc.line
+                )),
+            );
+        }
+    }
+}
+
+impl Visitor for FlowChecker<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        let ExprKind::Closure(closure) = &expr.kind else {
+            if let Some((local, ident)) = self.local(expr) {
+                return self.read(local, ident);
+            }
+            return walk::walk_expr(self, expr);
+        };
+        match &closure.body {
+            ClosureBody::Expr(body) => self.visit_expr(body),
+            ClosureBody::Block(body) => {
+                let ret = match self.results.expr_ty(self.module, expr) {
+                    Some(Ty::Fn(fn_ty)) => (*fn_ty.ret).clone(),
+                    _ => Ty::Error,
+                };
+                let unassigned = self
+                    .state
+                    .as_ref()
+                    .map(|state| state.unassigned.clone())
+                    .unwrap_or_default();
+                let dead_after = self.dead_after;
+                self.body(body, unassigned, &ret, None, &expr.loc);
+                self.dead_after = dead_after;
+            }
+        }
+    }
+}
+
+/// Finds the locals named in a pattern or a loop condition, noting whether the condition depends on
+/// anything else than locals, literals and operators.
+struct LocalFinder<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    locals: Vec<(LocalId, String)>,
+    opaque: bool,
+}
+
+impl Visitor for LocalFinder<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Literal(_)
+            | ExprKind::Path(_)
+            | ExprKind::Tuple(_)
+            | ExprKind::Unary { .. }
+            | ExprKind::Binary { .. } => walk::walk_expr(self, expr),
+            _ => self.opaque = true,
+        }
+    }
+
+    fn visit_ident(&mut self, ident: &Ident) {
+        if let Some(Res::Local(local)) = self.res.get(self.module, ident) {
+            if !self.locals.iter().any(|(l, _)| *l == local) {
+                self.locals.push((local, ident.name.clone()));
+            }
+        }
+    }
+}
+
+/// Finds the locals a loop body may change: those it assigns, calls methods on or passes to calls,
+/// including inside closures.
+struct ChangeFinder<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    changed: HashSet<LocalId>,
+}
+
+impl ChangeFinder<'_> {
+    /// Record the local at the root of a place such as `a->b->c`.
+    fn change(&mut self, mut expr: &Expr) {
+        loop {
+            match &expr.kind {
+                ExprKind::Field { base, .. } => expr = base,
+                ExprKind::Path(path) => {
+                    if let [segment] = path.segments.as_slice() {
+                        if let Some(Res::Local(local)) = self.res.get(self.module, &segment.ident) {
+                            self.changed.insert(local);
+                        }
+                    }
+                    return;
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+impl Visitor for ChangeFinder<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let StmtKind::Assign { target, .. } = &stmt.kind {
+            self.change(target);
+        }
+        walk::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::MethodCall { receiver, args, .. } => {
+                self.change(receiver);
+                for arg in args {
+                    self.change(arg);
+                }
+            }
+            ExprKind::Call { args, .. } => {
+                for arg in args {
+                    self.change(arg);
+                }
+            }
+            _ => {}
+        }
+        walk::walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sema::modules::ModuleLoader;
+    use std::path::Path;
+
+    /// The diagnostics of every check for the source, so the programs also pass the checks that build
+    /// on flow analysis.
+    fn check_flow(src: &str) -> Vec<String> {
+        let graph = ModuleLoader::new(vec![])
+            .load_source(Path::new("main.paca"), src.to_string())
+            .unwrap();
+        let (_, diagnostics) = crate::sema::analyze(&graph);
+        diagnostics.into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn reports_missing_returns() {
+        let errors = check_flow(
+            "def sign(n: int) int {\n    if n < 0 {\n        return -1;\n    } else if n > 0 {\n        return 1;\n    }\n}\n\n\
+             def first(n: int) int {\n    while true {\n        return n;\n    }\n}\n\n\
+             def fail() int {\n    panic(\"no\");\n}\n\n\
+             def apply(f: def(int) int) int {\n    return f(1);\n}\n\n\
+             def main() void {\n    apply() { (x) =>\n        if x > 0 {\n            return x;\n        }\n    };\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "`sign` returns `int`, but the end of its body can be reached without a `return`.",
+                "This closure returns `int`, but the end of its body can be reached without a \
+                 `return`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_definite_assignment() {
+        let errors = check_flow(
+            "def pick(flag: bool, n: int) int {\n    \
+             let x: int;\n    let y: int;\n    let z: int;\n    \
+             if flag {\n        x = 1;\n        y = 1;\n    } else {\n        y = 2;\n    }\n    \
+             match n {\n        0 => z = 0,\n        _ => {\n            z = 1;\n        }\n    }\n    \
+             let w: int;\n    while flag {\n        w = 1;\n        break;\n    }\n    \
+             return x + y + z + w + x;\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "`x` is used before it's assigned a value.",
+                "`w` is used before it's assigned a value.",
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_unreachable_code_and_endless_loops() {
+        let errors = check_flow(
+            "def main() void {\n    let mut i = 0;\n    \
+             while i < 10 {\n        println(\"again\");\n    }\n    \
+             while i < 10 {\n        i += 1;\n        continue;\n        println(i);\n    }\n    \
+             while true {\n        println(i);\n    }\n    \
+             println(i);\n}\n\n\
+             def stop() void {\n    break;\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "This loop never ends once it starts: nothing in it changes `i` or leaves the loop.",
+                "Unreachable code: it comes after a `continue`.",
+                "This loop never ends: its condition is always true and nothing in it leaves the \
+                 loop.",
+                "Unreachable code: it comes after a loop that never ends.",
+                "`break` can only be used inside a `while` loop.",
+            ]
+        );
+    }
+
+    #[test]
+    fn treats_never_calls_as_diverging() {
+        let errors = check_flow(
+            "def fail(message: str) never {\n    panic(message);\n}\n\n\
+             def loop_forever() never {\n    println(\"once\");\n}\n\n\
+             def pick(n: int) int {\n    if n > 0 {\n        return n;\n    }\n    \
+             fail(\"negative\");\n    println(n);\n}\n",
+        );
+        assert_eq!(
+            errors,
+            [
+                "`loop_forever` never returns, but the end of its body can be reached.",
+                "Unreachable code: it comes after a call that never returns.",
+            ]
+        );
+    }
+}
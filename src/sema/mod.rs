@@ -0,0 +1,241 @@
+//! Semantic analysis of parsed modules.
+
+use crate::parse::SourceCodeLocation;
+use crate::util::GenerateErrorMessage;
+use modules::ModuleGraph;
+
+pub mod builtins;
+pub mod closures;
+pub mod consts;
+pub mod exhaustive;
+pub mod flow;
+pub mod modules;
+pub mod mono;
+pub mod mutability;
+pub mod ops;
+pub mod resolve;
+pub mod ty;
+pub mod typeck;
+
+/// How serious a diagnostic is. Only errors stop the compilation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// An error or warning found by one of the semantic checks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub loc: SourceCodeLocation,
+    /// A suggestion on how to fix the problem.
+    pub help: Option<String>,
+    /// An edit that fixes the problem, which tools can apply without asking.
+    pub fix: Option<Box<FixIt>>,
+}
+
+/// A replacement of the source code covered by `loc`, inserting `replacement` when it's empty.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixIt {
+    pub loc: SourceCodeLocation,
+    pub replacement: String,
+}
+
+impl FixIt {
+    /// Insert `text` right before `loc`.
+    pub fn insert_before(loc: &SourceCodeLocation, text: impl Into<String>) -> Self {
+        Self {
+            loc: SourceCodeLocation {
+                length: 0,
+                ..loc.clone()
+            },
+            replacement: text.into(),
+        }
+    }
+
+    /// The line of the fix with the replacement applied.
+    fn apply_to_line(&self, source_code: &str) -> Option<String> {
+        let line = source_code.lines().nth(self.loc.line.checked_sub(1)?)?;
+        let start = line
+            .char_indices()
+            .nth(self.loc.column - 1)
+            .map_or(line.len(), |(i, _)| i);
+        let end = (start + self.loc.length).min(line.len());
+        Some(format!(
+            "{}{}{}",
+            &line[..start],
+            self.replacement,
+            &line[end..]
+        ))
+    }
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, loc: SourceCodeLocation) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            loc,
+            help: None,
+            fix: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, loc: SourceCodeLocation) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message, loc)
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn with_fix(mut self, fix: FixIt) -> Self {
+        self.fix = Some(Box::new(fix));
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl GenerateErrorMessage for Diagnostic {
+    fn generate_error_message(self, source_code: &str) -> String {
+        let mut message = self.loc.line_in_source_code(source_code);
+        if self.severity == Severity::Warning {
+            message = message.replacen("Error at", "Warning at", 1);
+            message += "\nWarning: ";
+        } else {
+            message += "\nError: ";
+        }
+        message += &self.message;
+        if let Some(help) = self.help {
+            message = message + "\nHelp: " + &help;
+        }
+        // The fixed line is only shown when the fix is in the same file, e.g. not for a field
+        // declared in another module.
+        if let Some(fix) = self.fix.filter(|fix| fix.loc.filename == self.loc.filename) {
+            if let Some(line) = fix.apply_to_line(source_code) {
+                message = message + "\nFix: " + &line;
+            }
+        }
+        message
+    }
+}
+
+/// What the semantic checks learn about a program that has no errors, which lowering builds on.
+#[derive(Debug)]
+pub struct Analysis {
+    pub resolutions: resolve::Resolutions,
+    pub results: typeck::TypeckResults,
+    pub closures: closures::Closures,
+    pub consts: consts::ConstValues,
+    pub instances: mono::Instances,
+}
+
+/// Run every semantic check on the program, returning the diagnostics of each file in source order.
+pub fn check(graph: &ModuleGraph) -> Vec<Diagnostic> {
+    analyze(graph).1
+}
+
+/// Resolve the names and check the types of the program, the phases the other checks build on.
+fn check_types(
+    graph: &ModuleGraph,
+) -> (resolve::Resolutions, typeck::TypeckResults, Vec<Diagnostic>) {
+    let mut diagnostics = graph.diagnostics.clone();
+    let (resolutions, resolve_diagnostics) = resolve::resolve(graph);
+    diagnostics.extend(resolve_diagnostics);
+    let (results, typeck_diagnostics) = typeck::check(graph, &resolutions);
+    diagnostics.extend(typeck_diagnostics);
+    (resolutions, results, diagnostics)
+}
+
+/// Load the source as the module `main.paca` and run the phases of `check_types` on it, for the
+/// tests of the checks that build on them. Panics if they find errors.
+#[cfg(test)]
+fn check_source(src: &str) -> (ModuleGraph, resolve::Resolutions, typeck::TypeckResults) {
+    let graph = modules::ModuleLoader::new(vec![])
+        .load_source(std::path::Path::new("main.paca"), src.to_string())
+        .unwrap();
+    let (resolutions, results, diagnostics) = check_types(&graph);
+    let errors: Vec<_> = diagnostics.iter().filter(|d| d.is_error()).collect();
+    assert!(errors.is_empty(), "{errors:?}");
+    (graph, resolutions, results)
+}
+
+/// Run every semantic check on the program, returning what they learned unless there are errors,
+/// and the diagnostics of each file in source order.
+pub fn analyze(graph: &ModuleGraph) -> (Option<Analysis>, Vec<Diagnostic>) {
+    let (resolutions, results, mut diagnostics) = check_types(graph);
+    let (initializations, flow_diagnostics) = flow::check(graph, &resolutions, &results);
+    diagnostics.extend(flow_diagnostics);
+    diagnostics.extend(mutability::check(
+        graph,
+        &resolutions,
+        &results,
+        &initializations,
+    ));
+    let (closures, closure_diagnostics) = closures::analyze(graph, &resolutions, &results);
+    diagnostics.extend(closure_diagnostics);
+    if !diagnostics.iter().any(Diagnostic::is_error) {
+        diagnostics.extend(exhaustive::check(graph, &results));
+    }
+    let mut consts = None;
+    if !diagnostics.iter().any(Diagnostic::is_error) {
+        let (values, const_diagnostics) = consts::evaluate(graph, &resolutions);
+        diagnostics.extend(const_diagnostics);
+        consts = Some(values);
+    }
+    let mut instances = None;
+    if !diagnostics.iter().any(Diagnostic::is_error) {
+        let (found, mono_diagnostics) = mono::collect(graph, &resolutions, &results);
+        diagnostics.extend(mono_diagnostics);
+        instances = Some(found);
+    }
+    diagnostics
+        .sort_by(|a, b| (&a.loc.filename, a.loc.offset).cmp(&(&b.loc.filename, b.loc.offset)));
+    let analysis = match (consts, instances) {
+        (Some(consts), Some(instances)) if !diagnostics.iter().any(Diagnostic::is_error) => {
+            Some(Analysis {
+                resolutions,
+                results,
+                closures,
+                consts,
+                instances,
+            })
+        }
+        _ => None,
+    };
+    (analysis, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modules::ModuleLoader;
+    use std::path::PathBuf;
+
+    fn load_fixture(name: &str) -> ModuleGraph {
+        let dir = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"));
+        ModuleLoader::new(vec![dir.clone()])
+            .load(&dir.join(name).with_extension("paca"))
+            .unwrap_or_else(|e| panic!("{}", e.message()))
+    }
+
+    #[test]
+    fn analyzes_the_readme_programs() {
+        for fixture in ["arrays", "either_option", "hashmap", "patterns"] {
+            let (analysis, diagnostics) = analyze(&load_fixture(fixture));
+            assert_eq!(diagnostics, vec![], "{fixture}");
+            assert!(analysis.is_some(), "{fixture}");
+        }
+        // The comments fixture is only about formatting, but its imports are real.
+        assert_eq!(load_fixture("comments").diagnostics, vec![]);
+    }
+}
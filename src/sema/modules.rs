@@ -0,0 +1,533 @@
+//! Loading the modules a program imports, and checking the imports and exports between them.
+//!
+//! The module `a::b::c` lives in the file `a/b/c.paca` under one of the source roots. Importing
+//! `a::b::c` brings the module `a::b::c` itself into scope if that file exists, and otherwise the item
+//! `c` exported by `a::b`. A grouped import such as `a::b::(c, d as e)` brings several items of `a::b`.
+//! Only the names a module lists in an `export` declaration can be imported from it.
+//!
+//! The std modules are built into the compiler, and found when no source root has a file for them.
+
+use super::builtins::{std_module, PRELUDE, PRELUDE_NAME};
+use super::Diagnostic;
+use crate::parse::ast::*;
+use crate::parse::{parse_source, SourceCodeLocation, SyntaxError};
+use crate::util::GenerateErrorMessage;
+use std::collections::HashMap;
+use std::path::{Path as FilePath, PathBuf};
+
+/// Index of a module in the `ModuleGraph`.
+pub type ModuleId = usize;
+
+/// A parsed module together with its source.
+#[derive(Debug)]
+pub struct LoadedModule {
+    /// The path of the module, e.g. `["std", "collections"]`.
+    pub name: Vec<String>,
+    pub file: PathBuf,
+    pub source: String,
+    pub ast: Module,
+    pub imports: Vec<ResolvedImport>,
+}
+
+impl LoadedModule {
+    /// The path of the module as written in imports.
+    pub fn display_name(&self) -> String {
+        self.name.join("::")
+    }
+
+    /// The item or import declaring `name` in this module.
+    pub fn declaration(&self, name: &str) -> Option<&Ident> {
+        declared_names(&self.ast)
+            .into_iter()
+            .find(|i| i.name == name)
+    }
+
+    /// Whether `name` is listed in one of the module's `export` declarations.
+    pub fn exports(&self, name: &str) -> bool {
+        self.ast.items.iter().any(|item| match &item.kind {
+            ItemKind::Export(names) => names.iter().any(|i| i.name == name),
+            _ => false,
+        })
+    }
+}
+
+/// A name brought into scope by an import.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResolvedImport {
+    /// The name in the importing module, which is the alias if there is one.
+    pub local: Ident,
+    pub module: ModuleId,
+    /// The imported item of `module`, `None` if the module itself is imported.
+    pub item: Option<String>,
+}
+
+/// All modules of a program, the entry module being the first one and the prelude the last one.
+#[derive(Debug)]
+pub struct ModuleGraph {
+    pub modules: Vec<LoadedModule>,
+    /// Problems with the imports and exports of the modules.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ModuleGraph {
+    /// The id of the prelude module.
+    pub fn prelude(&self) -> ModuleId {
+        self.modules.len() - 1
+    }
+
+    /// The source of the file a location points into, used to render diagnostics.
+    pub fn source_of(&self, loc: &SourceCodeLocation) -> &str {
+        self.modules
+            .iter()
+            .find(|m| loc.filename.as_deref() == Some(&*m.file.to_string_lossy()))
+            .map_or("", |m| &m.source)
+    }
+}
+
+/// Errors that stop a program from being loaded at all.
+#[derive(Debug)]
+pub enum LoadError {
+    Io {
+        file: PathBuf,
+        error: std::io::Error,
+    },
+    Syntax {
+        source: String,
+        error: Box<SyntaxError>,
+    },
+}
+
+impl LoadError {
+    pub fn message(self) -> String {
+        match self {
+            LoadError::Io { file, error } => format!("Can't read {}: {error}", file.display()),
+            LoadError::Syntax { source, error } => (*error).generate_error_message(&source),
+        }
+    }
+}
+
+/// Finds and parses the modules of a program.
+pub struct ModuleLoader {
+    roots: Vec<PathBuf>,
+}
+
+impl ModuleLoader {
+    /// Create a loader looking for modules in the given source roots, in order.
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        Self { roots }
+    }
+
+    /// Load the module in `entry` and every module it imports, directly or not.
+    pub fn load(&self, entry: &FilePath) -> Result<ModuleGraph, LoadError> {
+        let source = std::fs::read_to_string(entry).map_err(|error| LoadError::Io {
+            file: entry.to_path_buf(),
+            error,
+        })?;
+        self.load_source(entry, source)
+    }
+
+    /// Load a module from source code as if it were in `entry`, and every module it imports.
+    pub fn load_source(&self, entry: &FilePath, source: String) -> Result<ModuleGraph, LoadError> {
+        let mut graph = ModuleGraph {
+            modules: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        let mut ids = HashMap::new();
+        add(
+            &mut graph,
+            self.module_name(entry),
+            entry.to_path_buf(),
+            source,
+        )?;
+        ids.insert(graph.modules[0].name.clone(), 0);
+
+        let mut next = 0;
+        while next < graph.modules.len() {
+            let imports = graph.modules[next]
+                .ast
+                .items
+                .iter()
+                .filter_map(|item| match &item.kind {
+                    ItemKind::Import(import) => Some(import.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            for import in imports {
+                let resolved = self.import(&mut graph, &mut ids, &import)?;
+                graph.modules[next].imports.extend(resolved);
+            }
+            next += 1;
+        }
+
+        add(
+            &mut graph,
+            vec![PRELUDE_NAME.to_string()],
+            PathBuf::from(format!("<{PRELUDE_NAME}>")),
+            PRELUDE.to_string(),
+        )?;
+
+        for id in 0..graph.modules.len() {
+            check_imports(&mut graph, id);
+            check_exports(&mut graph, id);
+        }
+        check_cycles(&mut graph);
+        Ok(graph)
+    }
+
+    /// The name of a module from the path of its file relative to a source root.
+    fn module_name(&self, file: &FilePath) -> Vec<String> {
+        let relative = self
+            .roots
+            .iter()
+            .find_map(|root| file.strip_prefix(root).ok())
+            .unwrap_or_else(|| FilePath::new(file.file_name().unwrap_or_default()));
+        relative
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn find(&self, name: &[String]) -> Option<PathBuf> {
+        self.roots.iter().find_map(|root| {
+            let mut file = root.join(name.join("/"));
+            file.set_extension("paca");
+            file.is_file().then_some(file)
+        })
+    }
+
+    /// Load the module a name refers to, unless it's loaded already.
+    fn module(
+        &self,
+        graph: &mut ModuleGraph,
+        ids: &mut HashMap<Vec<String>, ModuleId>,
+        name: &[String],
+    ) -> Result<Option<ModuleId>, LoadError> {
+        if let Some(&id) = ids.get(name) {
+            return Ok(Some(id));
+        }
+        let (file, source) = match self.find(name) {
+            Some(file) => {
+                let source = std::fs::read_to_string(&file).map_err(|error| LoadError::Io {
+                    file: file.clone(),
+                    error,
+                })?;
+                (file, source)
+            }
+            None => match std_module(name) {
+                Some(source) => (
+                    PathBuf::from(format!("<{}>", name.join("::"))),
+                    source.to_string(),
+                ),
+                None => return Ok(None),
+            },
+        };
+        let id = add(graph, name.to_vec(), file, source)?;
+        ids.insert(name.to_vec(), id);
+        Ok(Some(id))
+    }
+
+    fn import(
+        &self,
+        graph: &mut ModuleGraph,
+        ids: &mut HashMap<Vec<String>, ModuleId>,
+        import: &Import,
+    ) -> Result<Vec<ResolvedImport>, LoadError> {
+        let path = import
+            .path
+            .iter()
+            .map(|i| i.name.clone())
+            .collect::<Vec<_>>();
+        let last = import
+            .path
+            .last()
+            .expect("imports have at least one segment");
+
+        if let Some(group) = &import.group {
+            let Some(module) = self.module(graph, ids, &path)? else {
+                graph.diagnostics.push(self.not_found(&path, &import.path));
+                return Ok(Vec::new());
+            };
+            return Ok(group
+                .iter()
+                .map(|name| ResolvedImport {
+                    local: name.local().clone(),
+                    module,
+                    item: Some(name.name.name.clone()),
+                })
+                .collect());
+        }
+
+        let local = import.alias.clone().unwrap_or_else(|| last.clone());
+        if let Some(module) = self.module(graph, ids, &path)? {
+            return Ok(vec![ResolvedImport {
+                local,
+                module,
+                item: None,
+            }]);
+        }
+        let parent = &path[..path.len() - 1];
+        match self.module(graph, ids, parent)? {
+            Some(module) if !parent.is_empty() => Ok(vec![ResolvedImport {
+                local,
+                module,
+                item: Some(last.name.clone()),
+            }]),
+            _ => {
+                graph.diagnostics.push(self.not_found(&path, &import.path));
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn not_found(&self, name: &[String], path: &[Ident]) -> Diagnostic {
+        let loc = path[0].loc.to(&path[path.len() - 1].loc);
+        let mut file = PathBuf::from(name.join("/"));
+        file.set_extension("paca");
+        let candidates = self
+            .roots
+            .iter()
+            .map(|root| root.join(&file).display().to_string())
+            .collect::<Vec<_>>();
+        Diagnostic::error(format!("Can't find the module `{}`.", name.join("::")), loc)
+            .with_help(format!("there is no {}.", candidates.join(" or ")))
+    }
+}
+
+/// Parse a module and add it to the graph.
+fn add(
+    graph: &mut ModuleGraph,
+    name: Vec<String>,
+    file: PathBuf,
+    source: String,
+) -> Result<ModuleId, LoadError> {
+    let ast = match parse_source(Some(file.to_string_lossy().into_owned()), &source) {
+        Ok(ast) => ast,
+        Err(error) => {
+            return Err(LoadError::Syntax {
+                source,
+                error: Box::new(error),
+            })
+        }
+    };
+    graph.modules.push(LoadedModule {
+        name,
+        file,
+        source,
+        ast,
+        imports: Vec::new(),
+    });
+    Ok(graph.modules.len() - 1)
+}
+
+/// The names of the items and imports declared at the top level of a module.
+fn declared_names(module: &Module) -> Vec<&Ident> {
+    let mut names = Vec::new();
+    for item in &module.items {
+        match &item.kind {
+            ItemKind::Struct(decl) => names.push(&decl.name),
+            ItemKind::Enum(decl) => names.push(&decl.name),
+            ItemKind::Fn(decl) => names.push(&decl.name),
+            ItemKind::Trait(decl) => names.push(&decl.name),
+            ItemKind::Const(decl) => names.push(&decl.name),
+            ItemKind::Import(Import {
+                group: Some(group), ..
+            }) => names.extend(group.iter().map(ImportName::local)),
+            ItemKind::Import(import) => names.extend(import.alias.as_ref().or(import.path.last())),
+            ItemKind::Export(_) | ItemKind::Impl(_) => {}
+        }
+    }
+    names
+}
+
+/// Check that every imported item exists and is exported.
+fn check_imports(graph: &mut ModuleGraph, id: ModuleId) {
+    let mut diagnostics = Vec::new();
+    for import in &graph.modules[id].imports {
+        let Some(item) = &import.item else {
+            continue;
+        };
+        let target = &graph.modules[import.module];
+        let loc = import.local.loc.clone();
+        if target.declaration(item).is_none() {
+            diagnostics.push(Diagnostic::error(
+                format!(
+                    "`{item}` is not declared in the module `{}`.",
+                    target.display_name()
+                ),
+                loc,
+            ));
+        } else if !target.exports(item) {
+            diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "`{item}` is private to the module `{}`.",
+                        target.display_name()
+                    ),
+                    loc,
+                )
+                .with_help(format!(
+                    "add `export {item};` to {}.",
+                    target.file.display()
+                )),
+            );
+        }
+    }
+    graph.diagnostics.extend(diagnostics);
+}
+
+/// Check that every exported name is declared in the module.
+fn check_exports(graph: &mut ModuleGraph, id: ModuleId) {
+    let module = &graph.modules[id];
+    for item in &module.ast.items {
+        if let ItemKind::Export(names) = &item.kind {
+            for name in names {
+                if module.declaration(&name.name).is_none() {
+                    graph.diagnostics.push(Diagnostic::error(
+                        format!(
+                            "`{}` can't be exported because it's not declared in this module.",
+                            name.name
+                        ),
+                        name.loc.clone(),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Report every import that closes a cycle of modules importing each other.
+fn check_cycles(graph: &mut ModuleGraph) {
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        Unvisited,
+        Visiting,
+        Done,
+    }
+
+    fn visit(
+        graph: &ModuleGraph,
+        id: ModuleId,
+        states: &mut [State],
+        stack: &mut Vec<ModuleId>,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        states[id] = State::Visiting;
+        stack.push(id);
+        for import in &graph.modules[id].imports {
+            match states[import.module] {
+                State::Unvisited => visit(graph, import.module, states, stack, diagnostics),
+                State::Visiting => {
+                    let start = stack.iter().position(|&m| m == import.module).unwrap_or(0);
+                    let cycle = stack[start..]
+                        .iter()
+                        .chain([&import.module])
+                        .map(|&m| graph.modules[m].display_name())
+                        .collect::<Vec<_>>();
+                    diagnostics.push(Diagnostic::error(
+                        format!("Cyclic import: {}.", cycle.join(" -> ")),
+                        import.local.loc.clone(),
+                    ));
+                }
+                State::Done => {}
+            }
+        }
+        stack.pop();
+        states[id] = State::Done;
+    }
+
+    let mut states = vec![State::Unvisited; graph.modules.len()];
+    let mut diagnostics = Vec::new();
+    for id in 0..graph.modules.len() {
+        if states[id] == State::Unvisited {
+            visit(graph, id, &mut states, &mut Vec::new(), &mut diagnostics);
+        }
+    }
+    graph.diagnostics.extend(diagnostics);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(case: &str) -> ModuleGraph {
+        let root = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/modules")).join(case);
+        ModuleLoader::new(vec![root.clone()])
+            .load(&root.join("main.paca"))
+            .unwrap()
+    }
+
+    #[test]
+    fn resolves_imports_across_files() {
+        let graph = load("ok");
+        assert_eq!(graph.diagnostics, vec![]);
+
+        let names = graph
+            .modules
+            .iter()
+            .map(LoadedModule::display_name)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "main",
+                "geometry::shapes",
+                "geometry::units",
+                "util",
+                "prelude"
+            ]
+        );
+
+        let imports = graph.modules[0]
+            .imports
+            .iter()
+            .map(|i| {
+                let module = graph.modules[i.module].display_name();
+                (i.local.name.as_str(), module, i.item.as_deref())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            imports,
+            [
+                ("Circle", "geometry::shapes".to_string(), Some("Circle")),
+                ("circle_area", "geometry::shapes".to_string(), Some("area")),
+                ("units", "geometry::units".to_string(), None),
+                ("Tuple", "util".to_string(), Some("Pair")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unresolved_private_and_cyclic_imports() {
+        let graph = load("errors");
+        let messages = graph
+            .diagnostics
+            .iter()
+            .map(|d| (d.loc.line, d.message.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                (1, "Can't find the module `missing::module`."),
+                (2, "`hidden` is private to the module `lib`."),
+                (2, "`nothing` is not declared in the module `lib`."),
+                (
+                    4,
+                    "`ghost` can't be exported because it's not declared in this module."
+                ),
+                (1, "Cyclic import: cycle_a -> cycle_b -> cycle_a."),
+            ]
+        );
+        assert!(graph.diagnostics[1]
+            .help
+            .as_deref()
+            .unwrap()
+            .starts_with("add `export hidden;` to "));
+        assert!(graph.diagnostics[4]
+            .loc
+            .filename
+            .as_deref()
+            .unwrap()
+            .ends_with("cycle_b.paca"));
+    }
+}
@@ -0,0 +1,364 @@
+//! Monomorphization: finding the concrete instances of the generic functions and methods a program
+//! uses, so that each one is lowered once per distinct list of generic arguments.
+//!
+//! The search starts from every non-generic function and method and walks their bodies. A call to a
+//! generic callee substitutes the generic arguments of the caller's own instance into the arguments
+//! recorded by the type checker, so `Option::unwrap` called from `first<T>` instantiated with `int`
+//! becomes `Option::unwrap<int>`. Trait methods called on a generic parameter become the method of
+//! the impl for the parameter's type, and default methods are instantiated for each impl that
+//! inherits them.
+
+use super::modules::{ModuleGraph, ModuleId};
+use super::resolve::{DefId, Res, Resolutions};
+use super::ty::Ty;
+use super::typeck::{Bound, Items, MethodId, TypeckResults};
+use super::Diagnostic;
+use crate::parse::ast::{Expr, ExprKind, FnDecl, ItemKind};
+use crate::parse::visit::{walk, Visitor};
+use crate::parse::SourceCodeLocation;
+use std::collections::HashMap;
+
+/// How deeply instances may instantiate each other before the arguments are assumed to grow
+/// forever, as in `def f<T>(x: T) { f([x]); }`.
+const MAX_DEPTH: usize = 64;
+
+/// A function or method with a body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Callee {
+    Fn(DefId),
+    Method(MethodId),
+}
+
+/// A callee with concrete types for its generic parameters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Instance {
+    pub callee: Callee,
+    /// The generic arguments, in the order of the generic parameters of the callee's signature.
+    pub args: Vec<Ty>,
+}
+
+/// The instances a program uses, each stored once.
+#[derive(Debug, Default)]
+pub struct Instances {
+    pub list: Vec<Instance>,
+    cache: HashMap<Instance, usize>,
+}
+
+impl Instances {
+    /// The index of the instance in `list`.
+    pub fn get(&self, instance: &Instance) -> Option<usize> {
+        self.cache.get(instance).copied()
+    }
+
+    /// Add the instance unless it's already known, returning its index and whether it's new.
+    fn insert(&mut self, instance: Instance) -> (usize, bool) {
+        if let Some(&index) = self.cache.get(&instance) {
+            return (index, false);
+        }
+        self.list.push(instance.clone());
+        self.cache.insert(instance, self.list.len() - 1);
+        (self.list.len() - 1, true)
+    }
+}
+
+/// The declaration of the callee and the module it's in, which is the trait's for the default
+/// methods an impl inherits.
+pub fn decl<'a>(graph: &'a ModuleGraph, items: &Items, callee: Callee) -> (ModuleId, &'a FnDecl) {
+    let (def, index) = match callee {
+        Callee::Fn(def) => (def, None),
+        Callee::Method(id) => match items.method(id).inherited {
+            Some(inherited) => (inherited.trait_def, Some(inherited.index)),
+            None => (id.imp, Some(id.index)),
+        },
+    };
+    let decl = match (&graph.modules[def.module].ast.items[def.item].kind, index) {
+        (ItemKind::Fn(decl), None) => decl,
+        (ItemKind::Impl(decl), Some(index)) => &decl.methods[index],
+        (ItemKind::Trait(decl), Some(index)) => &decl.methods[index],
+        _ => unreachable!("callees are functions or methods"),
+    };
+    (def.module, decl)
+}
+
+/// What the generic parameters in the body of an instance stand for: its generic arguments, the
+/// associated types of the traits they're bound by, and inside methods of trait impls, `Self` and
+/// the parameters of the trait.
+pub fn substs(items: &Items, instance: &Instance) -> HashMap<String, Ty> {
+    let generics = match instance.callee {
+        Callee::Fn(def) => &items.fns[&def].generics,
+        Callee::Method(id) => &items.method(id).sig.generics,
+    };
+    let mut substs = generics
+        .iter()
+        .map(|g| g.name.clone())
+        .zip(instance.args.iter().cloned())
+        .collect::<HashMap<_, _>>();
+    for (generic, arg) in generics.iter().zip(&instance.args) {
+        for bound in &generic.bounds {
+            let Bound::Trait(trait_def) = *bound else {
+                continue;
+            };
+            if let Some((imp, impl_substs)) = items.trait_impl(trait_def, arg) {
+                for (name, ty) in &items.impls[&imp].assoc_types {
+                    substs.insert(format!("{}::{name}", generic.name), ty.subst(&impl_substs));
+                }
+            }
+        }
+    }
+    if let Callee::Method(id) = instance.callee {
+        let imp = &items.impls[&id.imp];
+        if let Some(trait_ref) = &imp.trait_ref {
+            let trait_generics = &items.traits[&trait_ref.def].generics;
+            let mut outer = HashMap::from([("Self".to_string(), imp.target.subst(&substs))]);
+            for (generic, arg) in trait_generics.iter().zip(&trait_ref.args) {
+                outer.insert(generic.name.clone(), arg.subst(&substs));
+            }
+            for (name, ty) in &imp.assoc_types {
+                outer.insert(format!("Self::{name}"), ty.subst(&substs));
+            }
+            substs.extend(outer);
+        }
+    }
+    substs
+}
+
+/// Collect the instances reachable from the non-generic functions and methods of a program that
+/// type checked without errors.
+pub fn collect(
+    graph: &ModuleGraph,
+    res: &Resolutions,
+    results: &TypeckResults,
+) -> (Instances, Vec<Diagnostic>) {
+    let mut instances = Instances::default();
+    let mut diagnostics = Vec::new();
+    let items = &results.items;
+    let mut roots = items
+        .fns
+        .iter()
+        .filter(|(def, sig)| sig.generics.is_empty() && !items.natives.contains_key(def))
+        .map(|(&def, _)| Callee::Fn(def))
+        .chain(items.impls.iter().flat_map(|(&imp, def)| {
+            (0..def.methods.len())
+                .map(move |index| MethodId { imp, index })
+                .filter(|&id| items.method(id).sig.generics.is_empty())
+                .map(Callee::Method)
+        }))
+        .collect::<Vec<_>>();
+    roots.sort_by_key(|callee| match *callee {
+        Callee::Fn(def) => (def, 0),
+        Callee::Method(id) => (id.imp, id.index + 1),
+    });
+    let mut worklist = Vec::new();
+    for callee in roots {
+        let (index, _) = instances.insert(Instance {
+            callee,
+            args: Vec::new(),
+        });
+        worklist.push((index, 0));
+    }
+
+    while let Some((index, depth)) = worklist.pop() {
+        let instance = instances.list[index].clone();
+        let (module, decl) = decl(graph, items, instance.callee);
+        let substs = substs(items, &instance);
+        let mut finder = CalleeFinder {
+            module,
+            res,
+            results,
+            substs,
+            found: Vec::new(),
+        };
+        if let Some(body) = &decl.body {
+            finder.visit_block(body);
+        }
+
+        for (instance, expr) in finder.found {
+            let callee = instance.callee;
+            let (index, new) = instances.insert(instance);
+            if !new {
+                continue;
+            }
+            if depth == MAX_DEPTH {
+                let name = &self::decl(graph, items, callee).1.name.name;
+                diagnostics.push(
+                    Diagnostic::error(
+                        format!("Instantiating `{name}` never ends: its generic arguments keep growing."),
+                        expr,
+                    )
+                    .with_help(format!(
+                        "`{name}` is instantiated more than {MAX_DEPTH} levels deep."
+                    )),
+                );
+                return (instances, diagnostics);
+            }
+            worklist.push((index, depth + 1));
+        }
+    }
+    (instances, diagnostics)
+}
+
+/// The instance an expression calls or names, with the generic arguments of the enclosing instance
+/// substituted: the impl method of a trait method called on a generic parameter, a method or
+/// associated function, or a function.
+pub fn instance_of(
+    module: ModuleId,
+    res: &Resolutions,
+    results: &TypeckResults,
+    substs: &HashMap<String, Ty>,
+    expr: &Expr,
+) -> Option<Instance> {
+    if let Some(instance) = trait_call(module, results, substs, expr) {
+        return Some(instance);
+    }
+    let callee = match (&expr.kind, results.method(module, expr)) {
+        (_, Some(id)) => Callee::Method(id),
+        (ExprKind::Path(path), None) => {
+            let last = path
+                .segments
+                .last()
+                .expect("paths have at least one segment");
+            match res.get(module, &last.ident) {
+                Some(Res::Def(def))
+                    if results.items.fns.contains_key(&def)
+                        && !results.items.natives.contains_key(&def) =>
+                {
+                    Callee::Fn(def)
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    let args = results
+        .generic_args(module, expr)
+        .iter()
+        .map(|arg| arg.subst(substs))
+        .collect();
+    Some(Instance { callee, args })
+}
+
+/// The method of the impl a trait method called on a generic parameter ends up calling.
+fn trait_call(
+    module: ModuleId,
+    results: &TypeckResults,
+    substs: &HashMap<String, Ty>,
+    expr: &Expr,
+) -> Option<Instance> {
+    let (id, self_ty) = results.trait_call(module, expr)?;
+    let items = &results.items;
+    let self_ty = self_ty.subst(substs);
+    let (imp, impl_substs) = items.trait_impl(id.trait_def, &self_ty)?;
+    let name = &items.trait_method(*id).name;
+    let index = items.impls[&imp]
+        .methods
+        .iter()
+        .position(|m| &m.name == name)?;
+    let trait_generics = items.traits[&id.trait_def].generics.len();
+    let own_args = results.generic_args(module, expr);
+    let args = items.impls[&imp]
+        .generics
+        .iter()
+        .map(|g| impl_substs.get(&g.name).cloned().unwrap_or(Ty::Error))
+        .chain(
+            own_args
+                .iter()
+                .skip(trait_generics)
+                .map(|arg| arg.subst(substs)),
+        )
+        .collect();
+    Some(Instance {
+        callee: Callee::Method(MethodId { imp, index }),
+        args,
+    })
+}
+
+/// Finds the callees of a body with the generic arguments of the enclosing instance substituted.
+struct CalleeFinder<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    results: &'a TypeckResults,
+    substs: HashMap<String, Ty>,
+    found: Vec<(Instance, SourceCodeLocation)>,
+}
+
+impl Visitor for CalleeFinder<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Some(instance) = instance_of(self.module, self.res, self.results, &self.substs, expr)
+        {
+            self.found.push((instance, expr.loc.clone()));
+        }
+        walk::walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::check_source;
+
+    fn instances(src: &str) -> (Vec<String>, Vec<String>) {
+        let (graph, res, results) = check_source(src);
+        let (instances, diagnostics) = collect(&graph, &res, &results);
+        let names = instances
+            .list
+            .iter()
+            .filter(|instance| !instance.args.is_empty())
+            .map(|instance| {
+                let args = instance.args.iter().map(Ty::to_string).collect::<Vec<_>>();
+                format!(
+                    "{}<{}>",
+                    decl(&graph, &results.items, instance.callee).1.name.name,
+                    args.join(", ")
+                )
+            })
+            .collect();
+        (names, diagnostics.into_iter().map(|d| d.message).collect())
+    }
+
+    #[test]
+    fn caches_instances() {
+        let (mut names, errors) = instances(
+            "def id<T>(x: T) T {\n    return x;\n}\n\n\
+             def main() void {\n    id(1);\n    id(2);\n    id(\"a\");\n}\n",
+        );
+        names.sort();
+        assert_eq!(names, ["id<int>", "id<str>"]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn propagates_arguments_through_generic_code() {
+        let (mut names, _) = instances(
+            "def first<T>(x: T) Option<T> {\n    return wrap(x);\n}\n\n\
+             def wrap<U>(x: U) Option<U> {\n    return Option::Some(x);\n}\n\n\
+             def main() void {\n    first(1.5)->unwrap();\n}\n",
+        );
+        names.sort();
+        assert_eq!(names, ["first<float>", "unwrap<float>", "wrap<float>"]);
+    }
+
+    #[test]
+    fn stops_growing_instantiations() {
+        let (_, errors) = instances(
+            "def grow<T>(x: T) void {\n    grow([x]);\n}\n\ndef main() void {\n    grow(1);\n}\n",
+        );
+        assert_eq!(
+            errors,
+            ["Instantiating `grow` never ends: its generic arguments keep growing."]
+        );
+    }
+
+    #[test]
+    fn resolves_trait_calls_to_impls() {
+        let (mut names, errors) = instances(
+            "trait Shape {\n    def area(self) float;\n\n    def double(self) float {\n        return self->area() * 2.0;\n    }\n}\n\n\
+             struct Square {\n    side: float,\n}\n\n\
+             impl Shape for Square {\n    def area(self) float {\n        return self->side;\n    }\n}\n\n\
+             def twice<S: Shape>(shape: S) float {\n    return shape->double();\n}\n\n\
+             def main() void {\n    twice(Square { side => 1.0 });\n}\n",
+        );
+        names.sort();
+        assert_eq!(names, ["twice<Square>"]);
+        assert!(errors.is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+//! Checking that assignments only write to what is declared mutable.
+//!
+//! Bindings are immutable unless declared with `mut`, as in `let mut total = 0;`, `mut n: int` or
+//! `Point { mut x, .. }`, and only those can be the target of `=` or a compound assignment. A local
+//! declared without a value, as in `let y: int;`, may still be assigned its first value wherever
+//! flow analysis finds it unassigned on every path. Struct
+//! values are shared by reference, so whether a field may change is decided by the struct rather
+//! than by whoever holds the value: only fields marked `$` can be assigned through `->`. Arrays are
+//! shared the same way, and the functions of `Array` change them in place whatever the binding.
+//!
+//! Every rejection carries a fix-it adding the missing `mut ` or `$` to the declaration.
+
+use super::flow::Initializations;
+use super::modules::{ModuleGraph, ModuleId};
+use super::resolve::{LocalId, Res, Resolutions};
+use super::ty::Ty;
+use super::typeck::{AdtKind, TypeckResults};
+use super::{Diagnostic, FixIt};
+use crate::parse::ast::{Expr, ExprKind, Param, Pattern, PatternKind, Stmt, StmtKind};
+use crate::parse::visit::{walk, Visitor};
+use std::collections::HashSet;
+
+/// Report the assignments to bindings that aren't `mut` and to fields that aren't `$`.
+pub fn check(
+    graph: &ModuleGraph,
+    res: &Resolutions,
+    results: &TypeckResults,
+    initializations: &Initializations,
+) -> Vec<Diagnostic> {
+    let mut checker = MutabilityChecker {
+        module: 0,
+        res,
+        results,
+        initializations,
+        in_param: false,
+        params: HashSet::new(),
+        diagnostics: Vec::new(),
+    };
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        checker.module = module;
+        checker.visit_module(&loaded.ast);
+    }
+    checker.diagnostics
+}
+
+struct MutabilityChecker<'a> {
+    module: ModuleId,
+    res: &'a Resolutions,
+    results: &'a TypeckResults,
+    initializations: &'a Initializations,
+    /// Whether the pattern being visited is the pattern of a function parameter.
+    in_param: bool,
+    /// The locals bound by function parameters, to name them as such.
+    params: HashSet<LocalId>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl MutabilityChecker<'_> {
+    fn assign(&mut self, target: &Expr) {
+        match &target.kind {
+            ExprKind::Path(path) if path.segments.len() == 1 => {
+                let Some(Res::Local(id)) = self.res.get(self.module, &path.segments[0].ident)
+                else {
+                    return;
+                };
+                let local = &self.res.locals[id];
+                if local.mutable || self.initializations.contains(self.module, target) {
+                    return;
+                }
+                let what = if self.params.contains(&id) {
+                    "The parameter"
+                } else {
+                    "The variable"
+                };
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "{what} `{}` can't be assigned to, because it isn't declared `mut`.",
+                            local.name
+                        ),
+                        target.loc.clone(),
+                    )
+                    .with_help(format!(
+                        "declare it as `mut {}` on line {}.",
+                        local.name, local.loc.line
+                    ))
+                    .with_fix(FixIt::insert_before(&local.loc, "mut ")),
+                );
+            }
+            ExprKind::Field { base, field } => {
+                let Some(Ty::Adt { def, name, .. }) = self.results.expr_ty(self.module, base)
+                else {
+                    return;
+                };
+                let AdtKind::Struct(fields) = &self.results.items.adts[def].kind else {
+                    return;
+                };
+                let Some(found) = fields.iter().find(|f| f.name == field.name) else {
+                    return;
+                };
+                if found.mutable {
+                    return;
+                }
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "The field `{}` of `{name}` can't be assigned to, because it isn't \
+                             marked `$`.",
+                            field.name
+                        ),
+                        target.loc.clone(),
+                    )
+                    .with_help(format!(
+                        "mark it as `${}` in the declaration of `{name}`.",
+                        field.name
+                    ))
+                    .with_fix(FixIt::insert_before(&found.loc, "$")),
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for MutabilityChecker<'_> {
+    fn visit_param(&mut self, param: &Param) {
+        self.in_param = true;
+        walk::walk_param(self, param);
+        self.in_param = false;
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        if let PatternKind::Binding { name, .. } = &pattern.kind {
+            if self.in_param {
+                if let Some(Res::Local(local)) = self.res.get(self.module, name) {
+                    self.params.insert(local);
+                }
+            }
+        }
+        walk::walk_pattern(self, pattern);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let StmtKind::Assign { target, .. } = &stmt.kind {
+            self.assign(target);
+        }
+        walk::walk_stmt(self, stmt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::{check_source, flow};
+
+    fn check_mutability(src: &str) -> Vec<Diagnostic> {
+        let (graph, res, results) = check_source(src);
+        let (initializations, _) = flow::check(&graph, &res, &results);
+        check(&graph, &res, &results, &initializations)
+    }
+
+    #[test]
+    fn rejects_writes_to_immutable_bindings() {
+        let diagnostics = check_mutability(
+            "def count(n: int, mut step: int) int {\n    let total = 0;\n    \
+             let mut seen = 0;\n    let (a, mut b) = (1, 2);\n    \
+             total += n;\n    seen += 1;\n    step = 2;\n    n = a;\n    b = a;\n    \
+             return total + seen + step;\n}\n",
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "The variable `total` can't be assigned to, because it isn't declared `mut`.",
+                "The parameter `n` can't be assigned to, because it isn't declared `mut`.",
+            ]
+        );
+        let fix = diagnostics[0].fix.as_ref().unwrap();
+        assert_eq!((fix.loc.line, fix.loc.column), (2, 9));
+        assert_eq!(fix.replacement, "mut ");
+    }
+
+    #[test]
+    fn allows_first_assignments_of_locals_declared_without_a_value() {
+        let diagnostics = check_mutability(
+            "def pick(c: bool) int {\n    let y: int;\n    if c {\n        y = 1;\n    } else {\n        \
+             y = 2;\n    }\n    let z: int;\n    z = 1;\n    z = 2;\n    \
+             let w: int;\n    while c {\n        w = 1;\n        break;\n    }\n    \
+             let v: int;\n    while c {\n        v = 1;\n    }\n    \
+             let u: int;\n    while c {\n        let t: int;\n        t = 1;\n        u = t;\n    }\n    \
+             return y + z;\n}\n",
+        );
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "The variable `z` can't be assigned to, because it isn't declared `mut`.",
+                "The variable `v` can't be assigned to, because it isn't declared `mut`.",
+                "The variable `u` can't be assigned to, because it isn't declared `mut`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_writes_to_fields_not_marked_dollar() {
+        let diagnostics = check_mutability(
+            "struct Counter {\n    $count: int,\n    step: int,\n}\n\n\
+             impl methods for Counter {\n    def bump(self) void {\n        \
+             self->count += self->step;\n        self->step = 1;\n    }\n}\n",
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "The field `step` of `Counter` can't be assigned to, because it isn't marked `$`."
+        );
+        let fix = diagnostics[0].fix.as_ref().unwrap();
+        assert_eq!((fix.loc.line, fix.loc.column), (3, 5));
+        assert_eq!(fix.replacement, "$");
+    }
+
+    #[test]
+    fn shows_the_fixed_line() {
+        use crate::util::GenerateErrorMessage;
+
+        let src = "def main() void {\n    let done = false;\n    done = true;\n}\n";
+        let diagnostics = check_mutability(src);
+        let message = diagnostics[0].clone().generate_error_message(src);
+        assert!(
+            message.ends_with(
+                "Help: declare it as `mut done` on line 2.\nFix:     let mut done = false;"
+            ),
+            "{message}"
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! Operator overloading through the std operator traits.
+//!
+//! Every arithmetic, comparison and negation operator dispatches through a trait, e.g. `a + b` calls
+//! `std::ops::Add::add` and `a < b` calls `std::cmp::Order::less`. The traits are declared in those
+//! std modules and imported like any other. The primitive types implement the traits they support
+//! built in, user types implement them with `impl Add for Point { ... }`, which type checking
+//! collects and checks like the impls of every other trait.
+//! `&&` and `||` short-circuit and only ever work on `bool`.
+//!
+//! Compound assignments such as `a += b` call the matching `AddAssign` method if the type implements
+//! it, and are evaluated as `a = a + b` otherwise.
+
+use super::builtins::Primitive;
+use super::ty::Ty;
+use super::Diagnostic;
+use crate::parse::ast::*;
+use crate::parse::SourceCodeLocation;
+
+/// A std trait that an operator dispatches through.
+#[derive(Debug, PartialEq)]
+pub struct OperatorTrait {
+    /// The std module the trait is declared in.
+    pub module: &'static str,
+    pub name: &'static str,
+    /// The single method of the trait.
+    pub method: &'static str,
+    /// Whether the method takes the right-hand side as a parameter besides `self`.
+    pub binary: bool,
+    /// The return type of the method, `None` meaning `Self`.
+    pub returns: Option<&'static str>,
+}
+
+impl OperatorTrait {
+    const fn new(
+        module: &'static str,
+        name: &'static str,
+        method: &'static str,
+        binary: bool,
+        returns: Option<&'static str>,
+    ) -> Self {
+        Self {
+            module,
+            name,
+            method,
+            binary,
+            returns,
+        }
+    }
+
+    /// The full path of the trait, e.g. `std::ops::Add`.
+    pub fn path(&self) -> String {
+        format!("std::{}::{}", self.module, self.name)
+    }
+
+    /// The signature of the method when implemented for `ty`, used in suggestions.
+    pub fn signature(&self, ty: &str) -> String {
+        let params = if self.binary {
+            format!("self, other: {ty}")
+        } else {
+            "self".to_string()
+        };
+        format!(
+            "def {}({params}) {}",
+            self.method,
+            self.returns.unwrap_or(ty)
+        )
+    }
+}
+
+pub const ADD: OperatorTrait = OperatorTrait::new("ops", "Add", "add", true, None);
+pub const SUB: OperatorTrait = OperatorTrait::new("ops", "Sub", "sub", true, None);
+pub const MUL: OperatorTrait = OperatorTrait::new("ops", "Mul", "mul", true, None);
+pub const DIV: OperatorTrait = OperatorTrait::new("ops", "Div", "div", true, None);
+pub const REM: OperatorTrait = OperatorTrait::new("ops", "Rem", "rem", true, None);
+pub const SHL: OperatorTrait = OperatorTrait::new("ops", "Shl", "shl", true, None);
+pub const SHR: OperatorTrait = OperatorTrait::new("ops", "Shr", "shr", true, None);
+pub const NEGATE: OperatorTrait = OperatorTrait::new("ops", "Negate", "negate", false, None);
+pub const NOT: OperatorTrait = OperatorTrait::new("ops", "Not", "not", false, None);
+pub const EQUAL: OperatorTrait = OperatorTrait::new("cmp", "Equal", "equal", true, Some("bool"));
+pub const ORDER: OperatorTrait = OperatorTrait::new("cmp", "Order", "less", true, Some("bool"));
+pub const ADD_ASSIGN: OperatorTrait =
+    OperatorTrait::new("ops", "AddAssign", "add_assign", true, Some("void"));
+pub const SUB_ASSIGN: OperatorTrait =
+    OperatorTrait::new("ops", "SubAssign", "sub_assign", true, Some("void"));
+pub const MUL_ASSIGN: OperatorTrait =
+    OperatorTrait::new("ops", "MulAssign", "mul_assign", true, Some("void"));
+pub const DIV_ASSIGN: OperatorTrait =
+    OperatorTrait::new("ops", "DivAssign", "div_assign", true, Some("void"));
+pub const REM_ASSIGN: OperatorTrait =
+    OperatorTrait::new("ops", "RemAssign", "rem_assign", true, Some("void"));
+/// No operator calls `std::hash::Hashable`, but the primitive types implement it built in like the
+/// operator traits.
+pub const HASHABLE: OperatorTrait =
+    OperatorTrait::new("hash", "Hashable", "hash", false, Some("int"));
+
+/// Every operator trait.
+pub const OPERATOR_TRAITS: [&OperatorTrait; 16] = [
+    &ADD,
+    &SUB,
+    &MUL,
+    &DIV,
+    &REM,
+    &SHL,
+    &SHR,
+    &NEGATE,
+    &NOT,
+    &EQUAL,
+    &ORDER,
+    &ADD_ASSIGN,
+    &SUB_ASSIGN,
+    &MUL_ASSIGN,
+    &DIV_ASSIGN,
+    &REM_ASSIGN,
+];
+
+/// The operator traits and `Hashable` implemented by a primitive type.
+pub fn builtin_traits(primitive: Primitive) -> &'static [&'static OperatorTrait] {
+    match primitive {
+        Primitive::Int => &[
+            &ADD,
+            &SUB,
+            &MUL,
+            &DIV,
+            &REM,
+            &SHL,
+            &SHR,
+            &NEGATE,
+            &EQUAL,
+            &ORDER,
+            &ADD_ASSIGN,
+            &SUB_ASSIGN,
+            &MUL_ASSIGN,
+            &DIV_ASSIGN,
+            &REM_ASSIGN,
+            &HASHABLE,
+        ],
+        Primitive::Float => &[
+            &ADD,
+            &SUB,
+            &MUL,
+            &DIV,
+            &REM,
+            &NEGATE,
+            &EQUAL,
+            &ORDER,
+            &ADD_ASSIGN,
+            &SUB_ASSIGN,
+            &MUL_ASSIGN,
+            &DIV_ASSIGN,
+            &REM_ASSIGN,
+        ],
+        Primitive::Str => &[&ADD, &EQUAL, &ORDER, &ADD_ASSIGN, &HASHABLE],
+        Primitive::Char => &[&EQUAL, &ORDER, &HASHABLE],
+        Primitive::Bool => &[&NOT, &EQUAL, &HASHABLE],
+        Primitive::Void | Primitive::Never => &[],
+    }
+}
+
+/// How a binary operator calls the method of its trait.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BinaryDispatch {
+    pub op_trait: &'static OperatorTrait,
+    /// Whether the right-hand side is the receiver, e.g. `a > b` is `b->less(a)`.
+    pub swap: bool,
+    /// Whether the result is negated, e.g. `a != b` is `!a->equal(b)`.
+    pub negate: bool,
+}
+
+impl BinaryDispatch {
+    /// Build the method call the operator stands for.
+    pub fn desugar(&self, lhs: Expr, rhs: Expr, loc: SourceCodeLocation) -> Expr {
+        let (receiver, arg) = if self.swap { (rhs, lhs) } else { (lhs, rhs) };
+        let call = Expr {
+            kind: ExprKind::MethodCall {
+                receiver: Box::new(receiver),
+                method: Ident {
+                    name: self.op_trait.method.to_string(),
+                    loc: loc.clone(),
+                },
+                args: vec![arg],
+            },
+            loc: loc.clone(),
+        };
+        if self.negate {
+            Expr {
+                kind: ExprKind::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(call),
+                },
+                loc,
+            }
+        } else {
+            call
+        }
+    }
+}
+
+/// The dispatch of a binary operator, `None` for the short-circuiting `&&` and `||`.
+pub fn binary_dispatch(op: BinaryOp) -> Option<BinaryDispatch> {
+    let (op_trait, swap, negate) = match op {
+        BinaryOp::Add => (&ADD, false, false),
+        BinaryOp::Sub => (&SUB, false, false),
+        BinaryOp::Mul => (&MUL, false, false),
+        BinaryOp::Div => (&DIV, false, false),
+        BinaryOp::Rem => (&REM, false, false),
+        BinaryOp::Shl => (&SHL, false, false),
+        BinaryOp::Shr => (&SHR, false, false),
+        BinaryOp::Eq => (&EQUAL, false, false),
+        BinaryOp::NotEq => (&EQUAL, false, true),
+        BinaryOp::Less => (&ORDER, false, false),
+        BinaryOp::Greater => (&ORDER, true, false),
+        // `a <= b` holds exactly when `b < a` doesn't.
+        BinaryOp::LessEq => (&ORDER, true, true),
+        BinaryOp::GreaterEq => (&ORDER, false, true),
+        BinaryOp::And | BinaryOp::Or => return None,
+    };
+    Some(BinaryDispatch {
+        op_trait,
+        swap,
+        negate,
+    })
+}
+
+pub fn unary_trait(op: UnaryOp) -> &'static OperatorTrait {
+    match op {
+        UnaryOp::Neg => &NEGATE,
+        UnaryOp::Not => &NOT,
+    }
+}
+
+/// The in-place trait of a compound assignment such as `+=`.
+pub fn assign_trait(op: BinaryOp) -> Option<&'static OperatorTrait> {
+    match op {
+        BinaryOp::Add => Some(&ADD_ASSIGN),
+        BinaryOp::Sub => Some(&SUB_ASSIGN),
+        BinaryOp::Mul => Some(&MUL_ASSIGN),
+        BinaryOp::Div => Some(&DIV_ASSIGN),
+        BinaryOp::Rem => Some(&REM_ASSIGN),
+        _ => None,
+    }
+}
+
+/// How an operator is evaluated for the types of its operands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dispatch {
+    /// Evaluated directly, either on a primitive type or by short-circuiting.
+    Builtin,
+    /// Calls the method of a user impl.
+    Method(BinaryDispatch),
+}
+
+/// How a compound assignment is evaluated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AssignDispatch {
+    Builtin,
+    /// Calls the method of the user's in-place trait impl, e.g. `a->add_assign(b)`.
+    InPlace(&'static OperatorTrait),
+    /// Evaluated as `a = a + b` through the plain operator trait.
+    Binary(BinaryDispatch),
+}
+
+/// Find how `lhs op rhs` is evaluated when the left-hand side has type `ty`, which implements the
+/// operator traits `implements` accepts.
+pub fn binary(
+    op: BinaryOp,
+    ty: &Ty,
+    implements: impl Fn(&OperatorTrait) -> bool,
+    loc: &SourceCodeLocation,
+) -> Result<Dispatch, Diagnostic> {
+    let Some(dispatch) = binary_dispatch(op) else {
+        return if *ty == Ty::BOOL {
+            Ok(Dispatch::Builtin)
+        } else {
+            Err(Diagnostic::error(
+                format!(
+                    "`{}` only works on `bool`, not on `{}`.",
+                    op.symbol(),
+                    ty.trait_name()
+                ),
+                loc.clone(),
+            ))
+        };
+    };
+    check(dispatch.op_trait, op.symbol(), ty, &implements, loc)?;
+    Ok(match ty {
+        Ty::Primitive(_) => Dispatch::Builtin,
+        _ => Dispatch::Method(dispatch),
+    })
+}
+
+/// Find how `op operand` is evaluated when the operand has type `ty`.
+pub fn unary(
+    op: UnaryOp,
+    ty: &Ty,
+    implements: impl Fn(&OperatorTrait) -> bool,
+    loc: &SourceCodeLocation,
+) -> Result<Dispatch, Diagnostic> {
+    let op_trait = unary_trait(op);
+    let symbol = match op {
+        UnaryOp::Neg => "-",
+        UnaryOp::Not => "!",
+    };
+    check(op_trait, symbol, ty, &implements, loc)?;
+    Ok(match ty {
+        Ty::Primitive(_) => Dispatch::Builtin,
+        _ => Dispatch::Method(BinaryDispatch {
+            op_trait,
+            swap: false,
+            negate: false,
+        }),
+    })
+}
+
+/// Find how `target op= value` is evaluated when the target has type `ty`.
+pub fn assign(
+    op: BinaryOp,
+    ty: &Ty,
+    implements: impl Fn(&OperatorTrait) -> bool,
+    loc: &SourceCodeLocation,
+) -> Result<AssignDispatch, Diagnostic> {
+    let symbol = format!("{}=", op.symbol());
+    let in_place = assign_trait(op).expect("only arithmetic operators have compound assignments");
+    if let Ty::Primitive(_) = ty {
+        check(in_place, &symbol, ty, &implements, loc)?;
+        return Ok(AssignDispatch::Builtin);
+    }
+    if implements(in_place) {
+        return Ok(AssignDispatch::InPlace(in_place));
+    }
+    match binary_dispatch(op) {
+        Some(dispatch) if implements(dispatch.op_trait) => Ok(AssignDispatch::Binary(dispatch)),
+        _ => {
+            let name = ty.trait_name();
+            Err(check(in_place, &symbol, ty, &implements, loc)
+                .unwrap_err()
+                .with_help(format!(
+                    "add `impl {} for {name}` with `{}`, or `impl {} for {name}` with `{}`.",
+                    in_place.name,
+                    in_place.signature(&name),
+                    binary_dispatch(op).map_or("", |d| d.op_trait.name),
+                    binary_dispatch(op).map_or(String::new(), |d| d.op_trait.signature(&name)),
+                )))
+        }
+    }
+}
+
+/// Report an operator on a type that doesn't implement its trait.
+fn check(
+    op_trait: &OperatorTrait,
+    symbol: &str,
+    ty: &Ty,
+    implements: &impl Fn(&OperatorTrait) -> bool,
+    loc: &SourceCodeLocation,
+) -> Result<(), Diagnostic> {
+    if implements(op_trait) {
+        return Ok(());
+    }
+    let name = ty.trait_name();
+    let error = Diagnostic::error(
+        format!(
+            "`{symbol}` can't be used on `{name}` because it doesn't implement `{}`.",
+            op_trait.path()
+        ),
+        loc.clone(),
+    );
+    Err(match ty {
+        Ty::Primitive(_) => error,
+        _ => error.with_help(format!(
+            "add `impl {} for {name}` with `{}`.",
+            op_trait.name,
+            op_trait.signature(&name)
+        )),
+    })
+}
+
+/// The built-in trait a trait declared in a std module is, e.g. `Add` in `std::ops`.
+pub fn std_builtin_trait(module: &[String], name: &str) -> Option<&'static OperatorTrait> {
+    match module {
+        [std, module] if std == "std" => OPERATOR_TRAITS
+            .into_iter()
+            .chain([&HASHABLE])
+            .find(|t| t.module == module && t.name == name),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::parse_source;
+    use crate::parse::printer::print_expr;
+    use crate::sema::resolve::DefId;
+
+    fn loc() -> SourceCodeLocation {
+        SourceCodeLocation::new(1, 1, 0, 1, None)
+    }
+
+    fn path(name: &str) -> Expr {
+        parse_source(None, &format!("def f() void {{ {name}; }}"))
+            .map(|m| match &m.items[0].kind {
+                ItemKind::Fn(decl) => match &decl.body.as_ref().unwrap().stmts[0].kind {
+                    StmtKind::Expr(expr) => expr.clone(),
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn comparisons_desugar_to_two_methods() {
+        let cases = [
+            (BinaryOp::Add, "a->add(b)"),
+            (BinaryOp::Eq, "a->equal(b)"),
+            (BinaryOp::NotEq, "!a->equal(b)"),
+            (BinaryOp::Less, "a->less(b)"),
+            (BinaryOp::Greater, "b->less(a)"),
+            (BinaryOp::LessEq, "!b->less(a)"),
+            (BinaryOp::GreaterEq, "!a->less(b)"),
+        ];
+        for (op, expected) in cases {
+            let call = binary_dispatch(op)
+                .unwrap()
+                .desugar(path("a"), path("b"), loc());
+            assert_eq!(print_expr(&call), expected);
+        }
+        assert_eq!(binary_dispatch(BinaryOp::And), None);
+    }
+
+    #[test]
+    fn dispatches_through_impls() {
+        let point = Ty::Adt {
+            def: DefId { module: 0, item: 0 },
+            name: "Point".to_string(),
+            args: vec![],
+        };
+        let implements = |t: &OperatorTrait| *t == ADD || *t == EQUAL;
+        let builtin = |t: &OperatorTrait| builtin_traits(Primitive::Int).contains(&t);
+
+        assert_eq!(
+            binary(BinaryOp::Add, &Ty::INT, builtin, &loc()),
+            Ok(Dispatch::Builtin)
+        );
+        assert!(matches!(
+            binary(BinaryOp::NotEq, &point, implements, &loc()),
+            Ok(Dispatch::Method(BinaryDispatch { negate: true, .. }))
+        ));
+        assert_eq!(
+            assign(BinaryOp::Add, &point, implements, &loc()),
+            Ok(AssignDispatch::Binary(
+                binary_dispatch(BinaryOp::Add).unwrap()
+            ))
+        );
+        assert_eq!(
+            binary(BinaryOp::Or, &Ty::BOOL, |_| false, &loc()),
+            Ok(Dispatch::Builtin)
+        );
+
+        let error = binary(BinaryOp::Mul, &point, implements, &loc()).unwrap_err();
+        assert_eq!(
+            error.message,
+            "`*` can't be used on `Point` because it doesn't implement `std::ops::Mul`."
+        );
+        assert_eq!(
+            error.help.as_deref(),
+            Some("add `impl Mul for Point` with `def mul(self, other: Point) Point`.")
+        );
+        let bool_traits = |t: &OperatorTrait| builtin_traits(Primitive::Bool).contains(&t);
+        assert!(unary(UnaryOp::Neg, &Ty::BOOL, bool_traits, &loc()).is_err());
+        assert!(binary(BinaryOp::And, &Ty::INT, builtin, &loc()).is_err());
+    }
+}
@@ -0,0 +1,892 @@
+//! Name resolution.
+//!
+//! Every identifier that refers to something is resolved to a local, an item, an enum variant, a
+//! module, a generic parameter or a builtin. Locals live in a tree of scopes: function bodies, blocks,
+//! match arms and closures each open one, and a `let` shadows earlier bindings of the same name from
+//! the statement after it on. Paths such as `Either::Left` or `units::meters` resolve their first
+//! segment by scope and the following ones as members, except for associated functions, which are left
+//! to the type checker. Names that can't be found get a suggestion of the closest visible name or
+//! keyword.
+
+use super::builtins::{Native, Primitive, NATIVES};
+use super::modules::{ModuleGraph, ModuleId};
+use super::ops;
+use super::Diagnostic;
+use crate::parse::ast::*;
+use crate::parse::visit::{walk, Visitor};
+use crate::parse::SourceCodeLocation;
+use crate::util::suggest;
+use std::collections::HashMap;
+
+/// The keywords that can stand where a name does, and so may be what a misspelled name meant.
+const NAME_KEYWORDS: [&str; 7] = ["true", "false", "self", "Self", "int", "float", "str"];
+
+pub type LocalId = usize;
+pub type ScopeId = usize;
+
+/// An item of a module, identified by its index among the module's items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DefId {
+    pub module: ModuleId,
+    pub item: usize,
+}
+
+/// What a name refers to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Res {
+    Local(LocalId),
+    /// A struct, enum, trait or function.
+    Def(DefId),
+    /// A variant of an enum, by index.
+    Variant(DefId, usize),
+    Module(ModuleId),
+    Primitive(Primitive),
+    Native(&'static Native),
+    /// A generic parameter of the enclosing item, method or impl.
+    Generic,
+    /// `Self` inside an impl or a trait.
+    SelfType,
+    /// A name whose declaration is already reported as broken, e.g. an import that failed.
+    Error,
+}
+
+/// A variable or parameter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Local {
+    pub name: String,
+    pub loc: SourceCodeLocation,
+    pub scope: ScopeId,
+    /// Whether it's declared `mut`, so it may be assigned to.
+    pub mutable: bool,
+}
+
+/// A scope of locals. The scopes of a function form a tree rooted at the scope of its parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+    pub module: ModuleId,
+    /// The locals declared in the scope, in the order of their declarations.
+    pub locals: Vec<LocalId>,
+}
+
+/// The result of name resolution for a whole program.
+#[derive(Debug, Default)]
+pub struct Resolutions {
+    names: HashMap<(ModuleId, usize), Res>,
+    pub locals: Vec<Local>,
+    pub scopes: Vec<Scope>,
+}
+
+impl Resolutions {
+    /// What an identifier in the given module refers to.
+    pub fn get(&self, module: ModuleId, ident: &Ident) -> Option<Res> {
+        self.names.get(&(module, ident.loc.offset)).copied()
+    }
+
+    fn insert(&mut self, module: ModuleId, ident: &Ident, res: Res) {
+        self.names.insert((module, ident.loc.offset), res);
+    }
+}
+
+/// Resolve the names in every module of the program.
+pub fn resolve(graph: &ModuleGraph) -> (Resolutions, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let namespaces = (0..graph.modules.len())
+        .map(|m| namespace(graph, m, &mut diagnostics))
+        .collect::<Vec<_>>();
+    let members = members(graph, &namespaces);
+
+    let mut resolutions = Resolutions::default();
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        let mut resolver = Resolver {
+            graph,
+            module,
+            namespaces: &namespaces,
+            members: &members,
+            res: &mut resolutions,
+            diagnostics: &mut diagnostics,
+            scope: None,
+            generics: Vec::new(),
+            in_impl: false,
+            has_self: false,
+        };
+        resolver.visit_module(&loaded.ast);
+    }
+    (resolutions, diagnostics)
+}
+
+/// Look up an item declared or imported at the top level of a module.
+fn lookup_item(graph: &ModuleGraph, module: ModuleId, name: &str, depth: usize) -> Option<Res> {
+    let loaded = &graph.modules[module];
+    for (item, declaration) in loaded.ast.items.iter().enumerate() {
+        let declared = match &declaration.kind {
+            ItemKind::Struct(decl) => &decl.name,
+            ItemKind::Enum(decl) => &decl.name,
+            ItemKind::Fn(decl) => &decl.name,
+            ItemKind::Trait(decl) => &decl.name,
+            ItemKind::Const(decl) => &decl.name,
+            _ => continue,
+        };
+        if declared.name == name {
+            return Some(Res::Def(DefId { module, item }));
+        }
+    }
+    // Imported names can be exported again, so follow them, giving up on cycles.
+    let import = loaded.imports.iter().find(|i| i.local.name == name)?;
+    match &import.item {
+        None => Some(Res::Module(import.module)),
+        Some(_) if depth > graph.modules.len() => None,
+        Some(item) => lookup_item(graph, import.module, item, depth + 1),
+    }
+}
+
+/// The names visible everywhere in a module, reporting the names declared more than once.
+fn namespace(
+    graph: &ModuleGraph,
+    module: ModuleId,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<String, Res> {
+    let mut names = HashMap::new();
+    for primitive in Primitive::ALL {
+        names.insert(primitive.name().to_string(), Res::Primitive(primitive));
+    }
+    for native in NATIVES.iter().filter(|n| n.module.is_none()) {
+        names.insert(native.name.to_string(), Res::Native(native));
+    }
+    let prelude = graph.prelude();
+    if module != prelude {
+        for item in &graph.modules[prelude].ast.items {
+            if let ItemKind::Export(exported) = &item.kind {
+                for name in exported {
+                    let res = lookup_item(graph, prelude, &name.name, 0).unwrap_or(Res::Error);
+                    names.insert(name.name.clone(), res);
+                }
+            }
+        }
+    }
+
+    let loaded = &graph.modules[module];
+    let mut declared: HashMap<&str, &Ident> = HashMap::new();
+    for (item, declaration) in loaded.ast.items.iter().enumerate() {
+        let idents: Vec<&Ident> = match &declaration.kind {
+            ItemKind::Struct(decl) => vec![&decl.name],
+            ItemKind::Enum(decl) => vec![&decl.name],
+            ItemKind::Fn(decl) => vec![&decl.name],
+            ItemKind::Trait(decl) => vec![&decl.name],
+            ItemKind::Const(decl) => vec![&decl.name],
+            ItemKind::Import(Import {
+                group: Some(group), ..
+            }) => group.iter().map(ImportName::local).collect(),
+            ItemKind::Import(import) => import
+                .alias
+                .iter()
+                .chain(import.path.last())
+                .take(1)
+                .collect(),
+            ItemKind::Export(_) | ItemKind::Impl(_) => continue,
+        };
+        for ident in idents {
+            if let Some(first) = declared.get(ident.name.as_str()) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        format!(
+                            "`{}` is declared more than once in this module.",
+                            ident.name
+                        ),
+                        ident.loc.clone(),
+                    )
+                    .with_help(format!(
+                        "the first declaration is on line {}.",
+                        first.loc.line
+                    )),
+                );
+                continue;
+            }
+            declared.insert(&ident.name, ident);
+            let res = match &declaration.kind {
+                // The loader already reported the imports that failed.
+                ItemKind::Import(_) => loaded
+                    .imports
+                    .iter()
+                    .find(|i| i.local == *ident)
+                    .and_then(|import| match &import.item {
+                        None => Some(Res::Module(import.module)),
+                        Some(name) => lookup_item(graph, import.module, name, 0),
+                    })
+                    .unwrap_or(Res::Error),
+                _ => Res::Def(DefId { module, item }),
+            };
+            names.insert(ident.name.clone(), res);
+        }
+    }
+    names
+}
+
+/// The names of the variants and methods of every struct and enum, including the methods an impl of
+/// a trait inherits from the trait.
+fn members(
+    graph: &ModuleGraph,
+    namespaces: &[HashMap<String, Res>],
+) -> HashMap<DefId, Vec<String>> {
+    let mut members: HashMap<DefId, Vec<String>> = HashMap::new();
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        for (item, declaration) in loaded.ast.items.iter().enumerate() {
+            match &declaration.kind {
+                ItemKind::Enum(decl) => members
+                    .entry(DefId { module, item })
+                    .or_default()
+                    .extend(decl.variants.iter().map(|v| v.name.name.clone())),
+                ItemKind::Impl(decl) => {
+                    let target = &decl.target.segments[0].ident.name;
+                    let Some(Res::Def(def)) = namespaces[module].get(target) else {
+                        continue;
+                    };
+                    let methods = members.entry(*def).or_default();
+                    methods.extend(decl.methods.iter().map(|m| m.name.name.clone()));
+                    let trait_name = decl.trait_ref.as_ref().map(|t| &t.segments[0].ident.name);
+                    if let Some(Res::Def(trait_def)) =
+                        trait_name.and_then(|name| namespaces[module].get(name))
+                    {
+                        let item = &graph.modules[trait_def.module].ast.items[trait_def.item];
+                        if let ItemKind::Trait(trait_decl) = &item.kind {
+                            methods.extend(trait_decl.methods.iter().map(|m| m.name.name.clone()));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    members
+}
+
+struct Resolver<'a> {
+    graph: &'a ModuleGraph,
+    module: ModuleId,
+    namespaces: &'a [HashMap<String, Res>],
+    members: &'a HashMap<DefId, Vec<String>>,
+    res: &'a mut Resolutions,
+    diagnostics: &'a mut Vec<Diagnostic>,
+    /// The innermost scope of locals, `None` outside of functions.
+    scope: Option<ScopeId>,
+    /// The generic parameters of the enclosing declarations, innermost last.
+    generics: Vec<String>,
+    /// Whether `Self` is available, inside an impl or a trait.
+    in_impl: bool,
+    /// Whether the enclosing method takes `self`.
+    has_self: bool,
+}
+
+/// The bindings of a pattern, used to find names bound twice and or-patterns binding different names.
+#[derive(Default)]
+struct Bindings {
+    bound: HashMap<String, LocalId>,
+    /// The bindings of the first alternative of an or-pattern, shared by the other alternatives.
+    alternative: Option<HashMap<String, LocalId>>,
+}
+
+impl Resolver<'_> {
+    fn open_scope(&mut self) {
+        self.res.scopes.push(Scope {
+            parent: self.scope,
+            module: self.module,
+            locals: Vec::new(),
+        });
+        self.scope = Some(self.res.scopes.len() - 1);
+    }
+
+    fn close_scope(&mut self) {
+        self.scope = self.scope.and_then(|s| self.res.scopes[s].parent);
+    }
+
+    /// Resolve the value of a constant or a discriminant, in a scope of its own for the closures it
+    /// may contain.
+    fn const_expr(&mut self, expr: &Expr) {
+        self.open_scope();
+        self.visit_expr(expr);
+        self.close_scope();
+    }
+
+    fn declare(&mut self, ident: &Ident, mutable: bool) -> LocalId {
+        let scope = self
+            .scope
+            .expect("locals are only declared inside functions");
+        self.res.locals.push(Local {
+            name: ident.name.clone(),
+            loc: ident.loc.clone(),
+            scope,
+            mutable,
+        });
+        let id = self.res.locals.len() - 1;
+        self.res.scopes[scope].locals.push(id);
+        self.res.insert(self.module, ident, Res::Local(id));
+        id
+    }
+
+    /// The locals visible in the current scope, innermost and latest first.
+    fn visible_locals(&self) -> Vec<LocalId> {
+        let mut locals = Vec::new();
+        let mut scope = self.scope;
+        while let Some(id) = scope {
+            locals.extend(self.res.scopes[id].locals.iter().rev());
+            scope = self.res.scopes[id].parent;
+        }
+        locals
+    }
+
+    fn lookup(&self, name: &str) -> Option<Res> {
+        if let Some(local) = self
+            .visible_locals()
+            .into_iter()
+            .find(|&l| self.res.locals[l].name == name)
+        {
+            return Some(Res::Local(local));
+        }
+        if self.generics.iter().any(|g| g == name) {
+            return Some(Res::Generic);
+        }
+        if name == "Self" {
+            return self.in_impl.then_some(Res::SelfType);
+        }
+        self.namespaces[self.module].get(name).copied()
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn unresolved(&mut self, ident: &Ident) {
+        if ident.name == "Self" {
+            return self.error(Diagnostic::error(
+                "`Self` is only available inside `impl` and `trait` blocks.",
+                ident.loc.clone(),
+            ));
+        }
+        let mut error = Diagnostic::error(
+            format!("Can't find `{}` in this scope.", ident.name),
+            ident.loc.clone(),
+        );
+        if let Some(op_trait) = ops::OPERATOR_TRAITS.iter().find(|t| t.name == ident.name) {
+            return self
+                .error(error.with_help(format!("import it with `import {};`.", op_trait.path())));
+        }
+        let locals = self
+            .visible_locals()
+            .into_iter()
+            .map(|l| self.res.locals[l].name.as_str())
+            .collect::<Vec<_>>();
+        let candidates = locals
+            .into_iter()
+            .chain(self.generics.iter().map(String::as_str))
+            .chain(self.namespaces[self.module].keys().map(String::as_str))
+            .chain(NAME_KEYWORDS);
+        if let Some(suggestion) = suggest(&ident.name, candidates) {
+            error = error.with_help(format!("did you mean `{suggestion}`?"));
+        }
+        self.error(error);
+    }
+
+    fn with_generics(&mut self, generics: &[GenericParam], f: impl FnOnce(&mut Self)) {
+        let depth = self.generics.len();
+        self.generics
+            .extend(generics.iter().map(|g| g.name.name.clone()));
+        for param in generics {
+            for bound in &param.bounds {
+                self.visit_path(bound);
+            }
+        }
+        f(self);
+        self.generics.truncate(depth);
+    }
+
+    /// Resolve the segments after the first one of a path.
+    fn members(&mut self, mut res: Res, segments: &[PathSegment]) {
+        for segment in segments {
+            let ident = &segment.ident;
+            res = match res {
+                Res::Module(module) => {
+                    let loaded = &self.graph.modules[module];
+                    match lookup_item(self.graph, module, &ident.name, 0) {
+                        Some(found) if loaded.exports(&ident.name) => found,
+                        found => {
+                            let message = if found.is_some() {
+                                format!(
+                                    "`{}` is private to the module `{}`.",
+                                    ident.name,
+                                    loaded.display_name()
+                                )
+                            } else {
+                                format!(
+                                    "The module `{}` has no item `{}`.",
+                                    loaded.display_name(),
+                                    ident.name
+                                )
+                            };
+                            let exported = loaded
+                                .ast
+                                .items
+                                .iter()
+                                .filter_map(|item| match &item.kind {
+                                    ItemKind::Export(names) => Some(names),
+                                    _ => None,
+                                })
+                                .flatten()
+                                .map(|i| i.name.as_str());
+                            let mut error = Diagnostic::error(message, ident.loc.clone());
+                            if let Some(suggestion) = suggest(&ident.name, exported) {
+                                error = error.with_help(format!("did you mean `{suggestion}`?"));
+                            }
+                            self.error(error);
+                            return;
+                        }
+                    }
+                }
+                Res::Def(def) => {
+                    let item = &self.graph.modules[def.module].ast.items[def.item];
+                    let variant = match &item.kind {
+                        ItemKind::Enum(decl) => {
+                            decl.variants.iter().position(|v| v.name.name == ident.name)
+                        }
+                        _ => None,
+                    };
+                    match variant {
+                        Some(index) => Res::Variant(def, index),
+                        None => return self.member_error(def, ident),
+                    }
+                }
+                Res::Local(_) => {
+                    return self.error(Diagnostic::error(
+                        "A variable has no members that can be accessed with `::`.",
+                        ident.loc.clone(),
+                    ))
+                }
+                // Associated functions of `Self`, generics and primitives are resolved with types.
+                _ => return,
+            };
+            self.res.insert(self.module, ident, res);
+        }
+    }
+
+    /// Report a missing member of a struct or enum, unless it's an associated function.
+    fn member_error(&mut self, def: DefId, ident: &Ident) {
+        let members = self.members.get(&def).cloned().unwrap_or_default();
+        if members.contains(&ident.name) {
+            return;
+        }
+        let name = match &self.graph.modules[def.module].ast.items[def.item].kind {
+            ItemKind::Struct(decl) => &decl.name.name,
+            ItemKind::Enum(decl) => &decl.name.name,
+            ItemKind::Fn(decl) => &decl.name.name,
+            ItemKind::Trait(decl) => &decl.name.name,
+            _ => "",
+        };
+        let mut error = Diagnostic::error(
+            format!("`{name}` has no variant or method `{}`.", ident.name),
+            ident.loc.clone(),
+        );
+        if let Some(suggestion) = suggest(&ident.name, members.iter().map(String::as_str)) {
+            error = error.with_help(format!("did you mean `{suggestion}`?"));
+        }
+        self.error(error);
+    }
+
+    fn pattern(&mut self, pattern: &Pattern, bindings: &mut Bindings) {
+        match &pattern.kind {
+            PatternKind::Wildcard
+            | PatternKind::Rest
+            | PatternKind::Literal(_)
+            | PatternKind::Range { .. } => {}
+            PatternKind::Binding { name, sub, mutable } => {
+                if bindings.bound.contains_key(&name.name) {
+                    self.error(Diagnostic::error(
+                        format!(
+                            "`{}` is bound more than once in the same pattern.",
+                            name.name
+                        ),
+                        name.loc.clone(),
+                    ));
+                } else if let Some(alternative) = &bindings.alternative {
+                    match alternative.get(&name.name) {
+                        Some(&local) => {
+                            self.res.insert(self.module, name, Res::Local(local));
+                            bindings.bound.insert(name.name.clone(), local);
+                        }
+                        None => self.error(Diagnostic::error(
+                            format!(
+                                "`{}` is not bound in every alternative of the pattern.",
+                                name.name
+                            ),
+                            name.loc.clone(),
+                        )),
+                    }
+                } else {
+                    let local = self.declare(name, *mutable);
+                    bindings.bound.insert(name.name.clone(), local);
+                }
+                if let Some(sub) = sub {
+                    self.pattern(sub, bindings);
+                }
+            }
+            PatternKind::Tuple(patterns) => {
+                for pattern in patterns {
+                    self.pattern(pattern, bindings);
+                }
+            }
+            PatternKind::Variant { path, fields } => {
+                self.visit_path(path);
+                for pattern in fields.iter().flatten() {
+                    self.pattern(pattern, bindings);
+                }
+            }
+            PatternKind::Struct { path, fields, .. } => {
+                self.visit_path(path);
+                for field in fields {
+                    self.pattern(&field.pattern, bindings);
+                }
+            }
+            PatternKind::Or(alternatives) => {
+                let before = bindings.bound.clone();
+                let outer = bindings.alternative.take();
+                let mut first = None;
+                for alternative in alternatives {
+                    bindings.bound = before.clone();
+                    bindings.alternative = first.clone().or_else(|| outer.clone());
+                    self.pattern(alternative, bindings);
+                    match &first {
+                        None => first = Some(bindings.bound.clone()),
+                        Some(first) => {
+                            for name in first.keys() {
+                                if !bindings.bound.contains_key(name) {
+                                    self.error(Diagnostic::error(
+                                        format!(
+                                            "`{name}` is not bound in every alternative of the pattern."
+                                        ),
+                                        alternative.loc.clone(),
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                }
+                bindings.bound = first.unwrap_or(before);
+                bindings.alternative = outer;
+            }
+        }
+    }
+}
+
+impl Visitor for Resolver<'_> {
+    fn visit_item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Import(_) | ItemKind::Export(_) => {}
+            ItemKind::Struct(decl) => {
+                self.with_generics(&decl.generics, |r| {
+                    for field in &decl.fields {
+                        r.visit_type(&field.ty);
+                    }
+                });
+            }
+            ItemKind::Enum(decl) => {
+                self.with_generics(&decl.generics, |r| {
+                    for variant in &decl.variants {
+                        for ty in &variant.fields {
+                            r.visit_type(ty);
+                        }
+                        if let Some(discriminant) = &variant.discriminant {
+                            r.const_expr(discriminant);
+                        }
+                    }
+                });
+            }
+            ItemKind::Trait(decl) => {
+                self.with_generics(&decl.generics, |r| {
+                    r.in_impl = true;
+                    for method in &decl.methods {
+                        r.visit_fn_decl(method);
+                    }
+                    r.in_impl = false;
+                });
+            }
+            ItemKind::Impl(decl) => {
+                self.with_generics(&decl.generics, |r| {
+                    if let Some(trait_ref) = &decl.trait_ref {
+                        r.visit_path(trait_ref);
+                    }
+                    r.visit_path(&decl.target);
+                    r.in_impl = true;
+                    for assoc in &decl.assoc_types {
+                        r.visit_assoc_type(assoc);
+                    }
+                    for method in &decl.methods {
+                        r.visit_fn_decl(method);
+                    }
+                    r.in_impl = false;
+                });
+            }
+            ItemKind::Fn(decl) => self.visit_fn_decl(decl),
+            ItemKind::Const(decl) => {
+                self.visit_type(&decl.ty);
+                self.const_expr(&decl.value);
+            }
+        }
+    }
+
+    fn visit_fn_decl(&mut self, decl: &FnDecl) {
+        self.with_generics(&decl.generics, |r| {
+            let has_self = std::mem::replace(&mut r.has_self, decl.self_param.is_some());
+            r.open_scope();
+            let mut bindings = Bindings::default();
+            for param in &decl.params {
+                r.visit_type(&param.ty);
+                r.pattern(&param.pattern, &mut bindings);
+            }
+            r.visit_type(&decl.ret);
+            if let Some(body) = &decl.body {
+                r.visit_block(body);
+            }
+            r.close_scope();
+            r.has_self = has_self;
+        });
+    }
+
+    fn visit_path(&mut self, path: &Path) {
+        let first = &path.segments[0].ident;
+        match self.lookup(&first.name) {
+            Some(res) => {
+                self.res.insert(self.module, first, res);
+                self.members(res, &path.segments[1..]);
+            }
+            None => self.unresolved(first),
+        }
+        for segment in &path.segments {
+            for ty in &segment.generics {
+                self.visit_type(ty);
+            }
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        self.open_scope();
+        walk::walk_block(self, block);
+        self.close_scope();
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { pattern, ty, value } => {
+                if let Some(ty) = ty {
+                    self.visit_type(ty);
+                }
+                if let Some(value) = value {
+                    self.visit_expr(value);
+                }
+                self.visit_pattern(pattern);
+            }
+            _ => walk::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_match_arm(&mut self, arm: &MatchArm) {
+        self.open_scope();
+        walk::walk_match_arm(self, arm);
+        self.close_scope();
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        if expr.kind == ExprKind::SelfValue && !self.has_self {
+            let error = Diagnostic::error(
+                "`self` is only available in methods that take `self`.",
+                expr.loc.clone(),
+            );
+            self.error(if self.in_impl {
+                error.with_help("add `self` as the first parameter of the method.")
+            } else {
+                error
+            });
+        }
+        walk::walk_expr(self, expr);
+    }
+
+    fn visit_closure(&mut self, closure: &Closure) {
+        self.open_scope();
+        let mut bindings = Bindings::default();
+        for param in &closure.params {
+            if let Some(ty) = &param.ty {
+                self.visit_type(ty);
+            }
+            self.pattern(&param.pattern, &mut bindings);
+        }
+        match &closure.body {
+            ClosureBody::Expr(expr) => self.visit_expr(expr),
+            ClosureBody::Block(block) => self.visit_block(block),
+        }
+        self.close_scope();
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        self.pattern(pattern, &mut Bindings::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::modules::ModuleLoader;
+    use std::path::Path;
+
+    fn resolve_source(src: &str) -> (Resolutions, Vec<Diagnostic>) {
+        let graph = ModuleLoader::new(vec![])
+            .load_source(Path::new("main.paca"), src.to_string())
+            .unwrap();
+        resolve(&graph)
+    }
+
+    /// The resolution of the `nth` occurrence of `needle` in the main module.
+    fn res_at(res: &Resolutions, src: &str, needle: &str, nth: usize) -> Option<Res> {
+        let (offset, _) = src.match_indices(needle).nth(nth).unwrap();
+        res.names.get(&(0, offset)).copied()
+    }
+
+    #[test]
+    fn resolves_locals_and_items() {
+        let src = "struct Count { n: int }
+enum Color { Red, Green }
+def bump(count: Count) int {
+    let n = 1;
+    let n = n + count->n;
+    match Color::Red {
+        Color::Green => print(\"green\"),
+        other => {},
+    }
+    return n;
+}
+";
+        let (res, diagnostics) = resolve_source(src);
+        assert_eq!(diagnostics, vec![]);
+        let struct_def = Res::Def(DefId { module: 0, item: 0 });
+        let enum_def = DefId { module: 0, item: 1 };
+        assert_eq!(res_at(&res, src, "Count", 1), Some(struct_def));
+        assert_eq!(
+            res_at(&res, src, "int", 1),
+            Some(Res::Primitive(Primitive::Int))
+        );
+        // The second `let n` sees the first one, and the `return` sees the second.
+        let Some(Res::Local(first)) = res_at(&res, src, "n =", 0) else {
+            panic!()
+        };
+        let Some(Res::Local(second)) = res_at(&res, src, "n =", 1) else {
+            panic!()
+        };
+        assert_ne!(first, second);
+        assert_eq!(res_at(&res, src, "n +", 0), Some(Res::Local(first)));
+        assert_eq!(res_at(&res, src, "n;\n}", 0), Some(Res::Local(second)));
+        assert_eq!(res_at(&res, src, "Color", 1), Some(Res::Def(enum_def)));
+        assert_eq!(
+            res_at(&res, src, "Green", 1),
+            Some(Res::Variant(enum_def, 1))
+        );
+        assert!(matches!(
+            res_at(&res, src, "print", 0),
+            Some(Res::Native(_))
+        ));
+    }
+
+    #[test]
+    fn resolves_operator_traits_through_std() {
+        let src = "import std::cmp::Equal;
+import std::ops;
+struct P { x: int }
+impl Equal for P {}
+impl ops::Add for P {}
+impl Order for P {}
+";
+        let graph = ModuleLoader::new(vec![])
+            .load_source(Path::new("main.paca"), src.to_string())
+            .unwrap();
+        let (res, diagnostics) = resolve(&graph);
+        let module_of = |needle: &str, nth: usize| match res_at(&res, src, needle, nth) {
+            Some(Res::Def(def)) => graph.modules[def.module].display_name(),
+            res => panic!("`{needle}` resolves to {res:?}"),
+        };
+        assert_eq!(module_of("Equal", 1), "std::cmp");
+        assert_eq!(module_of("Add", 0), "std::ops");
+        let messages = diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.help.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [(
+                "Can't find `Order` in this scope.",
+                Some("import it with `import std::cmp::Order;`.")
+            )]
+        );
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        let src = "def main() void {
+    let count = 1;
+    let x = conut;
+    let y = ture;
+    let z = Either::Lft(1);
+    let w = Option::Some(Either::Right(1))->unwrap;
+}
+";
+        let (_, diagnostics) = resolve_source(src);
+        let messages = diagnostics
+            .iter()
+            .map(|d| (d.message.as_str(), d.help.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    "Can't find `conut` in this scope.",
+                    Some("did you mean `count`?")
+                ),
+                (
+                    "Can't find `ture` in this scope.",
+                    Some("did you mean `true`?")
+                ),
+                (
+                    "`Either` has no variant or method `Lft`.",
+                    Some("did you mean `Left`?")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_misused_names() {
+        let src = "struct Point { x: int }
+def Point() void {}
+impl methods for Point {
+    def origin() Self {
+        return self;
+    }
+}
+def main(p: Self) void {
+    match 1 {
+        x | 2 => {},
+        (a, a) => {},
+    }
+}
+";
+        let (_, diagnostics) = resolve_source(src);
+        let messages = diagnostics
+            .iter()
+            .map(|d| d.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`Point` is declared more than once in this module.",
+                "`self` is only available in methods that take `self`.",
+                "`Self` is only available inside `impl` and `trait` blocks.",
+                "`x` is not bound in every alternative of the pattern.",
+                "`a` is bound more than once in the same pattern.",
+            ]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! The types of Paca values and the unification of types during inference.
+
+use super::builtins::Primitive;
+use super::resolve::DefId;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An inference variable, standing for a type that isn't known yet.
+pub type TyVar = usize;
+
+/// A type.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Ty {
+    Primitive(Primitive),
+    /// A struct or enum with its generic arguments.
+    Adt {
+        def: DefId,
+        name: String,
+        args: Vec<Ty>,
+    },
+    /// `[]T`
+    Array(Box<Ty>),
+    /// `(A, B)`
+    Tuple(Vec<Ty>),
+    /// A function, closure or enum variant constructor.
+    Fn(FnTy),
+    /// A generic parameter, e.g. `T` inside `enum Option<T>`.
+    Param(String),
+    Var(TyVar),
+    /// The type of an expression that is already reported as broken. It unifies with every type so
+    /// that one mistake is reported once.
+    Error,
+}
+
+/// The type of a function.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FnTy {
+    pub params: Vec<Ty>,
+    pub ret: Box<Ty>,
+    /// Whether the last parameter, an array, collects the rest of the arguments.
+    pub variadic: bool,
+}
+
+impl Ty {
+    pub const VOID: Ty = Ty::Primitive(Primitive::Void);
+    pub const NEVER: Ty = Ty::Primitive(Primitive::Never);
+    pub const BOOL: Ty = Ty::Primitive(Primitive::Bool);
+    pub const INT: Ty = Ty::Primitive(Primitive::Int);
+
+    pub fn func(params: Vec<Ty>, ret: Ty) -> Self {
+        Ty::Fn(FnTy {
+            params,
+            ret: Box::new(ret),
+            variadic: false,
+        })
+    }
+
+    /// Replace the generic parameters named in `substs`.
+    pub fn subst(&self, substs: &HashMap<String, Ty>) -> Ty {
+        self.map(&mut |ty| match ty {
+            Ty::Param(name) => substs.get(name).cloned(),
+            _ => None,
+        })
+    }
+
+    /// Rebuild the type bottom-up, replacing every node for which `f` returns a type.
+    fn map(&self, f: &mut impl FnMut(&Ty) -> Option<Ty>) -> Ty {
+        if let Some(ty) = f(self) {
+            return ty;
+        }
+        match self {
+            Ty::Adt { def, name, args } => Ty::Adt {
+                def: *def,
+                name: name.clone(),
+                args: args.iter().map(|a| a.map(f)).collect(),
+            },
+            Ty::Array(elem) => Ty::Array(Box::new(elem.map(f))),
+            Ty::Tuple(elems) => Ty::Tuple(elems.iter().map(|e| e.map(f)).collect()),
+            Ty::Fn(fn_ty) => Ty::Fn(FnTy {
+                params: fn_ty.params.iter().map(|p| p.map(f)).collect(),
+                ret: Box::new(fn_ty.ret.map(f)),
+                variadic: fn_ty.variadic,
+            }),
+            Ty::Primitive(_) | Ty::Param(_) | Ty::Var(_) | Ty::Error => self.clone(),
+        }
+    }
+
+    /// Whether `f` holds for the type or any type inside it.
+    pub fn any(&self, f: &impl Fn(&Ty) -> bool) -> bool {
+        f(self)
+            || match self {
+                Ty::Adt { args, .. } => args.iter().any(|a| a.any(f)),
+                Ty::Array(elem) => elem.any(f),
+                Ty::Tuple(elems) => elems.iter().any(|e| e.any(f)),
+                Ty::Fn(fn_ty) => fn_ty.params.iter().any(|p| p.any(f)) || fn_ty.ret.any(f),
+                Ty::Primitive(_) | Ty::Param(_) | Ty::Var(_) | Ty::Error => false,
+            }
+    }
+
+    /// The name operator traits are implemented for, e.g. `Option` for `Option<int>`.
+    pub fn trait_name(&self) -> String {
+        match self {
+            Ty::Primitive(primitive) => primitive.name().to_string(),
+            Ty::Adt { name, .. } => name.clone(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, tys: &[Ty]) -> fmt::Result {
+            for (i, ty) in tys.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{ty}")?;
+            }
+            Ok(())
+        }
+        match self {
+            Ty::Primitive(primitive) => write!(f, "{}", primitive.name()),
+            Ty::Adt { name, args, .. } => {
+                write!(f, "{name}")?;
+                if !args.is_empty() {
+                    write!(f, "<")?;
+                    list(f, args)?;
+                    write!(f, ">")?;
+                }
+                Ok(())
+            }
+            Ty::Array(elem) => write!(f, "[]{elem}"),
+            Ty::Tuple(elems) => {
+                write!(f, "(")?;
+                list(f, elems)?;
+                write!(f, ")")
+            }
+            Ty::Fn(fn_ty) => {
+                write!(f, "def(")?;
+                for (i, param) in fn_ty.params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    if fn_ty.variadic && i == fn_ty.params.len() - 1 {
+                        write!(f, "*")?;
+                    }
+                    write!(f, "{param}")?;
+                }
+                write!(f, ") {}", fn_ty.ret)
+            }
+            Ty::Param(name) => write!(f, "{name}"),
+            Ty::Var(_) => write!(f, "_"),
+            Ty::Error => write!(f, "{{unknown}}"),
+        }
+    }
+}
+
+/// The inference variables of a program and the types they were unified with.
+#[derive(Clone, Debug, Default)]
+pub struct InferTable {
+    vars: Vec<Option<Ty>>,
+}
+
+impl InferTable {
+    pub fn fresh(&mut self) -> Ty {
+        self.vars.push(None);
+        Ty::Var(self.vars.len() - 1)
+    }
+
+    /// Follow the variables bound at the top of the type.
+    pub fn shallow(&self, ty: &Ty) -> Ty {
+        let mut ty = ty.clone();
+        while let Ty::Var(var) = ty {
+            match &self.vars[var] {
+                Some(bound) => ty = bound.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Replace every bound variable in the type, leaving only the unbound ones.
+    pub fn resolve(&self, ty: &Ty) -> Ty {
+        ty.map(&mut |ty| match ty {
+            Ty::Var(var) => self.vars[*var].as_ref().map(|bound| self.resolve(bound)),
+            _ => None,
+        })
+    }
+
+    /// Resolve the type once inference is over, replacing the variables that were never bound with
+    /// `Ty::Error`.
+    pub fn finish(&self, ty: &Ty) -> Ty {
+        self.resolve(ty).map(&mut |ty| match ty {
+            Ty::Var(_) => Some(Ty::Error),
+            _ => None,
+        })
+    }
+
+    /// Make the two types equal by binding variables, returning whether that is possible.
+    pub fn unify(&mut self, a: &Ty, b: &Ty) -> bool {
+        let (a, b) = (self.shallow(a), self.shallow(b));
+        match (&a, &b) {
+            (Ty::Var(x), Ty::Var(y)) if x == y => true,
+            (Ty::Var(var), ty) | (ty, Ty::Var(var)) => {
+                if self.resolve(ty).any(&|t| *t == Ty::Var(*var)) {
+                    return false;
+                }
+                self.vars[*var] = Some(ty.clone());
+                true
+            }
+            (Ty::Error, _) | (_, Ty::Error) => true,
+            (Ty::Primitive(x), Ty::Primitive(y)) => x == y,
+            (
+                Ty::Adt {
+                    def: x, args: xs, ..
+                },
+                Ty::Adt {
+                    def: y, args: ys, ..
+                },
+            ) => x == y && self.unify_all(xs, ys),
+            (Ty::Array(x), Ty::Array(y)) => self.unify(x, y),
+            (Ty::Tuple(xs), Ty::Tuple(ys)) => self.unify_all(xs, ys),
+            (Ty::Fn(x), Ty::Fn(y)) => {
+                x.variadic == y.variadic
+                    && self.unify_all(&x.params, &y.params)
+                    && self.unify(&x.ret, &y.ret)
+            }
+            (Ty::Param(x), Ty::Param(y)) => x == y,
+            _ => false,
+        }
+    }
+
+    fn unify_all(&mut self, xs: &[Ty], ys: &[Ty]) -> bool {
+        xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| self.unify(x, y))
+    }
+
+    /// Unify the types only if that succeeds, leaving the variables untouched otherwise.
+    pub fn try_unify(&mut self, a: &Ty, b: &Ty) -> bool {
+        let snapshot = self.vars.clone();
+        let unified = self.unify(a, b);
+        if !unified {
+            self.vars = snapshot;
+        }
+        unified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unifies_nested_types() {
+        let mut table = InferTable::default();
+        let option = |arg| Ty::Adt {
+            def: DefId { module: 0, item: 0 },
+            name: "Option".to_string(),
+            args: vec![arg],
+        };
+        let (a, b) = (table.fresh(), table.fresh());
+        let int = Ty::Primitive(Primitive::Int);
+        assert!(table.unify(&option(a.clone()), &b));
+        assert!(table.unify(
+            &Ty::Array(Box::new(b.clone())),
+            &Ty::Array(Box::new(option(int.clone())))
+        ));
+        assert_eq!(table.resolve(&b), option(int.clone()));
+        assert_eq!(table.resolve(&b).to_string(), "Option<int>");
+
+        // A variable can't contain itself, and failed attempts leave no bindings behind.
+        let c = table.fresh();
+        assert!(!table.unify(&c, &Ty::Array(Box::new(c.clone()))));
+        assert!(!table.try_unify(
+            &Ty::Tuple(vec![c.clone(), int.clone()]),
+            &Ty::Tuple(vec![int, Ty::BOOL])
+        ));
+        assert_eq!(table.resolve(&c), c);
+    }
+}
@@ -0,0 +1,3118 @@
+//! Type checking.
+//!
+//! Functions declare the types of their parameters and their return type, so every body is checked on
+//! its own against the signatures of the items it uses. Inside a body types are inferred by
+//! unification: a `let` without an annotation gets an inference variable that the uses of the local
+//! pin down, and generic items are instantiated with fresh variables at every use. Where the type an
+//! expression should have is already known, it is pushed down into the expression, which is how the
+//! parameters of a closure passed as an argument get their types.
+
+use super::builtins::{self, Native, Primitive};
+use super::modules::{ModuleGraph, ModuleId};
+use super::ops::{self, assign_trait, binary_dispatch, unary_trait, OperatorTrait};
+use super::resolve::{DefId, LocalId, Res, Resolutions};
+use super::ty::{FnTy, InferTable, Ty};
+use super::Diagnostic;
+use crate::parse::ast::*;
+use crate::parse::SourceCodeLocation;
+use crate::util::suggest;
+use std::collections::HashMap;
+
+/// A method or associated function, by its impl and its index among the methods of the impl.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MethodId {
+    pub imp: DefId,
+    pub index: usize,
+}
+
+/// A method of a trait, by the trait and its index among the methods of the trait.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TraitMethodId {
+    pub trait_def: DefId,
+    pub index: usize,
+}
+
+/// A trait a generic parameter is bound by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Trait(DefId),
+    /// A bound that is already reported as broken, which is taken to provide everything.
+    Error,
+}
+
+/// A generic parameter with the traits the types it's instantiated with must implement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Generic {
+    pub name: String,
+    pub bounds: Vec<Bound>,
+}
+
+/// The signature of a function, method or associated function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FnSig {
+    /// The generic parameters, starting with those of the enclosing impl.
+    pub generics: Vec<Generic>,
+    /// The parameters besides `self`.
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+    pub variadic: bool,
+    pub has_self: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldDef {
+    pub name: String,
+    pub ty: Ty,
+    /// Whether it's marked `$`, so it may be assigned to.
+    pub mutable: bool,
+    /// The location of its name in the struct declaration.
+    pub loc: SourceCodeLocation,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantDef {
+    pub name: String,
+    pub fields: Vec<Ty>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AdtKind {
+    Struct(Vec<FieldDef>),
+    Enum(Vec<VariantDef>),
+}
+
+/// A struct or enum.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdtDef {
+    pub name: String,
+    pub generics: Vec<Generic>,
+    pub kind: AdtKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MethodDef {
+    pub name: String,
+    pub sig: FnSig,
+    /// The trait method whose default body is used, as the impl doesn't define the method.
+    pub inherited: Option<TraitMethodId>,
+}
+
+/// A trait declaration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraitDef {
+    pub name: String,
+    pub generics: Vec<Generic>,
+    pub assoc_types: Vec<String>,
+    /// The methods, whose signatures have `Self` and `Self::Output` as generic parameters.
+    pub methods: Vec<TraitMethodDef>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraitMethodDef {
+    pub name: String,
+    /// The generic parameters start with those of the trait.
+    pub sig: FnSig,
+    pub has_default: bool,
+}
+
+/// A trait with its generic arguments, e.g. `From<int>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraitRef {
+    pub def: DefId,
+    pub args: Vec<Ty>,
+}
+
+/// An impl block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImplDef {
+    pub generics: Vec<Generic>,
+    /// The implemented trait, `None` for `impl methods for`.
+    pub trait_ref: Option<TraitRef>,
+    /// The type the methods are implemented for, generic over `generics`.
+    pub target: Ty,
+    /// The types of the associated types of the trait.
+    pub assoc_types: HashMap<String, Ty>,
+    /// The methods of the impl followed by the default methods it inherits from the trait.
+    pub methods: Vec<MethodDef>,
+}
+
+/// The types of every item of the program.
+#[derive(Debug, Default)]
+pub struct Items {
+    pub adts: HashMap<DefId, AdtDef>,
+    pub fns: HashMap<DefId, FnSig>,
+    pub traits: HashMap<DefId, TraitDef>,
+    pub impls: HashMap<DefId, ImplDef>,
+    /// The declared type of every constant.
+    pub consts: HashMap<DefId, Ty>,
+    /// The runtime function of every `@native` function.
+    pub natives: HashMap<DefId, &'static Native>,
+    /// The std traits the primitive types implement built in, the operator traits and `Hashable`,
+    /// by their declarations.
+    pub builtin_traits: HashMap<DefId, &'static OperatorTrait>,
+    /// The impls of every struct and enum that don't implement a trait, in source order.
+    impls_of: HashMap<DefId, Vec<DefId>>,
+    /// The impls of every trait, in source order.
+    trait_impls: HashMap<DefId, Vec<DefId>>,
+}
+
+impl Items {
+    pub fn method(&self, id: MethodId) -> &MethodDef {
+        &self.impls[&id.imp].methods[id.index]
+    }
+
+    pub fn trait_method(&self, id: TraitMethodId) -> &TraitMethodDef {
+        &self.traits[&id.trait_def].methods[id.index]
+    }
+
+    /// Find a method or associated function of a struct or enum outside of trait impls.
+    pub fn lookup_method(&self, adt: DefId, name: &str) -> Option<MethodId> {
+        self.methods_of(adt)
+            .find(|&id| self.method(id).name == name)
+    }
+
+    fn methods_of(&self, adt: DefId) -> impl Iterator<Item = MethodId> + '_ {
+        self.impls_of
+            .get(&adt)
+            .into_iter()
+            .flatten()
+            .flat_map(move |&imp| self.impl_methods(imp))
+    }
+
+    fn impl_methods(&self, imp: DefId) -> impl Iterator<Item = MethodId> {
+        (0..self.impls[&imp].methods.len()).map(move |index| MethodId { imp, index })
+    }
+
+    /// The impls of traits whose target the type matches, with the generic arguments of the impl.
+    fn trait_impls_for<'a>(
+        &'a self,
+        ty: &'a Ty,
+    ) -> impl Iterator<Item = (DefId, HashMap<String, Ty>)> + 'a {
+        let mut imps = self
+            .trait_impls
+            .values()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        imps.sort();
+        imps.into_iter().filter_map(move |imp| {
+            let mut substs = HashMap::new();
+            match_ty(&self.impls[&imp].target, ty, &mut substs).then_some((imp, substs))
+        })
+    }
+
+    /// The impl of the trait for the type, with the generic arguments of the impl.
+    pub fn trait_impl(&self, trait_def: DefId, ty: &Ty) -> Option<(DefId, HashMap<String, Ty>)> {
+        self.trait_impls_for(ty).find(|(imp, _)| {
+            self.impls[imp]
+                .trait_ref
+                .as_ref()
+                .is_some_and(|t| t.def == trait_def)
+        })
+    }
+
+    /// Whether the type implements the trait, built in or with an impl.
+    pub fn implements(&self, trait_def: DefId, ty: &Ty) -> bool {
+        match ty {
+            Ty::Primitive(primitive) => self
+                .builtin_traits
+                .get(&trait_def)
+                .is_some_and(|op_trait| ops::builtin_traits(*primitive).contains(op_trait)),
+            _ => self.trait_impl(trait_def, ty).is_some(),
+        }
+    }
+
+    /// The declaration of a built-in trait, unless no loaded module has it.
+    pub fn builtin_trait(&self, op_trait: &OperatorTrait) -> Option<DefId> {
+        let mut defs = self.builtin_traits.iter();
+        defs.find(|(_, t)| **t == op_trait).map(|(&def, _)| def)
+    }
+
+    /// The method an operator calls on a struct or enum type, from the type's impl of its trait.
+    pub fn operator_method(&self, op_trait: &OperatorTrait, ty: &Ty) -> Option<MethodId> {
+        let (imp, _) = self.trait_impl(self.builtin_trait(op_trait)?, ty)?;
+        let index = self.impls[&imp]
+            .methods
+            .iter()
+            .position(|m| m.name == op_trait.method)?;
+        Some(MethodId { imp, index })
+    }
+}
+
+/// Match a type against the target of an impl, binding the generic parameters of the impl. Variables
+/// and broken types match anything.
+pub fn match_ty(pattern: &Ty, ty: &Ty, substs: &mut HashMap<String, Ty>) -> bool {
+    match (pattern, ty) {
+        (_, Ty::Var(_) | Ty::Error) | (Ty::Error, _) => true,
+        (Ty::Param(name), _) => match substs.get(name) {
+            Some(bound) => bound == ty || bound.any(&|t| matches!(t, Ty::Var(_))),
+            None => {
+                substs.insert(name.clone(), ty.clone());
+                true
+            }
+        },
+        (Ty::Primitive(a), Ty::Primitive(b)) => a == b,
+        (
+            Ty::Adt {
+                def: a, args: xs, ..
+            },
+            Ty::Adt {
+                def: b, args: ys, ..
+            },
+        ) => a == b && match_all(xs, ys, substs),
+        (Ty::Array(a), Ty::Array(b)) => match_ty(a, b, substs),
+        (Ty::Tuple(xs), Ty::Tuple(ys)) => match_all(xs, ys, substs),
+        (Ty::Fn(a), Ty::Fn(b)) => {
+            a.variadic == b.variadic
+                && match_all(&a.params, &b.params, substs)
+                && match_ty(&a.ret, &b.ret, substs)
+        }
+        _ => false,
+    }
+}
+
+fn match_all(patterns: &[Ty], tys: &[Ty], substs: &mut HashMap<String, Ty>) -> bool {
+    patterns.len() == tys.len()
+        && patterns
+            .iter()
+            .zip(tys)
+            .all(|(p, t)| match_ty(p, t, substs))
+}
+
+/// Identifies an expression by its module and its span.
+type ExprKey = (ModuleId, usize, usize);
+
+fn key(module: ModuleId, loc: &SourceCodeLocation) -> ExprKey {
+    (module, loc.offset, loc.length)
+}
+
+/// The result of type checking a whole program.
+#[derive(Debug, Default)]
+pub struct TypeckResults {
+    pub items: Items,
+    exprs: HashMap<ExprKey, Ty>,
+    /// The type of every local, by `LocalId`.
+    pub locals: Vec<Ty>,
+    /// The methods called by method calls and associated function paths.
+    methods: HashMap<ExprKey, MethodId>,
+    /// The generic arguments of every use of a generic function or method, in the order of the
+    /// generic parameters of its signature.
+    generic_args: HashMap<ExprKey, Vec<Ty>>,
+    /// The trait methods called on generic parameters, whose impl is only known once the parameter
+    /// is, with the type they're called on.
+    trait_calls: HashMap<ExprKey, (TraitMethodId, Ty)>,
+}
+
+impl TypeckResults {
+    pub fn expr_ty(&self, module: ModuleId, expr: &Expr) -> Option<&Ty> {
+        self.exprs.get(&key(module, &expr.loc))
+    }
+
+    pub fn method(&self, module: ModuleId, expr: &Expr) -> Option<MethodId> {
+        self.methods.get(&key(module, &expr.loc)).copied()
+    }
+
+    /// The generic arguments a function path or method call instantiates its callee with.
+    pub fn generic_args(&self, module: ModuleId, expr: &Expr) -> &[Ty] {
+        self.generic_args
+            .get(&key(module, &expr.loc))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The trait method a call on a generic parameter calls, with the type it's called on.
+    pub fn trait_call(&self, module: ModuleId, expr: &Expr) -> Option<&(TraitMethodId, Ty)> {
+        self.trait_calls.get(&key(module, &expr.loc))
+    }
+}
+
+/// A method found for a type.
+#[derive(Clone, Copy, Debug)]
+enum Candidate {
+    /// A method of an impl, inherent or of a trait.
+    Impl(MethodId),
+    /// A method of a trait a generic parameter is bound by.
+    Bound(TraitMethodId),
+}
+
+/// A type that must implement a trait, checked once the type is inferred.
+struct Obligation {
+    ty: Ty,
+    bound: Bound,
+    loc: SourceCodeLocation,
+}
+
+/// Check the types of every module of the program.
+pub fn check(graph: &ModuleGraph, resolutions: &Resolutions) -> (TypeckResults, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        graph,
+        res: resolutions,
+        results: TypeckResults {
+            locals: vec![Ty::Error; resolutions.locals.len()],
+            ..Default::default()
+        },
+        table: InferTable::default(),
+        diagnostics: Vec::new(),
+        module: 0,
+        self_ty: None,
+        self_value: None,
+        returns: Vec::new(),
+        fn_locals: Vec::new(),
+        bounds: HashMap::new(),
+        obligations: Vec::new(),
+        self_trait: None,
+        assoc_types: HashMap::new(),
+    };
+    checker.collect();
+    for (module, loaded) in graph.modules.iter().enumerate() {
+        checker.module = module;
+        for (item, declaration) in loaded.ast.items.iter().enumerate() {
+            let id = DefId { module, item };
+            match &declaration.kind {
+                ItemKind::Fn(decl) => {
+                    let sig = checker.results.items.fns[&id].clone();
+                    checker.check_fn(decl, &sig);
+                }
+                ItemKind::Trait(decl) => {
+                    checker.enter_trait(id);
+                    let trait_def = checker.results.items.traits[&id].clone();
+                    for (decl, method) in decl.methods.iter().zip(&trait_def.methods) {
+                        checker.check_fn(decl, &method.sig);
+                    }
+                    checker.leave_trait();
+                }
+                ItemKind::Impl(decl) => {
+                    let Some(imp) = checker.results.items.impls.get(&id).cloned() else {
+                        continue;
+                    };
+                    checker.self_ty = Some(imp.target);
+                    checker.assoc_types = imp.assoc_types;
+                    for (decl, method) in decl.methods.iter().zip(&imp.methods) {
+                        checker.check_fn(decl, &method.sig);
+                    }
+                    checker.self_ty = None;
+                    checker.assoc_types.clear();
+                }
+                ItemKind::Const(decl) => {
+                    let ty = checker.results.items.consts[&id].clone();
+                    checker.check_const(&decl.value, &ty);
+                }
+                ItemKind::Enum(decl) => {
+                    for variant in &decl.variants {
+                        if let Some(discriminant) = &variant.discriminant {
+                            checker.check_const(discriminant, &Ty::INT);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let Checker {
+        mut results,
+        table,
+        diagnostics,
+        ..
+    } = checker;
+    for ty in results
+        .exprs
+        .values_mut()
+        .chain(&mut results.locals)
+        .chain(results.generic_args.values_mut().flatten())
+        .chain(results.trait_calls.values_mut().map(|(_, ty)| ty))
+    {
+        *ty = table.finish(ty);
+    }
+    (results, diagnostics)
+}
+
+fn literal_ty(literal: &Literal) -> Ty {
+    Ty::Primitive(match literal {
+        Literal::Int(_) => Primitive::Int,
+        Literal::Float(_) => Primitive::Float,
+        Literal::Str(_) => Primitive::Str,
+        Literal::Char(_) => Primitive::Char,
+        Literal::Bool(_) => Primitive::Bool,
+    })
+}
+
+/// The type of a native function, whose parameter types that aren't primitives are generic.
+fn native_ty(native: &Native) -> (Vec<Generic>, Ty) {
+    let ty =
+        |name: &str| Primitive::from_name(name).map_or(Ty::Param(name.to_string()), Ty::Primitive);
+    let generics = native
+        .params
+        .iter()
+        .filter(|p| Primitive::from_name(p).is_none())
+        .map(|p| Generic {
+            name: p.to_string(),
+            bounds: Vec::new(),
+        })
+        .collect();
+    let params = native.params.iter().map(|p| ty(p)).collect();
+    (generics, Ty::func(params, ty(native.ret)))
+}
+
+fn path_name(path: &Path) -> String {
+    path.segments
+        .iter()
+        .map(|s| s.ident.name.as_str())
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+/// `1 argument`, `2 arguments`.
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {noun}")
+    } else {
+        format!("{n} {noun}s")
+    }
+}
+
+/// `1 was`, `2 were`.
+fn given(n: usize) -> String {
+    if n == 1 {
+        "1 was".to_string()
+    } else {
+        format!("{n} were")
+    }
+}
+
+struct Checker<'a> {
+    graph: &'a ModuleGraph,
+    res: &'a Resolutions,
+    results: TypeckResults,
+    table: InferTable,
+    diagnostics: Vec<Diagnostic>,
+    module: ModuleId,
+    /// The type `Self` stands for inside an impl.
+    self_ty: Option<Ty>,
+    /// The type of `self` inside a method that takes it.
+    self_value: Option<Ty>,
+    /// The return types of the enclosing function and closures, innermost last, with whether a
+    /// `return` was seen.
+    returns: Vec<(Ty, bool)>,
+    /// The locals bound in the function being checked, whose types must be inferred by its end.
+    fn_locals: Vec<LocalId>,
+    /// The bounds of the generic parameters in scope.
+    bounds: HashMap<String, Vec<Bound>>,
+    /// The bounds to check at the end of the current item.
+    obligations: Vec<Obligation>,
+    /// The trait being declared, which `Self` implements.
+    self_trait: Option<DefId>,
+    /// The types `Self::Output` and the like stand for.
+    assoc_types: HashMap<String, Ty>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn mismatch(&mut self, expected: &Ty, found: &Ty, loc: &SourceCodeLocation) {
+        let (expected, found) = (self.table.resolve(expected), self.table.resolve(found));
+        self.error(Diagnostic::error(
+            format!("Mismatched types: expected `{expected}`, found `{found}`."),
+            loc.clone(),
+        ));
+    }
+
+    fn expect(&mut self, expected: &Ty, found: &Ty, loc: &SourceCodeLocation) {
+        // A value that is never produced fits any type that's already known.
+        if self.table.shallow(found) == Ty::NEVER
+            && !matches!(self.table.shallow(expected), Ty::Var(_))
+        {
+            return;
+        }
+        if !self.table.unify(expected, found) {
+            self.mismatch(expected, found, loc);
+        }
+    }
+
+    /// The type with its variables resolved, reporting it if it isn't known yet. `None` if it isn't
+    /// known or is already broken.
+    fn known(&mut self, ty: &Ty, loc: &SourceCodeLocation) -> Option<Ty> {
+        match self.table.resolve(ty) {
+            Ty::Error => None,
+            Ty::Var(_) => {
+                self.error(
+                    Diagnostic::error("The type of this value must be known here.", loc.clone())
+                        .with_help("add a type annotation."),
+                );
+                None
+            }
+            ty => Some(ty),
+        }
+    }
+
+    /// Fresh variables for the generic parameters, which must implement the bounds of the
+    /// parameters.
+    fn instantiate(
+        &mut self,
+        generics: &[Generic],
+        loc: &SourceCodeLocation,
+    ) -> HashMap<String, Ty> {
+        let mut substs = HashMap::new();
+        for generic in generics {
+            let ty = self.table.fresh();
+            self.require(&ty, &generic.bounds, loc);
+            substs.insert(generic.name.clone(), ty);
+        }
+        substs
+    }
+
+    fn require(&mut self, ty: &Ty, bounds: &[Bound], loc: &SourceCodeLocation) {
+        for &bound in bounds {
+            self.obligations.push(Obligation {
+                ty: ty.clone(),
+                bound,
+                loc: loc.clone(),
+            });
+        }
+    }
+
+    /// Bring the generic parameters of an item into scope, along with `Self` inside a trait.
+    fn enter_generics(&mut self, generics: &[Generic]) {
+        self.bounds = generics
+            .iter()
+            .map(|g| (g.name.clone(), g.bounds.clone()))
+            .collect();
+        if let Some(trait_def) = self.self_trait {
+            self.bounds
+                .insert("Self".to_string(), vec![Bound::Trait(trait_def)]);
+        }
+    }
+
+    /// The name a bound is written with.
+    fn bound_name(&self, bound: Bound) -> String {
+        match bound {
+            Bound::Trait(def) => self.results.items.traits[&def].name.clone(),
+            Bound::Error => "{unknown}".to_string(),
+        }
+    }
+
+    /// The full path of the trait of a bound, used in diagnostics.
+    fn bound_path(&self, bound: Bound) -> String {
+        match bound {
+            Bound::Trait(def) => match self.results.items.builtin_traits.get(&def) {
+                Some(op_trait) if ops::OPERATOR_TRAITS.contains(op_trait) => op_trait.path(),
+                _ => self.bound_name(bound),
+            },
+            _ => self.bound_name(bound),
+        }
+    }
+
+    fn implements(&self, ty: &Ty, bound: Bound) -> bool {
+        match (ty, bound) {
+            (Ty::Var(_) | Ty::Error, _) | (_, Bound::Error) => true,
+            (Ty::Param(name), _) => self
+                .bounds
+                .get(name)
+                .is_some_and(|b| b.contains(&bound) || b.contains(&Bound::Error)),
+            (_, Bound::Trait(def)) => self.results.items.implements(def, ty),
+        }
+    }
+
+    /// Whether a type other than a generic parameter implements an operator trait. The primitive
+    /// types implement theirs even when no module declaring the trait is loaded.
+    fn implements_operator(&self, ty: &Ty, op_trait: &OperatorTrait) -> bool {
+        match ty {
+            Ty::Primitive(primitive) => ops::builtin_traits(*primitive).contains(&op_trait),
+            _ => self
+                .results
+                .items
+                .builtin_trait(op_trait)
+                .is_some_and(|def| self.results.items.implements(def, ty)),
+        }
+    }
+
+    /// Report the types that don't implement the bounds they were required to.
+    fn check_obligations(&mut self) {
+        for obligation in std::mem::take(&mut self.obligations) {
+            let ty = self.table.resolve(&obligation.ty);
+            if self.implements(&ty, obligation.bound) {
+                continue;
+            }
+            let error = Diagnostic::error(
+                format!(
+                    "`{ty}` doesn't implement `{}`.",
+                    self.bound_path(obligation.bound)
+                ),
+                obligation.loc,
+            );
+            self.error(match ty {
+                Ty::Param(name) => error.with_help(format!(
+                    "add the bound `{name}: {}` to the generic parameter.",
+                    self.bound_name(obligation.bound)
+                )),
+                _ => error,
+            });
+        }
+    }
+
+    /// Check that a generic parameter's bounds allow an operator, through any of the traits.
+    fn param_operator(
+        &mut self,
+        name: &str,
+        traits: &[&'static OperatorTrait],
+        symbol: &str,
+        loc: &SourceCodeLocation,
+    ) {
+        let bounds = self.bounds.get(name).cloned().unwrap_or_default();
+        let items = &self.results.items;
+        if bounds.contains(&Bound::Error)
+            || traits.iter().any(|t| {
+                items
+                    .builtin_trait(t)
+                    .is_some_and(|def| bounds.contains(&Bound::Trait(def)))
+            })
+        {
+            return;
+        }
+        self.error(
+            Diagnostic::error(
+                format!(
+                    "`{symbol}` can't be used on `{name}` because it doesn't implement `{}`.",
+                    traits[0].path()
+                ),
+                loc.clone(),
+            )
+            .with_help(format!(
+                "add the bound `{name}: {}` to the generic parameter.",
+                traits[0].name
+            )),
+        );
+    }
+
+    /// The generic parameters of a declaration with their bounds.
+    fn generics(&mut self, params: &[GenericParam]) -> Vec<Generic> {
+        params
+            .iter()
+            .map(|param| Generic {
+                name: param.name.name.clone(),
+                bounds: param.bounds.iter().map(|bound| self.bound(bound)).collect(),
+            })
+            .collect()
+    }
+
+    fn bound(&mut self, path: &Path) -> Bound {
+        let last = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        match self.res.get(self.module, &last.ident) {
+            Some(Res::Def(def)) if self.is_trait(def) => Bound::Trait(def),
+            None | Some(Res::Error) => Bound::Error,
+            Some(_) => {
+                self.error(Diagnostic::error(
+                    format!("`{}` is not a trait.", path_name(path)),
+                    path.loc.clone(),
+                ));
+                Bound::Error
+            }
+        }
+    }
+
+    fn is_trait(&self, def: DefId) -> bool {
+        let item = &self.graph.modules[def.module].ast.items[def.item];
+        matches!(item.kind, ItemKind::Trait(_))
+    }
+
+    /// Bind explicit generic arguments such as `<str>` in `Array::init<str>` to the variables of an
+    /// instantiation.
+    fn explicit_generics(
+        &mut self,
+        name: &str,
+        generics: &[Generic],
+        substs: &HashMap<String, Ty>,
+        args: &[Type],
+        loc: &SourceCodeLocation,
+    ) {
+        if args.is_empty() {
+            return;
+        }
+        if args.len() != generics.len() {
+            return self.error(Diagnostic::error(
+                format!(
+                    "`{name}` takes {} but {} given.",
+                    count(generics.len(), "generic argument"),
+                    given(args.len())
+                ),
+                loc.clone(),
+            ));
+        }
+        for (generic, arg) in generics.iter().zip(args) {
+            let arg_ty = self.lower_ty(arg, true);
+            self.expect(&substs[&generic.name], &arg_ty, &arg.loc);
+        }
+    }
+
+    fn adt_substs(&self, def: DefId, args: &[Ty]) -> HashMap<String, Ty> {
+        self.results.items.adts[&def]
+            .generics
+            .iter()
+            .map(|g| g.name.clone())
+            .zip(args.iter().cloned())
+            .collect()
+    }
+
+    /// The type of a struct or enum with fresh variables for its generics, or the explicit ones.
+    fn adt_ty(&mut self, def: DefId, explicit: &[Type], loc: &SourceCodeLocation) -> Ty {
+        let adt = &self.results.items.adts[&def];
+        let (name, generics) = (adt.name.clone(), adt.generics.clone());
+        let substs = self.instantiate(&generics, loc);
+        self.explicit_generics(&name, &generics, &substs, explicit, loc);
+        Ty::Adt {
+            def,
+            name,
+            args: generics.iter().map(|g| substs[&g.name].clone()).collect(),
+        }
+    }
+
+    // Signatures.
+
+    fn collect(&mut self) {
+        let graph = self.graph;
+        // The generics of every struct and enum come first, as the types of fields and signatures
+        // refer to them.
+        for (module, loaded) in graph.modules.iter().enumerate() {
+            self.module = module;
+            for (item, declaration) in loaded.ast.items.iter().enumerate() {
+                let (name, generics, kind) = match &declaration.kind {
+                    ItemKind::Struct(decl) => (&decl.name, &decl.generics, AdtKind::Struct(vec![])),
+                    ItemKind::Enum(decl) => (&decl.name, &decl.generics, AdtKind::Enum(vec![])),
+                    ItemKind::Trait(decl) => {
+                        let generics = self.generics(&decl.generics);
+                        let name = &decl.name.name;
+                        if let Some(op_trait) = ops::std_builtin_trait(&loaded.name, name) {
+                            self.results
+                                .items
+                                .builtin_traits
+                                .insert(DefId { module, item }, op_trait);
+                        }
+                        self.results.items.traits.insert(
+                            DefId { module, item },
+                            TraitDef {
+                                name: decl.name.name.clone(),
+                                generics,
+                                assoc_types: decl
+                                    .assoc_types
+                                    .iter()
+                                    .map(|a| a.name.name.clone())
+                                    .collect(),
+                                methods: Vec::new(),
+                            },
+                        );
+                        continue;
+                    }
+                    _ => continue,
+                };
+                let generics = self.generics(generics);
+                self.results.items.adts.insert(
+                    DefId { module, item },
+                    AdtDef {
+                        name: name.name.clone(),
+                        generics,
+                        kind,
+                    },
+                );
+            }
+        }
+
+        // The methods of every trait come next, as impls in any module may implement them.
+        for (module, loaded) in graph.modules.iter().enumerate() {
+            self.module = module;
+            for (item, declaration) in loaded.ast.items.iter().enumerate() {
+                if let ItemKind::Trait(decl) = &declaration.kind {
+                    self.collect_trait(DefId { module, item }, decl);
+                    self.check_obligations();
+                }
+            }
+        }
+
+        for (module, loaded) in graph.modules.iter().enumerate() {
+            self.module = module;
+            for (item, declaration) in loaded.ast.items.iter().enumerate() {
+                let id = DefId { module, item };
+                let generics = self
+                    .results
+                    .items
+                    .adts
+                    .get(&id)
+                    .map_or(vec![], |adt| adt.generics.clone());
+                self.enter_generics(&generics);
+                match &declaration.kind {
+                    ItemKind::Struct(decl) => {
+                        let fields = decl
+                            .fields
+                            .iter()
+                            .map(|field| FieldDef {
+                                name: field.name.name.clone(),
+                                ty: self.lower_ty(&field.ty, false),
+                                mutable: field.mutable,
+                                loc: field.name.loc.clone(),
+                            })
+                            .collect();
+                        self.results.items.adts.get_mut(&id).unwrap().kind =
+                            AdtKind::Struct(fields);
+                    }
+                    ItemKind::Enum(decl) => {
+                        let variants = decl
+                            .variants
+                            .iter()
+                            .map(|variant| VariantDef {
+                                name: variant.name.name.clone(),
+                                fields: variant
+                                    .fields
+                                    .iter()
+                                    .map(|ty| self.lower_ty(ty, false))
+                                    .collect(),
+                            })
+                            .collect();
+                        self.results.items.adts.get_mut(&id).unwrap().kind =
+                            AdtKind::Enum(variants);
+                    }
+                    ItemKind::Fn(decl) => {
+                        let sig = self.signature(decl, &[]);
+                        self.results.items.fns.insert(id, sig);
+                        if let Some(loc) = &decl.native {
+                            self.native(id, decl, loc);
+                        }
+                    }
+                    ItemKind::Impl(decl) => self.collect_impl(id, decl),
+                    ItemKind::Const(decl) => {
+                        let ty = self.lower_ty(&decl.ty, false);
+                        self.results.items.consts.insert(id, ty);
+                    }
+                    ItemKind::Trait(_) | ItemKind::Import(_) | ItemKind::Export(_) => {}
+                }
+                self.check_obligations();
+            }
+        }
+        self.check_coherence();
+    }
+
+    fn collect_trait(&mut self, id: DefId, decl: &TraitDecl) {
+        let trait_def = self.results.items.traits[&id].clone();
+        self.enter_trait(id);
+        let mut methods: Vec<TraitMethodDef> = Vec::new();
+        for method in &decl.methods {
+            if methods.iter().any(|m| m.name == method.name.name) {
+                self.error(Diagnostic::error(
+                    format!(
+                        "`{}` is declared more than once in `{}`.",
+                        method.name.name, trait_def.name
+                    ),
+                    method.name.loc.clone(),
+                ));
+            }
+            self.native_method(method);
+            methods.push(TraitMethodDef {
+                name: method.name.name.clone(),
+                sig: self.signature(method, &trait_def.generics),
+                has_default: method.body.is_some(),
+            });
+        }
+        self.leave_trait();
+        self.results.items.traits.get_mut(&id).unwrap().methods = methods;
+    }
+
+    /// Find the runtime function a `@native` function declares, which only the std modules may do.
+    fn native(&mut self, id: DefId, decl: &FnDecl, loc: &SourceCodeLocation) {
+        let module = &self.graph.modules[id.module];
+        let name = &decl.name.name;
+        let error = match builtins::native(name) {
+            _ if module.name.first().map(String::as_str) != Some("std") => {
+                "Only the std modules can declare `@native` functions.".to_string()
+            }
+            Some(native) if native.module == Some(&*module.display_name()) => {
+                self.results.items.natives.insert(id, native);
+                return;
+            }
+            _ => format!(
+                "The runtime has no native function `{name}` for `{}`.",
+                module.display_name()
+            ),
+        };
+        self.error(Diagnostic::error(error, loc.clone()));
+    }
+
+    fn native_method(&mut self, decl: &FnDecl) {
+        if let Some(loc) = &decl.native {
+            self.error(Diagnostic::error(
+                "Only functions can be `@native`, not methods.",
+                loc.clone(),
+            ));
+        }
+    }
+
+    /// Check the signatures and bodies of a trait with `Self` as a generic parameter bound by it.
+    fn enter_trait(&mut self, id: DefId) {
+        self.self_trait = Some(id);
+        self.self_ty = Some(Ty::Param("Self".to_string()));
+        self.assoc_types = self.results.items.traits[&id]
+            .assoc_types
+            .iter()
+            .map(|name| (name.clone(), Ty::Param(format!("Self::{name}"))))
+            .collect();
+    }
+
+    fn leave_trait(&mut self) {
+        self.self_trait = None;
+        self.self_ty = None;
+        self.assoc_types.clear();
+    }
+
+    /// The trait of an `impl Trait for Type`, `None` for broken paths.
+    fn trait_ref(&mut self, path: &Path) -> Option<TraitRef> {
+        let last = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        match self.res.get(self.module, &last.ident)? {
+            Res::Def(def) if self.is_trait(def) => {
+                let args = last
+                    .generics
+                    .iter()
+                    .map(|ty| self.lower_ty(ty, false))
+                    .collect::<Vec<_>>();
+                let trait_def = &self.results.items.traits[&def];
+                if args.len() != trait_def.generics.len() {
+                    let message = format!(
+                        "`{}` takes {} but {} given.",
+                        trait_def.name,
+                        count(trait_def.generics.len(), "generic argument"),
+                        given(args.len())
+                    );
+                    self.error(Diagnostic::error(message, path.loc.clone()));
+                    return None;
+                }
+                Some(TraitRef { def, args })
+            }
+            Res::Error => None,
+            _ => {
+                self.error(Diagnostic::error(
+                    format!("`{}` is not a trait.", path_name(path)),
+                    path.loc.clone(),
+                ));
+                None
+            }
+        }
+    }
+
+    /// What the generic parameters of a trait's signatures stand for in an impl of it.
+    fn trait_substs(
+        &self,
+        trait_ref: &TraitRef,
+        target: &Ty,
+        assoc_types: &HashMap<String, Ty>,
+    ) -> HashMap<String, Ty> {
+        let trait_def = &self.results.items.traits[&trait_ref.def];
+        let mut substs = trait_def
+            .generics
+            .iter()
+            .map(|g| g.name.clone())
+            .zip(trait_ref.args.iter().cloned())
+            .collect::<HashMap<_, _>>();
+        substs.insert("Self".to_string(), target.clone());
+        for name in &trait_def.assoc_types {
+            let ty = assoc_types.get(name).cloned().unwrap_or(Ty::Error);
+            substs.insert(format!("Self::{name}"), ty);
+        }
+        substs
+    }
+
+    /// Report the impls of the same trait whose targets overlap, so that a method call could pick
+    /// either of them. The later impl of each pair is left out of method lookup.
+    fn check_coherence(&mut self) {
+        let mut traits = self
+            .results
+            .items
+            .trait_impls
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        traits.sort();
+        for trait_def in traits {
+            let imps = self.results.items.trait_impls[&trait_def].clone();
+            let mut kept = Vec::new();
+            for &later in &imps {
+                let Some(&earlier) = kept.iter().find(|&&earlier| self.overlap(earlier, later))
+                else {
+                    kept.push(later);
+                    continue;
+                };
+                let ItemKind::Impl(decl) =
+                    &self.graph.modules[later.module].ast.items[later.item].kind
+                else {
+                    unreachable!("impls are declared by impl items")
+                };
+                let imp = &self.results.items.impls[&later];
+                let earlier_item = &self.graph.modules[earlier.module].ast.items[earlier.item];
+                let message = format!(
+                    "Conflicting implementations of `{}` for `{}`.",
+                    self.results.items.traits[&trait_def].name, imp.target
+                );
+                let help = format!(
+                    "the other implementation is on line {} of {}.",
+                    earlier_item.loc.line,
+                    earlier_item.loc.filename.as_deref().unwrap_or("the input")
+                );
+                self.error(Diagnostic::error(message, decl.target.loc.clone()).with_help(help));
+            }
+            self.results.items.trait_impls.insert(trait_def, kept);
+        }
+    }
+
+    /// Whether a type could match the targets and trait arguments of both impls.
+    fn overlap(&self, a: DefId, b: DefId) -> bool {
+        let mut table = InferTable::default();
+        let mut fresh = |imp: &ImplDef| {
+            let substs = imp
+                .generics
+                .iter()
+                .map(|g| (g.name.clone(), table.fresh()))
+                .collect();
+            let args = imp.trait_ref.as_ref().map_or(vec![], |t| t.args.clone());
+            Ty::Tuple([vec![imp.target.clone()], args].concat()).subst(&substs)
+        };
+        let (a, b) = (
+            fresh(&self.results.items.impls[&a]),
+            fresh(&self.results.items.impls[&b]),
+        );
+        table.unify(&a, &b)
+    }
+
+    fn collect_impl(&mut self, id: DefId, decl: &ImplDecl) {
+        let generics = self.generics(&decl.generics);
+        self.enter_generics(&generics);
+        let target = self.lower_path_ty(&decl.target, false);
+        let adt = match &target {
+            Ty::Adt { def, .. } => *def,
+            Ty::Error => return,
+            _ => {
+                return self.error(Diagnostic::error(
+                    "Methods can only be implemented for structs and enums.",
+                    decl.target.loc.clone(),
+                ))
+            }
+        };
+        let trait_ref = decl
+            .trait_ref
+            .as_ref()
+            .and_then(|path| self.trait_ref(path));
+        self.self_ty = Some(target.clone());
+        let assoc_types = match &trait_ref {
+            Some(trait_ref) => self.impl_assoc_types(trait_ref, &target, decl),
+            None => HashMap::new(),
+        };
+        self.assoc_types = assoc_types.clone();
+        let mut methods = Vec::new();
+        for method in &decl.methods {
+            let defined_before = methods
+                .iter()
+                .any(|m: &MethodDef| m.name == method.name.name);
+            let inherent = trait_ref.is_none()
+                && self
+                    .results
+                    .items
+                    .lookup_method(adt, &method.name.name)
+                    .is_some();
+            if defined_before || inherent {
+                self.error(Diagnostic::error(
+                    format!(
+                        "`{}` is defined more than once for `{target}`.",
+                        method.name.name
+                    ),
+                    method.name.loc.clone(),
+                ));
+            }
+            self.native_method(method);
+            methods.push(MethodDef {
+                name: method.name.name.clone(),
+                sig: self.signature(method, &generics),
+                inherited: None,
+            });
+        }
+        if let Some(trait_ref) = &trait_ref {
+            let substs = self.trait_substs(trait_ref, &target, &assoc_types);
+            self.check_impl_methods(trait_ref, &substs, decl, &mut methods, &generics);
+            let trait_module = trait_ref.def.module;
+            if trait_module != self.module && adt.module != self.module {
+                self.error(Diagnostic::error(
+                    format!(
+                        "Can't implement `{}` for `{target}` here: the trait or the type must be \
+                         declared in this module.",
+                        self.results.items.traits[&trait_ref.def].name
+                    ),
+                    decl.target.loc.clone(),
+                ));
+            }
+        }
+        self.self_ty = None;
+        self.assoc_types.clear();
+        match &trait_ref {
+            Some(trait_ref) => self
+                .results
+                .items
+                .trait_impls
+                .entry(trait_ref.def)
+                .or_default()
+                .push(id),
+            None => self.results.items.impls_of.entry(adt).or_default().push(id),
+        }
+        self.results.items.impls.insert(
+            id,
+            ImplDef {
+                generics,
+                trait_ref,
+                target,
+                assoc_types,
+                methods,
+            },
+        );
+    }
+
+    /// The associated types an impl defines, which must be those of the trait.
+    fn impl_assoc_types(
+        &mut self,
+        trait_ref: &TraitRef,
+        target: &Ty,
+        decl: &ImplDecl,
+    ) -> HashMap<String, Ty> {
+        let trait_def = self.results.items.traits[&trait_ref.def].clone();
+        let mut assoc_types = HashMap::new();
+        for assoc in &decl.assoc_types {
+            let ty = assoc
+                .ty
+                .as_ref()
+                .map_or(Ty::Error, |ty| self.lower_ty(ty, false));
+            if !trait_def.assoc_types.contains(&assoc.name.name) {
+                let mut error = Diagnostic::error(
+                    format!(
+                        "`{}` is not an associated type of `{}`.",
+                        assoc.name.name, trait_def.name
+                    ),
+                    assoc.name.loc.clone(),
+                );
+                let names = trait_def.assoc_types.iter().map(String::as_str);
+                if let Some(suggestion) = suggest(&assoc.name.name, names) {
+                    error = error.with_help(format!("did you mean `{suggestion}`?"));
+                }
+                self.error(error);
+                continue;
+            }
+            assoc_types.insert(assoc.name.name.clone(), ty);
+        }
+        let missing = trait_def
+            .assoc_types
+            .iter()
+            .filter(|name| !assoc_types.contains_key(*name))
+            .map(|name| format!("`{name}`"))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.error(Diagnostic::error(
+                format!(
+                    "Missing associated types in the implementation of `{}` for `{target}`: {}.",
+                    trait_def.name,
+                    missing.join(", ")
+                ),
+                decl.target.loc.clone(),
+            ));
+        }
+        assoc_types
+    }
+
+    /// Check that the methods of an impl are those of the trait with the same signatures, and add
+    /// the default methods the impl doesn't define.
+    fn check_impl_methods(
+        &mut self,
+        trait_ref: &TraitRef,
+        substs: &HashMap<String, Ty>,
+        decl: &ImplDecl,
+        methods: &mut Vec<MethodDef>,
+        impl_generics: &[Generic],
+    ) {
+        let trait_def = self.results.items.traits[&trait_ref.def].clone();
+        let trait_generics = trait_def.generics.len();
+        for (method, def) in decl.methods.iter().zip(methods.iter()) {
+            let Some(expected) = trait_def.methods.iter().find(|m| m.name == def.name) else {
+                let mut error = Diagnostic::error(
+                    format!("`{}` is not a method of `{}`.", def.name, trait_def.name),
+                    method.name.loc.clone(),
+                );
+                let names = trait_def.methods.iter().map(|m| m.name.as_str());
+                if let Some(suggestion) = suggest(&def.name, names) {
+                    error = error.with_help(format!("did you mean `{suggestion}`?"));
+                }
+                self.error(error);
+                continue;
+            };
+            let own = |sig: &FnSig, outer: usize| sig.generics[outer..].to_vec();
+            let (expected_own, found_own) = (
+                own(&expected.sig, trait_generics),
+                own(&def.sig, impl_generics.len()),
+            );
+            let message = if expected.sig.has_self != def.sig.has_self {
+                let takes = if expected.sig.has_self {
+                    "takes"
+                } else {
+                    "doesn't take"
+                };
+                Some(format!(
+                    "`{}` {takes} `self` in `{}`.",
+                    def.name, trait_def.name
+                ))
+            } else if expected_own.len() != found_own.len() {
+                Some(format!(
+                    "`{}` takes {} in `{}`, but {} here.",
+                    def.name,
+                    count(expected_own.len(), "generic parameter"),
+                    trait_def.name,
+                    found_own.len()
+                ))
+            } else {
+                // The method's own generic parameters may have other names in the impl.
+                let mut substs = substs.clone();
+                for (expected, found) in expected_own.iter().zip(&found_own) {
+                    substs.insert(expected.name.clone(), Ty::Param(found.name.clone()));
+                }
+                let sig_ty = |sig: &FnSig, substs: &HashMap<String, Ty>| {
+                    Ty::Fn(FnTy {
+                        params: sig.params.iter().map(|p| p.subst(substs)).collect(),
+                        ret: Box::new(sig.ret.subst(substs)),
+                        variadic: sig.variadic,
+                    })
+                };
+                let expected_ty = sig_ty(&expected.sig, &substs);
+                let found_ty = sig_ty(&def.sig, &HashMap::new());
+                let broken = |ty: &Ty| ty.any(&|t| *t == Ty::Error);
+                (expected_ty != found_ty && !broken(&expected_ty) && !broken(&found_ty)).then(
+                    || {
+                        format!(
+                            "The signature of `{}` doesn't match `{}`: expected `{expected_ty}`, \
+                             found `{found_ty}`.",
+                            def.name, trait_def.name
+                        )
+                    },
+                )
+            };
+            if let Some(message) = message {
+                self.error(Diagnostic::error(message, method.name.loc.clone()));
+            }
+        }
+
+        let mut missing = Vec::new();
+        for (index, expected) in trait_def.methods.iter().enumerate() {
+            if methods.iter().any(|m| m.name == expected.name) {
+                continue;
+            }
+            if !expected.has_default {
+                missing.push(format!("`{}`", expected.name));
+                continue;
+            }
+            let mut generics = impl_generics.to_vec();
+            generics.extend_from_slice(&expected.sig.generics[trait_generics..]);
+            methods.push(MethodDef {
+                name: expected.name.clone(),
+                sig: FnSig {
+                    generics,
+                    params: expected
+                        .sig
+                        .params
+                        .iter()
+                        .map(|p| p.subst(substs))
+                        .collect(),
+                    ret: expected.sig.ret.subst(substs),
+                    variadic: expected.sig.variadic,
+                    has_self: expected.sig.has_self,
+                },
+                inherited: Some(TraitMethodId {
+                    trait_def: trait_ref.def,
+                    index,
+                }),
+            });
+        }
+        if !missing.is_empty() {
+            self.error(Diagnostic::error(
+                format!(
+                    "Missing methods in the implementation of `{}` for `{}`: {}.",
+                    trait_def.name,
+                    substs["Self"],
+                    missing.join(", ")
+                ),
+                decl.target.loc.clone(),
+            ));
+        }
+    }
+
+    fn signature(&mut self, decl: &FnDecl, outer_generics: &[Generic]) -> FnSig {
+        let mut generics = outer_generics.to_vec();
+        generics.extend(self.generics(&decl.generics));
+        self.enter_generics(&generics);
+        let params = decl
+            .params
+            .iter()
+            .map(|param| self.lower_ty(&param.ty, false))
+            .collect::<Vec<_>>();
+        for (i, param) in decl.params.iter().enumerate() {
+            if !param.variadic {
+                continue;
+            }
+            if i != decl.params.len() - 1 {
+                self.error(Diagnostic::error(
+                    "Only the last parameter can be variadic.",
+                    param.loc.clone(),
+                ));
+            } else if !matches!(params[i], Ty::Array(_) | Ty::Error) {
+                self.error(
+                    Diagnostic::error(
+                        "A variadic parameter collects the arguments into an array.",
+                        param.ty.loc.clone(),
+                    )
+                    .with_help(format!("use `[]{}` as its type.", params[i])),
+                );
+            }
+        }
+        FnSig {
+            generics,
+            params,
+            ret: self.lower_ty(&decl.ret, false),
+            variadic: decl.params.last().is_some_and(|p| p.variadic),
+            has_self: decl.self_param.is_some(),
+        }
+    }
+
+    /// The type a type annotation stands for. Inside function bodies the generic arguments of a struct
+    /// or enum may be left out to have them inferred.
+    fn lower_ty(&mut self, ty: &Type, infer_generics: bool) -> Ty {
+        match &ty.kind {
+            TypeKind::Path(path) => self.lower_path_ty(path, infer_generics),
+            TypeKind::Array(elem) => Ty::Array(Box::new(self.lower_ty(elem, infer_generics))),
+            TypeKind::Tuple(elems) => Ty::Tuple(
+                elems
+                    .iter()
+                    .map(|e| self.lower_ty(e, infer_generics))
+                    .collect(),
+            ),
+            TypeKind::Fn { params, ret } => Ty::func(
+                params
+                    .iter()
+                    .map(|p| self.lower_ty(p, infer_generics))
+                    .collect(),
+                self.lower_ty(ret, infer_generics),
+            ),
+        }
+    }
+
+    fn lower_path_ty(&mut self, path: &Path, infer_generics: bool) -> Ty {
+        if let [owner, assoc] = path.segments.as_slice() {
+            match self.res.get(self.module, &owner.ident) {
+                Some(Res::SelfType) => return self.self_assoc_type(&assoc.ident),
+                Some(Res::Generic) => return self.param_assoc_type(&owner.ident, &assoc.ident),
+                _ => {}
+            }
+        }
+        let segment = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        let Some(res) = self.res.get(self.module, &segment.ident) else {
+            return Ty::Error;
+        };
+        let args = segment
+            .generics
+            .iter()
+            .map(|ty| self.lower_ty(ty, infer_generics))
+            .collect::<Vec<_>>();
+        let name = &segment.ident.name;
+        let ty = match res {
+            Res::Def(def) if self.results.items.adts.contains_key(&def) => {
+                let generics = self.results.items.adts[&def].generics.len();
+                if args.is_empty() && infer_generics {
+                    let loc = path.loc.clone();
+                    return self.adt_ty(def, &[], &loc);
+                }
+                if args.len() != generics {
+                    self.error(Diagnostic::error(
+                        format!(
+                            "`{name}` takes {} but {} given.",
+                            count(generics, "generic argument"),
+                            given(args.len())
+                        ),
+                        path.loc.clone(),
+                    ));
+                    return Ty::Error;
+                }
+                let bounds = self.results.items.adts[&def]
+                    .generics
+                    .iter()
+                    .map(|g| g.bounds.clone())
+                    .collect::<Vec<_>>();
+                for (arg, bounds) in args.iter().zip(&bounds) {
+                    self.require(arg, bounds, &path.loc);
+                }
+                return Ty::Adt {
+                    def,
+                    name: name.clone(),
+                    args,
+                };
+            }
+            Res::Primitive(primitive) => Ty::Primitive(primitive),
+            Res::Generic => Ty::Param(name.clone()),
+            Res::SelfType => self.self_ty.clone().unwrap_or(Ty::Error),
+            Res::Error => return Ty::Error,
+            _ => {
+                self.error(Diagnostic::error(
+                    format!("`{}` is not a type.", path_name(path)),
+                    path.loc.clone(),
+                ));
+                return Ty::Error;
+            }
+        };
+        if !args.is_empty() {
+            self.error(Diagnostic::error(
+                format!("`{name}` doesn't take generic arguments."),
+                path.loc.clone(),
+            ));
+        }
+        ty
+    }
+
+    /// `Self::Output` inside a trait or an impl of it.
+    fn self_assoc_type(&mut self, name: &Ident) -> Ty {
+        if let Some(ty) = self.assoc_types.get(&name.name) {
+            return ty.clone();
+        }
+        if self.self_ty.as_ref().is_some_and(|ty| *ty != Ty::Error) {
+            self.error(Diagnostic::error(
+                format!("`Self` has no associated type `{}`.", name.name),
+                name.loc.clone(),
+            ));
+        }
+        Ty::Error
+    }
+
+    /// `T::Output`, an associated type of a trait the generic parameter is bound by.
+    fn param_assoc_type(&mut self, param: &Ident, name: &Ident) -> Ty {
+        let bounds = self.bounds.get(&param.name).cloned().unwrap_or_default();
+        let provided = bounds.iter().any(|&bound| match bound {
+            Bound::Trait(def) => self.results.items.traits[&def]
+                .assoc_types
+                .contains(&name.name),
+            _ => false,
+        });
+        if provided {
+            return Ty::Param(format!("{}::{}", param.name, name.name));
+        }
+        if !bounds.contains(&Bound::Error) {
+            self.error(
+                Diagnostic::error(
+                    format!("`{}` has no associated type `{}`.", param.name, name.name),
+                    name.loc.clone(),
+                )
+                .with_help(format!(
+                    "the associated types of `{}` come from the traits it's bound by.",
+                    param.name
+                )),
+            );
+        }
+        Ty::Error
+    }
+
+    // Bodies.
+
+    fn check_fn(&mut self, decl: &FnDecl, sig: &FnSig) {
+        self.fn_locals.clear();
+        self.enter_generics(&sig.generics);
+        self.self_value = decl.self_param.as_ref().and(self.self_ty.clone());
+        for (param, ty) in decl.params.iter().zip(&sig.params) {
+            self.pattern(&param.pattern, ty);
+        }
+        self.returns.push((sig.ret.clone(), false));
+        if let Some(body) = &decl.body {
+            self.block(body);
+        }
+        self.returns.pop();
+
+        for local in std::mem::take(&mut self.fn_locals) {
+            let ty = self.table.resolve(&self.results.locals[local]);
+            if ty.any(&|t| matches!(t, Ty::Var(_))) {
+                let local = &self.res.locals[local];
+                self.error(
+                    Diagnostic::error(
+                        format!("Can't infer the type of `{}`.", local.name),
+                        local.loc.clone(),
+                    )
+                    .with_help(format!("add a type annotation to `{}`.", local.name)),
+                );
+            }
+        }
+        self.check_obligations();
+        self.self_value = None;
+    }
+
+    /// Check the value of a constant or a discriminant, which is evaluated at compile time.
+    fn check_const(&mut self, value: &Expr, ty: &Ty) {
+        self.fn_locals.clear();
+        self.check(value, ty);
+        self.check_obligations();
+    }
+
+    fn block(&mut self, block: &Block) {
+        for stmt in &block.stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Let { pattern, ty, value } => {
+                let ty = match ty {
+                    Some(ty) => self.lower_ty(ty, true),
+                    None => self.table.fresh(),
+                };
+                if let Some(value) = value {
+                    self.check(value, &ty);
+                }
+                self.pattern(pattern, &ty);
+            }
+            StmtKind::Assign { target, op, value } => {
+                let ty = self.expr(target, None);
+                let assignable = match &target.kind {
+                    ExprKind::Path(path) => matches!(
+                        self.res.get(self.module, &path.segments[0].ident),
+                        Some(Res::Local(_) | Res::Error) | None
+                    ),
+                    ExprKind::Field { .. } => true,
+                    _ => false,
+                };
+                if !assignable {
+                    self.error(Diagnostic::error(
+                        "Only variables and fields can be assigned to.",
+                        target.loc.clone(),
+                    ));
+                }
+                self.check(value, &ty);
+                if let Some(op) = op {
+                    match self.known(&ty, &target.loc) {
+                        Some(Ty::Param(name)) => {
+                            let in_place = assign_trait(*op)
+                                .expect("only arithmetic operators have compound assignments");
+                            let binary = binary_dispatch(*op).map(|d| d.op_trait);
+                            let symbol = format!("{}=", op.symbol());
+                            let traits = [Some(in_place), binary]
+                                .into_iter()
+                                .flatten()
+                                .collect::<Vec<_>>();
+                            self.param_operator(&name, &traits, &symbol, &stmt.loc);
+                        }
+                        Some(ty) => {
+                            let implements = |t: &OperatorTrait| self.implements_operator(&ty, t);
+                            if let Err(error) = ops::assign(*op, &ty, implements, &stmt.loc) {
+                                self.error(error);
+                            }
+                        }
+                        None => {}
+                    }
+                }
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr, None);
+            }
+            StmtKind::Return(value) => {
+                let (ret, seen) = self
+                    .returns
+                    .last_mut()
+                    .expect("statements are inside functions");
+                *seen = true;
+                let ret = ret.clone();
+                if self.table.resolve(&ret) == Ty::NEVER {
+                    self.error(Diagnostic::error(
+                        "This function never returns, so it can't use `return`.",
+                        stmt.loc.clone(),
+                    ));
+                    return;
+                }
+                match value {
+                    Some(value) => self.check(value, &ret),
+                    None => {
+                        if !self.table.unify(&ret, &Ty::VOID) {
+                            let ret = self.table.resolve(&ret);
+                            self.error(Diagnostic::error(
+                                format!(
+                                    "This function returns `{ret}`, so `return` needs a value."
+                                ),
+                                stmt.loc.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
+            StmtKind::Break | StmtKind::Continue => {}
+            StmtKind::If {
+                cond,
+                then_block,
+                else_block,
+            } => {
+                self.check(cond, &Ty::BOOL);
+                self.block(then_block);
+                if let Some(else_block) = else_block {
+                    self.block(else_block);
+                }
+            }
+            StmtKind::While { cond, body } => {
+                self.check(cond, &Ty::BOOL);
+                self.block(body);
+            }
+            StmtKind::Match { scrutinee, arms } => {
+                let ty = self.expr(scrutinee, None);
+                for arm in arms {
+                    self.pattern(&arm.pattern, &ty);
+                    if let Some(guard) = &arm.guard {
+                        self.check(guard, &Ty::BOOL);
+                    }
+                    self.stmt(&arm.body);
+                }
+            }
+            StmtKind::Block(block) => self.block(block),
+        }
+    }
+
+    /// Check that the expression has the expected type.
+    fn check(&mut self, expr: &Expr, expected: &Ty) {
+        let found = self.expr(expr, Some(expected));
+        self.expect(expected, &found, &expr.loc);
+    }
+
+    /// Infer the type of an expression, given the type it is expected to have if that's known.
+    fn expr(&mut self, expr: &Expr, expected: Option<&Ty>) -> Ty {
+        let ty = match &expr.kind {
+            ExprKind::Literal(literal) => literal_ty(literal),
+            ExprKind::Path(path) => self.path_expr(expr, path),
+            ExprKind::SelfValue => self.self_value.clone().unwrap_or(Ty::Error),
+            ExprKind::Tuple(elems) => {
+                let expected = match expected.map(|e| self.table.shallow(e)) {
+                    Some(Ty::Tuple(tys)) if tys.len() == elems.len() => tys,
+                    _ => elems.iter().map(|_| self.table.fresh()).collect(),
+                };
+                for (elem, ty) in elems.iter().zip(&expected) {
+                    self.check(elem, ty);
+                }
+                Ty::Tuple(expected)
+            }
+            ExprKind::Array(elems) => {
+                let elem_ty = match expected.map(|e| self.table.shallow(e)) {
+                    Some(Ty::Array(elem)) => *elem,
+                    _ => self.table.fresh(),
+                };
+                for elem in elems {
+                    self.check(elem, &elem_ty);
+                }
+                Ty::Array(Box::new(elem_ty))
+            }
+            ExprKind::Unary { op, expr: operand } => {
+                let ty = self.expr(operand, None);
+                match self.known(&ty, &operand.loc) {
+                    Some(Ty::Param(name)) => {
+                        let symbol = match op {
+                            UnaryOp::Neg => "-",
+                            UnaryOp::Not => "!",
+                        };
+                        self.param_operator(&name, &[unary_trait(*op)], symbol, &expr.loc);
+                        Ty::Param(name)
+                    }
+                    Some(ty) => {
+                        let implements = |t: &OperatorTrait| self.implements_operator(&ty, t);
+                        if let Err(error) = ops::unary(*op, &ty, implements, &expr.loc) {
+                            self.error(error);
+                        }
+                        ty
+                    }
+                    None => Ty::Error,
+                }
+            }
+            ExprKind::Binary { op, lhs, rhs } => self.binary(*op, lhs, rhs, &expr.loc),
+            ExprKind::Call { callee, args } => {
+                let callee_ty = self.expr(callee, None);
+                let ty = self.call(&callee_ty, args, expected, &callee.loc);
+                if self.table.resolve(&ty) == Ty::NEVER {
+                    self.continuation_call(callee, &expr.loc);
+                }
+                ty
+            }
+            ExprKind::MethodCall {
+                receiver,
+                method,
+                args,
+            } => self.method_call(expr, receiver, method, args, expected),
+            ExprKind::Field { base, field } => self.field(base, field),
+            ExprKind::StructLit { path, fields } => {
+                self.struct_lit(path, fields, expected, &expr.loc)
+            }
+            ExprKind::Closure(closure) => self.closure(closure, expected),
+        };
+        self.results
+            .exprs
+            .insert(key(self.module, &expr.loc), ty.clone());
+        ty
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr, loc: &SourceCodeLocation) -> Ty {
+        let Some(dispatch) = binary_dispatch(op) else {
+            self.check(lhs, &Ty::BOOL);
+            self.check(rhs, &Ty::BOOL);
+            return Ty::BOOL;
+        };
+        let lhs_ty = self.expr(lhs, None);
+        if let Ty::Var(_) = self.table.shallow(&lhs_ty) {
+            // `x + 1` learns the type of `x` from the right-hand side.
+            let rhs_ty = self.expr(rhs, None);
+            self.expect(&lhs_ty, &rhs_ty, &rhs.loc);
+        } else {
+            self.check(rhs, &lhs_ty);
+        }
+        let returns_bool = dispatch.op_trait.returns.is_some();
+        match self.known(&lhs_ty, &lhs.loc) {
+            Some(ty) => {
+                let implements = |t: &OperatorTrait| self.implements_operator(&ty, t);
+                if let Ty::Param(name) = &ty {
+                    self.param_operator(name, &[dispatch.op_trait], op.symbol(), loc);
+                } else if let Err(error) = ops::binary(op, &ty, implements, loc) {
+                    self.error(error);
+                }
+                if returns_bool {
+                    Ty::BOOL
+                } else {
+                    ty
+                }
+            }
+            None if returns_bool => Ty::BOOL,
+            None => Ty::Error,
+        }
+    }
+
+    /// Check the arguments of a call against the type of the callee, returning the type of the call.
+    fn call(
+        &mut self,
+        callee_ty: &Ty,
+        args: &[Expr],
+        expected: Option<&Ty>,
+        loc: &SourceCodeLocation,
+    ) -> Ty {
+        let fn_ty = match self.known(callee_ty, loc) {
+            Some(Ty::Fn(fn_ty)) => fn_ty,
+            ty => {
+                if let Some(ty) = ty {
+                    self.error(Diagnostic::error(
+                        format!("`{ty}` is not a function."),
+                        loc.clone(),
+                    ));
+                }
+                for arg in args {
+                    self.expr(arg, Some(&Ty::Error));
+                }
+                return Ty::Error;
+            }
+        };
+        // Knowing the result first gives the arguments their types, e.g. a closure its parameters'.
+        if let Some(expected) = expected {
+            self.table.try_unify(&fn_ty.ret, expected);
+        }
+        self.args(&fn_ty, args, loc);
+        *fn_ty.ret
+    }
+
+    fn args(&mut self, fn_ty: &FnTy, args: &[Expr], loc: &SourceCodeLocation) {
+        let fixed = fn_ty.params.len() - usize::from(fn_ty.variadic);
+        if args.len() < fixed || (!fn_ty.variadic && args.len() > fixed) {
+            let at_least = if fn_ty.variadic { "at least " } else { "" };
+            self.error(Diagnostic::error(
+                format!(
+                    "This function takes {at_least}{} but {} given.",
+                    count(fixed, "argument"),
+                    given(args.len())
+                ),
+                loc.clone(),
+            ));
+        }
+        let rest = match fn_ty.params.last().map(|p| self.table.resolve(p)) {
+            Some(Ty::Array(elem)) if fn_ty.variadic => *elem,
+            _ => Ty::Error,
+        };
+        for (i, arg) in args.iter().enumerate() {
+            match fn_ty.params.get(i) {
+                Some(param) if i < fixed => self.check(arg, param),
+                _ if fn_ty.variadic => self.check(arg, &rest),
+                _ => {
+                    self.expr(arg, None);
+                }
+            }
+        }
+    }
+
+    fn path_expr(&mut self, expr: &Expr, path: &Path) -> Ty {
+        let segments = &path.segments;
+        let last = segments.last().expect("paths have at least one segment");
+        if let Some(res) = self.res.get(self.module, &last.ident) {
+            return self.value(expr, res, path);
+        }
+        // `Type::name`, an associated function or a variant of `Self`.
+        let [.., owner, _] = segments.as_slice() else {
+            return Ty::Error;
+        };
+        let owner_ty = match self.res.get(self.module, &owner.ident) {
+            Some(Res::Def(def)) if self.results.items.adts.contains_key(&def) => {
+                self.adt_ty(def, &owner.generics, &path.loc)
+            }
+            Some(Res::SelfType) => self.self_ty.clone().unwrap_or(Ty::Error),
+            Some(Res::Primitive(primitive)) => Ty::Primitive(primitive),
+            Some(Res::Generic) => Ty::Param(owner.ident.name.clone()),
+            _ => return Ty::Error,
+        };
+        if let Ty::Adt { def, ref args, .. } = owner_ty {
+            if let AdtKind::Enum(variants) = &self.results.items.adts[&def].kind {
+                if let Some(index) = variants.iter().position(|v| v.name == last.ident.name) {
+                    let substs = self.adt_substs(def, args);
+                    return self.variant_value(def, index, owner_ty.clone(), &substs);
+                }
+            }
+        }
+        let Some(candidate) = self.pick_method(&owner_ty, &last.ident) else {
+            // The resolver reports the missing members of named structs and enums.
+            let named_adt = matches!(owner_ty, Ty::Adt { .. })
+                && !matches!(self.res.get(self.module, &owner.ident), Some(Res::SelfType));
+            if !named_adt && !self.unknown_bounds(&owner_ty) {
+                let names = self.method_names(&owner_ty, false);
+                let mut error = Diagnostic::error(
+                    format!(
+                        "`{owner_ty}` has no associated function `{}`.",
+                        last.ident.name
+                    ),
+                    last.ident.loc.clone(),
+                );
+                if let Some(suggestion) =
+                    suggest(&last.ident.name, names.iter().map(String::as_str))
+                {
+                    error = error.with_help(format!("did you mean `{suggestion}`?"));
+                }
+                self.error(error);
+            }
+            return Ty::Error;
+        };
+        let (self_ty, mut fn_ty) =
+            self.instantiate_candidate(expr, candidate, &owner_ty, &last.generics, &path.loc);
+        self.expect(&self_ty, &owner_ty, &path.loc);
+        if self.candidate_sig(candidate).has_self {
+            fn_ty.params.insert(0, self_ty);
+        }
+        Ty::Fn(fn_ty)
+    }
+
+    /// The methods of a type with the name: inherent methods come first, then those of the traits
+    /// the type implements, or is bound by if it's a generic parameter.
+    fn find_methods(&self, ty: &Ty, name: &str) -> Vec<Candidate> {
+        let items = &self.results.items;
+        match ty {
+            Ty::Adt { def, .. } => {
+                if let Some(id) = items.lookup_method(*def, name) {
+                    return vec![Candidate::Impl(id)];
+                }
+            }
+            Ty::Param(param) => {
+                return self
+                    .bounds
+                    .get(param)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|bound| match *bound {
+                        Bound::Trait(trait_def) => items.traits[&trait_def]
+                            .methods
+                            .iter()
+                            .position(|m| m.name == name)
+                            .map(|index| Candidate::Bound(TraitMethodId { trait_def, index })),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+        items
+            .trait_impls_for(ty)
+            .flat_map(|(imp, _)| items.impl_methods(imp))
+            .filter(|&id| items.method(id).name == name)
+            .map(Candidate::Impl)
+            .collect()
+    }
+
+    /// The method of a type with the name, reporting it if several traits provide one.
+    fn pick_method(&mut self, ty: &Ty, name: &Ident) -> Option<Candidate> {
+        let candidates = self.find_methods(ty, &name.name);
+        if candidates.len() > 1 {
+            let traits = candidates
+                .iter()
+                .map(|&candidate| {
+                    let trait_def = match candidate {
+                        Candidate::Impl(id) => {
+                            self.results.items.impls[&id.imp]
+                                .trait_ref
+                                .as_ref()
+                                .expect("only trait impls share method names")
+                                .def
+                        }
+                        Candidate::Bound(id) => id.trait_def,
+                    };
+                    format!("`{}`", self.results.items.traits[&trait_def].name)
+                })
+                .collect::<Vec<_>>();
+            self.error(Diagnostic::error(
+                format!(
+                    "`{ty}` gets a method `{}` from more than one trait: {}.",
+                    name.name,
+                    traits.join(", ")
+                ),
+                name.loc.clone(),
+            ));
+        }
+        candidates.first().copied()
+    }
+
+    /// The names of the methods, or associated functions, of a type.
+    fn method_names(&self, ty: &Ty, with_self: bool) -> Vec<String> {
+        let items = &self.results.items;
+        let mut sigs = Vec::new();
+        if let Ty::Adt { def, .. } = ty {
+            sigs.extend(
+                items
+                    .methods_of(*def)
+                    .map(|id| items.method(id))
+                    .map(|m| (&m.name, &m.sig)),
+            );
+        }
+        if let Ty::Param(param) = ty {
+            for bound in self.bounds.get(param).into_iter().flatten() {
+                if let Bound::Trait(def) = bound {
+                    sigs.extend(items.traits[def].methods.iter().map(|m| (&m.name, &m.sig)));
+                }
+            }
+        } else {
+            for (imp, _) in items.trait_impls_for(ty) {
+                sigs.extend(items.impls[&imp].methods.iter().map(|m| (&m.name, &m.sig)));
+            }
+        }
+        sigs.into_iter()
+            .filter(|(_, sig)| sig.has_self == with_self)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Whether the type is a generic parameter with a bound that is already reported as broken,
+    /// which may have provided the missing method.
+    fn unknown_bounds(&self, ty: &Ty) -> bool {
+        match ty {
+            Ty::Param(param) => self
+                .bounds
+                .get(param)
+                .is_some_and(|b| b.contains(&Bound::Error)),
+            _ => false,
+        }
+    }
+
+    fn candidate_sig(&self, candidate: Candidate) -> &FnSig {
+        match candidate {
+            Candidate::Impl(id) => &self.results.items.method(id).sig,
+            Candidate::Bound(id) => &self.results.items.trait_method(id).sig,
+        }
+    }
+
+    /// The type the method is called on and the type of the method, instantiated with fresh
+    /// variables.
+    fn instantiate_candidate(
+        &mut self,
+        expr: &Expr,
+        candidate: Candidate,
+        self_ty: &Ty,
+        explicit: &[Type],
+        loc: &SourceCodeLocation,
+    ) -> (Ty, FnTy) {
+        match candidate {
+            Candidate::Impl(id) => {
+                self.results.methods.insert(key(self.module, &expr.loc), id);
+                self.instantiate_method(expr, id, explicit, loc)
+            }
+            Candidate::Bound(id) => {
+                let fn_ty = self.instantiate_trait_method(expr, id, self_ty, explicit, loc);
+                (self_ty.clone(), fn_ty)
+            }
+        }
+    }
+
+    /// The type of a trait method called on a generic parameter bound by the trait.
+    fn instantiate_trait_method(
+        &mut self,
+        expr: &Expr,
+        id: TraitMethodId,
+        self_ty: &Ty,
+        explicit: &[Type],
+        loc: &SourceCodeLocation,
+    ) -> FnTy {
+        let trait_def = self.results.items.traits[&id.trait_def].clone();
+        let method = &trait_def.methods[id.index];
+        let mut substs = self.instantiate(&method.sig.generics, loc);
+        self.record_generic_args(expr, &method.sig.generics, &substs);
+        self.explicit_generics(
+            &method.name,
+            &method.sig.generics[trait_def.generics.len()..],
+            &substs,
+            explicit,
+            loc,
+        );
+        substs.insert("Self".to_string(), self_ty.clone());
+        for name in &trait_def.assoc_types {
+            let projection = match self_ty {
+                Ty::Param(param) => Ty::Param(format!("{param}::{name}")),
+                _ => Ty::Error,
+            };
+            substs.insert(format!("Self::{name}"), projection);
+        }
+        self.results
+            .trait_calls
+            .insert(key(self.module, &expr.loc), (id, self_ty.clone()));
+        FnTy {
+            params: method.sig.params.iter().map(|p| p.subst(&substs)).collect(),
+            ret: Box::new(method.sig.ret.subst(&substs)),
+            variadic: method.sig.variadic,
+        }
+    }
+
+    /// The type of the impl's target and of the method, instantiated with fresh variables.
+    fn instantiate_method(
+        &mut self,
+        expr: &Expr,
+        id: MethodId,
+        explicit: &[Type],
+        loc: &SourceCodeLocation,
+    ) -> (Ty, FnTy) {
+        let imp = &self.results.items.impls[&id.imp];
+        let target = imp.target.clone();
+        let impl_generics = imp.generics.len();
+        let method = self.results.items.method(id).clone();
+        let substs = self.instantiate(&method.sig.generics, loc);
+        self.record_generic_args(expr, &method.sig.generics, &substs);
+        self.explicit_generics(
+            &method.name,
+            &method.sig.generics[impl_generics..],
+            &substs,
+            explicit,
+            loc,
+        );
+        let fn_ty = FnTy {
+            params: method.sig.params.iter().map(|p| p.subst(&substs)).collect(),
+            ret: Box::new(method.sig.ret.subst(&substs)),
+            variadic: method.sig.variadic,
+        };
+        (target.subst(&substs), fn_ty)
+    }
+
+    fn record_generic_args(
+        &mut self,
+        expr: &Expr,
+        generics: &[Generic],
+        substs: &HashMap<String, Ty>,
+    ) {
+        if generics.is_empty() {
+            return;
+        }
+        let args = generics.iter().map(|g| substs[&g.name].clone()).collect();
+        self.results
+            .generic_args
+            .insert(key(self.module, &expr.loc), args);
+    }
+
+    /// The type of a resolved name used as a value.
+    fn value(&mut self, expr: &Expr, res: Res, path: &Path) -> Ty {
+        let last = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        let name = path_name(path);
+        match res {
+            Res::Local(local) => self.results.locals[local].clone(),
+            Res::Def(def) => {
+                if let Some(ty) = self.results.items.consts.get(&def) {
+                    return ty.clone();
+                }
+                if let Some(sig) = self.results.items.fns.get(&def).cloned() {
+                    let substs = self.instantiate(&sig.generics, &path.loc);
+                    self.record_generic_args(expr, &sig.generics, &substs);
+                    self.explicit_generics(
+                        &name,
+                        &sig.generics,
+                        &substs,
+                        &last.generics,
+                        &path.loc,
+                    );
+                    return Ty::Fn(FnTy {
+                        params: sig.params.iter().map(|p| p.subst(&substs)).collect(),
+                        ret: Box::new(sig.ret.subst(&substs)),
+                        variadic: sig.variadic,
+                    });
+                }
+                let mut error = Diagnostic::error(
+                    format!("`{name}` is a type, not a value."),
+                    path.loc.clone(),
+                );
+                if let Some(AdtKind::Struct(_)) = self.results.items.adts.get(&def).map(|a| &a.kind)
+                {
+                    error = error.with_help(format!("build a value with `{name} {{ ... }}`."));
+                }
+                self.error(error);
+                Ty::Error
+            }
+            Res::Variant(def, index) => {
+                // The generic arguments may be on the enum, `Option<int>::None`, or on the variant.
+                let explicit = path
+                    .segments
+                    .iter()
+                    .rev()
+                    .take(2)
+                    .find(|s| !s.generics.is_empty())
+                    .map_or(&[][..], |s| &s.generics);
+                let adt = self.adt_ty(def, explicit, &path.loc);
+                let Ty::Adt { ref args, .. } = adt else {
+                    unreachable!("enums have ADT types")
+                };
+                let substs = self.adt_substs(def, args);
+                self.variant_value(def, index, adt.clone(), &substs)
+            }
+            Res::Native(native) => {
+                let (generics, ty) = native_ty(native);
+                let substs = self.instantiate(&generics, &path.loc);
+                ty.subst(&substs)
+            }
+            Res::Module(_) => {
+                self.error(Diagnostic::error(
+                    format!("`{name}` is a module, not a value."),
+                    path.loc.clone(),
+                ));
+                Ty::Error
+            }
+            Res::Primitive(_) | Res::Generic | Res::SelfType => {
+                self.error(Diagnostic::error(
+                    format!("`{name}` is a type, not a value."),
+                    path.loc.clone(),
+                ));
+                Ty::Error
+            }
+            Res::Error => Ty::Error,
+        }
+    }
+
+    /// A variant without fields is a value of the enum, one with fields constructs the enum.
+    fn variant_value(
+        &mut self,
+        def: DefId,
+        index: usize,
+        adt: Ty,
+        substs: &HashMap<String, Ty>,
+    ) -> Ty {
+        let AdtKind::Enum(variants) = &self.results.items.adts[&def].kind else {
+            unreachable!("variants belong to enums")
+        };
+        let fields = &variants[index].fields;
+        if fields.is_empty() {
+            adt
+        } else {
+            Ty::func(fields.iter().map(|f| f.subst(substs)).collect(), adt)
+        }
+    }
+
+    fn method_call(
+        &mut self,
+        expr: &Expr,
+        receiver: &Expr,
+        method: &Ident,
+        args: &[Expr],
+        expected: Option<&Ty>,
+    ) -> Ty {
+        let receiver_ty = self.expr(receiver, None);
+        let found = match self.known(&receiver_ty, &receiver.loc) {
+            Some(ty) => match self.pick_method(&ty, method) {
+                Some(candidate) if self.candidate_sig(candidate).has_self => Ok((candidate, ty)),
+                Some(_) => {
+                    let owner = ty.trait_name();
+                    Err(Some(
+                        Diagnostic::error(
+                            format!(
+                                "`{}` is an associated function of `{owner}`, not a method.",
+                                method.name
+                            ),
+                            method.loc.clone(),
+                        )
+                        .with_help(format!("call it as `{owner}::{}(...)`.", method.name)),
+                    ))
+                }
+                None if self.unknown_bounds(&ty) => Err(None),
+                None => {
+                    let names = self.method_names(&ty, true);
+                    let suggestion = suggest(&method.name, names.iter().map(String::as_str));
+                    let error = Diagnostic::error(
+                        format!("`{ty}` has no method `{}`.", method.name),
+                        method.loc.clone(),
+                    );
+                    Err(Some(match (suggestion, &ty) {
+                        (Some(suggestion), _) => {
+                            error.with_help(format!("did you mean `{suggestion}`?"))
+                        }
+                        (None, Ty::Param(param)) => error.with_help(format!(
+                            "the methods of `{param}` come from the traits it's bound by."
+                        )),
+                        (None, _) => error,
+                    }))
+                }
+            },
+            None => Err(None),
+        };
+        let (candidate, ty) = match found {
+            Ok(found) => found,
+            Err(error) => {
+                if let Some(error) = error {
+                    self.error(error);
+                }
+                for arg in args {
+                    self.expr(arg, Some(&Ty::Error));
+                }
+                return Ty::Error;
+            }
+        };
+        let (self_ty, fn_ty) = self.instantiate_candidate(expr, candidate, &ty, &[], &method.loc);
+        self.expect(&self_ty, &receiver_ty, &receiver.loc);
+        self.call(&Ty::Fn(fn_ty), args, expected, &method.loc)
+    }
+
+    fn field(&mut self, base: &Expr, field: &Ident) -> Ty {
+        let base_ty = self.expr(base, None);
+        let Some(base_ty) = self.known(&base_ty, &base.loc) else {
+            return Ty::Error;
+        };
+        if let Ty::Adt { def, args, .. } = &base_ty {
+            if let AdtKind::Struct(fields) = &self.results.items.adts[def].kind {
+                if let Some(found) = fields.iter().find(|f| f.name == field.name) {
+                    let substs = self.adt_substs(*def, args);
+                    return found.ty.subst(&substs);
+                }
+                let suggestion = suggest(&field.name, fields.iter().map(|f| f.name.as_str()));
+                let error = Diagnostic::error(
+                    format!("`{base_ty}` has no field `{}`.", field.name),
+                    field.loc.clone(),
+                );
+                self.error(match suggestion {
+                    Some(suggestion) => error.with_help(format!("did you mean `{suggestion}`?")),
+                    None => error,
+                });
+                return Ty::Error;
+            }
+        }
+        self.error(Diagnostic::error(
+            format!("`{base_ty}` has no field `{}`.", field.name),
+            field.loc.clone(),
+        ));
+        Ty::Error
+    }
+
+    /// The struct named by the path of a struct literal or pattern, with fresh generic arguments.
+    fn struct_path(&mut self, path: &Path) -> Option<(DefId, Ty)> {
+        let last = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        let ty = match self.res.get(self.module, &last.ident) {
+            Some(Res::Def(def)) if self.results.items.adts.contains_key(&def) => {
+                self.adt_ty(def, &last.generics, &path.loc)
+            }
+            Some(Res::SelfType) => self.self_ty.clone().unwrap_or(Ty::Error),
+            Some(Res::Error) | None => return None,
+            Some(_) => Ty::Error,
+        };
+        match &ty {
+            Ty::Adt { def, .. }
+                if matches!(self.results.items.adts[def].kind, AdtKind::Struct(_)) =>
+            {
+                Some((*def, ty))
+            }
+            _ => {
+                self.error(Diagnostic::error(
+                    format!("`{}` is not a struct.", path_name(path)),
+                    path.loc.clone(),
+                ));
+                None
+            }
+        }
+    }
+
+    fn struct_lit(
+        &mut self,
+        path: &Path,
+        inits: &[FieldInit],
+        expected: Option<&Ty>,
+        loc: &SourceCodeLocation,
+    ) -> Ty {
+        let Some((def, ty)) = self.struct_path(path) else {
+            for init in inits {
+                self.expr(&init.value, None);
+            }
+            return Ty::Error;
+        };
+        if let Some(expected) = expected {
+            self.table.try_unify(&ty, expected);
+        }
+        let Ty::Adt { args, .. } = &ty else {
+            unreachable!("structs have ADT types")
+        };
+        let substs = self.adt_substs(def, args);
+        let AdtKind::Struct(fields) = self.results.items.adts[&def].kind.clone() else {
+            unreachable!("checked by struct_path")
+        };
+        let mut initialized: Vec<&str> = Vec::new();
+        for init in inits {
+            let name = init.name.name.as_str();
+            match fields.iter().find(|f| f.name == name) {
+                Some(field) => {
+                    if initialized.contains(&name) {
+                        self.error(Diagnostic::error(
+                            format!("`{name}` is initialized more than once."),
+                            init.name.loc.clone(),
+                        ));
+                    }
+                    initialized.push(name);
+                    self.check(&init.value, &field.ty.subst(&substs));
+                }
+                None => {
+                    let suggestion = suggest(name, fields.iter().map(|f| f.name.as_str()));
+                    let error = Diagnostic::error(
+                        format!("`{}` has no field `{name}`.", path_name(path)),
+                        init.name.loc.clone(),
+                    );
+                    self.error(match suggestion {
+                        Some(suggestion) => {
+                            error.with_help(format!("did you mean `{suggestion}`?"))
+                        }
+                        None => error,
+                    });
+                    self.expr(&init.value, None);
+                }
+            }
+        }
+        let missing = fields
+            .iter()
+            .filter(|f| !initialized.contains(&f.name.as_str()))
+            .map(|f| format!("`{}`", f.name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.error(Diagnostic::error(
+                format!(
+                    "Missing fields in `{}`: {}.",
+                    path_name(path),
+                    missing.join(", ")
+                ),
+                loc.clone(),
+            ));
+        }
+        ty
+    }
+
+    /// A function value that never returns may be a continuation such as `quit`, which returns from
+    /// the function calling it like `return;` once it has told its iterator to stop.
+    fn continuation_call(&mut self, callee: &Expr, loc: &SourceCodeLocation) {
+        let name = match &callee.kind {
+            ExprKind::Path(path) => {
+                let last = &path.segments.last().expect("paths have a segment").ident;
+                match self.res.get(self.module, last) {
+                    Some(Res::Local(_)) => format!("`{}`", last.name),
+                    _ => return,
+                }
+            }
+            _ => "this function".to_string(),
+        };
+        let (ret, seen) = self.returns.last_mut().expect("calls are inside functions");
+        *seen = true;
+        let ret = ret.clone();
+        if !self.table.unify(&ret, &Ty::VOID) {
+            let ret = self.table.resolve(&ret);
+            self.error(
+                Diagnostic::error(
+                    format!("Calling {name} returns from this function, which returns `{ret}`."),
+                    loc.clone(),
+                )
+                .with_help("continuations can only be called in functions returning `void`."),
+            );
+        }
+    }
+
+    fn closure(&mut self, closure: &Closure, expected: Option<&Ty>) -> Ty {
+        let expected = match expected.map(|e| self.table.resolve(e)) {
+            Some(Ty::Fn(fn_ty)) if fn_ty.params.len() == closure.params.len() => Some(fn_ty),
+            // A closure passed to something broken can't be checked either.
+            Some(Ty::Error) => Some(FnTy {
+                params: vec![Ty::Error; closure.params.len()],
+                ret: Box::new(Ty::Error),
+                variadic: false,
+            }),
+            _ => None,
+        };
+        let mut params = Vec::new();
+        for (i, param) in closure.params.iter().enumerate() {
+            let expected = expected.as_ref().map(|e| e.params[i].clone());
+            let ty = match (&param.ty, expected) {
+                (Some(annotation), expected) => {
+                    let ty = self.lower_ty(annotation, true);
+                    if let Some(expected) = expected {
+                        self.expect(&expected, &ty, &annotation.loc);
+                    }
+                    ty
+                }
+                (None, Some(expected)) => expected,
+                (None, None) => self.table.fresh(),
+            };
+            self.pattern(&param.pattern, &ty);
+            params.push(ty);
+        }
+        let ret = match &expected {
+            Some(fn_ty) => (*fn_ty.ret).clone(),
+            None => self.table.fresh(),
+        };
+        match &closure.body {
+            ClosureBody::Expr(expr) => {
+                self.returns.push((ret.clone(), false));
+                self.check(expr, &ret);
+                self.returns.pop();
+            }
+            ClosureBody::Block(block) => {
+                self.returns.push((ret.clone(), false));
+                self.block(block);
+                let (_, seen) = self.returns.pop().expect("pushed above");
+                if !seen {
+                    self.table.try_unify(&ret, &Ty::VOID);
+                }
+            }
+        }
+        Ty::func(params, ret)
+    }
+
+    // Patterns.
+
+    fn pattern(&mut self, pattern: &Pattern, expected: &Ty) {
+        match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Rest => {}
+            PatternKind::Binding { name, sub, .. } => {
+                if let Some(Res::Local(local)) = self.res.get(self.module, name) {
+                    // The alternatives of an or-pattern bind the same locals.
+                    if self.fn_locals.contains(&local) {
+                        let ty = self.results.locals[local].clone();
+                        self.expect(&ty, expected, &name.loc);
+                    } else {
+                        self.fn_locals.push(local);
+                        self.results.locals[local] = expected.clone();
+                    }
+                }
+                if let Some(sub) = sub {
+                    self.pattern(sub, expected);
+                }
+            }
+            PatternKind::Literal(literal) => {
+                self.expect(expected, &literal_ty(literal), &pattern.loc);
+            }
+            PatternKind::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let ty = literal_ty(start);
+                if !matches!(ty, Ty::Primitive(Primitive::Int | Primitive::Char)) {
+                    self.error(Diagnostic::error(
+                        format!("Range patterns only match `int` and `char` values, not `{ty}`."),
+                        pattern.loc.clone(),
+                    ));
+                    return;
+                }
+                self.expect(&ty, &literal_ty(end), &pattern.loc);
+                self.expect(expected, &ty, &pattern.loc);
+                let bounds = match (start, end) {
+                    (Literal::Int(a), Literal::Int(b)) => Some((*a as i128, *b as i128)),
+                    (Literal::Char(a), Literal::Char(b)) => Some((*a as i128, *b as i128)),
+                    _ => None,
+                };
+                if bounds.is_some_and(|(a, b)| a > b || (a == b && !inclusive)) {
+                    self.error(Diagnostic::error(
+                        "This range pattern is empty, so it never matches.",
+                        pattern.loc.clone(),
+                    ));
+                }
+            }
+            PatternKind::Tuple(patterns) => {
+                let tys = if patterns.iter().any(|p| p.kind == PatternKind::Rest) {
+                    match self.known(expected, &pattern.loc) {
+                        Some(Ty::Tuple(tys)) => tys,
+                        Some(ty) => {
+                            self.error(Diagnostic::error(
+                                format!("Mismatched types: expected `{ty}`, found a tuple."),
+                                pattern.loc.clone(),
+                            ));
+                            vec![Ty::Error; patterns.len()]
+                        }
+                        None => vec![Ty::Error; patterns.len()],
+                    }
+                } else {
+                    let tys = patterns
+                        .iter()
+                        .map(|_| self.table.fresh())
+                        .collect::<Vec<_>>();
+                    self.expect(expected, &Ty::Tuple(tys.clone()), &pattern.loc);
+                    tys
+                };
+                self.fields(patterns, &tys, "tuple", &pattern.loc);
+            }
+            PatternKind::Variant { path, fields } => {
+                self.variant_pattern(pattern, path, fields.as_deref(), expected)
+            }
+            PatternKind::Struct { path, fields, rest } => {
+                let Some((def, ty)) = self.struct_path(path) else {
+                    for field in fields {
+                        self.pattern(&field.pattern, &Ty::Error);
+                    }
+                    return;
+                };
+                self.expect(expected, &ty, &pattern.loc);
+                let Ty::Adt { args, .. } = &ty else {
+                    unreachable!("structs have ADT types")
+                };
+                let substs = self.adt_substs(def, args);
+                let AdtKind::Struct(decls) = self.results.items.adts[&def].kind.clone() else {
+                    unreachable!("checked by struct_path")
+                };
+                for field in fields {
+                    match decls.iter().find(|f| f.name == field.name.name) {
+                        Some(decl) => self.pattern(&field.pattern, &decl.ty.subst(&substs)),
+                        None => {
+                            self.error(Diagnostic::error(
+                                format!(
+                                    "`{}` has no field `{}`.",
+                                    path_name(path),
+                                    field.name.name
+                                ),
+                                field.name.loc.clone(),
+                            ));
+                            self.pattern(&field.pattern, &Ty::Error);
+                        }
+                    }
+                }
+                let missing = decls
+                    .iter()
+                    .filter(|d| !fields.iter().any(|f| f.name.name == d.name))
+                    .map(|d| format!("`{}`", d.name))
+                    .collect::<Vec<_>>();
+                if !rest && !missing.is_empty() {
+                    self.error(
+                        Diagnostic::error(
+                            format!("Missing fields in the pattern: {}.", missing.join(", ")),
+                            pattern.loc.clone(),
+                        )
+                        .with_help("add `..` to ignore them."),
+                    );
+                }
+            }
+            PatternKind::Or(alternatives) => {
+                for alternative in alternatives {
+                    self.pattern(alternative, expected);
+                }
+            }
+        }
+    }
+
+    fn variant_pattern(
+        &mut self,
+        pattern: &Pattern,
+        path: &Path,
+        fields: Option<&[Pattern]>,
+        expected: &Ty,
+    ) {
+        let last = path
+            .segments
+            .last()
+            .expect("paths have at least one segment");
+        let variant = match self.res.get(self.module, &last.ident) {
+            Some(Res::Variant(def, index)) => Some((def, index)),
+            // `Self::Variant`
+            None => match self.self_ty.clone() {
+                Some(Ty::Adt { def, .. })
+                    if path.segments.len() == 2
+                        && self.res.get(self.module, &path.segments[0].ident)
+                            == Some(Res::SelfType) =>
+                {
+                    match &self.results.items.adts[&def].kind {
+                        AdtKind::Enum(variants) => variants
+                            .iter()
+                            .position(|v| v.name == last.ident.name)
+                            .map(|index| (def, index)),
+                        AdtKind::Struct(_) => None,
+                    }
+                }
+                _ => None,
+            },
+            Some(Res::Error) => None,
+            Some(_) => {
+                self.error(Diagnostic::error(
+                    format!("`{}` is not an enum variant.", path_name(path)),
+                    path.loc.clone(),
+                ));
+                None
+            }
+        };
+        let Some((def, index)) = variant else {
+            for field in fields.into_iter().flatten() {
+                self.pattern(field, &Ty::Error);
+            }
+            return;
+        };
+        let adt = self.adt_ty(def, &last.generics, &path.loc);
+        self.expect(expected, &adt, &pattern.loc);
+        let Ty::Adt { args, .. } = &adt else {
+            unreachable!("enums have ADT types")
+        };
+        let substs = self.adt_substs(def, args);
+        let AdtKind::Enum(variants) = &self.results.items.adts[&def].kind else {
+            unreachable!("variants belong to enums")
+        };
+        let tys = variants[index]
+            .fields
+            .iter()
+            .map(|f| f.subst(&substs))
+            .collect::<Vec<_>>();
+        match fields {
+            Some(fields) => self.fields(fields, &tys, "variant", &pattern.loc),
+            None if !tys.is_empty() => self.error(
+                Diagnostic::error(
+                    format!(
+                        "The variant `{}` has {}.",
+                        path_name(path),
+                        count(tys.len(), "field")
+                    ),
+                    pattern.loc.clone(),
+                )
+                .with_help(format!("match it with `{}(..)`.", path_name(path))),
+            ),
+            None => {}
+        }
+    }
+
+    /// Check the fields of a tuple or variant pattern, where a `..` stands for any number of fields.
+    fn fields(&mut self, patterns: &[Pattern], tys: &[Ty], what: &str, loc: &SourceCodeLocation) {
+        let rest = patterns.iter().position(|p| p.kind == PatternKind::Rest);
+        let listed = patterns.len() - usize::from(rest.is_some());
+        if listed > tys.len() || (rest.is_none() && listed != tys.len()) {
+            self.error(Diagnostic::error(
+                format!(
+                    "The {what} has {}, but the pattern has {listed}.",
+                    count(tys.len(), "field")
+                ),
+                loc.clone(),
+            ));
+            for pattern in patterns {
+                self.pattern(pattern, &Ty::Error);
+            }
+            return;
+        }
+        let (before, after) = match rest {
+            Some(rest) => (&patterns[..rest], &patterns[rest + 1..]),
+            None => (patterns, &[][..]),
+        };
+        for (pattern, ty) in before.iter().zip(tys) {
+            self.pattern(pattern, ty);
+        }
+        for (pattern, ty) in after.iter().zip(&tys[tys.len() - after.len()..]) {
+            self.pattern(pattern, ty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sema::modules::ModuleLoader;
+    use crate::sema::resolve::resolve;
+    use std::path::Path;
+
+    /// Check the source, returning the type of each named local and the error messages.
+    fn check_source(src: &str) -> (HashMap<String, String>, Vec<String>) {
+        let graph = ModuleLoader::new(vec![])
+            .load_source(Path::new("main.paca"), src.to_string())
+            .unwrap();
+        check_graph(&graph)
+    }
+
+    /// Check every module of the graph, returning the type of each named local of `main.paca` and
+    /// the error messages.
+    fn check_graph(graph: &ModuleGraph) -> (HashMap<String, String>, Vec<String>) {
+        let (resolutions, mut diagnostics) = resolve(graph);
+        let (results, typeck_diagnostics) = check(graph, &resolutions);
+        diagnostics.extend(typeck_diagnostics);
+        let locals = resolutions
+            .locals
+            .iter()
+            .zip(&results.locals)
+            .filter(|(local, _)| {
+                let file = local.loc.filename.as_deref().unwrap_or_default();
+                file.ends_with("main.paca")
+            })
+            .map(|(local, ty)| (local.name.clone(), ty.to_string()))
+            .collect();
+        (locals, diagnostics.into_iter().map(|d| d.message).collect())
+    }
+
+    #[test]
+    fn implements_traits_of_std_modules() {
+        let (locals, errors) = check_source(
+            "import std::convert::From;
+struct Meters { value: int }
+impl From<int> for Meters {
+    def from(value: int) Self {
+        return Meters { value => value };
+    }
+}
+@native def read_file(path: str) str;
+def main() void {
+    let meters = Meters::from(3);
+}
+",
+        );
+        assert_eq!(locals["meters"], "Meters");
+        assert_eq!(
+            errors,
+            ["Only the std modules can declare `@native` functions."]
+        );
+    }
+
+    #[test]
+    fn infers_local_types() {
+        let (locals, errors) = check_source(
+            "struct Pair<A, B> { first: A, second: B }
+def swap<A, B>(pair: Pair<A, B>) Pair<B, A> {
+    return Pair { first => pair->second, second => pair->first };
+}
+def apply(x: int, f: def(int) int) int {
+    return f(x);
+}
+def main() void {
+    let a: Option<int> = Option::Some(123);
+    let b = a->unwrap();
+    let c: Option<int> = Option::None;
+    let d = c->unwrap_or(321);
+    let swapped = swap(Pair { first => \"one\", second => 1.5 });
+    let items: []str = [];
+    let (first, rest) = (Option::Some('c'), items);
+    let doubled = apply(2) { (x) = x * 2 };
+    let total = d + b;
+    let greater = total > 1 && !false;
+    println(greater);
+}
+",
+        );
+        assert_eq!(errors, Vec::<String>::new());
+        for (name, ty) in [
+            ("b", "int"),
+            ("d", "int"),
+            ("swapped", "Pair<float, str>"),
+            ("first", "Option<char>"),
+            ("rest", "[]str"),
+            ("x", "int"),
+            ("total", "int"),
+            ("greater", "bool"),
+        ] {
+            assert_eq!(locals[name], ty, "{name}");
+        }
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let (_, errors) = check_source(
+            "struct Point { x: int, y: int }
+def norm(p: Point) int {
+    return p->x * p->x + p->y;
+}
+def main() void {
+    let a: Option<int> = Option::Some(\"x\");
+    let b: Option<int> = Option::Some(1)->unwrap();
+    let c = norm(Point { x => 1 }, 2);
+    let d = Point { x => 1, y => 2, z => 3 }->z;
+    let e = Option::None;
+    if 1 {
+        return 2;
+    }
+    match a {
+        Option::Some(\"one\") => {},
+        Option::Some(x, y) => {},
+        _ => {},
+    }
+}
+",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "Mismatched types: expected `int`, found `str`.",
+                "Mismatched types: expected `Option<int>`, found `int`.",
+                "This function takes 1 argument but 2 were given.",
+                "Missing fields in `Point`: `y`.",
+                "`Point` has no field `z`.",
+                "`Point` has no field `z`.",
+                "Mismatched types: expected `bool`, found `int`.",
+                "Mismatched types: expected `void`, found `int`.",
+                "Mismatched types: expected `int`, found `str`.",
+                "The variant has 1 field, but the pattern has 2.",
+                "Can't infer the type of `e`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_through_continuations() {
+        let (_, errors) = check_source(
+            "def each(f: def(def() never, int) void) void {
+    return;
+}
+def first(abort: def() never) int {
+    abort();
+}
+def main() void {
+    each() { (quit, n) =>
+        if n > 1 {
+            quit();
+        }
+    };
+    each() { (quit, _) = quit() };
+}
+",
+        );
+        assert_eq!(
+            errors,
+            vec!["Calling `abort` returns from this function, which returns `int`."]
+        );
+    }
+
+    #[test]
+    fn checks_methods_and_operators() {
+        let (locals, errors) = check_source(
+            "struct Counter { count: int }
+impl methods for Counter {
+    def init() Self {
+        return Self { count => 0 };
+    }
+    def next(self) int {
+        return self->count + 1;
+    }
+}
+def main() void {
+    let counter = Counter::init();
+    let next = counter->next();
+    let wrong = counter->nxt();
+    let static = counter->init();
+    let sum = counter + counter;
+    let text = \"a\" + \"b\";
+}
+",
+        );
+        assert_eq!(locals["next"], "int");
+        assert_eq!(locals["text"], "str");
+        assert_eq!(
+            errors,
+            vec![
+                "`Counter` has no method `nxt`.",
+                "`init` is an associated function of `Counter`, not a method.",
+                "`+` can't be used on `Counter` because it doesn't implement `std::ops::Add`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_generic_bounds() {
+        let (locals, errors) = check_source(
+            "import std::ops::Add;
+struct Wrapper<T: Add> { value: T }
+def sum<T: Add>(a: T, b: T) T {
+    return a + b;
+}
+def negate<T: Add>(a: T) T {
+    return -a;
+}
+def main() void {
+    let total = sum(1, 2);
+    let pair = sum((1, 2), (3, 4));
+    let wrapper = Wrapper { value => true };
+}
+",
+        );
+        assert_eq!(locals["total"], "int");
+        assert_eq!(
+            errors,
+            vec![
+                "`-` can't be used on `T` because it doesn't implement `std::ops::Negate`.",
+                "`(int, int)` doesn't implement `std::ops::Add`.",
+                "`bool` doesn't implement `std::ops::Add`.",
+            ]
+        );
+    }
+    #[test]
+    fn checks_trait_impls() {
+        let (locals, errors) = check_source(
+            "trait Shape {
+    type Unit;
+    def area(self) float;
+    def double(self) float {
+        return self->area() * 2.0;
+    }
+}
+struct Square { side: float }
+struct Circle { radius: float }
+impl Shape for Square {
+    type Unit = int;
+    def area(self) int {
+        return 1;
+    }
+    def perimeter(self) float {
+        return 1.0;
+    }
+}
+impl Shape for Circle {
+    type Unit = float;
+    def area(self) float {
+        return self->radius;
+    }
+}
+impl Shape for Circle {
+    type Unit = float;
+    def area(self) float {
+        return 0.0;
+    }
+}
+def twice<S: Shape>(shape: S) float {
+    return shape->double();
+}
+def unbound<S>(shape: S) float {
+    return shape->area();
+}
+def main() void {
+    let circle = Circle { radius => 1.0 };
+    let doubled = circle->double();
+    let total = twice(circle);
+    let wrong = twice(1);
+}
+",
+        );
+        assert_eq!(locals["doubled"], "float");
+        assert_eq!(locals["total"], "float");
+        assert_eq!(
+            errors,
+            vec![
+                "The signature of `area` doesn't match `Shape`: expected `def() float`, found `def() int`.",
+                "`perimeter` is not a method of `Shape`.",
+                "Conflicting implementations of `Shape` for `Circle`.",
+                "`S` has no method `area`.",
+                "`int` doesn't implement `Shape`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn projects_associated_types() {
+        let (locals, errors) = check_source(
+            "trait Source {
+    type Item;
+    def next(self) Self::Item;
+}
+struct Numbers { start: int }
+impl Source for Numbers {
+    type Item = int;
+    def next(self) int {
+        return self->start;
+    }
+}
+struct Empty {}
+impl Source for Empty {
+    def next(self) int {
+        return 0;
+    }
+}
+def first<S: Source>(source: S) S::Item {
+    return source->next();
+}
+def main() void {
+    let numbers = Numbers { start => 1 };
+    let item = numbers->next();
+}
+",
+        );
+        assert_eq!(locals["item"], "int");
+        assert_eq!(
+            errors,
+            vec!["Missing associated types in the implementation of `Source` for `Empty`: `Item`."]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_operator_impls() {
+        let (_, errors) = check_source(
+            "import std::cmp::(Equal, Order);
+import std::ops::Not;
+struct P { x: int }
+impl Equal for P { def equal(self, other: P) int { return 1; } }
+impl Equal for P { def equal(self, other: P) bool { return true; } }
+impl Order for P { def greater(self, other: P) bool { return true; } }
+impl Not for bool { def not(self) bool { return self; } }
+",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "The signature of `equal` doesn't match `Equal`: expected `def(P) bool`, found \
+                 `def(P) int`.",
+                "`greater` is not a method of `Order`.",
+                "Missing methods in the implementation of `Order` for `P`: `less`.",
+                "Methods can only be implemented for structs and enums.",
+                "Conflicting implementations of `Equal` for `P`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_operator_impls_by_type() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/modules/operators");
+        let graph = ModuleLoader::new(vec![root.clone()])
+            .load(&root.join("main.paca"))
+            .unwrap();
+        let (locals, errors) = check_graph(&graph);
+        assert_eq!(locals["sum"], "P");
+        assert_eq!(
+            errors,
+            vec![
+                "Can't implement `Equal` for `Option<T>` here: the trait or the type must be \
+                 declared in this module.",
+                "`+` can't be used on `P` because it doesn't implement `std::ops::Add`.",
+            ]
+        );
+    }
+
+    #[test]
+    fn primitives_implement_hashable() {
+        let (_, errors) = check_source(
+            "import std::hash::Hashable;
+def h<T: Hashable>(x: T) int { return x->hash(); }
+def main() void {
+    let hashes = (h(3), h(\"key\"), h('k'), h(true));
+    let broken = h(1.5);
+}
+",
+        );
+        assert_eq!(errors, vec!["`float` doesn't implement `Hashable`."]);
+    }
+}
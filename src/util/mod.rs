@@ -1,23 +1,3 @@
-/// Idea taken from https://gist.github.com/huonw/8435502.
-macro_rules! weird_while {
-    ($body:expr, $cond:expr) => {
-        while {
-            $body;
-            $cond
-        } {}
-    };
-
-    ($body:expr, $cond:expr, $special:expr) => {
-        while {
-            $body;
-            if $cond {
-                $special;
-            }
-            $cond
-        } {}
-    };
-}
-
 /// Boilerplate for escaping characters.
 macro_rules! escape_char {
     ($self:expr, $str:expr) => {
@@ -34,6 +14,10 @@ macro_rules! escape_char {
                 $str.push('"');
                 $self.next();
             }
+            Some(&'\'') => {
+                $str.push('\'');
+                $self.next();
+            }
             Some(&'n') => {
                 $str.push('\n');
                 $self.next();
@@ -51,9 +35,64 @@ macro_rules! escape_char {
     };
 }
 
-pub(crate) use {escape_char, weird_while};
+pub(crate) use escape_char;
 
 /// This trait is for error enums and structs to properly format error messages.
 pub trait GenerateErrorMessage: Clone {
-    fn generate_error_message(self, source_code: &String) -> String;
+    fn generate_error_message(self, source_code: &str) -> String;
+}
+
+/// The edit distance between two strings, counting insertions, deletions, substitutions and swaps of
+/// adjacent characters as one edit each.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Pick the candidate closest to a misspelled `name`, if any is close enough to be a likely typo.
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_close_names() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("ture", "true"), 1);
+        assert_eq!(
+            suggest("lenght", ["length", "len", "height"]),
+            Some("length")
+        );
+        assert_eq!(suggest("ture", ["true", "false"]), Some("true"));
+        assert_eq!(suggest("x", ["y"]), Some("y"));
+        assert_eq!(suggest("entries", ["keys", "values"]), None);
+    }
 }